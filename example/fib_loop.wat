@@ -4,6 +4,7 @@
     i32.const 10
     call $fib2
     return
+    drop
     i32.const 0
   )
   (func $fib2
@@ -16,12 +17,15 @@
     local.get $a
     i32.const 1
     i32.le_s
-    (if
-      (then
-    local.get $a
-    return
-      )
-    )
+    if
+      local.get $a
+      return
+      drop
+      i32.const 0
+      drop
+    end
+    i32.const 0
+    drop
     i32.const 0
     local.tee $p0
     drop
@@ -31,35 +35,40 @@
     i32.const 2
     local.tee $i
     drop
-    (block $block41
-      (loop $loop41
-    local.get $i
-    local.get $a
-    i32.le_s
+    block $block0
+      loop $loop0
+        local.get $i
+        local.get $a
+        i32.le_s
         i32.const 0
         i32.eq
-        br_if $block41
-    local.get $p0
-    local.get $p1
-    i32.add
-    local.tee $p2
-    drop
-    local.get $p1
-    local.tee $p0
-    drop
-    local.get $p2
-    local.tee $p1
-    drop
-    local.get $i
-    i32.const 1
-    i32.add
-    local.tee $i
+        br_if $block0
+        local.get $p0
+        local.get $p1
+        i32.add
+        local.tee $p2
         drop
-        br $loop41
-      )
-    )
+        local.get $p1
+        local.tee $p0
+        drop
+        local.get $p2
+        local.tee $p1
+        drop
+        i32.const 0
+        drop
+        local.get $i
+        i32.const 1
+        i32.add
+        local.tee $i
+        drop
+        br $loop0
+      end
+    end
+    i32.const 0
+    drop
     local.get $p2
     return
+    drop
     i32.const 0
   )
   (export "main" (func $main))
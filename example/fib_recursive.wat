@@ -4,6 +4,7 @@
     i32.const 10
     call $fib1
     return
+    drop
     i32.const 0
   )
   (func $fib1
@@ -12,12 +13,15 @@
     local.get $a
     i32.const 1
     i32.le_s
-    (if
-      (then
-    local.get $a
-    return
-      )
-    )
+    if
+      local.get $a
+      return
+      drop
+      i32.const 0
+      drop
+    end
+    i32.const 0
+    drop
     local.get $a
     i32.const 2
     i32.sub
@@ -28,6 +32,7 @@
     call $fib1
     i32.add
     return
+    drop
     i32.const 0
   )
   (export "main" (func $main))
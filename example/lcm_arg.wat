@@ -7,6 +7,7 @@
     local.get $b
     call $lcm
     return
+    drop
     i32.const 0
   )
   (func $lcm
@@ -21,6 +22,7 @@
     local.get $b
     i32.mul
     return
+    drop
     i32.const 0
   )
   (func $gcd
@@ -30,32 +32,41 @@
     local.get $a
     local.get $b
     i32.lt_s
-    (if
-      (then
-    local.get $b
-    local.get $a
-    call $gcd
-    return
-      )
-    )
+    if
+      local.get $b
+      local.get $a
+      call $gcd
+      return
+      drop
+      i32.const 0
+      drop
+    end
+    i32.const 0
+    drop
     local.get $a
     local.get $b
     i32.eq
-    (if
-      (then
-    local.get $a
-    return
-      )
-    )
+    if
+      local.get $a
+      return
+      drop
+      i32.const 0
+      drop
+    end
+    i32.const 0
+    drop
     local.get $b
     i32.const 0
     i32.eq
-    (if
-      (then
-    local.get $a
-    return
-      )
-    )
+    if
+      local.get $a
+      return
+      drop
+      i32.const 0
+      drop
+    end
+    i32.const 0
+    drop
     local.get $b
     local.get $a
     local.get $a
@@ -66,6 +77,7 @@
     i32.sub
     call $gcd
     return
+    drop
     i32.const 0
   )
   (export "main" (func $main))
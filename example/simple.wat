@@ -3,6 +3,7 @@
     (result i32)
     i32.const 1
     return
+    drop
     i32.const 0
   )
   (export "main" (func $main))
@@ -12,10 +12,11 @@ mod if_node;
 mod while_node;
 mod for_node;
 mod call;
-mod leb128;
+mod inline_node;
+mod tail_call;
+mod visitor;
 
-use std::any::Any;
-pub use module::Module;
+pub use module::{BUILTINS, Module, STRING_BASE};
 pub use function::Function;
 pub use wasm_type::WasmType;
 pub use param::Param;
@@ -29,34 +30,125 @@ pub use if_node::IfNode;
 pub use while_node::WhileNode;
 pub use for_node::ForNode;
 pub use call::Call;
+pub use inline_node::{InlineContext, InlineNode};
+pub use tail_call::{TailCallNode, TailLoopNode};
+pub use visitor::*;
 
-use std::io::{Write, Result};
-use std::sync::atomic::{AtomicU32, Ordering};
+use crate::ir::Instr;
 
-pub trait WatWriter {
-    fn write_wat(&self, write: &mut dyn Write) -> Result<()>;
+// 式。どれも i32 の値を 1 つ残す
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Number(Number),
+    Variable(Variable),
+    Assign(Assign),
+    BiOperator(BiOperator),
+    Call(Call),
+    Inline(InlineNode),
 }
-pub trait WasmWriter {
-    fn write_wasm(&self, module: Option<&Module>, function: Option<&Function>, write: &mut dyn Write) -> Result<()>;
+
+// 文。式と同じく値を 1 つ残し、ブロックがそれを捨てる
+#[derive(Clone, Debug)]
+pub enum Stmt {
+    Expr(Expr),
+    Return(ReturnNode),
+    If(IfNode),
+    While(WhileNode),
+    For(ForNode),
+    Block(Block),
+    TailLoop(TailLoopNode),
+    TailCall(TailCallNode),
 }
-pub trait AstNode: WatWriter + WasmWriter + Any {
-    fn as_variable(&self) -> Option<&Variable> {
-        None
+
+impl Expr {
+
+    pub fn write_instructions(&self, labels: &mut Labels, instructions: &mut Vec<Instr>) {
+        match self {
+            Expr::Number(number) => number.write_instructions(labels, instructions),
+            Expr::Variable(variable) => variable.write_instructions(labels, instructions),
+            Expr::Assign(assign) => assign.write_instructions(labels, instructions),
+            Expr::BiOperator(operator) => operator.write_instructions(labels, instructions),
+            Expr::Call(call) => call.write_instructions(labels, instructions),
+            Expr::Inline(inline) => inline.write_instructions(labels, instructions),
+        }
     }
-    fn children(&self) -> Vec<&Box<dyn AstNode>> {
-        vec![]
+
+    // 副作用もトラップもなく、評価を省略・複製してよい式か
+    pub fn is_pure(&self) -> bool {
+        match self {
+            Expr::Number(_) | Expr::Variable(_) => true,
+            // 除算は 0 除算でトラップしうる
+            Expr::BiOperator(operator) => operator.kind != BiOpKind::Div && operator.lhs.is_pure() && operator.rhs.is_pure(),
+            _ => false
+        }
     }
 
-    fn collect_locals(&self, locals: &mut Vec<String>) {
-        for child in self.children().iter() {
-            child.collect_locals(locals);
+    pub fn as_number(&self) -> Option<i32> {
+        match self {
+            Expr::Number(number) if !number.address => Some(number.value),
+            _ => None
         }
     }
 
 }
 
-static NODE_COUNTER: AtomicU32 = AtomicU32::new(0);
+impl Stmt {
+
+    pub fn write_instructions(&self, labels: &mut Labels, instructions: &mut Vec<Instr>) {
+        match self {
+            Stmt::Expr(expr) => expr.write_instructions(labels, instructions),
+            Stmt::Return(ret) => ret.write_instructions(labels, instructions),
+            Stmt::If(node) => node.write_instructions(labels, instructions),
+            Stmt::While(node) => node.write_instructions(labels, instructions),
+            Stmt::For(node) => node.write_instructions(labels, instructions),
+            Stmt::Block(block) => block.write_instructions(labels, instructions),
+            Stmt::TailLoop(node) => node.write_instructions(labels, instructions),
+            Stmt::TailCall(node) => node.write_instructions(labels, instructions),
+        }
+    }
+
+    // 実行がこの文の後ろへ抜けることがないか (return や無限ループ)
+    pub fn terminates(&self) -> bool {
+        match self {
+            Stmt::Expr(_) => false,
+            Stmt::Return(_) | Stmt::TailCall(_) => true,
+            Stmt::If(node) => match &node.else_block {
+                Some(els) => node.then_block.terminates() && els.terminates(),
+                None => false
+            },
+            // break がないので、条件が定数の真なら抜けることはない
+            Stmt::While(node) => matches!(node.condition.as_number(), Some(n) if n != 0),
+            Stmt::For(node) => match &node.condition {
+                Some(cond) => matches!(cond.as_number(), Some(n) if n != 0),
+                None => true
+            },
+            Stmt::Block(block) => block.statements.iter().any(|s| s.terminates()),
+            Stmt::TailLoop(node) => node.body.terminates(),
+        }
+    }
+
+}
+
+// コード生成中のラベルの割り当て。番号は関数ごとに 0 から振るので、同じ入力からは同じ名前になる
+#[derive(Default)]
+pub struct Labels {
+    next_id: u32,
+    // 開いているインライン展開のブロック。return はいちばん内側へ抜ける
+    pub inline: Vec<String>,
+    // 開いている末尾呼び出しのループ
+    pub tail: Vec<String>,
+}
+
+impl Labels {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn next_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
 
-pub fn node_id() -> u32 {
-    NODE_COUNTER.fetch_add(1, Ordering::SeqCst)
 }
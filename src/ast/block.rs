@@ -1,36 +1,9 @@
-use std::io::Write;
-use crate::ast::{AstNode, Function, Module, WasmWriter, WatWriter};
+use crate::ast::{Labels, Stmt};
+use crate::ir::Instr;
 
+#[derive(Clone, Debug, Default)]
 pub struct Block {
-    statements: Vec<Box<dyn AstNode>>
-}
-
-impl WatWriter for Block {
-    fn write_wat(&self, write: &mut dyn Write) -> std::io::Result<()> {
-        for statement in &self.statements {
-            statement.write_wat(write)?;
-            writeln!(write, "drop")?;
-        }
-        writeln!(write, "i32.const 0")?;
-        Ok(())
-    }
-}
-
-impl WasmWriter for Block {
-    fn write_wasm(&self, module: Option<&Module>, function: Option<&Function>, write: &mut dyn Write) -> std::io::Result<()> {
-        for statement in &self.statements {
-            statement.write_wasm(module, function, write)?;
-            write.write(&[0x1a])?; // drop
-        }
-        write.write(&[0x41, 0x00])?; // i32.const 0
-        Ok(())
-    }
-}
-
-impl AstNode for Block {
-    fn children(&self) -> Vec<&Box<dyn AstNode>> {
-        self.statements.iter().map(|b| b).collect()
-    }
+    pub statements: Vec<Stmt>
 }
 
 impl Block {
@@ -40,8 +13,15 @@ impl Block {
         }
     }
 
-    pub fn add_statement(&mut self, statement: Box<dyn AstNode>) {
+    pub fn add_statement(&mut self, statement: Stmt) {
         self.statements.push(statement);
     }
-}
 
+    pub fn write_instructions(&self, labels: &mut Labels, instructions: &mut Vec<Instr>) {
+        for statement in &self.statements {
+            statement.write_instructions(labels, instructions);
+            instructions.push(Instr::Drop);
+        }
+        instructions.push(Instr::I32Const(0));
+    }
+}
@@ -1,38 +1,23 @@
-use std::io::Write;
-use crate::ast::{AstNode, Function, Module, WasmWriter, WatWriter};
+use crate::ast::{Expr, Labels};
+use crate::ir::Instr;
 
+#[derive(Clone, Debug)]
 pub struct Call {
-    name: String,
-    arguments: Vec<Box<dyn AstNode>>
+    pub name: String,
+    pub arguments: Vec<Expr>
 }
 
-impl WatWriter for Call {
-    fn write_wat(&self, write: &mut dyn Write) -> std::io::Result<()> {
-        for arg in &self.arguments {
-            arg.write_wat(write)?;
+impl Call {
+    pub fn new(name: String, arguments: Vec<Expr>) -> Self {
+        Self {
+            name, arguments
         }
-        writeln!(write, "call ${}", self.name)?;
-        Ok(())
     }
-}
 
-impl WasmWriter for Call {
-    fn write_wasm(&self, module: Option<&Module>, function: Option<&Function>, write: &mut dyn Write) -> std::io::Result<()> {
+    pub fn write_instructions(&self, labels: &mut Labels, instructions: &mut Vec<Instr>) {
         for arg in &self.arguments {
-            arg.write_wasm(module, function, write)?;
-        }
-        let func_idx = module.unwrap().get_function_index(self.name.as_str());
-        write.write(&[0x10, func_idx as u8])?; // call
-        Ok(())
-    }
-}
-
-impl AstNode for Call {}
-
-impl Call {
-    pub fn new(name: String, arguments: Vec<Box<dyn AstNode>>) -> Self {
-        Self {
-            name, arguments
+            arg.write_instructions(labels, instructions);
         }
+        instructions.push(Instr::Call(self.name.to_string()));
     }
 }
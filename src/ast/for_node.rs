@@ -1,92 +1,47 @@
-use std::io::Write;
-use crate::ast::{AstNode, Function, Module, node_id, WasmWriter, WatWriter};
+use crate::ast::{Expr, Labels, Stmt};
+use crate::ir::{Instr, NumOp};
 
+#[derive(Clone, Debug)]
 pub struct ForNode {
-    id: u32,
-    initialize: Option<Box<dyn AstNode>>,
-    condition: Option<Box<dyn AstNode>>,
-    increment: Option<Box<dyn AstNode>>,
-    body: Box<dyn AstNode>,
+    pub initialize: Option<Expr>,
+    pub condition: Option<Expr>,
+    pub increment: Option<Expr>,
+    pub body: Box<Stmt>,
 }
 
-impl WatWriter for ForNode {
-    fn write_wat(&self, write: &mut dyn Write) -> std::io::Result<()> {
-        if let Some(init) = &self.initialize {
-            init.write_wat(write)?;
-            writeln!(write, "drop")?;
-        }
-        writeln!(write, "(block $block{}", self.id)?;
-        writeln!(write, "(loop $loop{}", self.id)?;
-        if let Some(cond) = &self.condition {
-            cond.write_wat(write)?;
-            writeln!(write, "i32.const 0")?;
-            writeln!(write, "i32.eq")?;
-            writeln!(write, "br_if $block{}", self.id)?;
-        }
-        self.body.write_wat(write)?;
-        if let Some(inc) = &self.increment {
-            inc.write_wat(write)?;
-            writeln!(write, "drop")?;
+impl ForNode {
+    pub fn new(initialize: Option<Expr>,
+               condition: Option<Expr>,
+               increment: Option<Expr>,
+               body: Stmt
+    ) -> Self {
+        Self {
+            initialize, condition, increment, body: Box::new(body)
         }
-        writeln!(write, "br $loop{}", self.id)?;
-        writeln!(write, ")")?;
-        writeln!(write, ")")?;
-        writeln!(write, "i32.const 0")?;
-        Ok(())
     }
-}
 
-impl WasmWriter for ForNode {
-    fn write_wasm(&self, module: Option<&Module>, function: Option<&Function>, write: &mut dyn Write) -> std::io::Result<()> {
+    pub fn write_instructions(&self, labels: &mut Labels, instructions: &mut Vec<Instr>) {
+        let id = labels.next_id();
         if let Some(init) = &self.initialize {
-            init.write_wasm(module, function, write)?;
-            write.write(&[0x1a])?; // drop
+            init.write_instructions(labels, instructions);
+            instructions.push(Instr::Drop);
         }
-        write.write(&[0x02, 0x40])?; // block
-        write.write(&[0x03, 0x40])?; // loop
+        let mut body = vec![];
         if let Some(cond) = &self.condition {
-            cond.write_wasm(module, function, write)?;
-            write.write(&[0x41, 0x00])?; // i32.const 0
-            write.write(&[0x46])?; // i32.eq
-            write.write(&[0x0d, 0x01])?; // br_if (block)
+            cond.write_instructions(labels, &mut body);
+            body.push(Instr::I32Const(0));
+            body.push(Instr::Numeric(NumOp::I32Eq));
+            body.push(Instr::BrIf(format!("block{}", id)));
         }
-        self.body.write_wasm(module, function, write)?;
+        self.body.write_instructions(labels, &mut body);
+        body.push(Instr::Drop);
         if let Some(inc) = &self.increment {
-            inc.write_wasm(module, function, write)?;
-            write.write(&[0x1a])?; // drop
-        }
-        write.write(&[0x0c, 0x00])?; // br (loop)
-        write.write(&[0x0b])?; // end
-        write.write(&[0x0b])?; // end
-        write.write(&[0x41, 0x00])?; // i32.const 0
-        Ok(())
-    }
-}
-
-impl AstNode for ForNode {
-    fn children(&self) -> Vec<&Box<dyn AstNode>> {
-        let mut children = vec![&self.body];
-        if let Some(init) = &self.initialize {
-            children.push(init);
-        }
-        if let Some(inc) = &self.increment {
-            children.push(inc);
-        }
-        if let Some(cond) = &self.condition {
-            children.push(cond);
-        }
-        children
-    }
-}
-
-impl ForNode {
-    pub fn new(initialize: Option<Box<dyn AstNode>>,
-               condition: Option<Box<dyn AstNode>>,
-               increment: Option<Box<dyn AstNode>>,
-               body: Box<dyn AstNode>
-    ) -> Self {
-        Self {
-            id: node_id(), initialize, condition, increment, body
+            inc.write_instructions(labels, &mut body);
+            body.push(Instr::Drop);
         }
+        body.push(Instr::Br(format!("loop{}", id)));
+        let body = vec![Instr::Loop { label: format!("loop{}", id), result: None, body }];
+        instructions.push(Instr::Block { label: format!("block{}", id), result: None, body });
+        instructions.push(Instr::I32Const(0));
     }
 }
@@ -1,85 +1,128 @@
-use std::collections::{HashMap};
-use std::io::{Write, Result};
-use crate::ast::{AstNode, Module, Param, WasmWriter, WatWriter};
-use crate::ast::leb128::usize_to_leb128;
-use crate::ast::WasmType::I32;
+use crate::ast::{Expr, Labels, Param, Stmt, Visitor, walk_expr};
+use crate::ir;
 
 pub struct Function {
     pub name: String,
     pub params: Vec<Param>,
-    pub body: Box<dyn AstNode>,
+    pub body: Stmt,
     locals: Vec<String>,
-    pub local_index: HashMap<String, usize>,
+    // 命令列に覗き穴最適化をかける
+    pub peephole: bool,
+    // 末尾呼び出しを return_call で書き出す
+    pub return_call: bool,
 }
 
 impl Function {
 
-    pub fn new(name: String, params: Vec<Param>, body: Box<dyn AstNode>) -> Self {
-        let mut function = Self { name, params,  body, locals: vec![], local_index: HashMap::new() };
+    pub fn new(name: String, params: Vec<Param>, body: Stmt) -> Self {
+        let mut function = Self { name, params,  body, locals: vec![], peephole: false, return_call: false };
+        function.update_locals();
+        function
+    }
+
+    // 本体を書き換えた後に、ローカル変数の一覧を作り直す
+    pub fn update_locals(&mut self) {
         let mut locals = vec![];
 
-        for param in function.params.iter() {
+        for param in self.params.iter() {
             let param_name = &param.name;
             locals.push(param_name.to_string());
         }
 
-        function.collect_locals(&mut locals);
-        function.locals = locals;
-        for i in 0..function.locals.len() {
-            function.local_index.insert(function.locals[i].to_string(), i);
-        }
-        function
+        LocalCollector { locals: &mut locals }.visit_stmt(&self.body);
+        self.locals = locals;
     }
 
-    pub fn write_wasm_type(&self, write: &mut dyn Write) -> Result<()>{
-        //self.collect_locals(&mut self);
-        write.write(&[0x60])?; // func
-        write.write(&[self.params.len() as u8])?; // num params
-        for param in self.params.iter() {
-            write.write(&[param.wtype.code()])?; // param type
+    pub fn calls(&self) -> Vec<String> {
+        let mut calls: Vec<String> = vec![];
+        for (name, _) in self.call_arities() {
+            if !calls.contains(&name) {
+                calls.push(name);
+            }
         }
-        // result タイプは i32 固定
-        write.write(&[0x01])?; // num results
-        write.write(&[I32.code()])?; // result type
-        Ok(())
+        calls
     }
 
-}
+    // 呼び出している関数の名前と引数の数。違う数で呼んでいればそれぞれ入る
+    pub fn call_arities(&self) -> Vec<(String, usize)> {
+        let mut collector = CallCollector { calls: vec![] };
+        collector.visit_stmt(&self.body);
+        collector.calls
+    }
 
-impl WatWriter for Function {
-    fn write_wat(&self, write: &mut dyn Write) -> Result<()>{
-        writeln!(write, "(func ${}", &self.name)?;
-        for param in self.params.iter() {
-            writeln!(write, "    (param ${} i32)", param.name)?;
+    pub fn locals(&self) -> &[String] {
+        &self.locals
+    }
+
+    // 中間表現に変換する。覗き穴最適化もここで済ませる
+    pub fn lower(&self) -> ir::Function {
+        let mut body = vec![];
+        self.body.write_instructions(&mut Labels::new(), &mut body);
+        if self.peephole {
+            body = ir::optimize(body);
         }
-        writeln!(write, "(result i32)")?;
-        for i in self.params.len() .. self.locals.len() {
-            writeln!(write, "    (local ${} i32)", self.locals[i])?;
+        if self.return_call {
+            body = ir::use_return_call(body);
+        }
+        ir::Function {
+            name: self.name.to_string(),
+            params: self.params.iter().map(|param| ir::Local::new(&param.name, param.wtype.lower())).collect(),
+            // result タイプは i32 固定
+            results: vec![ir::ValType::I32],
+            locals: self.locals[self.params.len()..].iter().map(|local| ir::Local::new(local, ir::ValType::I32)).collect(),
+            body,
         }
-        self.body.write_wat(write)?;
-        writeln!(write, ")")?;
-
-        Ok(())
     }
+
+}
+
+// 代入される変数を出てきた順に集める
+struct LocalCollector<'a> {
+    locals: &'a mut Vec<String>,
 }
 
-impl WasmWriter for Function {
-    fn write_wasm(&self, module: Option<&Module>, _function: Option<&Function>, write: &mut dyn Write) -> Result<()> {
-        let mut buf : Vec<u8> = Vec::new();
-        buf.write(&[(self.locals.len() - self.params.len()) as u8])?; // local decl count
-        for _ in self.params.len() .. self.locals.len() {
-            buf.write(&[0x01, 0x7f])?; // i32
+impl Visitor for LocalCollector<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Assign(assign) = expr {
+            if !self.locals.contains(&assign.lhs.name) {
+                self.locals.push(assign.lhs.name.to_string());
+            }
         }
-        self.body.write_wasm(module, Some(self), &mut buf)?; // function body
-        buf.write(&[0x0b])?; //end
-        write.write(&usize_to_leb128(buf.len()))?; // function body size
-        write.write(&buf)?;
-        Ok(())
+        walk_expr(self, expr);
     }
 }
 
-impl AstNode for Function {
-    fn children(&self) -> Vec<&Box<dyn AstNode>> {
-        vec![&self.body]
+// 呼び出している関数の名前を集める
+struct CallCollector {
+    calls: Vec<(String, usize)>,
+}
+
+impl Visitor for CallCollector {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if let Expr::Call(call) = expr {
+            let key = (call.name.to_string(), call.arguments.len());
+            if !self.calls.contains(&key) {
+                self.calls.push(key);
+            }
+        }
+        walk_expr(self, expr);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+fn locals_of(exp: &str) -> Vec<String> {
+    crate::wasmc::parse(exp).functions().next().unwrap().locals().to_vec()
+}
+
+#[test]
+fn test_locals() {
+    assert_eq!(locals_of("main(x){a=b=1;return a;}"), vec!["x", "a", "b"]);
+    assert_eq!(locals_of("main(){return f(c=2)+(d=3);}"), vec!["c", "d"]);
+    assert_eq!(locals_of("main(){if(e=1)return e;for(i=0;i<3;i=i+1)j=i;return 0;}"), vec!["e", "i", "j"]);
+}
+
+#[test]
+fn test_calls() {
+    let module = crate::wasmc::parse("main(){return f(g(1))+f(h());}");
+    assert_eq!(module.functions().next().unwrap().calls(), vec!["f", "g", "h"]);
+}
@@ -1,66 +1,35 @@
-use std::io::Write;
-use crate::ast::{AstNode, Function, Module, WasmWriter, WatWriter};
+use crate::ast::{Expr, Labels, Stmt};
+use crate::ir::Instr;
 
+#[derive(Clone, Debug)]
 pub struct IfNode {
-    condition: Box<dyn AstNode>,
-    then_block: Box<dyn AstNode>,
-    else_block: Option<Box<dyn AstNode>>,
+    pub condition: Expr,
+    pub then_block: Box<Stmt>,
+    pub else_block: Option<Box<Stmt>>,
 }
 
-impl WatWriter for IfNode {
-    fn write_wat(&self, write: &mut dyn Write) -> std::io::Result<()> {
-        self.condition.write_wat(write)?;
-        writeln!(write, "(if")?;
-        writeln!(write, "(then")?;
-        self.then_block.write_wat(write)?;
-        writeln!(write, "drop")?;
-        writeln!(write, ")")?;
-        if let Some(els) = &self.else_block {
-            writeln!(write, "(else")?;
-            els.write_wat(write)?;
-            writeln!(write, "drop")?;
-            writeln!(write, ")")?;
+impl IfNode {
+    pub fn new(condition: Expr,
+               then_block: Stmt,
+               else_block: Option<Stmt>) -> Self {
+        Self {
+            condition,
+            then_block: Box::new(then_block),
+            else_block: else_block.map(Box::new),
         }
-        writeln!(write, ")")?;
-        writeln!(write, "i32.const 0")?;
-        Ok(())
     }
-}
 
-impl WasmWriter for IfNode {
-    fn write_wasm(&self, module: Option<&Module>, function: Option<&Function>, write: &mut dyn Write) -> std::io::Result<()> {
-        self.condition.write_wasm(module, function, write)?;
-        write.write(&[0x04])?; // if
-        write.write(&[0x40])?; // block type
-        self.then_block.write_wasm(module, function, write)?;
-        write.write(&[0x1a])?; // drop
+    pub fn write_instructions(&self, labels: &mut Labels, instructions: &mut Vec<Instr>) {
+        self.condition.write_instructions(labels, instructions);
+        let mut then = vec![];
+        self.then_block.write_instructions(labels, &mut then);
+        then.push(Instr::Drop);
+        let mut otherwise = vec![];
         if let Some(els) = &self.else_block {
-            write.write(&[0x05])?; // else
-            els.write_wasm(module, function, write)?;
-            write.write(&[0x1a])?; // drop
-        }
-        write.write(&[0x0b])?; // end
-        write.write(&[0x41, 0x00])?; // i32.const 0
-        Ok(())
-    }
-}
-
-impl AstNode for IfNode {
-    fn children(&self) -> Vec<&Box<dyn AstNode>> {
-        let mut children = vec![&self.condition, &self.then_block];
-        if let Some(els) = &self.else_block {
-            children.push(els);
-        }
-        children
-    }
-}
-
-impl IfNode {
-    pub fn new(condition: Box<dyn AstNode>,
-               then_block: Box<dyn AstNode>,
-               else_block: Option<Box<dyn AstNode>>) -> Self {
-        Self {
-            condition, then_block, else_block
+            els.write_instructions(labels, &mut otherwise);
+            otherwise.push(Instr::Drop);
         }
+        instructions.push(Instr::If { label: None, result: None, then, otherwise });
+        instructions.push(Instr::I32Const(0));
     }
 }
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use crate::ast::{Expr, Labels, Stmt, VisitorMut, walk_expr_mut};
+use crate::ir::{Instr, ValType};
+
+// 複製した木の変数名を付け替える
+#[derive(Clone, Default)]
+pub struct InlineContext {
+    pub renames: HashMap<String, String>,
+}
+
+impl InlineContext {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn rename(&self, name: &str) -> String {
+        self.renames.get(name).cloned().unwrap_or_else(|| name.to_string())
+    }
+
+}
+
+impl VisitorMut for InlineContext {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Variable(variable) => variable.name = self.rename(&variable.name),
+            Expr::Assign(assign) => assign.lhs.name = self.rename(&assign.lhs.name),
+            _ => {}
+        }
+        walk_expr_mut(self, expr);
+    }
+}
+
+// インライン展開された関数呼び出し。
+// 引数を一度だけ評価して呼び出し先のローカル変数へ代入し、本体を値を返すブロックとして埋め込む。
+// 本体の return はこのブロックを抜ける分岐になる
+#[derive(Clone, Debug)]
+pub struct InlineNode {
+    pub bindings: Vec<Expr>,
+    pub body: Box<Stmt>,
+}
+
+impl InlineNode {
+    pub fn new(bindings: Vec<Expr>, body: Stmt) -> Self {
+        Self {
+            bindings, body: Box::new(body)
+        }
+    }
+
+    pub fn write_instructions(&self, labels: &mut Labels, instructions: &mut Vec<Instr>) {
+        for binding in &self.bindings {
+            binding.write_instructions(labels, instructions);
+            instructions.push(Instr::Drop);
+        }
+        let label = format!("inline{}", labels.next_id());
+        labels.inline.push(label.to_string());
+        let mut body = vec![];
+        self.body.write_instructions(labels, &mut body);
+        labels.inline.pop();
+        instructions.push(Instr::Block { label, result: Some(ValType::I32), body });
+    }
+}
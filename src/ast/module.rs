@@ -1,16 +1,27 @@
 use std::collections::HashMap;
-use std::io::{Write, Result};
 #[cfg(test)]
-use crate::ast::{Block, Param};
-use crate::ast::{WasmWriter, WatWriter};
+use crate::ast::{Block, Param, Stmt};
 use crate::ast::Function;
-use crate::ast::leb128::usize_to_leb128;
+use crate::ir;
 #[cfg(test)]
 use crate::ast::WasmType::I32;
 
+// 文字列リテラルを置き始めるアドレス。それより前は実行時ライブラリが使う
+pub const STRING_BASE: i32 = 1024;
+// 宣言しなくても呼べる組み込み関数。同じ名前の関数を定義すればそちらを呼ぶ
+pub const BUILTINS: [&str; 2] = ["print_int", "print_str"];
+const PAGE_SIZE: usize = 65536;
+
+#[derive(Default)]
 pub struct Module {
-    functions: Vec<Box<Function>>,
+    functions: Vec<Function>,
     function_index: HashMap<String, usize>,
+    // 文字列リテラルを 0 で終えて並べたもの。STRING_BASE に置く
+    strings: Vec<u8>,
+    // 分割コンパイルするもの。ほかのオブジェクトから呼ばれうるので main がなくてもよく、関数をすべて残す
+    relocatable: bool,
+    // import name(a, b); で宣言した、ホストが env から渡す関数の名前と引数の数
+    hosts: Vec<(String, usize)>,
 }
 
 impl Module {
@@ -18,133 +29,195 @@ impl Module {
     pub fn new() -> Self {
         Self {
             functions: Vec::new(),
-            function_index: HashMap::new()
+            function_index: HashMap::new(),
+            strings: Vec::new(),
+            relocatable: false,
+            hosts: Vec::new(),
         }
     }
 
-    pub fn add_function(&mut self, function: Function) {
-        let _ = &self.function_index.insert(function.name.to_string(), self.functions.len());
-        let _ = &self.functions.push(Box::new(function));
+    // 同じ関数を違う引数の数で宣言すれば誤りにする
+    pub fn declare_host(&mut self, name: &str, arity: usize) {
+        match self.host_arity(name) {
+            Some(declared) if declared != arity => panic!("関数 {} が違う引数の数で import されています", name),
+            Some(_) => {},
+            None => self.hosts.push((name.to_string(), arity)),
+        }
     }
 
-    pub fn get_function_index(&self, name: &str) -> usize {
-        *self.function_index.get(name).unwrap()
+    pub fn hosts(&self) -> &[(String, usize)] {
+        &self.hosts
     }
 
-    pub fn write_wasm_type_section(&self, write: &mut dyn Write) -> Result<()>{
-        write.write(&[0x01])?; // section code
+    pub fn host_arity(&self, name: &str) -> Option<usize> {
+        self.hosts.iter().find(|(host, _)| host == name).map(|(_, arity)| *arity)
+    }
 
-        let mut buf : Vec<u8> = Vec::new();
-        buf.write(&[self.functions.len() as u8])?; // num types
-        for function in self.functions.iter() {
-            function.write_wasm_type(&mut buf)?;
-        }
-        write.write(&usize_to_leb128(buf.len()))?; // section size
-        write.write(&buf)?;
-        Ok(())
+    pub fn set_relocatable(&mut self) {
+        self.relocatable = true;
     }
 
-    pub fn write_wasm_function_section(&self, write: &mut dyn Write) -> Result<()>{
-        write.write(&[0x03])?; // section code
+    pub fn is_relocatable(&self) -> bool {
+        self.relocatable
+    }
 
-        let mut buf : Vec<u8> = Vec::new();
-        buf.write(&[self.functions.len() as u8])?; // num functions
-        for i in 0.. self.functions.len() {
-            buf.write(&[i as u8])?; // function signature index
+    // 文字列リテラルを置いて、そのアドレスを返す。同じ内容なら同じアドレスになる
+    pub fn add_string(&mut self, bytes: &[u8]) -> i32 {
+        let mut start = 0;
+        for string in self.strings.split_inclusive(|byte| *byte == 0) {
+            if &string[..string.len() - 1] == bytes {
+                return STRING_BASE + start as i32;
+            }
+            start += string.len();
         }
-        write.write(&usize_to_leb128(buf.len()))?; // section size
-        write.write(&buf)?;
-        Ok(())
+        self.strings.extend(bytes);
+        self.strings.push(0);
+        STRING_BASE + start as i32
     }
 
-    pub fn write_wasm_export_section(&self, write: &mut dyn Write) -> Result<()> {
-        write.write(&[0x07])?; // section code
-
-        let mut buf : Vec<u8> = Vec::new();
-        buf.write(&[0x01])?; // num exports (1固定)
-        let main_name = "main";
-        buf.write(&[main_name.len() as u8])?; // string length
-        buf.write(main_name.as_bytes())?; // export name
-        buf.write(&[0x00])?; // export kind
-
-        let main_func = self.functions.iter().enumerate().find(|(_, function)| {
-            function.name == main_name
-        });
-
-        match main_func {
-            Some((i, _)) => {
-                buf.write(&[i as u8])?; // export func index
-            },
-            None => {
-                panic!("function `main` not found");
-            }
+    pub fn add_function(&mut self, function: Function) {
+        if self.function_index.contains_key(&function.name) {
+            panic!("関数 {} が重複して定義されています", function.name);
         }
-        write.write(&usize_to_leb128(buf.len()))?; // section size
-        write.write(&buf)?;
-        Ok(())
+        let _ = &self.function_index.insert(function.name.to_string(), self.functions.len());
+        let _ = &self.functions.push(function);
     }
 
-    pub fn write_wasm_code_section(&self, write: &mut dyn Write) -> Result<()> {
-        write.write(&[0x0a])?; // section code
-        let mut buf : Vec<u8> = Vec::new();
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.function_index.get(name).map(|index| &self.functions[*index])
+    }
 
-        buf.write(&[self.functions.len() as u8])?; // num functions
-        for function in self.functions.iter() {
-            function.write_wasm(Some(self), None, &mut buf)?;
-        }
-        write.write(&usize_to_leb128(buf.len()))?; // section size
-        write.write(&buf)?;
-        Ok(())
+    pub fn functions(&self) -> std::slice::Iter<'_, Function> {
+        self.functions.iter()
     }
 
-}
+    pub fn functions_mut(&mut self) -> std::slice::IterMut<'_, Function> {
+        self.functions.iter_mut()
+    }
 
-impl WatWriter for Module {
-    fn write_wat(&self, write: &mut dyn Write) -> Result<()>{
-        writeln!(write, "(module")?;
-        for func in self.functions.iter() {
-            let _ = func.write_wat(write)?;
+    pub fn retain_functions<F: FnMut(&Function) -> bool>(&mut self, f: F) {
+        self.functions.retain(f);
+        self.function_index = self.functions.iter().enumerate()
+            .map(|(i, function)| (function.name.to_string(), i))
+            .collect();
+    }
+
+    // 中間表現に変換する。書き出しはすべてこの結果から行う
+    pub fn lower(&self) -> ir::Module {
+        let has_main = self.function_index.contains_key("main");
+        if !has_main && !self.relocatable {
+            panic!("function `main` not found");
+        }
+        let mut module = ir::Module {
+            imports: self.imports(),
+            functions: self.functions.iter().map(|function| function.lower()).collect(),
+            exports: if has_main { vec![ir::Export::function("main", "main")] } else { vec![] },
+            ..Default::default()
+        };
+        if self.relocatable {
+            module.unresolved = module.imports.iter()
+                .filter(|import| !BUILTINS.contains(&import.name.as_str()) && self.host_arity(&import.name).is_none())
+                .map(|import| import.name.to_string())
+                .collect();
+        }
+        if !self.strings.is_empty() {
+            let end = STRING_BASE as usize + self.strings.len();
+            module.memory = Some(ir::Memory { min: end.div_ceil(PAGE_SIZE) as u32, max: None });
+            module.data.push(ir::Data { offset: STRING_BASE, bytes: self.strings.clone() });
+            module.exports.push(ir::Export { name: "memory".to_string(), kind: ir::ExportKind::Memory });
         }
-        writeln!(write, "(export \"main\" (func $main))")?;
-        writeln!(write, ")")?;
-        Ok(())
+        module
     }
-}
 
-impl WasmWriter for Module {
-    fn write_wasm(&self, _: Option<&Module>, _: Option<&Function>, write: &mut dyn Write) -> Result<()> {
-        write.write(&[0x00, 0x61, 0x73, 0x6d])?; // WASM_BINARY_MAGIC
-        write.write(&[0x01, 0x00, 0x00, 0x00])?; // WASM_BINARY_VERSION
-        self.write_wasm_type_section(write)?;
-        self.write_wasm_function_section(write)?;
-        self.write_wasm_export_section(write)?;
-        self.write_wasm_code_section(write)?;
-        Ok(())
+    // 定義されていない関数の呼び出しは、ホストが env から渡すものとして import する
+    fn imports(&self) -> Vec<ir::Import> {
+        let mut imports: Vec<ir::Import> = vec![];
+        for function in self.functions.iter() {
+            for (name, arity) in function.call_arities() {
+                if self.function_index.contains_key(&name) {
+                    continue;
+                }
+                match imports.iter().find(|import| import.name == name) {
+                    Some(import) if import.params.len() != arity =>
+                        panic!("関数 {} の呼び出しで引数の数が違います", name),
+                    Some(_) => {},
+                    None => imports.push(ir::Import {
+                        module: "env".to_string(),
+                        field: name.to_string(),
+                        name,
+                        params: vec![ir::ValType::I32; arity],
+                        results: vec![ir::ValType::I32],
+                    }),
+                }
+            }
+        }
+        imports
     }
+
 }
 
 #[test]
 fn test_wat() {
     let function = Function::new("main".to_string(),
                                  vec![Param{wtype: I32, name: "abc".to_string()}],
-                                 Box::new(Block::new())
+                                 Stmt::Block(Block::new())
     );
     let mut module = Module::new();
     module.add_function(function);
     let mut write = std::io::stdout();
-    let _ = module.write_wat(&mut write);
+    let _ = module.lower().write_wat(&mut write);
 }
 
 #[test]
 fn test_wasm() {
     let function = Function::new("main".to_string(),
                                  vec![Param{wtype: I32, name: "abc".to_string()}],
-                                  Box::new(Block::new())
+                                  Stmt::Block(Block::new())
     );
     let mut module = Module::new();
     module.add_function(function);
     let mut buf = vec![];
-    let _ = module.write_wasm(None, None, &mut buf);
+    let _ = module.lower().write_wasm(&mut buf);
     println!("{:x?}", buf);
 
 }
+
+#[cfg(test)]
+fn compile_to_bytes(exp: &str) -> (String, Vec<u8>) {
+    let mut module = crate::wasmc::parse(exp);
+    crate::optimizer::PassManager::new(crate::optimizer::OptLevel::O3).run(&mut module);
+    let module = module.lower();
+    let mut wat = vec![];
+    module.write_wat(&mut wat).unwrap();
+    let mut wasm = vec![];
+    module.write_wasm(&mut wasm).unwrap();
+    (String::from_utf8(wat).unwrap(), wasm)
+}
+
+#[test]
+fn test_strings() {
+    let module = crate::wasmc::parse(r#"main(){a="ab";b="";c="ab";return a+b+c;}"#).lower();
+    assert_eq!(module.data, vec![ir::Data { offset: STRING_BASE, bytes: b"ab\0\0".to_vec() }]);
+    assert_eq!(module.memory, Some(ir::Memory { min: 1, max: None }));
+    let mut wat = vec![];
+    module.write_wat(&mut wat).unwrap();
+    let wat = String::from_utf8(wat).unwrap();
+    assert!(wat.contains("i32.const 1024\n"));
+    assert!(wat.contains("i32.const 1027\n"));
+    assert!(wat.contains("(export \"memory\" (memory 0))"));
+}
+
+#[test]
+fn test_reproducible() {
+    let exp = "main(){s=0;for(i=0;i<10;i=i+1){s=s+sq(i);}while(s>100)s=s-7;return count(s,0);}\
+               sq(x){r=0;j=0;while(j<x){r=r+x;j=j+1;}return r;}\
+               count(n,acc){if(n==0)return acc;return count(n-1,acc+1);}";
+    let first = compile_to_bytes(exp);
+    // 間に別のプログラムをコンパイルしても番号はずれない
+    compile_to_bytes("main(){while(1)return 1;}f(){for(;;)return 2;}");
+    let second = compile_to_bytes(exp);
+    assert_eq!(first, second);
+    // ラベルは関数ごとに 0 から振られる
+    assert!(first.0.contains("block $block0\n"));
+    assert!(first.0.contains("block $inline1 (result i32)\n"));
+}
@@ -1,30 +1,27 @@
-use std::io::Write;
-use crate::ast::{AstNode, Function, Module, WasmWriter, WatWriter};
-use crate::ast::leb128::i32_to_leb128;
+use crate::ast::Labels;
+use crate::ir::Instr;
 
+#[derive(Clone, Debug)]
 pub struct Number {
-    value: i32
+    pub value: i32,
+    // 文字列リテラルのアドレス。オブジェクトに書き出すときは再配置するので、定数として畳み込まない
+    pub address: bool,
 }
 
-impl WatWriter for Number {
-    fn write_wat(&self, write: &mut dyn Write) -> std::io::Result<()> {
-        writeln!(write, "i32.const {}", self.value)?;
-        Ok(())
+impl Number {
+    pub fn new(value: i32) -> Self {
+        Self { value, address: false }
     }
-}
 
-impl WasmWriter for Number {
-    fn write_wasm(&self, _module: Option<&Module>, _function: Option<&Function>, write: &mut dyn Write) -> std::io::Result<()> {
-        write.write(&[0x41])?;             // i32.const
-        write.write(&i32_to_leb128(self.value))?; // i32 literal
-        Ok(())
+    pub fn address(value: i32) -> Self {
+        Self { value, address: true }
     }
-}
 
-impl AstNode for Number {}
-
-impl Number {
-    pub fn new(value: i32) -> Self {
-        Self { value }
+    pub fn write_instructions(&self, _labels: &mut Labels, instructions: &mut Vec<Instr>) {
+        if self.address {
+            instructions.push(Instr::Address(self.value));
+        } else {
+            instructions.push(Instr::I32Const(self.value));
+        }
     }
 }
@@ -1,6 +1,7 @@
-use std::io::Write;
-use crate::ast::{AstNode, Function, Module, WasmWriter, WatWriter};
+use crate::ast::{Expr, Labels, Number, Variable};
+use crate::ir::{Instr, NumOp};
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum BiOpKind {
     Add,
     Sub,
@@ -12,64 +13,126 @@ pub enum BiOpKind {
     GreaterThanOrEqual,
     LessThan,
     LessThanOrEqual,
+    ShiftLeft,
+    ShiftRight,
+    ShiftRightUnsigned,
 }
 
+#[derive(Clone, Debug)]
 pub struct BiOperator {
-    kind: BiOpKind,
-    lhs: Box<dyn AstNode>,
-    rhs: Box<dyn AstNode>,
+    pub kind: BiOpKind,
+    pub lhs: Box<Expr>,
+    pub rhs: Box<Expr>,
 }
 
-impl WatWriter for BiOperator {
-    fn write_wat(&self, write: &mut dyn Write) -> std::io::Result<()> {
-        self.lhs.write_wat(write)?;
-        self.rhs.write_wat(write)?;
-        let operator = match &self.kind {
-            BiOpKind::Add => "add",
-            BiOpKind::Sub => "sub",
-            BiOpKind::Mult => "mul",
-            BiOpKind::Div => "div_s",
-            BiOpKind::Equal => "eq",
-            BiOpKind::NotEqual => "ne",
-            BiOpKind::GreaterThan => "gt_s",
-            BiOpKind::GreaterThanOrEqual => "ge_s",
-            BiOpKind::LessThan => "lt_s",
-            BiOpKind::LessThanOrEqual => "le_s"
-        };
-        writeln!(write, "i32.{}", operator)?;
-        Ok(())
+impl BiOperator {
+    pub fn new(kind: BiOpKind, lhs: Expr, rhs: Expr) -> Self {
+        Self {
+            kind,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+        }
     }
-}
 
-impl WasmWriter for BiOperator {
-    fn write_wasm(&self, module: Option<&Module>, function: Option<&Function>, write: &mut dyn Write) -> std::io::Result<()> {
-        self.lhs.write_wasm(module, function, write)?;
-        self.rhs.write_wasm(module, function, write)?;
-        let operator:u8 = match &self.kind {
-            BiOpKind::Add => 0x6a,
-            BiOpKind::Sub => 0x6b,
-            BiOpKind::Mult => 0x6c,
-            BiOpKind::Div => 0x6d,
-            BiOpKind::Equal => 0x46,
-            BiOpKind::NotEqual => 0x47,
-            BiOpKind::GreaterThan => 0x4a,
-            BiOpKind::GreaterThanOrEqual => 0x4e,
-            BiOpKind::LessThan => 0x48,
-            BiOpKind::LessThanOrEqual => 0x4c,
-        };
-        write.write(&[operator])?;
-        Ok(())
+    pub fn write_instructions(&self, labels: &mut Labels, instructions: &mut Vec<Instr>) {
+        self.lhs.write_instructions(labels, instructions);
+        self.rhs.write_instructions(labels, instructions);
+        instructions.push(Instr::Numeric(self.kind.op()));
+    }
+
+    // 定数畳み込みと代数的な簡約をした式を返す。子は簡約済みとする
+    pub fn simplify(self) -> Expr {
+        let kind = self.kind;
+        let lhs = *self.lhs;
+        let rhs = *self.rhs;
+        let left = lhs.as_number();
+        let right = rhs.as_number();
+
+        if let (Some(l), Some(r)) = (left, right) {
+            if let Some(value) = kind.evaluate(l, r) {
+                return Expr::Number(Number::new(value));
+            }
+        }
+
+        match (kind, left, right) {
+            (BiOpKind::Add | BiOpKind::Sub, _, Some(0)) => lhs,
+            (BiOpKind::Mult | BiOpKind::Div, _, Some(1)) => lhs,
+            (BiOpKind::Add, Some(0), _) => rhs,
+            (BiOpKind::Mult, Some(1), _) => rhs,
+            (BiOpKind::Mult, _, Some(0)) if lhs.is_pure() => rhs,
+            (BiOpKind::Mult, Some(0), _) if rhs.is_pure() => lhs,
+            (BiOpKind::Mult, _, Some(r)) if is_power_of_two(r) => {
+                Expr::BiOperator(BiOperator::new(BiOpKind::ShiftLeft, lhs, shift_amount(r)))
+            },
+            (BiOpKind::Mult, Some(l), _) if is_power_of_two(l) => {
+                Expr::BiOperator(BiOperator::new(BiOpKind::ShiftLeft, rhs, shift_amount(l)))
+            },
+            (BiOpKind::Div, _, Some(r)) if is_power_of_two(r) => match &lhs {
+                Expr::Variable(variable) => signed_div_by_power_of_two(&variable.name, r),
+                _ => Expr::BiOperator(BiOperator::new(kind, lhs, rhs)),
+            },
+            _ => Expr::BiOperator(BiOperator::new(kind, lhs, rhs)),
+        }
     }
 }
 
-impl AstNode for BiOperator {}
+impl BiOpKind {
 
-impl BiOperator {
-    pub fn new(kind: BiOpKind, lhs: Box<dyn AstNode>, rhs: Box<dyn AstNode>) -> Self {
-        Self {
-            kind,
-            lhs,
-            rhs
+    pub fn op(&self) -> NumOp {
+        match self {
+            BiOpKind::Add => NumOp::I32Add,
+            BiOpKind::Sub => NumOp::I32Sub,
+            BiOpKind::Mult => NumOp::I32Mul,
+            BiOpKind::Div => NumOp::I32DivS,
+            BiOpKind::Equal => NumOp::I32Eq,
+            BiOpKind::NotEqual => NumOp::I32Ne,
+            BiOpKind::GreaterThan => NumOp::I32GtS,
+            BiOpKind::GreaterThanOrEqual => NumOp::I32GeS,
+            BiOpKind::LessThan => NumOp::I32LtS,
+            BiOpKind::LessThanOrEqual => NumOp::I32LeS,
+            BiOpKind::ShiftLeft => NumOp::I32Shl,
+            BiOpKind::ShiftRight => NumOp::I32ShrS,
+            BiOpKind::ShiftRightUnsigned => NumOp::I32ShrU,
         }
     }
+
+    // wasm の i32 命令と同じ結果を返す。トラップする組み合わせは None
+    pub fn evaluate(&self, lhs: i32, rhs: i32) -> Option<i32> {
+        let value = match self {
+            BiOpKind::Add => lhs.wrapping_add(rhs),
+            BiOpKind::Sub => lhs.wrapping_sub(rhs),
+            BiOpKind::Mult => lhs.wrapping_mul(rhs),
+            BiOpKind::Div => lhs.checked_div(rhs)?,
+            BiOpKind::Equal => (lhs == rhs) as i32,
+            BiOpKind::NotEqual => (lhs != rhs) as i32,
+            BiOpKind::GreaterThan => (lhs > rhs) as i32,
+            BiOpKind::GreaterThanOrEqual => (lhs >= rhs) as i32,
+            BiOpKind::LessThan => (lhs < rhs) as i32,
+            BiOpKind::LessThanOrEqual => (lhs <= rhs) as i32,
+            BiOpKind::ShiftLeft => lhs.wrapping_shl(rhs as u32),
+            BiOpKind::ShiftRight => lhs.wrapping_shr(rhs as u32),
+            BiOpKind::ShiftRightUnsigned => (lhs as u32).wrapping_shr(rhs as u32) as i32,
+        };
+        Some(value)
+    }
+
+}
+
+fn is_power_of_two(value: i32) -> bool {
+    value > 1 && value & (value - 1) == 0
+}
+
+fn shift_amount(value: i32) -> Expr {
+    Expr::Number(Number::new(value.trailing_zeros() as i32))
+}
+
+// x / 2^k は 0 方向への丸めを保つため (x + ((x >> 31) >>> (32 - k))) >> k に変換する
+fn signed_div_by_power_of_two(name: &str, divisor: i32) -> Expr {
+    let k = divisor.trailing_zeros() as i32;
+    let variable = || Expr::Variable(Variable::new(name.to_string()));
+    let number = |value| Expr::Number(Number::new(value));
+    let sign = Expr::BiOperator(BiOperator::new(BiOpKind::ShiftRight, variable(), number(31)));
+    let bias = Expr::BiOperator(BiOperator::new(BiOpKind::ShiftRightUnsigned, sign, number(32 - k)));
+    let biased = Expr::BiOperator(BiOperator::new(BiOpKind::Add, variable(), bias));
+    Expr::BiOperator(BiOperator::new(BiOpKind::ShiftRight, biased, number(k)))
 }
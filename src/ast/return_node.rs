@@ -1,32 +1,24 @@
-use std::io::Write;
-use crate::ast::{AstNode, Function, Module, WasmWriter, WatWriter};
+use crate::ast::{Expr, Labels};
+use crate::ir::Instr;
 
+#[derive(Clone, Debug)]
 pub struct ReturnNode {
-    child: Box<dyn AstNode>
+    pub child: Expr,
 }
 
-impl WatWriter for ReturnNode {
-    fn write_wat(&self, write: &mut dyn Write) -> std::io::Result<()> {
-        self.child.write_wat(write)?;
-        writeln!(write, "return")?;
-        Ok(())
-    }
-}
-
-impl WasmWriter for ReturnNode {
-    fn write_wasm(&self, module: Option<&Module>, function: Option<&Function>, write: &mut dyn Write) -> std::io::Result<()> {
-        self.child.write_wasm(module, function, write)?;
-        write.write(&[0x0f])?; // return
-        Ok(())
-    }
-}
-
-impl AstNode for ReturnNode {}
-
 impl ReturnNode {
-    pub fn new(child: Box<dyn AstNode>) -> Self {
+    pub fn new(child: Expr) -> Self {
         Self {
-            child
+            child,
+        }
+    }
+
+    pub fn write_instructions(&self, labels: &mut Labels, instructions: &mut Vec<Instr>) {
+        self.child.write_instructions(labels, instructions);
+        // インライン展開された本体の中では、展開先のブロックを抜ける分岐になる
+        match labels.inline.last() {
+            Some(label) => instructions.push(Instr::Br(label.to_string())),
+            None => instructions.push(Instr::Return),
         }
     }
 }
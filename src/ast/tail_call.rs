@@ -0,0 +1,47 @@
+use crate::ast::{Expr, Labels, Stmt};
+use crate::ir::{Instr, ValType};
+
+// 自己末尾呼び出しの飛び先になるよう、関数本体全体を囲むループ
+#[derive(Clone, Debug)]
+pub struct TailLoopNode {
+    pub body: Box<Stmt>,
+}
+
+impl TailLoopNode {
+    pub fn new(body: Stmt) -> Self {
+        Self { body: Box::new(body) }
+    }
+
+    pub fn write_instructions(&self, labels: &mut Labels, instructions: &mut Vec<Instr>) {
+        let label = format!("tail{}", labels.next_id());
+        labels.tail.push(label.to_string());
+        let mut body = vec![];
+        self.body.write_instructions(labels, &mut body);
+        labels.tail.pop();
+        instructions.push(Instr::Loop { label, result: Some(ValType::I32), body });
+    }
+}
+
+// return f(...) を、引数を一時変数に評価してからパラメータへ代入し、いちばん内側の TailLoopNode の先頭へ戻る分岐に置き換えたもの
+#[derive(Clone, Debug)]
+pub struct TailCallNode {
+    pub temporaries: Vec<Expr>,
+    pub assignments: Vec<Expr>,
+}
+
+impl TailCallNode {
+    pub fn new(temporaries: Vec<Expr>, assignments: Vec<Expr>) -> Self {
+        Self { temporaries, assignments }
+    }
+
+    pub fn write_instructions(&self, labels: &mut Labels, instructions: &mut Vec<Instr>) {
+        for node in self.temporaries.iter().chain(self.assignments.iter()) {
+            node.write_instructions(labels, instructions);
+            instructions.push(Instr::Drop);
+        }
+        match labels.tail.last() {
+            Some(label) => instructions.push(Instr::Br(label.to_string())),
+            None => panic!("tail call outside of tail loop"),
+        }
+    }
+}
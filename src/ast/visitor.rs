@@ -0,0 +1,135 @@
+use crate::ast::{Expr, Stmt};
+
+// 木をたどる解析。既定の実装は子ノードをすべて訪れる
+pub trait Visitor {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt);
+    }
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+}
+
+// 木を書き換えるパス。ノードごと置き換えるときは *stmt や *expr に代入する
+pub trait VisitorMut {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+    }
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Expr(expr) => visitor.visit_expr(expr),
+        Stmt::Return(ret) => visitor.visit_expr(&ret.child),
+        Stmt::If(node) => {
+            visitor.visit_expr(&node.condition);
+            visitor.visit_stmt(&node.then_block);
+            if let Some(els) = &node.else_block {
+                visitor.visit_stmt(els);
+            }
+        },
+        Stmt::While(node) => {
+            visitor.visit_expr(&node.condition);
+            visitor.visit_stmt(&node.body);
+        },
+        Stmt::For(node) => {
+            for expr in [&node.initialize, &node.condition, &node.increment].into_iter().flatten() {
+                visitor.visit_expr(expr);
+            }
+            visitor.visit_stmt(&node.body);
+        },
+        Stmt::Block(block) => {
+            for statement in block.statements.iter() {
+                visitor.visit_stmt(statement);
+            }
+        },
+        Stmt::TailLoop(node) => visitor.visit_stmt(&node.body),
+        Stmt::TailCall(node) => {
+            for expr in node.temporaries.iter().chain(node.assignments.iter()) {
+                visitor.visit_expr(expr);
+            }
+        },
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Number(_) | Expr::Variable(_) => {},
+        Expr::Assign(assign) => visitor.visit_expr(&assign.rhs),
+        Expr::BiOperator(operator) => {
+            visitor.visit_expr(&operator.lhs);
+            visitor.visit_expr(&operator.rhs);
+        },
+        Expr::Call(call) => {
+            for arg in call.arguments.iter() {
+                visitor.visit_expr(arg);
+            }
+        },
+        Expr::Inline(inline) => {
+            for binding in inline.bindings.iter() {
+                visitor.visit_expr(binding);
+            }
+            visitor.visit_stmt(&inline.body);
+        },
+    }
+}
+
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Expr(expr) => visitor.visit_expr_mut(expr),
+        Stmt::Return(ret) => visitor.visit_expr_mut(&mut ret.child),
+        Stmt::If(node) => {
+            visitor.visit_expr_mut(&mut node.condition);
+            visitor.visit_stmt_mut(&mut node.then_block);
+            if let Some(els) = &mut node.else_block {
+                visitor.visit_stmt_mut(els);
+            }
+        },
+        Stmt::While(node) => {
+            visitor.visit_expr_mut(&mut node.condition);
+            visitor.visit_stmt_mut(&mut node.body);
+        },
+        Stmt::For(node) => {
+            for expr in [&mut node.initialize, &mut node.condition, &mut node.increment].into_iter().flatten() {
+                visitor.visit_expr_mut(expr);
+            }
+            visitor.visit_stmt_mut(&mut node.body);
+        },
+        Stmt::Block(block) => {
+            for statement in block.statements.iter_mut() {
+                visitor.visit_stmt_mut(statement);
+            }
+        },
+        Stmt::TailLoop(node) => visitor.visit_stmt_mut(&mut node.body),
+        Stmt::TailCall(node) => {
+            for expr in node.temporaries.iter_mut().chain(node.assignments.iter_mut()) {
+                visitor.visit_expr_mut(expr);
+            }
+        },
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Number(_) | Expr::Variable(_) => {},
+        Expr::Assign(assign) => visitor.visit_expr_mut(&mut assign.rhs),
+        Expr::BiOperator(operator) => {
+            visitor.visit_expr_mut(&mut operator.lhs);
+            visitor.visit_expr_mut(&mut operator.rhs);
+        },
+        Expr::Call(call) => {
+            for arg in call.arguments.iter_mut() {
+                visitor.visit_expr_mut(arg);
+            }
+        },
+        Expr::Inline(inline) => {
+            for binding in inline.bindings.iter_mut() {
+                visitor.visit_expr_mut(binding);
+            }
+            visitor.visit_stmt_mut(&mut inline.body);
+        },
+    }
+}
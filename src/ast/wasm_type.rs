@@ -1,13 +1,16 @@
+use crate::ir::ValType;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum WasmType {
     I32
 }
 
 impl WasmType {
 
-    pub fn code(&self) -> u8 {
+    pub fn lower(&self) -> ValType {
         match &self {
-            WasmType::I32 => 0x7f
+            WasmType::I32 => ValType::I32
         }
     }
 
-}
\ No newline at end of file
+}
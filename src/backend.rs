@@ -0,0 +1,168 @@
+mod wasi;
+
+use std::io::{Write, Result};
+use crate::ir::Module;
+use crate::wasmc::encode;
+
+// 最適化を済ませた中間表現を書き出す出力形式。compile は --target の名前でこれを選んで呼ぶだけなので、
+// 新しい形式は ast には手を入れずに Backend を実装して Backends に登録すればよい。
+// 関数本体を命令ごとに書き出すなら ir::Context でラベルとスタックの高さを数えられる
+pub trait Backend {
+    // --target で指定する名前
+    fn name(&self) -> &str;
+    // 出力ファイル out.<extension> の拡張子
+    fn extension(&self) -> &str;
+    // テキストなら標準出力にも表示する。バイナリのときは代わりに WAT を表示して out.wat にも書く
+    fn is_text(&self) -> bool {
+        true
+    }
+    // 書き出す前にモジュールに手を加える。実行時ライブラリを足すときなどに使う
+    fn prepare(&self, module: Module) -> Module {
+        module
+    }
+    fn write_module(&self, module: &Module, write: &mut dyn Write) -> Result<()>;
+}
+
+pub struct Wat;
+
+impl Backend for Wat {
+    fn name(&self) -> &str {
+        "wat"
+    }
+    fn extension(&self) -> &str {
+        "wat"
+    }
+    fn write_module(&self, module: &Module, write: &mut dyn Write) -> Result<()> {
+        module.write_wat(write)
+    }
+}
+
+pub struct Wasm;
+
+impl Backend for Wasm {
+    fn name(&self) -> &str {
+        "wasm"
+    }
+    fn extension(&self) -> &str {
+        "wasm"
+    }
+    fn is_text(&self) -> bool {
+        false
+    }
+    fn write_module(&self, module: &Module, write: &mut dyn Write) -> Result<()> {
+        write.write_all(&encode(module))
+    }
+}
+
+// WASI のコマンド。_start から main を呼び、print_int と print_str を使える
+pub struct Wasi;
+
+impl Backend for Wasi {
+    fn name(&self) -> &str {
+        "wasi"
+    }
+    fn extension(&self) -> &str {
+        "wasm"
+    }
+    fn is_text(&self) -> bool {
+        false
+    }
+    fn prepare(&self, module: Module) -> Module {
+        wasi::command(module)
+    }
+    fn write_module(&self, module: &Module, write: &mut dyn Write) -> Result<()> {
+        write.write_all(&encode(module))
+    }
+}
+
+pub struct X86_64;
+
+impl Backend for X86_64 {
+    fn name(&self) -> &str {
+        "x86-64"
+    }
+    fn extension(&self) -> &str {
+        "s"
+    }
+    fn write_module(&self, module: &Module, write: &mut dyn Write) -> Result<()> {
+        module.write_x86_64(write)
+    }
+}
+
+pub struct C99;
+
+impl Backend for C99 {
+    fn name(&self) -> &str {
+        "c99"
+    }
+    fn extension(&self) -> &str {
+        "c"
+    }
+    fn write_module(&self, module: &Module, write: &mut dyn Write) -> Result<()> {
+        module.write_c99(write)
+    }
+}
+
+// 名前で選べるバックエンドの一覧。既定では組み込みのものが入っている
+pub struct Backends {
+    backends: Vec<Box<dyn Backend>>,
+}
+
+impl Default for Backends {
+    fn default() -> Self {
+        Backends { backends: vec![Box::new(Wasm), Box::new(Wat), Box::new(Wasi), Box::new(X86_64), Box::new(C99)] }
+    }
+}
+
+impl Backends {
+
+    // 同じ名前のものがあれば置き換える
+    pub fn register(&mut self, backend: Box<dyn Backend>) {
+        self.backends.retain(|registered| registered.name() != backend.name());
+        self.backends.push(backend);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Backend> {
+        self.backends.iter().find(|backend| backend.name() == name).map(|backend| backend.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.backends.iter().map(|backend| backend.name()).collect()
+    }
+
+}
+
+#[cfg(test)]
+struct FunctionNames;
+
+#[cfg(test)]
+impl Backend for FunctionNames {
+    fn name(&self) -> &str {
+        "names"
+    }
+    fn extension(&self) -> &str {
+        "txt"
+    }
+    fn write_module(&self, module: &Module, write: &mut dyn Write) -> Result<()> {
+        for function in module.functions.iter() {
+            writeln!(write, "{}", function.name)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_register() {
+    use crate::wasmc::{lower, CompileOptions};
+    let mut backends = Backends::default();
+    assert_eq!(backends.names(), vec!["wasm", "wat", "wasi", "x86-64", "c99"]);
+    assert!(backends.get("names").is_none());
+    backends.register(Box::new(FunctionNames));
+    let module = lower("main(){return f();}f(){return 1;}", &CompileOptions { opt_level: crate::optimizer::OptLevel::O0, ..CompileOptions::default() });
+    let mut buf = vec![];
+    backends.get("names").unwrap().write_module(&module, &mut buf).unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap(), "main\nf\n");
+    // 同じ名前で登録し直すと置き換わる
+    backends.register(Box::new(FunctionNames));
+    assert_eq!(backends.names(), vec!["wasm", "wat", "wasi", "x86-64", "c99", "names"]);
+}
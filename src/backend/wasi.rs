@@ -0,0 +1,273 @@
+use crate::ast::{BUILTINS, STRING_BASE};
+use crate::ir::{Export, ExportKind, Memory, Module};
+
+const PAGE_SIZE: usize = 65536;
+
+// WASI のコマンドとして動かすための実行時ライブラリ。メモリの 0 から STRING_BASE までを使う。
+//   0: fd_write に渡す iovec、8: 書いたバイト数、12: argc、16: 引数の文字列の大きさ、
+//   32..44: print_int で数字を並べる場所、64 からはメッセージ
+// 引数のポインタの表と文字列は、データより後ろのページ {args} に置く
+const RUNTIME: &str = r#"
+(module
+  (import "wasi_snapshot_preview1" "fd_write" (func $wasi_fd_write (param i32 i32 i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "proc_exit" (func $wasi_proc_exit (param i32)))
+  (import "wasi_snapshot_preview1" "args_sizes_get" (func $wasi_args_sizes_get (param i32 i32) (result i32)))
+  (import "wasi_snapshot_preview1" "args_get" (func $wasi_args_get (param i32 i32) (result i32)))
+  ;; 呼び出し先のモジュールの main。取り込むときには外す
+  (import "wasmc" "main" (func ${main} (param{main_params}) (result i32)))
+  (memory 1)
+  (data (i32.const 64) "\0a")
+  (data (i32.const 80) "`main` takes \00")
+  (data (i32.const 112) " arguments, got \00")
+  (data (i32.const 144) "invalid i32 argument: \00")
+  (data (i32.const 176) "arguments are too long\0a\00")
+
+  ;; 組み込み関数。式の中で呼べるように 0 を返す
+  (func $print_int (param $value i32) (result i32)
+    (call $wasmc_print_int (i32.const 1) (local.get $value))
+    (i32.const 0))
+  (func $print_str (param $string i32) (result i32)
+    (call $wasmc_print_str (i32.const 1) (local.get $string))
+    (i32.const 0))
+
+  (func $wasmc_write (param $fd i32) (param $pointer i32) (param $length i32)
+    (i32.store (i32.const 0) (local.get $pointer))
+    (i32.store (i32.const 4) (local.get $length))
+    (drop (call $wasi_fd_write (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 8))))
+
+  ;; 0 で終わる文字列を書く
+  (func $wasmc_print_str (param $fd i32) (param $string i32) (local $end i32)
+    (local.set $end (local.get $string))
+    (block $done
+      (loop $scan
+        (br_if $done (i32.eqz (i32.load8_u (local.get $end))))
+        (local.set $end (i32.add (local.get $end) (i32.const 1)))
+        (br $scan)))
+    (call $wasmc_write (local.get $fd) (local.get $string) (i32.sub (local.get $end) (local.get $string))))
+
+  ;; 0 以下に揃えてから下の桁から取り出すので、i32 の最小値もそのまま書ける
+  (func $wasmc_print_int (param $fd i32) (param $value i32) (local $pointer i32) (local $n i32) (local $q i32)
+    (local.set $pointer (i32.const 44))
+    (local.set $n (local.get $value))
+    (if (i32.gt_s (local.get $n) (i32.const 0))
+      (then (local.set $n (i32.sub (i32.const 0) (local.get $n)))))
+    (loop $digits
+      (local.set $pointer (i32.sub (local.get $pointer) (i32.const 1)))
+      (local.set $q (i32.div_s (local.get $n) (i32.const 10)))
+      (i32.store8 (local.get $pointer)
+        (i32.add (i32.const 48) (i32.sub (i32.mul (local.get $q) (i32.const 10)) (local.get $n))))
+      (local.set $n (local.get $q))
+      (br_if $digits (i32.ne (local.get $n) (i32.const 0))))
+    (if (i32.lt_s (local.get $value) (i32.const 0))
+      (then
+        (local.set $pointer (i32.sub (local.get $pointer) (i32.const 1)))
+        (i32.store8 (local.get $pointer) (i32.const 45))))
+    (call $wasmc_write (local.get $fd) (local.get $pointer) (i32.sub (i32.const 44) (local.get $pointer))))
+
+  (func $wasmc_invalid (param $string i32)
+    (call $wasmc_print_str (i32.const 2) (i32.const 144))
+    (call $wasmc_print_str (i32.const 2) (local.get $string))
+    (call $wasmc_write (i32.const 2) (i32.const 64) (i32.const 1))
+    (call $wasi_proc_exit (i32.const 255)))
+
+  ;; 引数を 10 進の i32 として読む。負の数の側で貯めて、範囲を超えたら終了する
+  (func $wasmc_arg (param $string i32) (result i32) (local $pointer i32) (local $negative i32) (local $n i32) (local $digit i32)
+    (local.set $pointer (local.get $string))
+    (if (i32.eq (i32.load8_u (local.get $pointer)) (i32.const 45))
+      (then
+        (local.set $negative (i32.const 1))
+        (local.set $pointer (i32.add (local.get $pointer) (i32.const 1))))
+      (else
+        (if (i32.eq (i32.load8_u (local.get $pointer)) (i32.const 43))
+          (then (local.set $pointer (i32.add (local.get $pointer) (i32.const 1)))))))
+    (if (i32.eqz (i32.load8_u (local.get $pointer)))
+      (then (call $wasmc_invalid (local.get $string))))
+    (block $done
+      (loop $digits
+        (local.set $digit (i32.load8_u (local.get $pointer)))
+        (br_if $done (i32.eqz (local.get $digit)))
+        (local.set $digit (i32.sub (local.get $digit) (i32.const 48)))
+        (if (i32.lt_s (local.get $digit) (i32.const 0))
+          (then (call $wasmc_invalid (local.get $string))))
+        (if (i32.gt_s (local.get $digit) (i32.const 9))
+          (then (call $wasmc_invalid (local.get $string))))
+        (if (i32.lt_s (local.get $n) (i32.const -214748364))
+          (then (call $wasmc_invalid (local.get $string))))
+        (local.set $n (i32.mul (local.get $n) (i32.const 10)))
+        (if (i32.lt_s (local.get $n) (i32.add (i32.const -2147483648) (local.get $digit)))
+          (then (call $wasmc_invalid (local.get $string))))
+        (local.set $n (i32.sub (local.get $n) (local.get $digit)))
+        (local.set $pointer (i32.add (local.get $pointer) (i32.const 1)))
+        (br $digits)))
+    (if (result i32) (local.get $negative)
+      (then (local.get $n))
+      (else
+        (if (i32.eq (local.get $n) (i32.const -2147483648))
+          (then (call $wasmc_invalid (local.get $string))))
+        (i32.sub (i32.const 0) (local.get $n)))))
+
+  ;; 引数を読んで main を呼び、戻り値を終了コードにする
+  (func $_start (local $argc i32)
+    (drop (call $wasi_args_sizes_get (i32.const 12) (i32.const 16)))
+    (local.set $argc (i32.sub (i32.load (i32.const 12)) (i32.const 1)))
+    (if (i32.ne (local.get $argc) (i32.const {params}))
+      (then
+        (call $wasmc_print_str (i32.const 2) (i32.const 80))
+        (call $wasmc_print_int (i32.const 2) (i32.const {params}))
+        (call $wasmc_print_str (i32.const 2) (i32.const 112))
+        (call $wasmc_print_int (i32.const 2) (local.get $argc))
+        (call $wasmc_write (i32.const 2) (i32.const 64) (i32.const 1))
+        (call $wasi_proc_exit (i32.const 255))))
+    (if (i32.gt_s (i32.add (i32.mul (i32.load (i32.const 12)) (i32.const 4)) (i32.load (i32.const 16))) (i32.const 65536))
+      (then
+        (call $wasmc_print_str (i32.const 2) (i32.const 176))
+        (call $wasi_proc_exit (i32.const 255))))
+    (drop (call $wasi_args_get (i32.const {args})
+      (i32.add (i32.const {args}) (i32.mul (i32.load (i32.const 12)) (i32.const 4)))))
+    (call $wasi_proc_exit (call ${main}{arguments})))
+)
+"#;
+
+// main を export したモジュールを WASI のコマンドにする。main の代わりに _start と memory を export し、
+// env から import している print_int と print_str を組み込み関数に置き換える。同じ名前の関数を定義していればそちらを使う
+pub fn command(mut module: Module) -> Module {
+    let main = match module.exports.iter().find_map(|export| match &export.kind {
+        ExportKind::Function(name) if export.name == "main" => module.function(name),
+        _ => None,
+    }) {
+        Some(main) => main,
+        None => panic!("function `main` not found"),
+    };
+    // データの後ろのページを引数に使う
+    let end = module.data.iter().map(|data| data.offset as u32 as usize + data.bytes.len()).max().unwrap_or(0);
+    let args = end.max(STRING_BASE as usize).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    let arguments: String = (0..main.params.len())
+        .map(|i| format!(" (call $wasmc_arg (i32.load (i32.const {})))", args + (i + 1) * 4))
+        .collect();
+    let text = RUNTIME
+        .replace("{params}", &main.params.len().to_string())
+        .replace("{args}", &args.to_string())
+        .replace("{main}", &main.name)
+        .replace("{main_params}", &" i32".repeat(main.params.len()))
+        .replace("{arguments}", &arguments);
+    let runtime = match Module::read_wat(&text) {
+        Ok(runtime) => runtime,
+        Err(error) => panic!("実行時ライブラリを読めません: {}", error),
+    };
+
+    module.imports.retain(|import| !(import.module == "env" && BUILTINS.contains(&import.name.as_str())));
+    for import in runtime.imports.into_iter().rev().filter(|import| import.module != "wasmc") {
+        reserve(&module, &import.name);
+        module.imports.insert(0, import);
+    }
+    for function in runtime.functions {
+        if BUILTINS.contains(&function.name.as_str()) && module.function(&function.name).is_some() {
+            continue;
+        }
+        reserve(&module, &function.name);
+        module.functions.push(function);
+    }
+    let mut data = runtime.data;
+    data.append(&mut module.data);
+    module.data = data;
+    module.memory = Some(Memory { min: (args / PAGE_SIZE + 1) as u32, max: None });
+    module.exports.retain(|export| !matches!(export.kind, ExportKind::Function(_) | ExportKind::Memory));
+    module.exports.push(Export::function("_start", "_start"));
+    module.exports.push(Export { name: "memory".to_string(), kind: ExportKind::Memory });
+    module
+}
+
+fn reserve(module: &Module, name: &str) {
+    if module.function_index(name).is_some() {
+        panic!("`{}` は実行時ライブラリが使う名前です", name);
+    }
+}
+
+// wasi_snapshot_preview1 の代わりを繋いで _start を呼び、(終了コード, 標準出力, 標準エラー出力) を返す
+#[cfg(test)]
+fn run_command(source: &str, args: &[&str]) -> (i32, String, String) {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::interpreter::{Instance, Trap};
+
+    fn load(memory: &[u8], address: i32) -> usize {
+        let address = address as usize;
+        u32::from_le_bytes(memory[address..address + 4].try_into().unwrap()) as usize
+    }
+    fn store(memory: &mut [u8], address: usize, value: usize) {
+        memory[address..address + 4].copy_from_slice(&(value as u32).to_le_bytes());
+    }
+
+    let module = command(crate::wasmc::parse(source).lower());
+    let mut instance = Instance::from_module(&module);
+    let output = Rc::new(RefCell::new([vec![], vec![], vec![]]));
+    let written = output.clone();
+    instance.link("wasi_snapshot_preview1", "fd_write", Box::new(move |memory, args| {
+        let (pointer, length) = (load(memory, args[1]), load(memory, args[1] + 4));
+        written.borrow_mut()[args[0] as usize].extend_from_slice(&memory[pointer..pointer + length]);
+        store(memory, args[3] as usize, length);
+        Ok(vec![0])
+    }));
+    instance.link("wasi_snapshot_preview1", "proc_exit", Box::new(|_, args| Err(Trap::Exit(args[0]))));
+    let argv: Vec<Vec<u8>> = std::iter::once("out.wasm").chain(args.iter().copied())
+        .map(|arg| [arg.as_bytes(), b"\0"].concat()).collect();
+    let sizes = argv.clone();
+    instance.link("wasi_snapshot_preview1", "args_sizes_get", Box::new(move |memory, args| {
+        store(memory, args[0] as usize, sizes.len());
+        store(memory, args[1] as usize, sizes.iter().map(|arg| arg.len()).sum());
+        Ok(vec![0])
+    }));
+    instance.link("wasi_snapshot_preview1", "args_get", Box::new(move |memory, args| {
+        let mut pointer = args[1] as usize;
+        for (i, arg) in argv.iter().enumerate() {
+            store(memory, args[0] as usize + i * 4, pointer);
+            memory[pointer..pointer + arg.len()].copy_from_slice(arg);
+            pointer += arg.len();
+        }
+        Ok(vec![0])
+    }));
+    let code = match instance.invoke("_start", &[]) {
+        Err(Trap::Exit(code)) => code,
+        result => panic!("_start did not exit: {:?}", result),
+    };
+    let output = output.borrow();
+    (code, String::from_utf8(output[1].clone()).unwrap(), String::from_utf8(output[2].clone()).unwrap())
+}
+
+#[test]
+fn test_command() {
+    let fib = "main(n){print_str(\"fib(\");print_int(n);print_str(\") = \");print_int(fib(n));print_str(\"\\n\");return n;}\
+               fib(n){a=0;b=1;while(n>0){t=a+b;a=b;b=t;n=n-1;}return a;}";
+    assert_eq!(run_command(fib, &["10"]), (10, "fib(10) = 55\n".to_string(), String::new()));
+    assert_eq!(run_command(fib, &["+7"]), (7, "fib(7) = 13\n".to_string(), String::new()));
+    assert_eq!(run_command("main(){print_int(0-2147483647-1);print_int(0);return 0;}", &[]),
+               (0, "-21474836480".to_string(), String::new()));
+    assert_eq!(run_command("main(a,b){return a-b;}", &["-2147483648", "-3"]), (-2147483645, String::new(), String::new()));
+}
+
+#[test]
+fn test_command_arguments() {
+    let usage = (255, String::new(), "`main` takes 1 arguments, got 2\n".to_string());
+    assert_eq!(run_command("main(n){return n;}", &["1", "2"]), usage);
+    for arg in ["", "-", "1x", "2147483648", "-2147483649"] {
+        let invalid = (255, String::new(), format!("invalid i32 argument: {}\n", arg));
+        assert_eq!(run_command("main(n){return n;}", &[arg]), invalid);
+    }
+}
+
+#[test]
+fn test_command_builtins() {
+    // 自分で定義した print_int が優先される
+    assert_eq!(run_command("main(){return print_int(4);}print_int(n){return n*2;}", &[]), (8, String::new(), String::new()));
+    let module = command(crate::wasmc::parse("main(){return print_str(\"a\");}").lower());
+    assert!(module.imports.iter().all(|import| import.module == "wasi_snapshot_preview1"));
+    assert!(module.exports.iter().map(|export| export.name.as_str()).eq(["_start", "memory"]));
+    let data = module.data.iter().find(|data| data.offset == STRING_BASE).unwrap();
+    assert_eq!(data.bytes, b"a\0");
+}
+
+#[test]
+#[should_panic(expected = "実行時ライブラリが使う名前です")]
+fn test_command_reserved() {
+    command(crate::wasmc::parse("main(){return wasmc_arg(1);}wasmc_arg(n){return n;}").lower());
+}
@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::panic;
+use std::thread;
+use crate::ast::{BiOpKind, Expr, Function, Module, Stmt};
+use crate::interpreter::{MAX_CALL_DEPTH, Trap};
+
+// 呼び出しの深さの分だけ Rust の再帰が深くなるので、大きなスタックのスレッドで評価する
+const STACK_SIZE: usize = 512 << 20;
+
+// コード生成を通さずに AST をそのまま評価する。write_wasm の出力と突き合わせるための基準で、
+// i32 の桁あふれとトラップは wasm と同じに扱う
+pub fn evaluate(module: &Module, name: &str, args: &[i32]) -> Result<i32, Trap> {
+    let function = match module.function(name) {
+        Some(function) => function,
+        None => return Err(Trap::UndefinedExport(name.to_string())),
+    };
+    if function.params.len() != args.len() {
+        return Err(Trap::ArgumentCount { expected: function.params.len(), actual: args.len() });
+    }
+    thread::scope(|scope| {
+        let evaluator = thread::Builder::new().stack_size(STACK_SIZE).spawn_scoped(scope, || {
+            Evaluator { module, depth: 0 }.call(function, args.to_vec())
+        }).unwrap();
+        evaluator.join().unwrap_or_else(|payload| panic::resume_unwind(payload))
+    })
+}
+
+// 両者の結果が一致するか。スタックの深さはインライン展開や末尾呼び出しで変わるので、
+// どちらかが StackExhausted なら比べない
+pub fn agrees(reference: &Result<i32, Trap>, compiled: &Result<Vec<i32>, Trap>) -> bool {
+    match (reference, compiled) {
+        (Err(Trap::StackExhausted), _) | (_, Err(Trap::StackExhausted)) => true,
+        (Ok(value), Ok(values)) => values == &[*value],
+        (Err(trap), Err(other)) => trap == other,
+        _ => false,
+    }
+}
+
+// 文を実行した後の行き先
+enum Flow {
+    // 次の文へ進む。値は生成するコードがスタックに残すもの
+    Next(i32),
+    // いちばん内側の関数かインライン展開を抜ける
+    Return(i32),
+    // いちばん内側の TailLoopNode の先頭へ戻る
+    TailCall,
+}
+
+struct Evaluator<'a> {
+    module: &'a Module,
+    depth: usize,
+}
+
+impl <'a> Evaluator<'a> {
+
+    fn call(&mut self, function: &'a Function, args: Vec<i32>) -> Result<i32, Trap> {
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(Trap::StackExhausted);
+        }
+        // wasm のローカル変数は 0 で初期化されている
+        let mut locals: HashMap<&str, i32> = function.locals().iter().map(|name| (name.as_str(), 0)).collect();
+        for (param, arg) in function.params.iter().zip(args) {
+            locals.insert(&param.name, arg);
+        }
+        self.depth += 1;
+        let flow = self.stmt(&function.body, &mut locals);
+        self.depth -= 1;
+        match flow? {
+            Flow::Next(value) | Flow::Return(value) => Ok(value),
+            Flow::TailCall => panic!("tail call outside of tail loop"),
+        }
+    }
+
+    fn stmt(&mut self, stmt: &'a Stmt, locals: &mut HashMap<&'a str, i32>) -> Result<Flow, Trap> {
+        let flow = match stmt {
+            Stmt::Expr(expr) => Flow::Next(self.expr(expr, locals)?),
+            Stmt::Return(ret) => Flow::Return(self.expr(&ret.child, locals)?),
+            Stmt::If(node) => {
+                let flow = if self.expr(&node.condition, locals)? != 0 {
+                    self.stmt(&node.then_block, locals)?
+                } else if let Some(els) = &node.else_block {
+                    self.stmt(els, locals)?
+                } else {
+                    Flow::Next(0)
+                };
+                match flow {
+                    Flow::Next(_) => Flow::Next(0),
+                    flow => flow,
+                }
+            },
+            Stmt::While(node) => {
+                while self.expr(&node.condition, locals)? != 0 {
+                    if let flow @ (Flow::Return(_) | Flow::TailCall) = self.stmt(&node.body, locals)? {
+                        return Ok(flow);
+                    }
+                }
+                Flow::Next(0)
+            },
+            Stmt::For(node) => {
+                if let Some(init) = &node.initialize {
+                    self.expr(init, locals)?;
+                }
+                loop {
+                    if let Some(cond) = &node.condition {
+                        if self.expr(cond, locals)? == 0 {
+                            break;
+                        }
+                    }
+                    if let flow @ (Flow::Return(_) | Flow::TailCall) = self.stmt(&node.body, locals)? {
+                        return Ok(flow);
+                    }
+                    if let Some(inc) = &node.increment {
+                        self.expr(inc, locals)?;
+                    }
+                }
+                Flow::Next(0)
+            },
+            Stmt::Block(block) => {
+                for statement in block.statements.iter() {
+                    if let flow @ (Flow::Return(_) | Flow::TailCall) = self.stmt(statement, locals)? {
+                        return Ok(flow);
+                    }
+                }
+                Flow::Next(0)
+            },
+            Stmt::TailLoop(node) => loop {
+                match self.stmt(&node.body, locals)? {
+                    Flow::TailCall => {},
+                    flow => break flow,
+                }
+            },
+            Stmt::TailCall(node) => {
+                for expr in node.temporaries.iter().chain(node.assignments.iter()) {
+                    self.expr(expr, locals)?;
+                }
+                Flow::TailCall
+            },
+        };
+        Ok(flow)
+    }
+
+    fn expr(&mut self, expr: &'a Expr, locals: &mut HashMap<&'a str, i32>) -> Result<i32, Trap> {
+        match expr {
+            Expr::Number(number) => Ok(number.value),
+            Expr::Variable(variable) => match locals.get(variable.name.as_str()) {
+                Some(value) => Ok(*value),
+                None => panic!("variable {} is not defined", variable.name)
+            },
+            Expr::Assign(assign) => {
+                let value = self.expr(&assign.rhs, locals)?;
+                locals.insert(&assign.lhs.name, value);
+                Ok(value)
+            },
+            Expr::BiOperator(operator) => {
+                let lhs = self.expr(&operator.lhs, locals)?;
+                let rhs = self.expr(&operator.rhs, locals)?;
+                match operator.kind.evaluate(lhs, rhs) {
+                    Some(value) => Ok(value),
+                    None if operator.kind == BiOpKind::Div && rhs == 0 => Err(Trap::DivisionByZero),
+                    None => Err(Trap::IntegerOverflow),
+                }
+            },
+            Expr::Call(call) => {
+                let mut args = vec![];
+                for arg in call.arguments.iter() {
+                    args.push(self.expr(arg, locals)?);
+                }
+                // 定義されていない関数は env から import するもので、評価器にはホストがない
+                match self.module.function(&call.name) {
+                    Some(function) => self.call(function, args),
+                    None => Err(Trap::UnresolvedImport(format!("env.{}", call.name))),
+                }
+            },
+            Expr::Inline(inline) => {
+                for binding in inline.bindings.iter() {
+                    self.expr(binding, locals)?;
+                }
+                match self.stmt(&inline.body, locals)? {
+                    Flow::Next(value) | Flow::Return(value) => Ok(value),
+                    Flow::TailCall => panic!("tail call outside of tail loop"),
+                }
+            },
+        }
+    }
+
+}
+
+#[cfg(test)]
+use crate::optimizer::{OptLevel, PassManager};
+#[cfg(test)]
+use crate::wasmc::parse;
+
+// 参照の評価器とインタープリタの結果を、すべての最適化レベルで突き合わせる
+#[cfg(test)]
+fn assert_agrees(source: &str, args: &[i32]) -> Result<i32, Trap> {
+    let reference = evaluate(&parse(source), "main", args);
+    for level in OptLevel::ALL {
+        let mut module = parse(source);
+        PassManager::new(level).run(&mut module);
+        let mut bytes = vec![];
+        module.lower().write_wasm(&mut bytes).unwrap();
+        let compiled = crate::interpreter::Instance::new(&bytes).unwrap().invoke("main", args);
+        assert!(agrees(&reference, &compiled), "{:?}: {:?} != {:?} in {}", level, reference, compiled, source);
+    }
+    reference
+}
+
+#[test]
+fn test_evaluate() {
+    assert_eq!(assert_agrees("main(){return 5+20-4;}", &[]), Ok(21));
+    assert_eq!(assert_agrees("main(){a=1;b=a=a+1;return a*10+b;}", &[]), Ok(22));
+    assert_eq!(assert_agrees("main(){return sub(5,2);}sub(a,b){return a-b;}", &[]), Ok(3));
+    assert_eq!(assert_agrees("main(){a=0;for(i=1;i<=5;i=i+1){a=a+i;a=a+1;}return a;}", &[]), Ok(20));
+    assert_eq!(assert_agrees("main(){return lcm(12,20);}lcm(a,b){return a/gcd(a,b)*b;}gcd(a,b){if(a<b)return gcd(b,a);if(a==b)return a;if(b==0)return a;return gcd(b,a-(a/b*b));}", &[]), Ok(60));
+    assert_eq!(assert_agrees("main(n){a=1;while(n>0){a=a*3;n=n-1;}return a;}", &[40]), Ok(3i32.wrapping_pow(40)));
+    assert_eq!(assert_agrees("main(a){return a/4+a/-8;}", &[-100]), Ok(-13));
+    // 末尾で return しなければ 0 を返す
+    assert_eq!(assert_agrees("main(){a=3;}", &[]), Ok(0));
+    // 末尾呼び出しとインライン展開を組み合わせる
+    assert_eq!(assert_agrees("sum(n,a){if(n==0)return a;return sum(n-1,a+n);}twice(x){return x+x;}main(){return twice(sum(100,0));}", &[]), Ok(10100));
+}
+
+#[test]
+fn test_trap() {
+    assert_eq!(assert_agrees("main(a){return 1/a;}", &[0]), Err(Trap::DivisionByZero));
+    assert_eq!(assert_agrees("main(a){return a/-1;}", &[i32::MIN]), Err(Trap::IntegerOverflow));
+    // 定数畳み込みでトラップが消えないこと
+    assert_eq!(assert_agrees("main(){a=0;b=1/a;return 1;}", &[]), Err(Trap::DivisionByZero));
+    assert_eq!(evaluate(&parse("f(n){return f(n+1)+1;}main(){return f(0);}"), "main", &[]), Err(Trap::StackExhausted));
+    assert_eq!(evaluate(&parse("main(a){return a;}"), "main", &[]), Err(Trap::ArgumentCount { expected: 1, actual: 0 }));
+}
+
+#[test]
+fn test_agrees() {
+    assert!(agrees(&Ok(1), &Ok(vec![1])));
+    assert!(!agrees(&Ok(1), &Ok(vec![2])));
+    assert!(!agrees(&Ok(1), &Err(Trap::DivisionByZero)));
+    assert!(agrees(&Err(Trap::StackExhausted), &Ok(vec![2])));
+}
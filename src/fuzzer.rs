@@ -0,0 +1,257 @@
+mod program;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use crate::evaluator::{agrees, evaluate};
+use crate::interpreter::{Instance, Trap};
+use crate::ir::validate;
+use crate::optimizer::{OptLevel, PassManager};
+use crate::wasmc::parse;
+pub use program::{generate, Program};
+
+// 失敗したプログラムを回帰テストとして保存する場所
+pub const REGRESSION_DIR: &str = "fuzz/regressions";
+
+// 外部のクレートを使わないための小さな疑似乱数 (xorshift64*)
+pub struct Rng(u64);
+
+impl Rng {
+
+    pub fn new(seed: u64) -> Self {
+        // 状態が 0 だと 0 しか出ないので混ぜておく
+        Rng(seed ^ 0x9e37_79b9_7f4a_7c15 | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // 0 以上 n 未満
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Failure {
+    // 評価器とコンパイル結果の食い違い
+    Mismatch { level: OptLevel, reference: Result<i32, Trap>, compiled: Result<Vec<i32>, Trap> },
+    // コンパイラか評価器が panic した。stage は reference か最適化レベル
+    Panic { stage: String, message: String },
+    // 生成したバイナリが検証を通らない
+    Invalid { level: OptLevel, message: String },
+}
+
+impl Failure {
+
+    // 縮小しても同じ失敗が続いているか
+    fn same_kind(&self, other: &Failure) -> bool {
+        match (self, other) {
+            (Failure::Mismatch { .. }, Failure::Mismatch { .. }) => true,
+            (Failure::Panic { message, .. }, Failure::Panic { message: other, .. }) => message == other,
+            (Failure::Invalid { message, .. }, Failure::Invalid { message: other, .. }) => message == other,
+            _ => false,
+        }
+    }
+
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Mismatch { level, reference, compiled } => {
+                write!(f, "{}: reference {}, compiled {:?}", level.flag(), outcome(reference), compiled)
+            },
+            Failure::Panic { stage, message } => write!(f, "{}: panicked: {}", stage, message),
+            Failure::Invalid { level, message } => write!(f, "{}: invalid module: {}", level.flag(), message),
+        }
+    }
+}
+
+// 評価器の結果を .out に書く形にする
+pub fn outcome(result: &Result<i32, Trap>) -> String {
+    match result {
+        Ok(value) => value.to_string(),
+        Err(trap) => format!("trap: {}", trap),
+    }
+}
+
+fn catch<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        match payload.downcast_ref::<String>() {
+            Some(message) => message.to_string(),
+            None => payload.downcast_ref::<&str>().map(|message| message.to_string()).unwrap_or_default(),
+        }
+    })
+}
+
+// 最適化前の AST の評価を基準に、すべての最適化レベルでコンパイルして実行した結果を比べる。
+// バイナリは実行する前に検証する
+pub fn check(source: &str, args: &[i32]) -> Option<Failure> {
+    let reference = match catch(|| evaluate(&parse(source), "main", args)) {
+        Ok(reference) => reference,
+        Err(message) => return Some(Failure::Panic { stage: "reference".to_string(), message }),
+    };
+    for level in OptLevel::ALL {
+        let compiled = catch(|| {
+            let mut module = parse(source);
+            PassManager::new(level).run(&mut module);
+            let mut bytes = vec![];
+            module.lower().write_wasm(&mut bytes).unwrap();
+            validate(&bytes).map(|_| Instance::new(&bytes).unwrap().invoke("main", args))
+        });
+        match compiled {
+            Err(message) => return Some(Failure::Panic { stage: level.flag().to_string(), message }),
+            Ok(Err(error)) => return Some(Failure::Invalid { level, message: error.to_string() }),
+            Ok(Ok(compiled)) if !agrees(&reference, &compiled) => return Some(Failure::Mismatch { level, reference, compiled }),
+            Ok(Ok(_)) => {},
+        }
+    }
+    None
+}
+
+// 同じ種類の失敗が続く限り、1 か所ずつ小さくしていく
+pub fn shrink(program: Program, fails: impl Fn(&Program) -> bool) -> Program {
+    let mut current = program;
+    'shrink: loop {
+        for candidate in current.candidates() {
+            if fails(&candidate) {
+                current = candidate;
+                continue 'shrink;
+            }
+        }
+        return current;
+    }
+}
+
+// name.wc にソース、name.args に main の引数、name.out に評価器の結果を書く
+pub fn save_regression(dir: &Path, name: &str, program: &Program) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let source = program.to_string();
+    let args: Vec<String> = program.args.iter().map(|arg| arg.to_string()).collect();
+    let expected = outcome(&evaluate(&parse(&source), "main", &program.args));
+    let path = dir.join(format!("{}.wc", name));
+    fs::write(&path, &source)?;
+    fs::write(dir.join(format!("{}.args", name)), args.join(" ") + "\n")?;
+    fs::write(dir.join(format!("{}.out", name)), expected + "\n")?;
+    Ok(path)
+}
+
+pub struct FuzzOptions {
+    pub seed: u64,
+    pub iterations: usize,
+    // None なら保存しない
+    pub save_dir: Option<PathBuf>,
+}
+
+// 見つけた失敗の数を返す。i 番目のプログラムは seed + i から作るので、
+// --seed と --iterations 1 で 1 つだけ作り直せる
+pub fn fuzz(options: &FuzzOptions) -> usize {
+    // 縮小の途中で何度も panic するので、その間はメッセージを出さない
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    let mut failures = 0;
+    for i in 0..options.iterations {
+        let seed = options.seed.wrapping_add(i as u64);
+        let program = generate(&mut Rng::new(seed));
+        let failure = match check(&program.to_string(), &program.args) {
+            Some(failure) => failure,
+            None => continue,
+        };
+        failures += 1;
+        let program = shrink(program, |candidate| {
+            matches!(check(&candidate.to_string(), &candidate.args), Some(other) if failure.same_kind(&other))
+        });
+        let failure = check(&program.to_string(), &program.args).unwrap_or(failure);
+        eprintln!("seed {}: {}", seed, failure);
+        eprintln!("args: {:?}", program.args);
+        eprint!("{}", program);
+        if let Some(dir) = &options.save_dir {
+            match save_regression(dir, &format!("seed-{}", seed), &program) {
+                Ok(path) => eprintln!("saved {}", path.display()),
+                Err(error) => eprintln!("{} に保存できません: {}", dir.display(), error),
+            }
+        }
+    }
+    panic::set_hook(hook);
+    failures
+}
+
+#[test]
+fn test_generate() {
+    // 同じ seed からは同じプログラムができ、どれもそのまま構文解析できる
+    for seed in 0..50 {
+        let source = generate(&mut Rng::new(seed)).to_string();
+        assert_eq!(source, generate(&mut Rng::new(seed)).to_string());
+        parse(&source);
+    }
+}
+
+#[test]
+fn test_fuzz() {
+    for seed in 0..100 {
+        let program = generate(&mut Rng::new(seed));
+        if let Some(failure) = check(&program.to_string(), &program.args) {
+            panic!("seed {}: {}\n{}", seed, failure, program);
+        }
+    }
+}
+
+#[test]
+fn test_shrink() {
+    // 割り算を含むという条件だけを残すと、割り算 1 つにまで縮む
+    let program = (0..).map(|seed| generate(&mut Rng::new(seed))).find(|program| program.to_string().contains('/')).unwrap();
+    let shrunk = shrink(program, |candidate| candidate.to_string().contains('/'));
+    assert_eq!(shrunk.to_string().matches('/').count(), 1);
+    assert!(shrunk.functions.len() == 1 && shrunk.args.iter().all(|arg| *arg == 0));
+}
+
+// dir に保存された回帰テストを流し、流した数を返す
+#[cfg(test)]
+fn run_regressions(dir: &Path) -> usize {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    let mut count = 0;
+    for entry in entries {
+        let path = entry.unwrap().path();
+        if path.extension().map(|ext| ext != "wc").unwrap_or(true) {
+            continue;
+        }
+        let source = fs::read_to_string(&path).unwrap();
+        let args: Vec<i32> = fs::read_to_string(path.with_extension("args")).unwrap_or_default()
+            .split_whitespace().map(|arg| arg.parse().unwrap()).collect();
+        let expected = fs::read_to_string(path.with_extension("out")).unwrap();
+        assert_eq!(outcome(&evaluate(&parse(&source), "main", &args)), expected.trim(), "{}", path.display());
+        assert_eq!(check(&source, &args), None, "{}", path.display());
+        count += 1;
+    }
+    count
+}
+
+#[test]
+fn test_regressions() {
+    run_regressions(Path::new(REGRESSION_DIR));
+}
+
+#[test]
+fn test_save_regression() {
+    let dir = std::env::temp_dir().join(format!("wasmc-fuzz-{}", std::process::id()));
+    let program = generate(&mut Rng::new(7));
+    save_regression(&dir, "seed-7", &program).unwrap();
+    assert_eq!(fs::read_to_string(dir.join("seed-7.wc")).unwrap(), program.to_string());
+    assert_eq!(run_regressions(&dir), 1);
+    fs::remove_dir_all(&dir).unwrap();
+}
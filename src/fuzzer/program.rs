@@ -0,0 +1,519 @@
+use std::fmt;
+use crate::fuzzer::Rng;
+
+// 生成するプログラムの木。必ず停止するように、ループは専用の変数で回数を決め、
+// 関数は後ろに定義したものだけを呼ぶ。再帰は先頭の引数 n を減らしながら 0 で止まる形に限る
+
+const MAX_FUNCTIONS: usize = 4;
+const MAX_PARAMS: usize = 3;
+const MAX_STATEMENTS: usize = 4;
+const MAX_STMT_DEPTH: usize = 3;
+const MAX_EXPR_DEPTH: usize = 3;
+const MAX_LOOP_DEPTH: usize = 2;
+const MAX_LOOP_COUNT: i32 = 4;
+const MAX_RECURSION: i32 = 3;
+const MAX_SELF_CALLS: usize = 2;
+const LOCALS: [&str; 3] = ["v0", "v1", "v2"];
+const OPERATORS: [&str; 10] = ["+", "-", "*", "/", "==", "!=", "<", "<=", ">", ">="];
+// 桁あふれや、2 の累乗での割り算の書き換えを狙う値
+const INTERESTING: [i32; 8] = [2147483647, 1073741824, 65536, 65535, 1024, 255, 16, 8];
+
+#[derive(Clone, Debug)]
+pub enum Depth {
+    Const(i32),
+    // 自分自身の呼び出しで n-1 を渡す
+    Decrement,
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    // 負の数は Negate で表す
+    Number(i32),
+    Variable(String),
+    Negate(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Assign(String, Box<Expr>),
+    // depth は再帰する関数を呼ぶときだけ先頭の引数として付く
+    Call { name: String, depth: Option<Depth>, args: Vec<Expr> },
+}
+
+#[derive(Clone, Debug)]
+pub enum Stmt {
+    Expr(Expr),
+    Return(Expr),
+    If(Expr, Vec<Stmt>, Option<Vec<Stmt>>),
+    // for(i=0;i<count;i=i+1)
+    For { var: String, count: i32, body: Vec<Stmt> },
+    // w=0;while(w<count){...;w=w+1;}
+    While { var: String, count: i32, body: Vec<Stmt> },
+    Block(Vec<Stmt>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<String>,
+    // 再帰する関数は先頭に n を取り、if(n<=0){return base;} から始まる
+    pub base: Option<Expr>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Program {
+    // 先頭が main
+    pub functions: Vec<Function>,
+    pub args: Vec<i32>,
+}
+
+pub fn generate(rng: &mut Rng) -> Program {
+    let count = 1 + rng.below(MAX_FUNCTIONS);
+    let mut signatures = vec![];
+    for i in 0..count {
+        let recursive = i > 0 && rng.chance(30);
+        let mut params: Vec<String> = (0..rng.below(MAX_PARAMS + 1)).map(|j| format!("p{}", j)).collect();
+        if recursive {
+            params.insert(0, "n".to_string());
+        }
+        let name = if i == 0 { "main".to_string() } else { format!("f{}", i) };
+        signatures.push((name, params, recursive));
+    }
+
+    let mut functions = vec![];
+    for (index, (name, params, recursive)) in signatures.iter().enumerate() {
+        let mut generator = Generator {
+            rng: &mut *rng,
+            signatures: &signatures,
+            current: index,
+            loop_vars: vec![],
+            assigned: params.clone(),
+            read: vec![],
+            self_calls: 0,
+        };
+        // n<=0 のときの値は自分を呼ばない
+        generator.self_calls = MAX_SELF_CALLS;
+        let base = if *recursive { Some(generator.expr(1)) } else { None };
+        generator.self_calls = 0;
+        let mut body = generator.statements(0);
+        if generator.rng.chance(70) {
+            body.push(Stmt::Return(generator.expr(0)));
+        }
+        // 代入のない変数は定義されないので、読んだだけの変数には最後に代入を置く。
+        // それより前に読めば 0 になる
+        for name in generator.read.iter().filter(|name| !generator.assigned.contains(name)) {
+            body.push(Stmt::Expr(Expr::Assign(name.to_string(), Box::new(Expr::Number(0)))));
+        }
+        functions.push(Function { name: name.to_string(), params: params.clone(), base, body });
+    }
+    let args = signatures[0].1.iter().map(|_| Generator::number(rng, true)).collect();
+    Program { functions, args }
+}
+
+struct Generator<'a> {
+    rng: &'a mut Rng,
+    signatures: &'a [(String, Vec<String>, bool)],
+    current: usize,
+    // 開いているループの変数。代入はしないが読むことはできる
+    loop_vars: Vec<String>,
+    // 関数のどこかで代入している変数
+    assigned: Vec<String>,
+    // 読んだ変数。代入より前に読むこともある
+    read: Vec<String>,
+    self_calls: usize,
+}
+
+impl Generator<'_> {
+
+    fn statements(&mut self, depth: usize) -> Vec<Stmt> {
+        (0..1 + self.rng.below(MAX_STATEMENTS)).map(|_| self.stmt(depth)).collect()
+    }
+
+    fn stmt(&mut self, depth: usize) -> Stmt {
+        let nested = depth < MAX_STMT_DEPTH;
+        let looping = nested && self.loop_vars.len() < MAX_LOOP_DEPTH;
+        match self.rng.below(20) {
+            0..=1 => Stmt::Return(self.expr(MAX_EXPR_DEPTH)),
+            2..=4 if nested => {
+                let condition = self.expr(2);
+                let then = self.statements(depth + 1);
+                let otherwise = if self.rng.chance(50) { Some(self.statements(depth + 1)) } else { None };
+                Stmt::If(condition, then, otherwise)
+            },
+            5..=6 if looping => {
+                let var = format!("i{}", self.loop_vars.len());
+                let count = self.rng.below(MAX_LOOP_COUNT as usize + 1) as i32;
+                let body = self.loop_body(&var, depth);
+                Stmt::For { var, count, body }
+            },
+            7..=8 if looping => {
+                let var = format!("w{}", self.loop_vars.len());
+                let count = self.rng.below(MAX_LOOP_COUNT as usize + 1) as i32;
+                let body = self.loop_body(&var, depth);
+                Stmt::While { var, count, body }
+            },
+            9 if nested => Stmt::Block(self.statements(depth + 1)),
+            10 => Stmt::Expr(self.expr(MAX_EXPR_DEPTH)),
+            _ => {
+                let target = self.assignable();
+                Stmt::Expr(Expr::Assign(target, Box::new(self.expr(MAX_EXPR_DEPTH))))
+            },
+        }
+    }
+
+    fn loop_body(&mut self, var: &str, depth: usize) -> Vec<Stmt> {
+        self.loop_vars.push(var.to_string());
+        let body = self.statements(depth + 1);
+        self.loop_vars.pop();
+        body
+    }
+
+    fn expr(&mut self, depth: usize) -> Expr {
+        if depth == 0 || self.rng.chance(25) {
+            return self.leaf();
+        }
+        match self.rng.below(10) {
+            0..=4 => {
+                let op = OPERATORS[self.rng.below(OPERATORS.len())];
+                let lhs = self.expr(depth - 1);
+                // 割る数はたいてい 0 以外の定数にして、トラップばかりにならないようにする
+                let rhs = if op == "/" && self.rng.chance(70) {
+                    let divisor = Expr::Number(Generator::number(self.rng, false).max(1));
+                    if self.rng.chance(30) { Expr::Negate(Box::new(divisor)) } else { divisor }
+                } else {
+                    self.expr(depth - 1)
+                };
+                Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+            },
+            5 => Expr::Negate(Box::new(self.expr(depth - 1))),
+            6..=7 => {
+                let target = self.assignable();
+                Expr::Assign(target, Box::new(self.expr(depth - 1)))
+            },
+            _ => self.call(depth).unwrap_or_else(|| self.leaf()),
+        }
+    }
+
+    fn call(&mut self, depth: usize) -> Option<Expr> {
+        // ループの中の呼び出しは実行回数が掛け算で増えるので、浅いところだけにする
+        if self.loop_vars.len() > 1 {
+            return None;
+        }
+        let (_, _, recursive) = &self.signatures[self.current];
+        let can_recurse = *recursive && self.loop_vars.is_empty() && self.self_calls < MAX_SELF_CALLS;
+        let first = if can_recurse { self.current } else { self.current + 1 };
+        if first >= self.signatures.len() {
+            return None;
+        }
+        let callee = first + self.rng.below(self.signatures.len() - first);
+        let (name, params, recursive) = &self.signatures[callee];
+        let depth_arg = if callee == self.current {
+            self.self_calls += 1;
+            Some(Depth::Decrement)
+        } else if *recursive {
+            Some(Depth::Const(self.rng.below(MAX_RECURSION as usize + 1) as i32))
+        } else {
+            None
+        };
+        let count = params.len() - depth_arg.iter().count();
+        let args = (0..count).map(|_| self.expr(depth - 1)).collect();
+        Some(Expr::Call { name: name.to_string(), depth: depth_arg, args })
+    }
+
+    fn leaf(&mut self) -> Expr {
+        let mut names = self.signatures[self.current].1.clone();
+        names.extend(LOCALS.iter().map(|name| name.to_string()));
+        names.extend(self.loop_vars.iter().cloned());
+        if self.rng.chance(50) {
+            let name = names[self.rng.below(names.len())].to_string();
+            if !self.read.contains(&name) && !self.loop_vars.contains(&name) {
+                self.read.push(name.to_string());
+            }
+            Expr::Variable(name)
+        } else {
+            Expr::Number(Generator::number(self.rng, false))
+        }
+    }
+
+    // 再帰の深さ n とループの変数には代入しない
+    fn assignable(&mut self) -> String {
+        let mut names: Vec<String> = LOCALS.iter().map(|name| name.to_string()).collect();
+        names.extend(self.signatures[self.current].1.iter().filter(|name| *name != "n").cloned());
+        let name = names[self.rng.below(names.len())].to_string();
+        if !self.assigned.contains(&name) {
+            self.assigned.push(name.to_string());
+        }
+        name
+    }
+
+    fn number(rng: &mut Rng, signed: bool) -> i32 {
+        let value = if rng.chance(80) { rng.below(11) as i32 } else { INTERESTING[rng.below(INTERESTING.len())] };
+        if signed && rng.chance(30) { -value } else { value }
+    }
+
+}
+
+impl Program {
+
+    // 1 か所だけ小さくしたプログラムの候補。どれも停止する条件を保つ
+    pub fn candidates(&self) -> Vec<Program> {
+        let mut candidates = vec![];
+        // main 以外の関数を、呼び出しを 0 に置き換えて取り除く
+        for i in 1..self.functions.len() {
+            let mut program = self.clone();
+            let removed = program.functions.remove(i);
+            for function in program.functions.iter_mut() {
+                for stmt in function.body.iter_mut() {
+                    remove_calls_in_stmt(stmt, &removed.name);
+                }
+                if let Some(base) = &mut function.base {
+                    remove_calls(base, &removed.name);
+                }
+            }
+            candidates.push(program);
+        }
+        for (i, function) in self.functions.iter().enumerate() {
+            for body in statements_variants(&function.body) {
+                let mut program = self.clone();
+                program.functions[i].body = body;
+                candidates.push(program);
+            }
+            if let Some(base) = &function.base {
+                for base in expr_variants(base) {
+                    let mut program = self.clone();
+                    program.functions[i].base = Some(base);
+                    candidates.push(program);
+                }
+            }
+        }
+        for (i, arg) in self.args.iter().enumerate() {
+            for value in number_variants(arg.abs()) {
+                let mut program = self.clone();
+                program.args[i] = value;
+                candidates.push(program);
+            }
+        }
+        candidates
+    }
+
+}
+
+fn remove_calls_in_stmt(stmt: &mut Stmt, name: &str) {
+    match stmt {
+        Stmt::Expr(expr) | Stmt::Return(expr) => remove_calls(expr, name),
+        Stmt::If(condition, then, otherwise) => {
+            remove_calls(condition, name);
+            then.iter_mut().chain(otherwise.iter_mut().flatten()).for_each(|stmt| remove_calls_in_stmt(stmt, name));
+        },
+        Stmt::For { body, .. } | Stmt::While { body, .. } | Stmt::Block(body) => {
+            body.iter_mut().for_each(|stmt| remove_calls_in_stmt(stmt, name));
+        },
+    }
+}
+
+fn remove_calls(expr: &mut Expr, name: &str) {
+    match expr {
+        Expr::Call { name: callee, .. } if callee == name => *expr = Expr::Number(0),
+        Expr::Call { args, .. } => args.iter_mut().for_each(|arg| remove_calls(arg, name)),
+        Expr::Negate(child) | Expr::Assign(_, child) => remove_calls(child, name),
+        Expr::Binary(_, lhs, rhs) => {
+            remove_calls(lhs, name);
+            remove_calls(rhs, name);
+        },
+        Expr::Number(_) | Expr::Variable(_) => {},
+    }
+}
+
+fn statements_variants(statements: &[Stmt]) -> Vec<Vec<Stmt>> {
+    let mut variants = vec![];
+    for i in 0..statements.len() {
+        let mut removed = statements.to_vec();
+        removed.remove(i);
+        variants.push(removed);
+        // ブロックは中身を外に出す
+        if let Stmt::Block(inner) = &statements[i] {
+            let mut spliced = statements[..i].to_vec();
+            spliced.extend(inner.iter().cloned());
+            spliced.extend(statements[i + 1..].iter().cloned());
+            variants.push(spliced);
+        }
+    }
+    for (i, stmt) in statements.iter().enumerate() {
+        for variant in stmt_variants(stmt) {
+            let mut replaced = statements.to_vec();
+            replaced[i] = variant;
+            variants.push(replaced);
+        }
+    }
+    variants
+}
+
+fn stmt_variants(stmt: &Stmt) -> Vec<Stmt> {
+    let mut variants = vec![];
+    match stmt {
+        Stmt::Expr(expr) => variants.extend(expr_variants(expr).into_iter().map(Stmt::Expr)),
+        Stmt::Return(expr) => {
+            variants.push(Stmt::Expr(expr.clone()));
+            variants.extend(expr_variants(expr).into_iter().map(Stmt::Return));
+        },
+        Stmt::If(condition, then, otherwise) => {
+            variants.push(Stmt::Block(then.clone()));
+            if let Some(otherwise) = otherwise {
+                variants.push(Stmt::Block(otherwise.clone()));
+                variants.push(Stmt::If(condition.clone(), then.clone(), None));
+                for otherwise in statements_variants(otherwise) {
+                    variants.push(Stmt::If(condition.clone(), then.clone(), Some(otherwise)));
+                }
+            }
+            for condition in expr_variants(condition) {
+                variants.push(Stmt::If(condition, then.clone(), otherwise.clone()));
+            }
+            for then in statements_variants(then) {
+                variants.push(Stmt::If(condition.clone(), then, otherwise.clone()));
+            }
+        },
+        Stmt::For { var, count, body } | Stmt::While { var, count, body } => {
+            let rebuild = |count: i32, body: Vec<Stmt>| match stmt {
+                Stmt::For { .. } => Stmt::For { var: var.to_string(), count, body },
+                _ => Stmt::While { var: var.to_string(), count, body },
+            };
+            variants.push(Stmt::Block(body.clone()));
+            if *count > 0 {
+                variants.push(rebuild(count - 1, body.clone()));
+            }
+            for body in statements_variants(body) {
+                variants.push(rebuild(*count, body));
+            }
+        },
+        Stmt::Block(body) => variants.extend(statements_variants(body).into_iter().map(Stmt::Block)),
+    }
+    variants
+}
+
+fn expr_variants(expr: &Expr) -> Vec<Expr> {
+    let mut variants = vec![];
+    match expr {
+        Expr::Number(value) => variants.extend(number_variants(*value).into_iter().map(Expr::Number)),
+        Expr::Variable(_) => variants.push(Expr::Number(0)),
+        Expr::Negate(child) => {
+            variants.push(Expr::Number(0));
+            variants.push(*child.clone());
+            variants.extend(expr_variants(child).into_iter().map(|child| Expr::Negate(Box::new(child))));
+        },
+        Expr::Binary(op, lhs, rhs) => {
+            variants.push(Expr::Number(0));
+            variants.push(*lhs.clone());
+            variants.push(*rhs.clone());
+            for lhs in expr_variants(lhs) {
+                variants.push(Expr::Binary(op, Box::new(lhs), rhs.clone()));
+            }
+            for rhs in expr_variants(rhs) {
+                variants.push(Expr::Binary(op, lhs.clone(), Box::new(rhs)));
+            }
+        },
+        Expr::Assign(name, child) => {
+            variants.push(*child.clone());
+            variants.extend(expr_variants(child).into_iter().map(|child| Expr::Assign(name.to_string(), Box::new(child))));
+        },
+        Expr::Call { name, depth, args } => {
+            variants.push(Expr::Number(0));
+            variants.extend(args.iter().cloned());
+            if let Some(Depth::Const(value)) = depth {
+                for value in number_variants(*value) {
+                    variants.push(Expr::Call { name: name.to_string(), depth: Some(Depth::Const(value)), args: args.clone() });
+                }
+            }
+            for (i, arg) in args.iter().enumerate() {
+                for arg in expr_variants(arg) {
+                    let mut args = args.clone();
+                    args[i] = arg;
+                    variants.push(Expr::Call { name: name.to_string(), depth: depth.clone(), args });
+                }
+            }
+        },
+    }
+    variants
+}
+
+// 絶対値が小さくなる方向の値
+fn number_variants(value: i32) -> Vec<i32> {
+    let mut variants = vec![];
+    for candidate in [0, 1, value / 2] {
+        if candidate.unsigned_abs() < value.unsigned_abs() && !variants.contains(&candidate) {
+            variants.push(candidate);
+        }
+    }
+    variants
+}
+
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for function in self.functions.iter() {
+            writeln!(f, "{}({}){{", function.name, function.params.join(","))?;
+            if let Some(base) = &function.base {
+                writeln!(f, "    if(n<=0){{return {};}}", base)?;
+            }
+            write_statements(f, &function.body, 1)?;
+            writeln!(f, "}}")?;
+        }
+        Ok(())
+    }
+}
+
+fn write_statements(f: &mut fmt::Formatter<'_>, statements: &[Stmt], depth: usize) -> fmt::Result {
+    let indent = "    ".repeat(depth);
+    for stmt in statements {
+        match stmt {
+            Stmt::Expr(Expr::Assign(name, child)) => writeln!(f, "{}{}={};", indent, name, child)?,
+            Stmt::Expr(expr) => writeln!(f, "{}{};", indent, expr)?,
+            Stmt::Return(expr) => writeln!(f, "{}return {};", indent, expr)?,
+            Stmt::If(condition, then, otherwise) => {
+                writeln!(f, "{}if({}){{", indent, condition)?;
+                write_statements(f, then, depth + 1)?;
+                if let Some(otherwise) = otherwise {
+                    writeln!(f, "{}}}else{{", indent)?;
+                    write_statements(f, otherwise, depth + 1)?;
+                }
+                writeln!(f, "{}}}", indent)?;
+            },
+            Stmt::For { var, count, body } => {
+                writeln!(f, "{}for({}=0;{}<{};{}={}+1){{", indent, var, var, count, var, var)?;
+                write_statements(f, body, depth + 1)?;
+                writeln!(f, "{}}}", indent)?;
+            },
+            Stmt::While { var, count, body } => {
+                writeln!(f, "{}{}=0;", indent, var)?;
+                writeln!(f, "{}while({}<{}){{", indent, var, count)?;
+                write_statements(f, body, depth + 1)?;
+                writeln!(f, "{}    {}={}+1;", indent, var, var)?;
+                writeln!(f, "{}}}", indent)?;
+            },
+            Stmt::Block(body) => {
+                writeln!(f, "{}{{", indent)?;
+                write_statements(f, body, depth + 1)?;
+                writeln!(f, "{}}}", indent)?;
+            },
+        }
+    }
+    Ok(())
+}
+
+// 優先順位を考えなくてよいように、演算はすべて括弧で囲む
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::Variable(name) => write!(f, "{}", name),
+            Expr::Negate(child) => write!(f, "-({})", child),
+            Expr::Binary(op, lhs, rhs) => write!(f, "({}{}{})", lhs, op, rhs),
+            Expr::Assign(name, child) => write!(f, "({}={})", name, child),
+            Expr::Call { name, depth, args } => {
+                let mut values: Vec<String> = match depth {
+                    Some(Depth::Const(value)) => vec![value.to_string()],
+                    Some(Depth::Decrement) => vec!["(n-1)".to_string()],
+                    None => vec![],
+                };
+                values.extend(args.iter().map(|arg| arg.to_string()));
+                write!(f, "{}({})", name, values.join(","))
+            },
+        }
+    }
+}
@@ -0,0 +1,574 @@
+use std::cell::RefCell;
+use std::fmt;
+use crate::ir::{DecodeError, ExportKind, Function, Instr, MemOp, Module, NumOp};
+
+// 再帰しすぎたときに StackExhausted にする上限
+pub const MAX_CALL_DEPTH: usize = 10000;
+const MAX_STACK_SIZE: usize = 1 << 20;
+const PAGE_SIZE: usize = 65536;
+
+// import した関数の代わりに呼ぶ Rust の関数。メモリと引数を受け取って結果を返す
+pub type HostFunction = Box<dyn Fn(&mut [u8], &[i32]) -> Result<Vec<i32>, Trap>>;
+
+// 実行を中断した理由
+#[derive(Clone, PartialEq, Debug)]
+pub enum Trap {
+    DivisionByZero,
+    // i32::MIN / -1
+    IntegerOverflow,
+    StackExhausted,
+    UndefinedExport(String),
+    ArgumentCount { expected: usize, actual: usize },
+    // 外から与える関数が link されていない。名前は module.field
+    UnresolvedImport(String),
+    MemoryOutOfBounds,
+    // 外から与えた関数がプログラムを終了させた (WASI の proc_exit など)
+    Exit(i32),
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trap::DivisionByZero => write!(f, "integer divide by zero"),
+            Trap::IntegerOverflow => write!(f, "integer overflow"),
+            Trap::StackExhausted => write!(f, "call stack exhausted"),
+            Trap::UndefinedExport(name) => write!(f, "export `{}` not found", name),
+            Trap::ArgumentCount { expected, actual } => write!(f, "expected {} arguments, got {}", expected, actual),
+            Trap::UnresolvedImport(name) => write!(f, "import `{}` is not available", name),
+            Trap::MemoryOutOfBounds => write!(f, "out of bounds memory access"),
+            Trap::Exit(code) => write!(f, "exit with code {}", code),
+        }
+    }
+}
+
+// ブロックを展開し、分岐先を命令の番号に解決したもの
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    // 値を arity 個残して、スタックを height まで戻してから target へ飛ぶ
+    Br { target: usize, arity: usize, height: usize },
+    BrIf { target: usize, arity: usize, height: usize },
+    // 条件が 0 なら target へ飛ぶ
+    IfFalse(usize),
+    Return,
+    Call(usize),
+    ReturnCall(usize),
+    Drop,
+    LocalGet(usize),
+    LocalSet(usize),
+    LocalTee(usize),
+    I32Const(i32),
+    Numeric(NumOp),
+    Memory(MemOp, u32),
+}
+
+struct Code {
+    params: usize,
+    // パラメータ以外のローカル変数の数
+    locals: usize,
+    results: usize,
+    ops: Vec<Op>,
+    // import した関数なら module.field と、link されていればその実装
+    import: Option<String>,
+    host: Option<HostFunction>,
+}
+
+// write_wasm の出力を読み込んで、エクスポートされた関数を呼べるようにしたもの
+pub struct Instance {
+    exports: Vec<(String, usize)>,
+    codes: Vec<Code>,
+    // 呼び出しをまたいで内容を保つ
+    memory: RefCell<Vec<u8>>,
+}
+
+impl Instance {
+
+    pub fn new(bytes: &[u8]) -> Result<Instance, DecodeError> {
+        Ok(Instance::from_module(&Module::read_wasm(bytes)?))
+    }
+
+    pub fn from_module(module: &Module) -> Instance {
+        let exports = module.exports.iter().filter_map(|export| match &export.kind {
+            ExportKind::Function(name) => Some((export.name.to_string(), function_index(module, name))),
+            _ => None,
+        }).collect();
+        // 関数の番号と揃えるため、import した関数も並べる
+        let imports = module.imports.iter().map(|import| Code {
+            params: import.params.len(),
+            locals: 0,
+            results: import.results.len(),
+            ops: vec![],
+            import: Some(format!("{}.{}", import.module, import.field)),
+            host: None,
+        });
+        let codes = imports.chain(module.functions.iter().map(|function| Compiler::compile(module, function))).collect();
+        let mut memory = vec![0; module.memory.as_ref().map(|memory| memory.min as usize * PAGE_SIZE).unwrap_or(0)];
+        for data in module.data.iter() {
+            let offset = data.offset as u32 as usize;
+            match memory.get_mut(offset..offset + data.bytes.len()) {
+                Some(range) => range.copy_from_slice(&data.bytes),
+                None => panic!("data segment at {} does not fit in memory", offset),
+            }
+        }
+        Instance { exports, codes, memory: RefCell::new(memory) }
+    }
+
+    // module.field の import を Rust の関数で与える。該当する import がなければ false
+    pub fn link(&mut self, module: &str, field: &str, function: HostFunction) -> bool {
+        let name = format!("{}.{}", module, field);
+        match self.codes.iter_mut().find(|code| code.import.as_ref() == Some(&name)) {
+            Some(code) => {
+                code.host = Some(function);
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn memory(&self) -> std::cell::Ref<'_, Vec<u8>> {
+        self.memory.borrow()
+    }
+
+    pub fn invoke(&self, name: &str, args: &[i32]) -> Result<Vec<i32>, Trap> {
+        let index = match self.exports.iter().find(|(export, _)| export == name) {
+            Some((_, index)) => *index,
+            None => return Err(Trap::UndefinedExport(name.to_string())),
+        };
+        let expected = self.codes[index].params;
+        if args.len() != expected {
+            return Err(Trap::ArgumentCount { expected, actual: args.len() });
+        }
+        let memory = &mut self.memory.borrow_mut();
+        Machine { codes: &self.codes, memory, frames: vec![], stack: args.to_vec(), locals: vec![] }.run(index)
+    }
+
+    // パラメータの数
+    pub fn params(&self, name: &str) -> Option<usize> {
+        self.exports.iter().find(|(export, _)| export == name).map(|(_, index)| self.codes[*index].params)
+    }
+
+}
+
+fn function_index(module: &Module, name: &str) -> usize {
+    match module.function_index(name) {
+        Some(index) => index,
+        None => panic!("function `{}` not found", name)
+    }
+}
+
+struct Label {
+    name: Option<String>,
+    // ブロックに入ったときのスタックの高さ (関数の中での相対)
+    height: usize,
+    // 分岐したときに残す値の数。loop は先頭へ戻るので 0
+    arity: usize,
+    // ブロックの終わりの高さに積まれている値の数
+    results: usize,
+    // loop なら先頭の番号。block と if は終わりが決まってから patches を埋める
+    start: Option<usize>,
+    patches: Vec<usize>,
+}
+
+struct Compiler<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    ops: Vec<Op>,
+    labels: Vec<Label>,
+    // 今の命令の前でのスタックの高さ。無条件分岐の後ろでは意味を持たないが、ブロックの終わりで戻す
+    height: usize,
+}
+
+impl <'a> Compiler<'a> {
+
+    fn compile(module: &'a Module, function: &'a Function) -> Code {
+        let mut compiler = Compiler { module, function, ops: vec![], labels: vec![], height: 0 };
+        compiler.instructions(&function.body);
+        Code {
+            params: function.params.len(),
+            locals: function.locals.len(),
+            results: function.results.len(),
+            ops: compiler.ops,
+            import: None,
+            host: None,
+        }
+    }
+
+    fn instructions(&mut self, instructions: &[Instr]) {
+        for instruction in instructions {
+            self.instruction(instruction);
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Instr) {
+        match instruction {
+            Instr::Block { label, result, body } => {
+                let results = result.iter().count();
+                self.push_label(Some(label), results, results, None);
+                self.instructions(body);
+                self.pop_label();
+            },
+            Instr::Loop { label, result, body } => {
+                let start = self.ops.len();
+                self.push_label(Some(label), 0, result.iter().count(), Some(start));
+                self.instructions(body);
+                self.pop_label();
+            },
+            Instr::If { label, result, then, otherwise } => {
+                self.pop_height(1);
+                let results = result.iter().count();
+                let branch = self.ops.len();
+                self.ops.push(Op::IfFalse(0));
+                self.push_label(label.as_ref(), results, results, None);
+                self.instructions(then);
+                if !otherwise.is_empty() {
+                    // then の終わりからは end へ飛ぶ
+                    let height = self.labels.last().unwrap().height;
+                    let jump = self.ops.len();
+                    self.ops.push(Op::Br { target: 0, arity: results, height });
+                    self.labels.last_mut().unwrap().patches.push(jump);
+                    self.height = height;
+                }
+                self.ops[branch] = Op::IfFalse(self.ops.len());
+                self.instructions(otherwise);
+                self.pop_label();
+            },
+            Instr::Br(name) => {
+                let (target, arity, height) = self.branch(name);
+                self.ops.push(Op::Br { target, arity, height });
+            },
+            Instr::BrIf(name) => {
+                self.pop_height(1);
+                let (target, arity, height) = self.branch(name);
+                self.ops.push(Op::BrIf { target, arity, height });
+            },
+            Instr::Return => self.ops.push(Op::Return),
+            Instr::Call(name) => {
+                let index = function_index(self.module, name);
+                let (params, results) = self.module.signature(name).unwrap();
+                self.pop_height(params.len());
+                self.height += results.len();
+                self.ops.push(Op::Call(index));
+            },
+            Instr::ReturnCall(name) => self.ops.push(Op::ReturnCall(function_index(self.module, name))),
+            Instr::Drop => {
+                self.pop_height(1);
+                self.ops.push(Op::Drop);
+            },
+            Instr::LocalGet(name) => {
+                self.height += 1;
+                self.ops.push(Op::LocalGet(self.local_index(name)));
+            },
+            Instr::LocalSet(name) => {
+                self.pop_height(1);
+                self.ops.push(Op::LocalSet(self.local_index(name)));
+            },
+            Instr::LocalTee(name) => self.ops.push(Op::LocalTee(self.local_index(name))),
+            Instr::I32Const(value) | Instr::Address(value) => {
+                self.height += 1;
+                self.ops.push(Op::I32Const(*value));
+            },
+            Instr::Numeric(op) => {
+                if *op != NumOp::I32Eqz {
+                    self.pop_height(1);
+                }
+                self.ops.push(Op::Numeric(*op));
+            },
+            Instr::Memory { op, offset } => {
+                self.pop_height(if op.is_store() { 2 } else { 0 });
+                self.ops.push(Op::Memory(*op, *offset));
+            },
+        }
+    }
+
+    // 無条件分岐の後ろは到達しないので、高さが足りなくてもよい
+    fn pop_height(&mut self, count: usize) {
+        self.height = self.height.saturating_sub(count);
+    }
+
+    fn push_label(&mut self, name: Option<&String>, arity: usize, results: usize, start: Option<usize>) {
+        self.labels.push(Label { name: name.cloned(), height: self.height, arity, results, start, patches: vec![] });
+    }
+
+    fn pop_label(&mut self) {
+        let label = self.labels.pop().unwrap();
+        let end = self.ops.len();
+        for patch in label.patches {
+            if let Op::Br { target, .. } | Op::BrIf { target, .. } = &mut self.ops[patch] {
+                *target = end;
+            }
+        }
+        self.height = label.height + label.results;
+    }
+
+    fn branch(&mut self, name: &str) -> (usize, usize, usize) {
+        let patch = self.ops.len();
+        let label = match self.labels.iter_mut().rev().find(|label| label.name.as_deref() == Some(name)) {
+            Some(label) => label,
+            None => panic!("label {} is not defined", name)
+        };
+        let target = match label.start {
+            Some(start) => start,
+            None => {
+                label.patches.push(patch);
+                0
+            }
+        };
+        (target, label.arity, label.height)
+    }
+
+    fn local_index(&self, name: &str) -> usize {
+        match self.function.local_index(name) {
+            Some(index) => index,
+            None => panic!("variable {} is not defined", name)
+        }
+    }
+
+}
+
+struct Frame {
+    function: usize,
+    pc: usize,
+    // この関数のローカル変数とスタックが始まる位置
+    locals: usize,
+    stack: usize,
+}
+
+// 関数呼び出しも Rust の再帰を使わずにフレームのスタックで回す
+struct Machine<'a> {
+    codes: &'a [Code],
+    memory: &'a mut Vec<u8>,
+    frames: Vec<Frame>,
+    stack: Vec<i32>,
+    locals: Vec<i32>,
+}
+
+impl <'a> Machine<'a> {
+
+    fn run(&mut self, index: usize) -> Result<Vec<i32>, Trap> {
+        self.call(index)?;
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let code = &self.codes[frame.function];
+            // 関数の終わりは return と同じ
+            let op = match code.ops.get(frame.pc) {
+                Some(op) => *op,
+                None => Op::Return,
+            };
+            frame.pc += 1;
+            match op {
+                Op::Br { target, arity, height } => self.branch(target, arity, height),
+                Op::BrIf { target, arity, height } => {
+                    if self.pop() != 0 {
+                        self.branch(target, arity, height);
+                    }
+                },
+                Op::IfFalse(target) => {
+                    if self.pop() == 0 {
+                        self.frames.last_mut().unwrap().pc = target;
+                    }
+                },
+                Op::Return => {
+                    let results = self.unwind();
+                    if self.frames.is_empty() {
+                        return Ok(results);
+                    }
+                    self.stack.extend(results);
+                },
+                Op::Call(index) => self.call(index)?,
+                Op::ReturnCall(index) => {
+                    // 引数だけを残して今のフレームを捨ててから呼ぶ
+                    let args = self.stack.split_off(self.stack.len() - self.codes[index].params);
+                    self.pop_frame();
+                    self.stack.extend(args);
+                    self.call(index)?;
+                },
+                Op::Drop => {
+                    self.pop();
+                },
+                Op::LocalGet(index) => {
+                    let value = self.locals[self.frames.last().unwrap().locals + index];
+                    self.stack.push(value);
+                },
+                Op::LocalSet(index) => {
+                    let value = self.pop();
+                    let base = self.frames.last().unwrap().locals;
+                    self.locals[base + index] = value;
+                },
+                Op::LocalTee(index) => {
+                    let value = *self.stack.last().unwrap();
+                    let base = self.frames.last().unwrap().locals;
+                    self.locals[base + index] = value;
+                },
+                Op::I32Const(value) => self.stack.push(value),
+                Op::Numeric(op) => self.numeric(op)?,
+                Op::Memory(op, offset) => self.memory(op, offset)?,
+            }
+        }
+    }
+
+    fn pop(&mut self) -> i32 {
+        self.stack.pop().expect("operand stack underflow")
+    }
+
+    // スタックに積まれた引数をローカル変数に移して、新しいフレームを始める
+    fn call(&mut self, index: usize) -> Result<(), Trap> {
+        let code = &self.codes[index];
+        if let Some(import) = &code.import {
+            let host = code.host.as_ref().ok_or_else(|| Trap::UnresolvedImport(import.to_string()))?;
+            let args = self.stack.split_off(self.stack.len() - code.params);
+            let results = host(self.memory, &args)?;
+            self.stack.extend(results);
+            return Ok(());
+        }
+        if self.frames.len() >= MAX_CALL_DEPTH || self.stack.len() + self.locals.len() + code.locals >= MAX_STACK_SIZE {
+            return Err(Trap::StackExhausted);
+        }
+        let locals = self.locals.len();
+        self.locals.extend(self.stack.drain(self.stack.len() - code.params..));
+        self.locals.resize(self.locals.len() + code.locals, 0);
+        self.frames.push(Frame { function: index, pc: 0, locals, stack: self.stack.len() });
+        Ok(())
+    }
+
+    // 今のフレームを捨てて、戻り値を返す
+    fn unwind(&mut self) -> Vec<i32> {
+        let function = self.frames.last().unwrap().function;
+        let results = self.stack.split_off(self.stack.len() - self.codes[function].results);
+        self.pop_frame();
+        results
+    }
+
+    fn pop_frame(&mut self) {
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.stack);
+        self.locals.truncate(frame.locals);
+    }
+
+    fn branch(&mut self, target: usize, arity: usize, height: usize) {
+        let frame = self.frames.last_mut().unwrap();
+        let values = self.stack.split_off(self.stack.len() - arity);
+        self.stack.truncate(frame.stack + height);
+        self.stack.extend(values);
+        frame.pc = target;
+    }
+
+    fn numeric(&mut self, op: NumOp) -> Result<(), Trap> {
+        if op == NumOp::I32Eqz {
+            let value = self.pop();
+            self.stack.push((value == 0) as i32);
+            return Ok(());
+        }
+        let rhs = self.pop();
+        let lhs = self.pop();
+        let value = match op {
+            NumOp::I32Eqz => unreachable!(),
+            NumOp::I32Eq => (lhs == rhs) as i32,
+            NumOp::I32Ne => (lhs != rhs) as i32,
+            NumOp::I32LtS => (lhs < rhs) as i32,
+            NumOp::I32GtS => (lhs > rhs) as i32,
+            NumOp::I32LeS => (lhs <= rhs) as i32,
+            NumOp::I32GeS => (lhs >= rhs) as i32,
+            NumOp::I32Add => lhs.wrapping_add(rhs),
+            NumOp::I32Sub => lhs.wrapping_sub(rhs),
+            NumOp::I32Mul => lhs.wrapping_mul(rhs),
+            NumOp::I32DivS => match rhs {
+                0 => return Err(Trap::DivisionByZero),
+                -1 if lhs == i32::MIN => return Err(Trap::IntegerOverflow),
+                _ => lhs / rhs,
+            },
+            // シフト量は下位 5 bit だけを使う
+            NumOp::I32Shl => lhs.wrapping_shl(rhs as u32),
+            NumOp::I32ShrS => lhs.wrapping_shr(rhs as u32),
+            NumOp::I32ShrU => ((lhs as u32).wrapping_shr(rhs as u32)) as i32,
+        };
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn memory(&mut self, op: MemOp, offset: u32) -> Result<(), Trap> {
+        let value = if op.is_store() { Some(self.pop()) } else { None };
+        // アドレスは符号なしで、offset を足しても桁あふれしない
+        let start = self.pop() as u32 as usize + offset as usize;
+        let bytes = self.memory.get_mut(start..start + op.size() as usize).ok_or(Trap::MemoryOutOfBounds)?;
+        match (op, value) {
+            (MemOp::I32Load, _) => self.stack.push(i32::from_le_bytes(bytes.try_into().unwrap())),
+            (MemOp::I32Load8U, _) => self.stack.push(bytes[0] as i32),
+            (MemOp::I32Store, Some(value)) => bytes.copy_from_slice(&value.to_le_bytes()),
+            (MemOp::I32Store8, Some(value)) => bytes[0] = value as u8,
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+}
+
+#[cfg(test)]
+fn run(source: &str, opt_level: crate::optimizer::OptLevel, args: &[i32]) -> Result<Vec<i32>, Trap> {
+    let mut module = crate::wasmc::parse(source);
+    crate::optimizer::PassManager::new(opt_level).run(&mut module);
+    let mut bytes = vec![];
+    module.lower().write_wasm(&mut bytes).unwrap();
+    Instance::new(&bytes).unwrap().invoke("main", args)
+}
+
+#[cfg(test)]
+use crate::optimizer::OptLevel;
+
+#[test]
+fn test_invoke() {
+    let fib = "fib(n){if(n<2)return n;return fib(n-1)+fib(n-2);}main(){a=0;for(i=0;i<10;i=i+1)a=a+fib(i);return a;}";
+    for level in [OptLevel::O0, OptLevel::O3] {
+        assert_eq!(run("main(){return 5+20-4;}", level, &[]), Ok(vec![21]));
+        assert_eq!(run(fib, level, &[]), Ok(vec![88]));
+        assert_eq!(run("main(){a=1;while(a<100)a=a*3;if(a==243)return -1;else return 1;}", level, &[]), Ok(vec![-1]));
+        assert_eq!(run("main(){return 2147483647+1;}", level, &[]), Ok(vec![i32::MIN]));
+        assert_eq!(run("main(){a=-7;return a/2;}", level, &[]), Ok(vec![-3]));
+        assert_eq!(run("main(){a=-7;return a*8+a/4;}", level, &[]), Ok(vec![-57]));
+    }
+    assert_eq!(run("main(a,b){return a-b;}", OptLevel::O0, &[3, 10]), Ok(vec![-7]));
+    assert_eq!(run("main(a){return a;}", OptLevel::O0, &[]), Err(Trap::ArgumentCount { expected: 1, actual: 0 }));
+}
+
+#[test]
+fn test_tail_call() {
+    // 末尾呼び出しはフレームを積まないので深く再帰できる
+    let mut module = crate::wasmc::parse("sum(n,a){if(n==0)return a;return sum(n-1,a+n);}main(){return sum(100000,0);}");
+    for function in module.functions_mut() {
+        function.return_call = true;
+    }
+    let module = module.lower();
+    assert!(module.functions[0].body.iter().any(|instruction| matches!(instruction, Instr::ReturnCall(_))));
+    assert_eq!(Instance::from_module(&module).invoke("main", &[]), Ok(vec![705082704]));
+}
+
+#[test]
+fn test_trap() {
+    assert_eq!(run("main(){a=0;return 1/a;}", OptLevel::O0, &[]), Err(Trap::DivisionByZero));
+    assert_eq!(run("main(){a=0-2147483647-1;return a/-1;}", OptLevel::O0, &[]), Err(Trap::IntegerOverflow));
+    assert_eq!(run("f(n){return f(n+1)+1;}main(){return f(0);}", OptLevel::O0, &[]), Err(Trap::StackExhausted));
+    assert_eq!(Trap::DivisionByZero.to_string(), "integer divide by zero");
+}
+
+#[test]
+fn test_memory() {
+    let module = Module::read_wat(r#"(module (memory 1) (data (i32.const 8) "\05")
+      (func $f (param $a i32) (result i32)
+        (i32.store8 offset=1 (local.get $a) (i32.const 300))
+        (i32.store (i32.const 12) (i32.load8_u (i32.const 8)))
+        (i32.add (i32.load (i32.const 12)) (i32.load8_u offset=1 (local.get $a))))
+      (export "f" (func $f)))"#).unwrap();
+    let instance = Instance::from_module(&module);
+    assert_eq!(instance.invoke("f", &[100]), Ok(vec![5 + 44]));
+    assert_eq!(instance.memory()[101], 44);
+    assert_eq!(instance.invoke("f", &[65535]), Err(Trap::MemoryOutOfBounds));
+    assert_eq!(instance.invoke("f", &[-1]), Err(Trap::MemoryOutOfBounds));
+}
+
+#[test]
+fn test_import() {
+    let module = crate::wasmc::parse("main(a){return twice(a)+1;}").lower();
+    let mut instance = Instance::from_module(&module);
+    assert_eq!(instance.invoke("main", &[3]), Err(Trap::UnresolvedImport("env.twice".to_string())));
+    assert!(instance.link("env", "twice", Box::new(|_, args| Ok(vec![args[0] * 2]))));
+    assert!(!instance.link("env", "thrice", Box::new(|_, args| Ok(vec![args[0] * 3]))));
+    assert_eq!(instance.invoke("main", &[3]), Ok(vec![7]));
+}
@@ -0,0 +1,327 @@
+mod asm;
+mod c99;
+mod context;
+mod decode;
+mod instr;
+mod js;
+mod leb128;
+mod link;
+mod object;
+mod peephole;
+mod rust;
+mod validate;
+mod wat;
+mod wasm;
+mod x86_64;
+
+use std::io::{Write, Result};
+pub use asm::AsmError;
+pub use context::{Context, Label};
+pub use decode::DecodeError;
+pub use instr::{Instr, instruction_count, MemOp, NumOp};
+pub use link::LinkError;
+pub use peephole::{optimize, use_return_call};
+pub use validate::{validate, ValidationError};
+
+// AST を一度だけ変換して作る中間表現。WAT もバイナリもこれをそのまま書き出す
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ValType {
+    I32
+}
+
+impl ValType {
+
+    pub fn code(&self) -> u8 {
+        match self {
+            ValType::I32 => 0x7f
+        }
+    }
+
+    pub fn wat_name(&self) -> &'static str {
+        match self {
+            ValType::I32 => "i32"
+        }
+    }
+
+    // コマンドラインなどで与えられた値を読む
+    pub fn parse_value(&self, text: &str) -> Option<i32> {
+        match self {
+            ValType::I32 => text.parse().ok()
+        }
+    }
+
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Local {
+    pub name: String,
+    pub vtype: ValType,
+}
+
+impl Local {
+    pub fn new(name: &str, vtype: ValType) -> Self {
+        Self { name: name.to_string(), vtype }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Function {
+    pub name: String,
+    pub params: Vec<Local>,
+    pub results: Vec<ValType>,
+    // パラメータ以外のローカル変数
+    pub locals: Vec<Local>,
+    pub body: Vec<Instr>,
+}
+
+impl Function {
+
+    // パラメータから通しで数えたローカル変数の番号
+    pub fn local_index(&self, name: &str) -> Option<usize> {
+        self.params.iter().chain(self.locals.iter()).position(|local| local.name == name)
+    }
+
+}
+
+// 外から取り込む関数。呼び出しでは定義した関数と同じく name で指し、番号は定義した関数より前になる
+#[derive(Clone, PartialEq, Debug)]
+pub struct Import {
+    pub module: String,
+    pub field: String,
+    pub name: String,
+    pub params: Vec<ValType>,
+    pub results: Vec<ValType>,
+}
+
+impl Import {
+
+    // env から import した print_int と print_str は、ホストが渡さなくても wasmc run やネイティブの出力が
+    // 自分で用意する。print_str は文字列を読むメモリがあるときだけ
+    pub fn is_builtin(&self, module: &Module) -> bool {
+        let builtin = match self.field.as_str() {
+            "print_int" => true,
+            "print_str" => module.memory.is_some(),
+            _ => false,
+        };
+        builtin && self.module == "env" && self.params.len() == 1 && self.results.len() == 1
+    }
+
+}
+
+// 大きさは 64KiB のページ数
+#[derive(Clone, PartialEq, Debug)]
+pub struct Memory {
+    pub min: u32,
+    pub max: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Global {
+    pub name: String,
+    pub vtype: ValType,
+    pub mutable: bool,
+    pub init: i32,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ExportKind {
+    Function(String),
+    // メモリは 1 つしか持たない
+    Memory,
+    Global(String),
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct Export {
+    pub name: String,
+    pub kind: ExportKind,
+}
+
+impl Export {
+    pub fn function(name: &str, function: &str) -> Self {
+        Self { name: name.to_string(), kind: ExportKind::Function(function.to_string()) }
+    }
+}
+
+// メモリの offset から置く初期値
+#[derive(Clone, PartialEq, Debug)]
+pub struct Data {
+    pub offset: i32,
+    pub bytes: Vec<u8>,
+}
+
+// name 以外の custom section。モジュールの最後に書き出す
+#[derive(Clone, PartialEq, Debug)]
+pub struct Custom {
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Module {
+    pub imports: Vec<Import>,
+    pub functions: Vec<Function>,
+    pub memory: Option<Memory>,
+    pub globals: Vec<Global>,
+    pub exports: Vec<Export>,
+    pub data: Vec<Data>,
+    pub customs: Vec<Custom>,
+    // 分割コンパイルしたもので、ほかのオブジェクトに定義があるはずの import の名前。
+    // それ以外の import はホストが渡すもので、リンクしても import のまま残す
+    pub unresolved: Vec<String>,
+}
+
+impl Module {
+
+    // import した関数から通しで数えた関数の番号
+    pub fn function_index(&self, name: &str) -> Option<usize> {
+        match self.imports.iter().position(|import| import.name == name) {
+            Some(index) => Some(index),
+            None => self.functions.iter().position(|function| function.name == name).map(|index| self.imports.len() + index),
+        }
+    }
+
+    // 定義した関数
+    pub fn function(&self, name: &str) -> Option<&Function> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    // import した関数も含めた、パラメータと結果の型
+    pub fn signature(&self, name: &str) -> Option<(Vec<ValType>, Vec<ValType>)> {
+        match self.imports.iter().find(|import| import.name == name) {
+            Some(import) => Some((import.params.clone(), import.results.clone())),
+            None => self.function(name).map(|function| {
+                (function.params.iter().map(|param| param.vtype).collect(), function.results.clone())
+            }),
+        }
+    }
+
+    pub fn global_index(&self, name: &str) -> Option<usize> {
+        self.globals.iter().position(|global| global.name == name)
+    }
+
+    pub fn write_wat(&self, write: &mut dyn Write) -> Result<()> {
+        wat::write_module(self, write)
+    }
+
+    pub fn write_wasm(&self, write: &mut dyn Write) -> Result<()> {
+        wasm::write_module(self, write)
+    }
+
+    pub fn write_x86_64(&self, write: &mut dyn Write) -> Result<()> {
+        x86_64::write_module(self, write)
+    }
+
+    pub fn write_c99(&self, write: &mut dyn Write) -> Result<()> {
+        c99::write_module(self, write)
+    }
+
+    // wasm を読み込む ES モジュール。wasm はそのファイルからの相対パスで指す
+    pub fn write_js(&self, wasm: &str, write: &mut dyn Write) -> Result<()> {
+        js::write_module(self, wasm, write)
+    }
+
+    // 組み込むホストのための C ヘッダ。name はインクルードガードに使う
+    pub fn write_header(&self, name: &str, write: &mut dyn Write) -> Result<()> {
+        c99::write_header(self, name, write)
+    }
+
+    // 組み込むホストのための Rust のモジュール
+    pub fn write_rust_bindings(&self, wasm: &str, write: &mut dyn Write) -> Result<()> {
+        rust::write_module(self, wasm, write)
+    }
+
+    // write_js で書いたモジュールの .d.ts
+    pub fn write_dts(&self, write: &mut dyn Write) -> Result<()> {
+        js::write_declarations(self, write)
+    }
+
+    // 再配置の情報を付けた、wasmc link に渡すオブジェクト
+    pub fn write_object(&self, write: &mut dyn Write) -> Result<()> {
+        object::write_module(self, write)
+    }
+
+    // write_object で書いたオブジェクトを (名前, バイナリ) で渡してリンクする
+    pub fn link(objects: &[(&str, &[u8])], allow_undefined: bool) -> std::result::Result<Module, Vec<LinkError>> {
+        link::link(objects, allow_undefined)
+    }
+
+    // write_wasm で書き出したバイナリを読み戻す
+    pub fn read_wasm(bytes: &[u8]) -> std::result::Result<Module, DecodeError> {
+        decode::read_module(bytes)
+    }
+
+    // WAT のテキストを読む
+    pub fn read_wat(text: &str) -> std::result::Result<Module, AsmError> {
+        asm::read_module(text)
+    }
+
+}
+
+#[cfg(test)]
+fn test_module() -> Module {
+    use Instr::*;
+    Module {
+        functions: vec![Function {
+            name: "main".to_string(),
+            params: vec![Local::new("n", ValType::I32)],
+            results: vec![ValType::I32],
+            locals: vec![Local::new("i", ValType::I32)],
+            body: vec![
+                Block { label: "b".to_string(), result: None, body: vec![
+                    Loop { label: "l".to_string(), result: None, body: vec![
+                        LocalGet("i".to_string()), LocalGet("n".to_string()), Numeric(NumOp::I32GeS), BrIf("b".to_string()),
+                        If { label: None, result: None, then: vec![Br("l".to_string())], otherwise: vec![] },
+                    ]},
+                ]},
+                LocalGet("i".to_string()),
+            ],
+        }],
+        exports: vec![Export::function("main", "main")],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_write_wat() {
+    let mut buf = vec![];
+    test_module().write_wat(&mut buf).unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap(), "\
+(module
+  (func $main
+    (param $n i32)
+    (result i32)
+    (local $i i32)
+    block $b
+      loop $l
+        local.get $i
+        local.get $n
+        i32.ge_s
+        br_if $b
+        if
+          br $l
+        end
+      end
+    end
+    local.get $i
+  )
+  (export \"main\" (func $main))
+)
+");
+}
+
+#[test]
+fn test_write_wasm() {
+    let mut buf = vec![];
+    test_module().write_wasm(&mut buf).unwrap();
+    // 関数本体の後ろには name section が続く
+    let code = [
+        0x01, 0x01, 0x7f, // local i32
+        0x02, 0x40, 0x03, 0x40, // block; loop
+        0x20, 0x01, 0x20, 0x00, 0x4e, 0x0d, 0x01, // i >= n; br_if 1
+        0x04, 0x40, 0x0c, 0x01, 0x0b, // if; br 1; end
+        0x0b, 0x0b, 0x20, 0x01, 0x0b, // end; end; local.get 1; end
+    ];
+    assert!(buf.windows(code.len()).any(|window| window == code));
+}
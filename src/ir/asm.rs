@@ -0,0 +1,927 @@
+use std::collections::HashSet;
+use std::fmt;
+use crate::ir::{Custom, Data, Export, ExportKind, Function, Global, Import, Instr, Local, MemOp, Memory, Module, NumOp, ValType};
+
+// WAT を読めなかった行と理由
+#[derive(Clone, PartialEq, Debug)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+type Result<T> = std::result::Result<T, AsmError>;
+
+fn error<T>(line: usize, message: &str) -> Result<T> {
+    Err(AsmError { line, message: message.to_string() })
+}
+
+// WAT のテキストを中間表現にする。平らな形式と折り畳んだ形式のどちらも読み、番号での参照は名前に直す。
+// 名前のないものには read_wasm と同じく func{i}、local{i}、global{i}、label{i} と付ける
+pub fn read_module(text: &str) -> Result<Module> {
+    let mut tokenizer = Tokenizer { chars: text.chars().collect(), pos: 0, line: 1 };
+    let items = tokenizer.items()?;
+    if tokenizer.pos < tokenizer.chars.len() {
+        return error(tokenizer.line, "unexpected `)`");
+    }
+    let fields = match items.as_slice() {
+        [SExpr::List { items, .. }] if items.first().and_then(SExpr::atom) == Some("module") => {
+            let start = if items.get(1).and_then(SExpr::id).is_some() { 2 } else { 1 };
+            &items[start..]
+        },
+        _ => &items[..],
+    };
+
+    // 関数とグローバル変数は後ろから参照されることがあるので、先に宣言だけを集める
+    let mut asm = Assembler { module: Module::default(), types: vec![], functions: vec![] };
+    let mut definitions = vec![];
+    for field in fields {
+        let list = match field {
+            SExpr::List { items, .. } => items,
+            _ => return error(field.line(), "expected a module field"),
+        };
+        match field.keyword() {
+            Some("type") => asm.type_definition(field.line(), &list[1..])?,
+            Some("import") => asm.import(field.line(), &list[1..])?,
+            Some("func") => definitions.push(asm.function_header(field.line(), &list[1..])?),
+            Some("memory") => asm.memory(field.line(), &list[1..])?,
+            Some("global") => asm.global(field.line(), &list[1..])?,
+            Some("export" | "data" | "@custom") => {},
+            Some(keyword) => return error(field.line(), &format!("unsupported module field `{}`", keyword)),
+            None => return error(field.line(), "expected a module field"),
+        }
+    }
+    for (header, body) in definitions {
+        let function = asm.function_body(header, body)?;
+        asm.module.functions.push(function);
+    }
+    for field in fields {
+        let list = field.list().unwrap();
+        match field.keyword() {
+            Some("export") => asm.export(field.line(), &list[1..])?,
+            Some("data") => asm.data(field.line(), &list[1..])?,
+            Some("@custom") => asm.custom(field.line(), &list[1..])?,
+            _ => {},
+        }
+    }
+    Ok(asm.module)
+}
+
+// S 式。行番号はエラーの表示に使う
+#[derive(Clone, Debug)]
+enum SExpr {
+    Atom { text: String, line: usize },
+    Str { bytes: Vec<u8>, line: usize },
+    List { items: Vec<SExpr>, line: usize },
+}
+
+impl SExpr {
+
+    fn line(&self) -> usize {
+        match self {
+            SExpr::Atom { line, .. } | SExpr::Str { line, .. } | SExpr::List { line, .. } => *line,
+        }
+    }
+
+    fn atom(&self) -> Option<&str> {
+        match self {
+            SExpr::Atom { text, .. } => Some(text),
+            _ => None,
+        }
+    }
+
+    // $ を除いた名前
+    fn id(&self) -> Option<&str> {
+        self.atom().and_then(|text| text.strip_prefix('$'))
+    }
+
+    fn list(&self) -> Option<&[SExpr]> {
+        match self {
+            SExpr::List { items, .. } => Some(items),
+            _ => None,
+        }
+    }
+
+    // リストの先頭の単語
+    fn keyword(&self) -> Option<&str> {
+        self.list().and_then(|items| items.first()).and_then(SExpr::atom)
+    }
+
+}
+
+struct Tokenizer {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Tokenizer {
+
+    // 閉じ括弧か終わりまでを読む
+    fn items(&mut self) -> Result<Vec<SExpr>> {
+        let mut items = vec![];
+        loop {
+            self.skip_blank()?;
+            let line = self.line;
+            match self.chars.get(self.pos) {
+                None | Some(')') => return Ok(items),
+                Some('(') => {
+                    self.pos += 1;
+                    let list = self.items()?;
+                    if self.chars.get(self.pos) != Some(&')') {
+                        return error(line, "unclosed parenthesis");
+                    }
+                    self.pos += 1;
+                    items.push(SExpr::List { items: list, line });
+                },
+                Some('"') => {
+                    self.pos += 1;
+                    let bytes = self.string()?;
+                    items.push(SExpr::Str { bytes, line });
+                },
+                Some(_) => {
+                    let start = self.pos;
+                    while self.chars.get(self.pos).map(|c| !c.is_whitespace() && !"()\";".contains(*c)).unwrap_or(false) {
+                        self.pos += 1;
+                    }
+                    if start == self.pos {
+                        return error(line, &format!("unexpected `{}`", self.chars[self.pos]));
+                    }
+                    items.push(SExpr::Atom { text: self.chars[start..self.pos].iter().collect(), line });
+                },
+            }
+        }
+    }
+
+    // 空白と ;; の行コメント、(; ;) のブロックコメント (入れ子可) を読み飛ばす
+    fn skip_blank(&mut self) -> Result<()> {
+        loop {
+            match (self.chars.get(self.pos), self.chars.get(self.pos + 1)) {
+                (Some('\n'), _) => {
+                    self.line += 1;
+                    self.pos += 1;
+                },
+                (Some(c), _) if c.is_whitespace() => self.pos += 1,
+                (Some(';'), Some(';')) => {
+                    while self.chars.get(self.pos).map(|c| *c != '\n').unwrap_or(false) {
+                        self.pos += 1;
+                    }
+                },
+                (Some('('), Some(';')) => {
+                    let line = self.line;
+                    let mut depth = 0;
+                    loop {
+                        match (self.chars.get(self.pos), self.chars.get(self.pos + 1)) {
+                            (Some('('), Some(';')) => {
+                                depth += 1;
+                                self.pos += 2;
+                            },
+                            (Some(';'), Some(')')) => {
+                                depth -= 1;
+                                self.pos += 2;
+                                if depth == 0 {
+                                    break;
+                                }
+                            },
+                            (Some(c), _) => {
+                                if *c == '\n' {
+                                    self.line += 1;
+                                }
+                                self.pos += 1;
+                            },
+                            (None, _) => return error(line, "unclosed block comment"),
+                        }
+                    }
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // 開きの " の後ろから閉じの " までを読む
+    fn string(&mut self) -> Result<Vec<u8>> {
+        let line = self.line;
+        let mut bytes = vec![];
+        loop {
+            let c = match self.chars.get(self.pos) {
+                Some(c) => *c,
+                None => return error(line, "unclosed string"),
+            };
+            self.pos += 1;
+            match c {
+                '"' => return Ok(bytes),
+                '\n' => return error(line, "newline in string"),
+                '\\' => {
+                    let escape = self.chars.get(self.pos).copied();
+                    self.pos += 1;
+                    match escape {
+                        Some('n') => bytes.push(b'\n'),
+                        Some('t') => bytes.push(b'\t'),
+                        Some('r') => bytes.push(b'\r'),
+                        Some(c @ ('"' | '\'' | '\\')) => bytes.push(c as u8),
+                        Some(high) if high.is_ascii_hexdigit() => {
+                            let low = self.chars.get(self.pos).filter(|c| c.is_ascii_hexdigit());
+                            match low {
+                                Some(low) => {
+                                    bytes.push((high.to_digit(16).unwrap() * 16 + low.to_digit(16).unwrap()) as u8);
+                                    self.pos += 1;
+                                },
+                                None => return error(line, "invalid escape in string"),
+                            }
+                        },
+                        _ => return error(line, "invalid escape in string"),
+                    }
+                },
+                c => bytes.extend(c.to_string().as_bytes()),
+            }
+        }
+    }
+
+}
+
+// 関数の本体より前の部分
+struct Header {
+    line: usize,
+    name: String,
+    params: Vec<Local>,
+    results: Vec<ValType>,
+    locals: Vec<Local>,
+}
+
+struct Assembler {
+    module: Module,
+    // (type) で定義したもの
+    types: Vec<(Option<String>, Vec<ValType>, Vec<ValType>)>,
+    // import した関数から通しで並べた関数の名前
+    functions: Vec<String>,
+}
+
+impl Assembler {
+
+    fn type_definition(&mut self, line: usize, items: &[SExpr]) -> Result<()> {
+        let (name, items) = split_id(items);
+        let func = match items {
+            [func] if func.keyword() == Some("func") => func.list().unwrap(),
+            _ => return error(line, "expected (func ...) in type definition"),
+        };
+        let mut pos = 1;
+        let (params, results) = self.signature(line, func, &mut pos)?;
+        if pos != func.len() {
+            return error(func[pos].line(), "unexpected item in function type");
+        }
+        let params = params.into_iter().map(|param| param.vtype).collect();
+        self.types.push((name.map(str::to_string), params, results));
+        Ok(())
+    }
+
+    fn import(&mut self, line: usize, items: &[SExpr]) -> Result<()> {
+        let (module, field, func) = match items {
+            [SExpr::Str { bytes: module, .. }, SExpr::Str { bytes: field, .. }, func] if func.keyword() == Some("func") => {
+                (utf8(line, module)?, utf8(line, field)?, func.list().unwrap())
+            },
+            _ => return error(line, "only function imports are supported"),
+        };
+        let (name, rest) = split_id(&func[1..]);
+        let name = name.map(str::to_string).unwrap_or_else(|| format!("func{}", self.functions.len()));
+        let mut pos = 0;
+        let (params, results) = self.signature(line, rest, &mut pos)?;
+        if pos != rest.len() {
+            return error(rest[pos].line(), "unexpected item in import");
+        }
+        if !self.module.functions.is_empty() {
+            return error(line, "imports must come before function definitions");
+        }
+        self.declare_function(line, &name)?;
+        let params = params.into_iter().map(|param| param.vtype).collect();
+        self.module.imports.push(Import { module, field, name, params, results });
+        Ok(())
+    }
+
+    // (export "name") の後ろに続く部分を返す
+    fn inline_exports<'b>(&mut self, items: &'b [SExpr], kind: ExportKind) -> Result<&'b [SExpr]> {
+        let mut rest = items;
+        while let Some(export) = rest.first().filter(|item| item.keyword() == Some("export")) {
+            match export.list().unwrap() {
+                [_, SExpr::Str { bytes, .. }] => {
+                    let name = utf8(export.line(), bytes)?;
+                    self.module.exports.push(Export { name, kind: kind.clone() });
+                },
+                _ => return error(export.line(), "expected (export \"name\")"),
+            }
+            rest = &rest[1..];
+        }
+        Ok(rest)
+    }
+
+    fn function_header<'b>(&mut self, line: usize, items: &'b [SExpr]) -> Result<(Header, &'b [SExpr])> {
+        let (name, items) = split_id(items);
+        let name = name.map(str::to_string).unwrap_or_else(|| format!("func{}", self.functions.len()));
+        self.declare_function(line, &name)?;
+        let items = self.inline_exports(items, ExportKind::Function(name.to_string()))?;
+        let mut pos = 0;
+        let (params, results) = self.signature(line, items, &mut pos)?;
+        let mut locals: Vec<Local> = vec![];
+        while let Some(item) = items.get(pos).filter(|item| item.keyword() == Some("local")) {
+            let list = item.list().unwrap();
+            locals.extend(self.locals(item.line(), &list[1..], params.len() + locals.len())?);
+            pos += 1;
+        }
+        Ok((Header { line, name, params, results, locals }, &items[pos..]))
+    }
+
+    fn declare_function(&mut self, line: usize, name: &str) -> Result<()> {
+        if self.functions.iter().any(|function| function == name) {
+            return error(line, &format!("duplicate function ${}", name));
+        }
+        self.functions.push(name.to_string());
+        Ok(())
+    }
+
+    // (type $t)、(param ...)、(result ...) を読む
+    fn signature(&self, line: usize, items: &[SExpr], pos: &mut usize) -> Result<(Vec<Local>, Vec<ValType>)> {
+        let mut type_use = None;
+        if let Some(item) = items.get(*pos).filter(|item| item.keyword() == Some("type")) {
+            let list = item.list().unwrap();
+            let index = match list.get(1) {
+                Some(reference) if list.len() == 2 => {
+                    let names: Vec<Option<&str>> = self.types.iter().map(|(name, _, _)| name.as_deref()).collect();
+                    resolve(reference, &names, "type")?
+                },
+                _ => return error(item.line(), "expected (type index)"),
+            };
+            type_use = Some(&self.types[index]);
+            *pos += 1;
+        }
+        let mut params: Vec<Local> = vec![];
+        while let Some(item) = items.get(*pos).filter(|item| item.keyword() == Some("param")) {
+            let list = item.list().unwrap();
+            params.extend(self.locals(item.line(), &list[1..], params.len())?);
+            *pos += 1;
+        }
+        let mut results = vec![];
+        while let Some(item) = items.get(*pos).filter(|item| item.keyword() == Some("result")) {
+            for vtype in item.list().unwrap()[1..].iter() {
+                results.push(val_type(vtype)?);
+            }
+            *pos += 1;
+        }
+        if let Some((_, type_params, type_results)) = type_use {
+            if params.is_empty() && results.is_empty() {
+                params = type_params.iter().enumerate().map(|(i, vtype)| Local::new(&format!("local{}", i), *vtype)).collect();
+                results = type_results.clone();
+            } else if params.iter().map(|param| param.vtype).collect::<Vec<_>>() != *type_params || results != *type_results {
+                return error(line, "signature does not match the type");
+            }
+        }
+        Ok((params, results))
+    }
+
+    // (param $x i32) か (param i32 i32) の中身。first は最初の 1 つのローカル変数の番号
+    fn locals(&self, line: usize, items: &[SExpr], first: usize) -> Result<Vec<Local>> {
+        match items {
+            [id, vtype] if id.id().is_some() => Ok(vec![Local::new(id.id().unwrap(), val_type(vtype)?)]),
+            _ if items.iter().any(|item| item.id().is_some()) => error(line, "a named local must have exactly one type"),
+            _ => {
+                let mut locals = vec![];
+                for (i, vtype) in items.iter().enumerate() {
+                    locals.push(Local::new(&format!("local{}", first + i), val_type(vtype)?));
+                }
+                Ok(locals)
+            },
+        }
+    }
+
+    fn memory(&mut self, line: usize, items: &[SExpr]) -> Result<()> {
+        if self.module.memory.is_some() {
+            return error(line, "multiple memories");
+        }
+        let (_, items) = split_id(items);
+        let items = self.inline_exports(items, ExportKind::Memory)?;
+        let memory = match items {
+            [min] => Memory { min: u32_value(min)?, max: None },
+            [min, max] => Memory { min: u32_value(min)?, max: Some(u32_value(max)?) },
+            _ => return error(line, "expected memory limits"),
+        };
+        self.module.memory = Some(memory);
+        Ok(())
+    }
+
+    fn global(&mut self, line: usize, items: &[SExpr]) -> Result<()> {
+        let (name, items) = split_id(items);
+        let name = name.map(str::to_string).unwrap_or_else(|| format!("global{}", self.module.globals.len()));
+        if self.module.global_index(&name).is_some() {
+            return error(line, &format!("duplicate global ${}", name));
+        }
+        let items = self.inline_exports(items, ExportKind::Global(name.to_string()))?;
+        let (vtype, mutable, init) = match items {
+            [vtype, init] => match vtype.list() {
+                Some([keyword, vtype]) if keyword.atom() == Some("mut") => (val_type(vtype)?, true, const_expr(init)?),
+                _ => (val_type(vtype)?, false, const_expr(init)?),
+            },
+            _ => return error(line, "expected global type and initial value"),
+        };
+        self.module.globals.push(Global { name, vtype, mutable, init });
+        Ok(())
+    }
+
+    fn export(&mut self, line: usize, items: &[SExpr]) -> Result<()> {
+        let (name, target) = match items {
+            [SExpr::Str { bytes, .. }, target] if target.list().map(|list| list.len() == 2).unwrap_or(false) => (utf8(line, bytes)?, target),
+            _ => return error(line, "expected (export \"name\" (kind index))"),
+        };
+        let reference = &target.list().unwrap()[1];
+        let kind = match target.keyword() {
+            Some("func") => {
+                let names: Vec<Option<&str>> = self.functions.iter().map(|name| Some(name.as_str())).collect();
+                ExportKind::Function(self.functions[resolve(reference, &names, "function")?].to_string())
+            },
+            Some("memory") => {
+                if self.module.memory.is_none() || reference.atom() != Some("0") && reference.id().is_none() {
+                    return error(line, "unknown memory");
+                }
+                ExportKind::Memory
+            },
+            Some("global") => {
+                let names: Vec<Option<&str>> = self.module.globals.iter().map(|global| Some(global.name.as_str())).collect();
+                ExportKind::Global(self.module.globals[resolve(reference, &names, "global")?].name.to_string())
+            },
+            _ => return error(line, "unsupported export kind"),
+        };
+        self.module.exports.push(Export { name, kind });
+        Ok(())
+    }
+
+    fn data(&mut self, line: usize, items: &[SExpr]) -> Result<()> {
+        let (_, mut items) = split_id(items);
+        if items.first().and_then(SExpr::keyword) == Some("memory") {
+            items = &items[1..];
+        }
+        let offset = match items.first() {
+            Some(offset) if offset.keyword() == Some("offset") => match offset.list().unwrap() {
+                [_, init] => const_expr(init)?,
+                _ => return error(line, "expected (offset (i32.const N))"),
+            },
+            Some(init) => const_expr(init)?,
+            None => return error(line, "expected data offset"),
+        };
+        let mut bytes = vec![];
+        for item in items[1..].iter() {
+            match item {
+                SExpr::Str { bytes: string, .. } => bytes.extend(string),
+                _ => return error(item.line(), "expected a string"),
+            }
+        }
+        self.module.data.push(Data { offset, bytes });
+        Ok(())
+    }
+
+    // (@custom "name" (after section)? "bytes"*)。置く場所の指定は使わない
+    fn custom(&mut self, line: usize, items: &[SExpr]) -> Result<()> {
+        let name = match items.first() {
+            Some(SExpr::Str { bytes, .. }) => utf8(line, bytes)?,
+            _ => return error(line, "expected custom section name"),
+        };
+        let mut bytes = vec![];
+        for item in items[1..].iter() {
+            match item {
+                SExpr::Str { bytes: string, .. } => bytes.extend(string),
+                SExpr::List { .. } => {},
+                _ => return error(item.line(), "expected a string"),
+            }
+        }
+        self.module.customs.push(Custom { name, bytes });
+        Ok(())
+    }
+
+    fn function_body(&self, header: Header, items: &[SExpr]) -> Result<Function> {
+        let locals: Vec<&str> = header.params.iter().chain(header.locals.iter()).map(|local| local.name.as_str()).collect();
+        let mut body = Body {
+            functions: &self.functions,
+            locals: &locals,
+            labels: vec![],
+            used_labels: HashSet::new(),
+            next_label: 0,
+        };
+        let mut pos = 0;
+        let (instructions, _) = body.sequence(items, &mut pos, &[], header.line)?;
+        Ok(Function { name: header.name, params: header.params, results: header.results, locals: header.locals, body: instructions })
+    }
+
+}
+
+struct Body<'a> {
+    functions: &'a [String],
+    // パラメータを含む
+    locals: &'a [&'a str],
+    // 開いているブロックのラベル
+    labels: Vec<String>,
+    // 分岐先になったラベル。名前のない if はこれに含まれるときだけラベルを残す
+    used_labels: HashSet<String>,
+    next_label: usize,
+}
+
+impl <'a> Body<'a> {
+
+    // terminators のどれかの単語か、items の終わりまでを読む。どの単語で終わったかも返す
+    fn sequence(&mut self, items: &[SExpr], pos: &mut usize, terminators: &[&str], line: usize) -> Result<(Vec<Instr>, Option<String>)> {
+        let mut instructions = vec![];
+        while let Some(item) = items.get(*pos) {
+            *pos += 1;
+            match item {
+                SExpr::List { items: list, line } => self.folded(*line, list, &mut instructions)?,
+                SExpr::Atom { text, line } => {
+                    if terminators.contains(&text.as_str()) {
+                        return Ok((instructions, Some(text.to_string())));
+                    }
+                    let instruction = self.plain(*line, text, items, pos)?;
+                    instructions.push(instruction);
+                },
+                SExpr::Str { line, .. } => return error(*line, "unexpected string"),
+            }
+        }
+        if !terminators.is_empty() {
+            return error(line, "missing end");
+        }
+        Ok((instructions, None))
+    }
+
+    // 平らな形式の命令 1 つ。即値とブロックの中身は items から読み進める
+    fn plain(&mut self, line: usize, name: &str, items: &[SExpr], pos: &mut usize) -> Result<Instr> {
+        let instruction = match name {
+            "block" | "loop" => {
+                let (label, result) = self.block_header(items, pos)?;
+                let (body, _) = self.sequence(items, pos, &["end"], line)?;
+                self.end_label(items, pos, &label);
+                self.labels.pop();
+                if name == "block" { Instr::Block { label, result, body } } else { Instr::Loop { label, result, body } }
+            },
+            "if" => {
+                let named = items.get(*pos).and_then(SExpr::id).is_some();
+                let (label, result) = self.block_header(items, pos)?;
+                let (then, terminator) = self.sequence(items, pos, &["else", "end"], line)?;
+                let otherwise = if terminator.as_deref() == Some("else") {
+                    self.end_label(items, pos, &label);
+                    self.sequence(items, pos, &["end"], line)?.0
+                } else {
+                    vec![]
+                };
+                self.end_label(items, pos, &label);
+                self.if_instr(named, label, result, then, otherwise)
+            },
+            _ => {
+                let mut immediate = || {
+                    let item = items.get(*pos);
+                    *pos += 1;
+                    item.ok_or_else(|| AsmError { line, message: format!("{} needs an operand", name) })
+                };
+                match name {
+                    "br" => Instr::Br(self.label(immediate()?)?),
+                    "br_if" => Instr::BrIf(self.label(immediate()?)?),
+                    "return" => Instr::Return,
+                    "call" => Instr::Call(self.function(immediate()?)?),
+                    "return_call" => Instr::ReturnCall(self.function(immediate()?)?),
+                    "drop" => Instr::Drop,
+                    "local.get" => Instr::LocalGet(self.local(immediate()?)?),
+                    "local.set" => Instr::LocalSet(self.local(immediate()?)?),
+                    "local.tee" => Instr::LocalTee(self.local(immediate()?)?),
+                    "i32.const" => Instr::I32Const(i32_value(immediate()?)?),
+                    _ => match (NumOp::from_wat_name(name), MemOp::from_wat_name(name)) {
+                        (Some(op), _) => Instr::Numeric(op),
+                        (None, Some(op)) => Instr::Memory { op, offset: memarg(line, op, items, pos)? },
+                        (None, None) => return error(line, &format!("unsupported instruction `{}`", name)),
+                    },
+                }
+            },
+        };
+        Ok(instruction)
+    }
+
+    // 折り畳んだ形式の命令。オペランドを先に、命令をその後ろに並べる
+    fn folded(&mut self, line: usize, list: &[SExpr], instructions: &mut Vec<Instr>) -> Result<()> {
+        let name = match list.first().and_then(SExpr::atom) {
+            Some(name) => name,
+            None => return error(line, "expected an instruction"),
+        };
+        let mut pos = 1;
+        match name {
+            "block" | "loop" => {
+                let (label, result) = self.block_header(list, &mut pos)?;
+                let (body, _) = self.sequence(list, &mut pos, &[], line)?;
+                self.labels.pop();
+                instructions.push(if name == "block" { Instr::Block { label, result, body } } else { Instr::Loop { label, result, body } });
+            },
+            "if" => {
+                let named = list.get(pos).and_then(SExpr::id).is_some();
+                let (label, result) = self.block_header(list, &mut pos)?;
+                // 条件は if のラベルの外側で評価する
+                let if_label = self.labels.pop().unwrap();
+                while let Some(item) = list.get(pos).filter(|item| !matches!(item.keyword(), Some("then" | "else"))) {
+                    match item.list() {
+                        Some(condition) => self.folded(item.line(), condition, instructions)?,
+                        None => return error(item.line(), "expected a folded condition"),
+                    }
+                    pos += 1;
+                }
+                self.labels.push(if_label);
+                let mut then = vec![];
+                let mut otherwise = vec![];
+                for (keyword, body) in [("then", &mut then), ("else", &mut otherwise)] {
+                    if let Some(item) = list.get(pos).filter(|item| item.keyword() == Some(keyword)) {
+                        *body = self.sequence(item.list().unwrap(), &mut 1, &[], item.line())?.0;
+                        pos += 1;
+                    } else if keyword == "then" {
+                        return error(line, "expected (then ...)");
+                    }
+                }
+                if let Some(item) = list.get(pos) {
+                    return error(item.line(), "unexpected item after if");
+                }
+                let instruction = self.if_instr(named, label, result, then, otherwise);
+                instructions.push(instruction);
+            },
+            _ => {
+                let instruction = self.plain(line, name, list, &mut pos)?;
+                for item in list[pos..].iter() {
+                    match item.list() {
+                        Some(operand) => self.folded(item.line(), operand, instructions)?,
+                        None => return error(item.line(), "expected a folded operand"),
+                    }
+                }
+                instructions.push(instruction);
+            },
+        }
+        Ok(())
+    }
+
+    // ラベルと (result t) を読み、ラベルを開く
+    fn block_header(&mut self, items: &[SExpr], pos: &mut usize) -> Result<(String, Option<ValType>)> {
+        let label = match items.get(*pos).and_then(SExpr::id) {
+            Some(id) => {
+                *pos += 1;
+                id.to_string()
+            },
+            None => format!("label{}", self.next_label),
+        };
+        self.next_label += 1;
+        let mut result = None;
+        if let Some(item) = items.get(*pos).filter(|item| item.keyword() == Some("result")) {
+            result = match item.list().unwrap() {
+                [_, vtype] => Some(val_type(vtype)?),
+                [_] => None,
+                _ => return error(item.line(), "multiple block results are not supported"),
+            };
+            *pos += 1;
+        }
+        self.labels.push(label.to_string());
+        Ok((label, result))
+    }
+
+    // end や else の後ろの $label を読み飛ばす
+    fn end_label(&self, items: &[SExpr], pos: &mut usize, label: &str) {
+        if items.get(*pos).and_then(SExpr::id) == Some(label) {
+            *pos += 1;
+        }
+    }
+
+    fn if_instr(&mut self, named: bool, label: String, result: Option<ValType>, then: Vec<Instr>, otherwise: Vec<Instr>) -> Instr {
+        self.labels.pop();
+        let label = if named || self.used_labels.contains(&label) { Some(label) } else { None };
+        Instr::If { label, result, then, otherwise }
+    }
+
+    fn label(&mut self, item: &SExpr) -> Result<String> {
+        let label = match (item.id(), item.atom().and_then(|text| text.parse::<usize>().ok())) {
+            (Some(id), _) if self.labels.iter().any(|label| label == id) => id.to_string(),
+            (None, Some(depth)) if depth < self.labels.len() => self.labels[self.labels.len() - 1 - depth].to_string(),
+            _ => return error(item.line(), &format!("unknown label {}", item.atom().unwrap_or("?"))),
+        };
+        self.used_labels.insert(label.to_string());
+        Ok(label)
+    }
+
+    fn function(&self, item: &SExpr) -> Result<String> {
+        let names: Vec<Option<&str>> = self.functions.iter().map(|name| Some(name.as_str())).collect();
+        Ok(self.functions[resolve(item, &names, "function")?].to_string())
+    }
+
+    fn local(&self, item: &SExpr) -> Result<String> {
+        let names: Vec<Option<&str>> = self.locals.iter().map(|name| Some(*name)).collect();
+        Ok(self.locals[resolve(item, &names, "local")?].to_string())
+    }
+
+}
+
+// 先頭の $name を分ける
+fn split_id(items: &[SExpr]) -> (Option<&str>, &[SExpr]) {
+    match items.first().and_then(SExpr::id) {
+        Some(id) => (Some(id), &items[1..]),
+        None => (None, items),
+    }
+}
+
+// $name か番号を、names の中の位置にする
+fn resolve(item: &SExpr, names: &[Option<&str>], kind: &str) -> Result<usize> {
+    let index = match (item.id(), item.atom().and_then(|text| text.parse::<usize>().ok())) {
+        (Some(id), _) => names.iter().position(|name| *name == Some(id)),
+        (None, Some(index)) if index < names.len() => Some(index),
+        _ => None,
+    };
+    match index {
+        Some(index) => Ok(index),
+        None => error(item.line(), &format!("unknown {} {}", kind, item.atom().unwrap_or("?"))),
+    }
+}
+
+fn val_type(item: &SExpr) -> Result<ValType> {
+    match item.atom() {
+        Some("i32") => Ok(ValType::I32),
+        _ => error(item.line(), "unsupported value type"),
+    }
+}
+
+fn utf8(line: usize, bytes: &[u8]) -> Result<String> {
+    String::from_utf8(bytes.to_vec()).or_else(|_| error(line, "name is not UTF-8"))
+}
+
+// 符号と 0x、桁区切りの _ を受け付ける
+fn integer(item: &SExpr) -> Option<i64> {
+    integer_text(item.atom()?)
+}
+
+fn integer_text(text: &str) -> Option<i64> {
+    let text = text.replace('_', "");
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text.strip_prefix('+').unwrap_or(&text)),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i64>().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+// 符号なしで書いた 2^31 以上の値は 2 の補数として読む
+fn i32_value(item: &SExpr) -> Result<i32> {
+    match integer(item) {
+        Some(value) if (i32::MIN as i64..=u32::MAX as i64).contains(&value) => Ok(value as u32 as i32),
+        _ => error(item.line(), "invalid i32 constant"),
+    }
+}
+
+fn u32_value(item: &SExpr) -> Result<u32> {
+    match integer(item) {
+        Some(value) if (0..=u32::MAX as i64).contains(&value) => Ok(value as u32),
+        _ => error(item.line(), "invalid integer"),
+    }
+}
+
+// 命令に続く offset=N と align=N を読み、offset を返す
+fn memarg(line: usize, op: MemOp, items: &[SExpr], pos: &mut usize) -> Result<u32> {
+    let mut offset = 0;
+    while let Some(text) = items.get(*pos).and_then(SExpr::atom) {
+        let value = if let Some(value) = text.strip_prefix("offset=") {
+            value
+        } else if let Some(value) = text.strip_prefix("align=") {
+            value
+        } else {
+            break;
+        };
+        let value = match integer_text(value) {
+            Some(value) if (0..=u32::MAX as i64).contains(&value) => value as u32,
+            _ => return error(line, &format!("invalid {}", text)),
+        };
+        if text.starts_with("offset=") {
+            offset = value;
+        } else if !value.is_power_of_two() || value > op.size() {
+            return error(line, "alignment must be a power of two not larger than natural");
+        }
+        *pos += 1;
+    }
+    Ok(offset)
+}
+
+// 定数式は (i32.const N) だけを扱う
+fn const_expr(item: &SExpr) -> Result<i32> {
+    match item.list() {
+        Some([op, value]) if op.atom() == Some("i32.const") => i32_value(value),
+        _ => error(item.line(), "unsupported constant expression"),
+    }
+}
+
+#[cfg(test)]
+fn assemble(text: &str) -> Result<String> {
+    let mut wat = vec![];
+    read_module(text)?.write_wat(&mut wat).unwrap();
+    Ok(String::from_utf8(wat).unwrap())
+}
+
+#[test]
+fn test_round_trip() {
+    // write_wat の出力はそのまま読み戻せる
+    let text = "\
+(module
+  (import \"env\" \"print\" (func $print (param i32)))
+  (memory 1 2)
+  (global $sp (mut i32) (i32.const 65536))
+  (func $main
+    (param $n i32)
+    (result i32)
+    (local $i i32)
+    block $b (result i32)
+      loop $l
+        local.get $i
+        local.get $n
+        i32.ge_s
+        br_if $l
+        i32.const -123456
+        local.tee $i
+        call $print
+      end
+      i32.const 1
+      if $if
+        br $if
+      else
+        local.get $n
+        return_call $main
+      end
+      i32.const 2
+    end
+  )
+  (export \"main\" (func $main))
+  (export \"memory\" (memory 0))
+  (export \"sp\" (global $sp))
+  (data (i32.const 8) \"hello\\0a\\\"\")
+  (@custom \"producers\" \"\\00\")
+)
+";
+    assert_eq!(assemble(text), Ok(text.to_string()));
+}
+
+#[test]
+fn test_folded() {
+    let flat = "(module (func $f (param $a i32) (result i32) local.get $a i32.const 1 i32.add \
+                block $b local.get $a if br $b end end local.get $a i32.eqz))";
+    let folded = "(module (func $f (param $a i32) (result i32) (i32.add (local.get $a) (i32.const 1)) \
+                  (block $b (if (local.get $a) (then (br $b)))) (i32.eqz (local.get $a))))";
+    assert_eq!(assemble(folded), assemble(flat));
+}
+
+#[test]
+fn test_memarg() {
+    let module = read_module("(module (memory 1) (func (param $p i32) (result i32) \
+        (i32.store8 offset=0x10 align=1 (local.get $p) (i32.load align=4 (local.get $p))) (i32.load8_u offset=3 (local.get $p))))").unwrap();
+    let body = &module.functions[0].body;
+    assert_eq!(body[2], Instr::Memory { op: MemOp::I32Load, offset: 0 });
+    assert_eq!(body[3], Instr::Memory { op: MemOp::I32Store8, offset: 16 });
+    assert_eq!(body[5].to_string(), "i32.load8_u offset=3");
+    let error = |text: &str| read_module(text).unwrap_err().to_string();
+    assert_eq!(error("(module (memory 1) (func i32.const 0 i32.load align=8 drop))"),
+               "line 1: alignment must be a power of two not larger than natural");
+    assert_eq!(error("(module (memory 1) (func i32.const 0 i32.load offset=x drop))"), "line 1: invalid offset=x");
+}
+
+#[test]
+fn test_indices() {
+    // 番号での参照と名前のないものには、read_wasm と同じ名前を付ける
+    let text = ";; コメント
+(module
+  (type (func (param i32) (result i32)))
+  (func (type 0) (local i32)
+    (; ブロック (; 入れ子 ;) コメント ;)
+    local.get 0 local.set 1
+    block (result i32) loop i32.const 0xff br_if 1 end local.get 1 end
+    (if (i32.const 1) (then i32.const 1 br 0))
+    (call 0))
+  (export \"f\" (func 0)))";
+    let module = read_module(text).unwrap();
+    let function = &module.functions[0];
+    assert_eq!(function.name, "func0");
+    assert_eq!(function.params, vec![Local::new("local0", ValType::I32)]);
+    assert_eq!(function.locals, vec![Local::new("local1", ValType::I32)]);
+    assert_eq!(function.body[1], Instr::LocalSet("local1".to_string()));
+    let Instr::Block { label, body, .. } = &function.body[2] else { panic!() };
+    assert_eq!((label.as_str(), &body[0]), ("label0", &Instr::Loop { label: "label1".to_string(), result: None, body: vec![
+        Instr::I32Const(255), Instr::BrIf("label0".to_string()),
+    ]}));
+    // 分岐先になった if にはラベルを残す
+    assert!(matches!(&function.body[4], Instr::If { label: Some(_), .. }));
+    assert_eq!(module.exports, vec![Export::function("f", "func0")]);
+}
+
+#[test]
+fn test_errors() {
+    let error = |text: &str| read_module(text).unwrap_err().to_string();
+    assert_eq!(error("(module\n  (func\n    br $x))"), "line 3: unknown label $x");
+    assert_eq!(error("(module (func call 1))"), "line 1: unknown function 1");
+    assert_eq!(error("(module\n (func block))"), "line 2: missing end");
+    assert_eq!(error("(module (func)"), "line 1: unclosed parenthesis");
+    assert_eq!(error("(module))"), "line 1: unexpected `)`");
+    assert_eq!(error("(module (func i32.rotl))"), "line 1: unsupported instruction `i32.rotl`");
+    assert_eq!(error("(module (func (local $a i32 i32)))"), "line 1: a named local must have exactly one type");
+}
@@ -0,0 +1,594 @@
+use std::io::{Write, Result};
+use crate::interpreter::MAX_CALL_DEPTH;
+use crate::ir::{Context, ExportKind, Function, Import, Instr, MemOp, Memory, Module, NumOp, ValType};
+
+// 単体でコンパイルできる C99 のソースを書き出す。オペランドスタックの各段を s0, s1, ... という
+// ローカル変数にして、高さは x86-64 と同じく静的に数える。分岐は goto にし、if だけはそのまま C の if にする。
+// 桁あふれする演算は uint32_t で行ってから int32_t に戻し、0 除算などは wasmc_trap を呼んで終了する
+pub fn write_module(module: &Module, write: &mut dyn Write) -> Result<()> {
+    writeln!(write, "#include <inttypes.h>")?;
+    writeln!(write, "#include <stdio.h>")?;
+    writeln!(write, "#include <stdlib.h>")?;
+    write_runtime(write)?;
+    if let Some(memory) = &module.memory {
+        write_memory(module, memory, write)?;
+    }
+    for import in module.imports.iter().filter(|import| import.is_builtin(module)) {
+        write_builtin(&import.field, write)?;
+    }
+    // import した関数は同じ名前の C の関数として外から与える
+    let imports: Vec<&Import> = module.imports.iter().filter(|import| !import.is_builtin(module)).collect();
+    if !imports.is_empty() {
+        writeln!(write)?;
+        for import in imports {
+            let params: Vec<String> = import.params.iter().map(|vtype| c_type(*vtype).to_string()).collect();
+            writeln!(write, "extern {} {}({});", result_type(&import.results), identifier(&import.field), parameter_list(params))?;
+        }
+    }
+    writeln!(write)?;
+    for function in module.functions.iter() {
+        writeln!(write, "{};", prototype(function))?;
+    }
+    for function in module.functions.iter() {
+        FunctionWriter::new(module, function).function(write)?;
+    }
+    let main = module.exports.iter().find_map(|export| match &export.kind {
+        ExportKind::Function(name) if export.name == "main" => module.function(name),
+        _ => None,
+    });
+    if let Some(main) = main {
+        write_entry(main, write)?;
+    }
+    Ok(())
+}
+
+fn c_type(vtype: ValType) -> &'static str {
+    match vtype {
+        ValType::I32 => "int32_t"
+    }
+}
+
+fn result_type(results: &[ValType]) -> &'static str {
+    match results.first() {
+        Some(vtype) => c_type(*vtype),
+        None => "void",
+    }
+}
+
+fn parameter_list(params: Vec<String>) -> String {
+    if params.is_empty() { "void".to_string() } else { params.join(", ") }
+}
+
+// C の識別子に使えない文字は _ にする
+fn identifier(name: &str) -> String {
+    name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect()
+}
+
+fn variable(name: &str) -> String {
+    format!("v_{}", identifier(name))
+}
+
+fn symbol(module: &Module, name: &str) -> String {
+    match module.imports.iter().find(|import| import.name == name) {
+        Some(import) if import.is_builtin(module) => format!("wasmc_{}", import.field),
+        Some(import) => identifier(&import.field),
+        None => format!("wc_{}", identifier(name)),
+    }
+}
+
+fn prototype(function: &Function) -> String {
+    let params = function.params.iter().map(|param| format!("{} {}", c_type(param.vtype), variable(&param.name))).collect();
+    format!("{} wc_{}({})", result_type(&function.results), identifier(&function.name), parameter_list(params))
+}
+
+fn slot(index: usize) -> String {
+    format!("s{}", index)
+}
+
+// 命令列のどこかに label への分岐があるか
+fn branches_to(instructions: &[Instr], label: &str) -> bool {
+    instructions.iter().any(|instruction| match instruction {
+        Instr::Br(target) | Instr::BrIf(target) => target == label,
+        Instr::Block { body, .. } | Instr::Loop { body, .. } => branches_to(body, label),
+        Instr::If { then, otherwise, .. } => branches_to(then, label) || branches_to(otherwise, label),
+        _ => false,
+    })
+}
+
+// 自分自身への return_call があれば、引数を入れ直して先頭へ戻るだけで済む
+fn calls_self(instructions: &[Instr], name: &str) -> bool {
+    instructions.iter().any(|instruction| match instruction {
+        Instr::ReturnCall(target) => target == name,
+        Instr::Block { body, .. } | Instr::Loop { body, .. } => calls_self(body, name),
+        Instr::If { then, otherwise, .. } => calls_self(then, name) || calls_self(otherwise, name),
+        _ => false,
+    })
+}
+
+struct FunctionWriter<'a> {
+    context: Context<'a>,
+    // 関数本体。使ったスロットを宣言してから書き出すので一度ためておく
+    body: Vec<u8>,
+    indent: usize,
+    next_label: usize,
+    // 使ったスロットの数
+    slots: usize,
+}
+
+impl <'a> FunctionWriter<'a> {
+
+    fn new(module: &'a Module, function: &'a Function) -> Self {
+        FunctionWriter { context: Context::new(module, function), body: vec![], indent: 1, next_label: 0, slots: 0 }
+    }
+
+    fn function(mut self, write: &mut dyn Write) -> Result<()> {
+        let function = self.context.function;
+        if calls_self(&function.body, &function.name) {
+            self.label("wasmc_start")?;
+        }
+        self.instructions(&function.body)?;
+        if !function.results.is_empty() {
+            let top = self.top(1);
+            self.line("wasmc_depth--;")?;
+            self.line(&format!("return {};", top))?;
+        } else {
+            self.line("wasmc_depth--;")?;
+        }
+
+        writeln!(write)?;
+        writeln!(write, "{} {{", prototype(function))?;
+        for local in function.locals.iter() {
+            writeln!(write, "    {} {} = 0;", c_type(local.vtype), variable(&local.name))?;
+        }
+        if self.slots > 0 {
+            let slots: Vec<String> = (0..self.slots).map(slot).collect();
+            writeln!(write, "    int32_t {};", slots.join(", "))?;
+        }
+        // 再帰が深すぎたらインタープリタと同じくトラップにする
+        writeln!(write, "    if (wasmc_depth >= {}) wasmc_trap(\"call stack exhausted\");", MAX_CALL_DEPTH)?;
+        writeln!(write, "    wasmc_depth++;")?;
+        write.write_all(&self.body)?;
+        writeln!(write, "}}")?;
+        Ok(())
+    }
+
+    fn line(&mut self, text: &str) -> Result<()> {
+        writeln!(self.body, "{}{}", "    ".repeat(self.indent), text)
+    }
+
+    // ラベルは 1 段浅く書く。ブロックの最後に置けるように空文を付ける
+    fn label(&mut self, name: &str) -> Result<()> {
+        writeln!(self.body, "{}{}:;", "    ".repeat(self.indent - 1), name)
+    }
+
+    fn new_label(&mut self, name: &str) -> String {
+        let label = format!("L{}_{}", self.next_label, identifier(name));
+        self.next_label += 1;
+        label
+    }
+
+    // 上から count 番目 (1 始まり) のスロット。到達しない場所では高さが足りないことがある
+    fn top(&mut self, count: usize) -> String {
+        let index = self.context.height.saturating_sub(count);
+        self.slots = self.slots.max(index + 1);
+        slot(index)
+    }
+
+    fn push(&mut self) -> String {
+        let index = self.context.height;
+        self.context.height += 1;
+        self.slots = self.slots.max(self.context.height);
+        slot(index)
+    }
+
+    fn instructions(&mut self, instructions: &[Instr]) -> Result<()> {
+        for instruction in instructions {
+            self.instruction(instruction)?;
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: &Instr) -> Result<()> {
+        match instruction {
+            Instr::Block { label, result, body } => {
+                let end = self.new_label(label);
+                let results = result.iter().count();
+                self.context.push_label(label, &end, results, results);
+                self.line("{")?;
+                self.indent += 1;
+                self.instructions(body)?;
+                self.indent -= 1;
+                self.line("}")?;
+                self.context.pop_label();
+                if branches_to(body, label) {
+                    self.indent += 1;
+                    self.label(&end)?;
+                    self.indent -= 1;
+                }
+            },
+            Instr::Loop { label, result, body } => {
+                let start = self.new_label(label);
+                self.context.push_label(label, &start, 0, result.iter().count());
+                self.line("{")?;
+                self.indent += 1;
+                if branches_to(body, label) {
+                    self.label(&start)?;
+                }
+                self.instructions(body)?;
+                self.indent -= 1;
+                self.line("}")?;
+                self.context.pop_label();
+            },
+            Instr::If { label, result, then, otherwise } => {
+                let condition = self.top(1);
+                self.context.pop_height(1);
+                let name = label.as_deref().unwrap_or("if");
+                let end = self.new_label(name);
+                let results = result.iter().count();
+                self.context.push_label(label.as_deref().unwrap_or_default(), &end, results, results);
+                self.line(&format!("if ({}) {{", condition))?;
+                self.indent += 1;
+                self.instructions(then)?;
+                self.indent -= 1;
+                if !otherwise.is_empty() {
+                    self.line("} else {")?;
+                    self.context.height = self.context.labels.last().unwrap().height;
+                    self.indent += 1;
+                    self.instructions(otherwise)?;
+                    self.indent -= 1;
+                }
+                self.line("}")?;
+                self.context.pop_label();
+                let used = label.as_ref().map(|label| branches_to(then, label) || branches_to(otherwise, label)).unwrap_or(false);
+                if used {
+                    self.indent += 1;
+                    self.label(&end)?;
+                    self.indent -= 1;
+                }
+            },
+            Instr::Br(label) => self.branch(label)?,
+            Instr::BrIf(label) => {
+                let condition = self.top(1);
+                self.context.pop_height(1);
+                self.line(&format!("if ({}) {{", condition))?;
+                self.indent += 1;
+                let height = self.context.height;
+                self.branch(label)?;
+                self.context.height = height;
+                self.indent -= 1;
+                self.line("}")?;
+            },
+            Instr::Return => {
+                self.line("wasmc_depth--;")?;
+                if self.context.function.results.is_empty() {
+                    self.line("return;")?;
+                } else {
+                    let top = self.top(1);
+                    self.line(&format!("return {};", top))?;
+                }
+            },
+            Instr::Call(name) => {
+                let (params, results) = self.context.signature(name);
+                let call = self.call(name, params.len());
+                if results.is_empty() {
+                    self.line(&format!("{};", call))?;
+                } else {
+                    let result = self.push();
+                    self.line(&format!("{} = {};", result, call))?;
+                }
+            },
+            Instr::ReturnCall(name) if *name == self.context.function.name => {
+                // 引数はスロットにあるので、そのままパラメータへ入れ直せる
+                let function = self.context.function;
+                let count = function.params.len();
+                for (i, param) in function.params.iter().enumerate() {
+                    let arg = self.top(count - i);
+                    self.line(&format!("{} = {};", variable(&param.name), arg))?;
+                }
+                for local in function.locals.iter() {
+                    self.line(&format!("{} = 0;", variable(&local.name)))?;
+                }
+                self.line("goto wasmc_start;")?;
+            },
+            Instr::ReturnCall(name) => {
+                // 呼ぶ前に深さを戻しておけば、末尾呼び出しを続けても深さは増えない
+                let (params, results) = self.context.signature(name);
+                let call = self.call(name, params.len());
+                self.line("wasmc_depth--;")?;
+                if results.is_empty() {
+                    self.line(&format!("{};", call))?;
+                    self.line("return;")?;
+                } else {
+                    self.line(&format!("return {};", call))?;
+                }
+            },
+            Instr::Drop => self.context.pop_height(1),
+            Instr::LocalGet(name) => {
+                let local = self.variable(name);
+                let result = self.push();
+                self.line(&format!("{} = {};", result, local))?;
+            },
+            Instr::LocalSet(name) => {
+                let local = self.variable(name);
+                let value = self.top(1);
+                self.context.pop_height(1);
+                self.line(&format!("{} = {};", local, value))?;
+            },
+            Instr::LocalTee(name) => {
+                let local = self.variable(name);
+                let value = self.top(1);
+                self.line(&format!("{} = {};", local, value))?;
+            },
+            Instr::I32Const(value) | Instr::Address(value) => {
+                let result = self.push();
+                // -2147483648 は C では int の範囲外の定数に単項マイナスを付けたものになる
+                let value = if *value == i32::MIN { "INT32_MIN".to_string() } else { value.to_string() };
+                self.line(&format!("{} = {};", result, value))?;
+            },
+            Instr::Numeric(op) => self.numeric(*op)?,
+            Instr::Memory { op, offset } => {
+                let size = op.size();
+                if op.is_store() {
+                    let (address, value) = (self.top(2), self.top(1));
+                    self.context.pop_height(2);
+                    let function = if *op == MemOp::I32Store { "wasmc_store" } else { "wasmc_store8" };
+                    self.line(&format!("{}(wasmc_address({}, {}, {}), {});", function, address, offset, size, value))?;
+                } else {
+                    let address = self.top(1);
+                    let function = if *op == MemOp::I32Load { "wasmc_load" } else { "wasmc_load8_u" };
+                    self.line(&format!("{} = {}(wasmc_address({}, {}, {}));", address, function, address, offset, size))?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    // 残す値をラベルの高さのスロットへ移してから飛ぶ
+    fn branch(&mut self, name: &str) -> Result<()> {
+        let label = self.context.label(name);
+        let (target, height, arity) = (label.target.to_string(), label.height, label.arity);
+        if arity > 0 && self.context.height != height + arity {
+            let value = self.top(1);
+            self.line(&format!("{} = {};", slot(height), value))?;
+        }
+        self.line(&format!("goto {};", target))?;
+        Ok(())
+    }
+
+    // スタックの上から count 個を引数にした呼び出し式
+    fn call(&mut self, name: &str, count: usize) -> String {
+        let args: Vec<String> = (0..count).map(|i| self.top(count - i)).collect();
+        self.context.pop_height(count);
+        format!("{}({})", symbol(self.context.module, name), args.join(", "))
+    }
+
+    fn numeric(&mut self, op: NumOp) -> Result<()> {
+        if op == NumOp::I32Eqz {
+            let value = self.top(1);
+            return self.line(&format!("{} = {} == 0;", value, value));
+        }
+        let (a, b) = (self.top(2), self.top(1));
+        self.context.pop_height(1);
+        let expression = match op {
+            NumOp::I32Eqz => unreachable!(),
+            NumOp::I32Eq => format!("{} == {}", a, b),
+            NumOp::I32Ne => format!("{} != {}", a, b),
+            NumOp::I32LtS => format!("{} < {}", a, b),
+            NumOp::I32GtS => format!("{} > {}", a, b),
+            NumOp::I32LeS => format!("{} <= {}", a, b),
+            NumOp::I32GeS => format!("{} >= {}", a, b),
+            NumOp::I32Add => format!("wasmc_wrap((uint32_t){} + (uint32_t){})", a, b),
+            NumOp::I32Sub => format!("wasmc_wrap((uint32_t){} - (uint32_t){})", a, b),
+            NumOp::I32Mul => format!("wasmc_wrap((uint32_t){} * (uint32_t){})", a, b),
+            NumOp::I32DivS => format!("wasmc_div_s({}, {})", a, b),
+            // シフト量は下位 5 bit だけを使う
+            NumOp::I32Shl => format!("wasmc_wrap((uint32_t){} << ({} & 31))", a, b),
+            NumOp::I32ShrS => format!("wasmc_shr_s({}, {} & 31)", a, b),
+            NumOp::I32ShrU => format!("wasmc_wrap((uint32_t){} >> ({} & 31))", a, b),
+        };
+        self.line(&format!("{} = {};", a, expression))
+    }
+
+    // 定義されていることを確かめてから C の変数名にする
+    fn variable(&self, name: &str) -> String {
+        self.context.local_index(name);
+        variable(name)
+    }
+
+}
+
+// 組み込む側のホストのための C ヘッダ。export した関数は write_module と同じく wc_ を付けた名前で、
+// import した関数はフィールド名のままで宣言する
+pub fn write_header(module: &Module, name: &str, write: &mut dyn Write) -> Result<()> {
+    let guard = format!("WASMC_{}_H", identifier(name).to_ascii_uppercase());
+    writeln!(write, "#ifndef {}", guard)?;
+    writeln!(write, "#define {}", guard)?;
+    writeln!(write)?;
+    writeln!(write, "#include <stdint.h>")?;
+    writeln!(write)?;
+    writeln!(write, "#ifdef __cplusplus")?;
+    writeln!(write, "extern \"C\" {{")?;
+    writeln!(write, "#endif")?;
+    if let Some(memory) = &module.memory {
+        writeln!(write)?;
+        writeln!(write, "#define WASMC_MEMORY_PAGES {}u", memory.min)?;
+        if let Some(export) = module.exports.iter().find(|export| export.kind == ExportKind::Memory) {
+            writeln!(write, "#define WASMC_MEMORY_EXPORT \"{}\"", export.name.escape_default())?;
+        }
+    }
+    let exports: Vec<_> = module.exports.iter().filter_map(|export| match &export.kind {
+        ExportKind::Function(function) => Some((export, function)),
+        _ => None,
+    }).collect();
+    if !exports.is_empty() {
+        writeln!(write)?;
+        writeln!(write, "/* export */")?;
+    }
+    for (export, function) in exports {
+        let (params, results) = match module.signature(function) {
+            Some(signature) => signature,
+            None => panic!("function `{}` not found", function),
+        };
+        let names: Vec<String> = match module.function(function) {
+            Some(function) => function.params.iter().map(|param| variable(&param.name)).collect(),
+            None => (0..params.len()).map(|i| format!("a{}", i)).collect(),
+        };
+        let params = names.iter().zip(params.iter()).map(|(name, vtype)| format!("{} {}", c_type(*vtype), name)).collect();
+        writeln!(write, "{} wc_{}({});", result_type(&results), identifier(&export.name), parameter_list(params))?;
+    }
+    let mut modules: Vec<&str> = vec![];
+    for import in module.imports.iter().filter(|import| !import.is_builtin(module)) {
+        if !modules.contains(&import.module.as_str()) {
+            modules.push(&import.module);
+        }
+    }
+    for import_module in modules {
+        writeln!(write)?;
+        writeln!(write, "/* import: ホストが \"{}\" として渡す */", import_module.escape_default())?;
+        for import in module.imports.iter().filter(|import| import.module == import_module && !import.is_builtin(module)) {
+            let params = import.params.iter().enumerate().map(|(i, vtype)| format!("{} a{}", c_type(*vtype), i)).collect();
+            writeln!(write, "extern {} {}({});", result_type(&import.results), identifier(&import.field), parameter_list(params))?;
+        }
+    }
+    writeln!(write)?;
+    writeln!(write, "#ifdef __cplusplus")?;
+    writeln!(write, "}}")?;
+    writeln!(write, "#endif")?;
+    writeln!(write)?;
+    writeln!(write, "#endif")?;
+    Ok(())
+}
+
+// C の main。引数の数を確かめて i32 に直し、結果を 1 行に 1 つずつ表示する
+fn write_entry(main: &Function, write: &mut dyn Write) -> Result<()> {
+    let count = main.params.len();
+    writeln!(write)?;
+    writeln!(write, "int main(int argc, char **argv) {{")?;
+    writeln!(write, "    if (argc != {}) {{", count + 1)?;
+    writeln!(write, "        fprintf(stderr, \"`main` takes %d arguments, got %d\\n\", {}, argc - 1);", count)?;
+    writeln!(write, "        exit(-1);")?;
+    writeln!(write, "    }}")?;
+    // 引数は前から順に読む
+    let args: Vec<String> = (0..count).map(|i| format!("a{}", i)).collect();
+    for (i, arg) in args.iter().enumerate() {
+        writeln!(write, "    int32_t {} = wasmc_arg(argv[{}]);", arg, i + 1)?;
+    }
+    let call = format!("wc_{}({})", identifier(&main.name), args.join(", "));
+    if main.results.is_empty() {
+        writeln!(write, "    {};", call)?;
+    } else {
+        writeln!(write, "    printf(\"%\" PRId32 \"\\n\", {});", call)?;
+    }
+    writeln!(write, "    return 0;")?;
+    writeln!(write, "}}")?;
+    Ok(())
+}
+
+// 線形メモリと読み書きの関数。data の初期値は指示付きの初期化子で置く
+fn write_memory(module: &Module, memory: &Memory, write: &mut dyn Write) -> Result<()> {
+    writeln!(write)?;
+    writeln!(write, "#define WASMC_MEMORY_SIZE {}u", memory.min as u64 * 65536)?;
+    writeln!(write, "static uint8_t wasmc_memory[WASMC_MEMORY_SIZE] = {{")?;
+    for data in module.data.iter() {
+        for (i, chunk) in data.bytes.chunks(16).enumerate() {
+            let bytes: Vec<String> = chunk.iter().map(|byte| byte.to_string()).collect();
+            writeln!(write, "    [{}] = {},", data.offset as u32 as usize + i * 16, bytes.join(", "))?;
+        }
+    }
+    // 空の初期化子は C99 では書けない
+    if module.data.is_empty() {
+        writeln!(write, "    0")?;
+    }
+    writeln!(write, "}};")?;
+    write.write_all(r#"
+/* アドレスは符号なしで、offset と大きさを足した終わりが範囲を超えればトラップにする */
+static inline uint32_t wasmc_address(int32_t address, uint32_t offset, uint32_t size) {
+    uint64_t start = (uint64_t)(uint32_t)address + offset;
+    if (start + size > WASMC_MEMORY_SIZE) wasmc_trap("out of bounds memory access");
+    return (uint32_t)start;
+}
+
+/* バイト順はリトルエンディアン */
+static inline int32_t wasmc_load(uint32_t address) {
+    uint8_t *bytes = &wasmc_memory[address];
+    return wasmc_wrap((uint32_t)bytes[0] | (uint32_t)bytes[1] << 8 | (uint32_t)bytes[2] << 16 | (uint32_t)bytes[3] << 24);
+}
+
+static inline int32_t wasmc_load8_u(uint32_t address) {
+    return wasmc_memory[address];
+}
+
+static inline void wasmc_store(uint32_t address, int32_t value) {
+    uint32_t bits = (uint32_t)value;
+    int i;
+    for (i = 0; i < 4; i++) {
+        wasmc_memory[address + i] = (uint8_t)(bits >> (8 * i));
+    }
+}
+
+static inline void wasmc_store8(uint32_t address, int32_t value) {
+    wasmc_memory[address] = (uint8_t)(uint32_t)value;
+}
+"#.as_bytes())
+}
+
+// 組み込み関数。WASI と同じく標準出力に書いて 0 を返す。print_str はメモリがあるときだけ使う
+fn write_builtin(field: &str, write: &mut dyn Write) -> Result<()> {
+    let code = match field {
+        "print_int" => r#"
+static int32_t wasmc_print_int(int32_t value) {
+    printf("%" PRId32, value);
+    return 0;
+}
+"#,
+        _ => r#"
+static int32_t wasmc_print_str(int32_t pointer) {
+    for (uint32_t offset = 0;; offset++) {
+        uint8_t byte = wasmc_memory[wasmc_address(pointer, offset, 1)];
+        if (byte == 0) return 0;
+        putchar(byte);
+    }
+}
+"#,
+    };
+    write.write_all(code.as_bytes())
+}
+
+// 引数の読み込みとトラップ、桁あふれする演算。メッセージは wasmc run と同じにする。
+// 使わないものがあっても警告にならないように inline にしておく
+fn write_runtime(write: &mut dyn Write) -> Result<()> {
+    write.write_all(r#"
+static int wasmc_depth = 0;
+
+static void wasmc_trap(const char *message) {
+    fprintf(stderr, "trap: %s\n", message);
+    exit(1);
+}
+
+/* uint32_t から int32_t へ、値を変えずにビット列のまま戻す */
+static inline int32_t wasmc_wrap(uint32_t value) {
+    return value <= INT32_MAX ? (int32_t)value : (int32_t)(value - 2147483648u) + INT32_MIN;
+}
+
+static inline int32_t wasmc_div_s(int32_t a, int32_t b) {
+    if (b == 0) wasmc_trap("integer divide by zero");
+    if (a == INT32_MIN && b == -1) wasmc_trap("integer overflow");
+    return a / b;
+}
+
+/* 負の数の右シフトは処理系定義なので、補数を取って正の数で行う */
+static inline int32_t wasmc_shr_s(int32_t value, int32_t shift) {
+    return value < 0 ? ~(~value >> shift) : value >> shift;
+}
+
+static inline int32_t wasmc_arg(const char *text) {
+    char *end;
+    long value = strtol(text, &end, 10);
+    if (end == text || *end != '\0' || value < INT32_MIN || value > INT32_MAX) {
+        fprintf(stderr, "invalid i32 argument: %s\n", text);
+        exit(-1);
+    }
+    return (int32_t)value;
+}
+"#.as_bytes())
+}
@@ -0,0 +1,74 @@
+use crate::ir::{Function, Module, ValType};
+
+// 関数本体を先頭から順に書き出すバックエンドが共通で持つ状態。
+// 入れ子のブロックのラベルと、オペランドスタックの高さを静的に数える
+pub struct Context<'a> {
+    pub module: &'a Module,
+    pub function: &'a Function,
+    pub labels: Vec<Label>,
+    // 今の命令の前でのオペランドスタックの高さ。無条件分岐の後ろでは意味を持たない
+    pub height: usize,
+}
+
+pub struct Label {
+    pub name: String,
+    // 分岐先を表すバックエンドごとの名前
+    pub target: String,
+    // ブロックに入ったときのオペランドスタックの高さ
+    pub height: usize,
+    // 分岐したときに残す値の数。loop は先頭へ戻るので 0
+    pub arity: usize,
+    pub results: usize,
+}
+
+impl <'a> Context<'a> {
+
+    pub fn new(module: &'a Module, function: &'a Function) -> Self {
+        Context { module, function, labels: vec![], height: 0 }
+    }
+
+    pub fn push_label(&mut self, name: &str, target: &str, arity: usize, results: usize) {
+        self.labels.push(Label { name: name.to_string(), target: target.to_string(), height: self.height, arity, results });
+    }
+
+    // ブロックの終わりには結果だけが積まれている
+    pub fn pop_label(&mut self) -> Label {
+        let label = self.labels.pop().unwrap();
+        self.height = label.height + label.results;
+        label
+    }
+
+    // 無条件分岐の後ろは到達しないので、高さが足りなくてもよい
+    pub fn pop_height(&mut self, count: usize) {
+        self.height = self.height.saturating_sub(count);
+    }
+
+    // 内側から探した分岐先
+    pub fn label(&self, name: &str) -> &Label {
+        match self.labels.iter().rev().find(|label| label.name == name) {
+            Some(label) => label,
+            None => panic!("label {} is not defined", name),
+        }
+    }
+
+    pub fn local_index(&self, name: &str) -> usize {
+        match self.function.local_index(name) {
+            Some(index) => index,
+            None => panic!("variable {} is not defined", name)
+        }
+    }
+
+    pub fn local_type(&self, name: &str) -> ValType {
+        let index = self.local_index(name);
+        self.function.params.iter().chain(self.function.locals.iter()).nth(index).unwrap().vtype
+    }
+
+    // 呼び出す関数のパラメータと結果の型
+    pub fn signature(&self, name: &str) -> (Vec<ValType>, Vec<ValType>) {
+        match self.module.signature(name) {
+            Some(signature) => signature,
+            None => panic!("function {} is not defined", name),
+        }
+    }
+
+}
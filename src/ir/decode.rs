@@ -0,0 +1,641 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::ir::{Custom, Data, Export, ExportKind, Function, Global, Import, Instr, Local, MemOp, Memory, Module, NumOp, ValType};
+use crate::ir::leb128::{leb128_to_i32, leb128_to_usize};
+
+// 入れ子のブロックを再帰で読むので、深さに上限を設ける
+const MAX_NESTING: usize = 1024;
+const MAX_LOCALS: usize = 50000;
+
+// バイナリを読めなかった位置 (先頭からのバイト数) と理由
+#[derive(Clone, PartialEq, Debug)]
+pub struct DecodeError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "offset {:#x}: {}", self.offset, self.message)
+    }
+}
+
+type Result<T> = std::result::Result<T, DecodeError>;
+
+// バイナリを中間表現に戻す。name section に名前がなければ、関数は func{i}、ローカル変数は
+// local{i}、ラベルは label{i}、グローバル変数は global{i} と番号から付ける
+pub fn read_module(bytes: &[u8]) -> Result<Module> {
+    let mut reader = Reader { bytes, offset: 0 };
+    if reader.take(4)? != [0x00, 0x61, 0x73, 0x6d] {
+        return Err(reader.error_at(0, "not a wasm binary"));
+    }
+    if reader.take(4)? != [0x01, 0x00, 0x00, 0x00] {
+        return Err(reader.error_at(4, "unsupported wasm version"));
+    }
+
+    // 名前は code section より後ろにあるので、先にセクションの範囲を集めて name section を読む
+    let mut sections = vec![];
+    while reader.offset < bytes.len() {
+        let code = reader.byte()?;
+        let size = reader.usize()?;
+        let start = reader.offset;
+        let end = start.checked_add(size).filter(|end| *end <= bytes.len())
+            .ok_or_else(|| reader.error_at(start, "section size out of bounds"))?;
+        sections.push((code, start, end));
+        reader.offset = end;
+    }
+    let mut names = Names::default();
+    let mut customs = vec![];
+    for (code, start, end) in sections.iter() {
+        if *code == 0x00 {
+            let mut section = Reader { bytes: &bytes[..*end], offset: *start };
+            let name = section.name()?;
+            if name == "name" {
+                names = section.names()?;
+            } else {
+                customs.push(Custom { name, bytes: section.take(end - section.offset)?.to_vec() });
+            }
+        }
+    }
+
+    let mut types: Vec<(Vec<ValType>, Vec<ValType>)> = vec![];
+    let mut imports: Vec<(String, String, usize)> = vec![];
+    let mut signatures: Vec<usize> = vec![];
+    let mut memories: Vec<Memory> = vec![];
+    let mut globals: Vec<(ValType, bool, i32)> = vec![];
+    let mut exports: Vec<(String, u8, usize, usize)> = vec![];
+    let mut functions: Vec<Function> = vec![];
+    let mut data: Vec<Data> = vec![];
+    for (code, start, end) in sections {
+        let mut section = Reader { bytes: &bytes[..end], offset: start };
+        match code {
+            0x00 => section.offset = end,
+            0x01 => types = section.vec(Reader::func_type)?,
+            0x02 => imports = section.vec(Reader::import)?,
+            0x03 => signatures = section.vec(Reader::usize)?,
+            0x05 => {
+                memories = section.vec(Reader::limits)?;
+                if memories.len() > 1 {
+                    return Err(section.error_at(start, "multiple memories"));
+                }
+            },
+            0x06 => globals = section.vec(Reader::global)?,
+            0x07 => exports = section.vec(Reader::export)?,
+            0x0a => {
+                let count = section.usize()?;
+                if count != signatures.len() {
+                    return Err(section.error("function and code section sizes differ"));
+                }
+                let num_functions = imports.len() + signatures.len();
+                for (i, type_index) in signatures.iter().enumerate() {
+                    let (params, results) = types.get(*type_index)
+                        .ok_or_else(|| section.error(&format!("type index {} out of range", type_index)))?;
+                    functions.push(section.function(&names, imports.len() + i, params, results, num_functions)?);
+                }
+            },
+            0x0b => data = section.vec(Reader::data)?,
+            _ => return Err(section.error_at(start - 1, &format!("unsupported section {:#04x}", code))),
+        }
+        if section.offset != end {
+            return Err(section.error("section size mismatch"));
+        }
+    }
+    if functions.len() != signatures.len() {
+        return Err(reader.error("code section is missing"));
+    }
+
+    let mut module = Module { functions, memory: memories.pop(), data, customs, ..Default::default() };
+    for (i, (module_name, field, type_index)) in imports.into_iter().enumerate() {
+        let (params, results) = types.get(type_index)
+            .ok_or_else(|| reader.error(&format!("type index {} out of range", type_index)))?;
+        let name = names.function(i);
+        module.imports.push(Import { module: module_name, field, name, params: params.clone(), results: results.clone() });
+    }
+    for (i, (vtype, mutable, init)) in globals.into_iter().enumerate() {
+        module.globals.push(Global { name: names.global(i), vtype, mutable, init });
+    }
+    for (name, kind, index, offset) in exports {
+        let kind = match kind {
+            0x00 if index < module.imports.len() + module.functions.len() => ExportKind::Function(names.function(index)),
+            0x02 if index == 0 && module.memory.is_some() => ExportKind::Memory,
+            0x03 if index < module.globals.len() => ExportKind::Global(names.global(index)),
+            _ => return Err(reader.error_at(offset, &format!("exported index {} out of range", index))),
+        };
+        module.exports.push(Export { name, kind });
+    }
+    Ok(module)
+}
+
+// name section から読んだ名前。番号は関数、ローカル変数、ラベル、グローバル変数ごと
+#[derive(Default)]
+struct Names {
+    functions: HashMap<usize, String>,
+    locals: HashMap<usize, HashMap<usize, String>>,
+    labels: HashMap<usize, HashMap<usize, String>>,
+    globals: HashMap<usize, String>,
+}
+
+impl Names {
+
+    fn function(&self, index: usize) -> String {
+        self.functions.get(&index).cloned().unwrap_or_else(|| format!("func{}", index))
+    }
+
+    fn local(&self, function: usize, index: usize) -> String {
+        self.locals.get(&function).and_then(|names| names.get(&index)).cloned().unwrap_or_else(|| format!("local{}", index))
+    }
+
+    fn label(&self, function: usize, index: usize) -> Option<String> {
+        self.labels.get(&function).and_then(|names| names.get(&index)).cloned()
+    }
+
+    fn global(&self, index: usize) -> String {
+        self.globals.get(&index).cloned().unwrap_or_else(|| format!("global{}", index))
+    }
+
+}
+
+pub(crate) struct Reader<'a> {
+    // 今読んでいる範囲の終わりまで。offset はファイルの先頭から数える
+    pub bytes: &'a [u8],
+    pub offset: usize,
+}
+
+impl <'a> Reader<'a> {
+
+    pub(crate) fn error(&self, message: &str) -> DecodeError {
+        self.error_at(self.offset, message)
+    }
+
+    pub(crate) fn error_at(&self, offset: usize, message: &str) -> DecodeError {
+        DecodeError { offset, message: message.to_string() }
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8> {
+        match self.bytes.get(self.offset) {
+            Some(byte) => {
+                self.offset += 1;
+                Ok(*byte)
+            },
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    pub(crate) fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        match self.offset.checked_add(len).and_then(|end| self.bytes.get(self.offset..end)) {
+            Some(bytes) => {
+                self.offset += len;
+                Ok(bytes)
+            },
+            None => Err(self.error("unexpected end")),
+        }
+    }
+
+    pub(crate) fn usize(&mut self) -> Result<usize> {
+        match leb128_to_usize(&self.bytes[self.offset..]) {
+            Some((value, len)) => {
+                self.offset += len;
+                Ok(value)
+            },
+            None => Err(self.error("invalid LEB128")),
+        }
+    }
+
+    pub(crate) fn i32(&mut self) -> Result<i32> {
+        match leb128_to_i32(&self.bytes[self.offset..]) {
+            Some((value, len)) => {
+                self.offset += len;
+                Ok(value)
+            },
+            None => Err(self.error("invalid LEB128")),
+        }
+    }
+
+    pub(crate) fn vec<T>(&mut self, f: fn(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        let count = self.usize()?;
+        let mut items = vec![];
+        for _ in 0..count {
+            items.push(f(self)?);
+        }
+        Ok(items)
+    }
+
+    fn val_type(&mut self) -> Result<ValType> {
+        match self.byte()? {
+            0x7f => Ok(ValType::I32),
+            code => Err(self.error_at(self.offset - 1, &format!("unsupported value type {:#04x}", code))),
+        }
+    }
+
+    fn func_type(&mut self) -> Result<(Vec<ValType>, Vec<ValType>)> {
+        if self.byte()? != 0x60 {
+            return Err(self.error_at(self.offset - 1, "expected function type"));
+        }
+        let params = self.vec(Reader::val_type)?;
+        let results = self.vec(Reader::val_type)?;
+        Ok((params, results))
+    }
+
+    pub(crate) fn name(&mut self) -> Result<String> {
+        let len = self.usize()?;
+        let start = self.offset;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| self.error_at(start, "name is not UTF-8"))
+    }
+
+    fn import(&mut self) -> Result<(String, String, usize)> {
+        let module = self.name()?;
+        let field = self.name()?;
+        match self.byte()? {
+            0x00 => Ok((module, field, self.usize()?)),
+            kind => Err(self.error_at(self.offset - 1, &format!("unsupported import kind {:#04x}", kind))),
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let offset = self.offset;
+        u32::try_from(self.usize()?).map_err(|_| self.error_at(offset, "integer too large"))
+    }
+
+    fn limits(&mut self) -> Result<Memory> {
+        match self.byte()? {
+            0x00 => Ok(Memory { min: self.u32()?, max: None }),
+            0x01 => Ok(Memory { min: self.u32()?, max: Some(self.u32()?) }),
+            flag => Err(self.error_at(self.offset - 1, &format!("unsupported limits {:#04x}", flag))),
+        }
+    }
+
+    // 定数式は i32.const だけを扱う
+    fn const_expr(&mut self) -> Result<i32> {
+        if self.byte()? != 0x41 {
+            return Err(self.error_at(self.offset - 1, "unsupported constant expression"));
+        }
+        let value = self.i32()?;
+        if self.byte()? != 0x0b {
+            return Err(self.error_at(self.offset - 1, "unsupported constant expression"));
+        }
+        Ok(value)
+    }
+
+    fn global(&mut self) -> Result<(ValType, bool, i32)> {
+        let vtype = self.val_type()?;
+        let mutable = match self.byte()? {
+            0x00 => false,
+            0x01 => true,
+            flag => return Err(self.error_at(self.offset - 1, &format!("invalid mutability {:#04x}", flag))),
+        };
+        Ok((vtype, mutable, self.const_expr()?))
+    }
+
+    // 名前、種類、番号と、エラーを出すための位置
+    fn export(&mut self) -> Result<(String, u8, usize, usize)> {
+        let name = self.name()?;
+        let offset = self.offset;
+        match self.byte()? {
+            kind @ (0x00 | 0x02 | 0x03) => Ok((name, kind, self.usize()?, offset)),
+            kind => Err(self.error_at(offset, &format!("unsupported export kind {:#04x}", kind))),
+        }
+    }
+
+    fn data(&mut self) -> Result<Data> {
+        if self.byte()? != 0x00 {
+            return Err(self.error_at(self.offset - 1, "only active data segments for memory 0 are supported"));
+        }
+        let offset = self.const_expr()?;
+        let len = self.usize()?;
+        Ok(Data { offset, bytes: self.take(len)?.to_vec() })
+    }
+
+    fn names(&mut self) -> Result<Names> {
+        let mut names = Names::default();
+        while self.offset < self.bytes.len() {
+            let id = self.byte()?;
+            let size = self.usize()?;
+            let end = self.offset.checked_add(size).filter(|end| *end <= self.bytes.len())
+                .ok_or_else(|| self.error("name subsection size out of bounds"))?;
+            match id {
+                0x01 => names.functions = self.name_map()?,
+                0x02 => names.locals = self.indirect_name_map()?,
+                0x03 => names.labels = self.indirect_name_map()?,
+                0x07 => names.globals = self.name_map()?,
+                // モジュール名やその他の名前は使わない
+                _ => self.offset = end,
+            }
+            if self.offset != end {
+                return Err(self.error("name subsection size mismatch"));
+            }
+        }
+        Ok(names)
+    }
+
+    fn name_map(&mut self) -> Result<HashMap<usize, String>> {
+        let mut names = HashMap::new();
+        for _ in 0..self.usize()? {
+            let index = self.usize()?;
+            names.insert(index, self.name()?);
+        }
+        Ok(names)
+    }
+
+    fn indirect_name_map(&mut self) -> Result<HashMap<usize, HashMap<usize, String>>> {
+        let mut maps = HashMap::new();
+        for _ in 0..self.usize()? {
+            let index = self.usize()?;
+            maps.insert(index, self.name_map()?);
+        }
+        Ok(maps)
+    }
+
+    fn function(&mut self, names: &'a Names, index: usize, params: &[ValType], results: &[ValType], num_functions: usize) -> Result<Function> {
+        let size = self.usize()?;
+        let end = self.offset.checked_add(size).filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| self.error("function body size out of bounds"))?;
+        let mut body = BodyReader {
+            reader: Reader { bytes: &self.bytes[..end], offset: self.offset },
+            names,
+            index,
+            num_functions,
+            num_locals: params.len(),
+            labels: vec![],
+            used_labels: HashSet::new(),
+            next_label: 0,
+        };
+
+        let mut locals = vec![];
+        for _ in 0..body.reader.usize()? {
+            let count = body.reader.usize()?;
+            let vtype = body.reader.val_type()?;
+            if locals.len() + count > MAX_LOCALS {
+                return Err(body.reader.error("too many locals"));
+            }
+            for _ in 0..count {
+                locals.push(Local::new(&names.local(index, params.len() + locals.len()), vtype));
+            }
+        }
+        body.num_locals += locals.len();
+        let instructions = body.end()?;
+        if body.reader.offset != end {
+            return Err(body.reader.error("function body size mismatch"));
+        }
+        self.offset = end;
+
+        Ok(Function {
+            name: names.function(index),
+            params: params.iter().enumerate().map(|(i, vtype)| Local::new(&names.local(index, i), *vtype)).collect(),
+            results: results.to_vec(),
+            locals,
+            body: instructions,
+        })
+    }
+
+}
+
+struct BodyReader<'a> {
+    reader: Reader<'a>,
+    names: &'a Names,
+    // 関数の番号
+    index: usize,
+    num_functions: usize,
+    // パラメータを含む
+    num_locals: usize,
+    // 開いているブロックのラベル
+    labels: Vec<String>,
+    // 分岐先になったラベル。if は名前があるか、これに含まれるときだけラベルを残す
+    used_labels: HashSet<String>,
+    next_label: usize,
+}
+
+impl <'a> BodyReader<'a> {
+
+    // else か end までを読み、どちらで終わったかも返す
+    fn instructions(&mut self) -> Result<(Vec<Instr>, u8)> {
+        let mut instructions = vec![];
+        loop {
+            let offset = self.reader.offset;
+            let opcode = self.reader.byte()?;
+            let instruction = match opcode {
+                0x05 | 0x0b => return Ok((instructions, opcode)),
+                0x02 => {
+                    let result = self.block_type()?;
+                    let label = self.push_label()?;
+                    let body = self.end()?;
+                    self.labels.pop();
+                    Instr::Block { label, result, body }
+                },
+                0x03 => {
+                    let result = self.block_type()?;
+                    let label = self.push_label()?;
+                    let body = self.end()?;
+                    self.labels.pop();
+                    Instr::Loop { label, result, body }
+                },
+                0x04 => {
+                    let result = self.block_type()?;
+                    let named = self.names.label(self.index, self.next_label).is_some();
+                    let label = self.push_label()?;
+                    let (then, terminator) = self.instructions()?;
+                    let otherwise = if terminator == 0x05 { self.end()? } else { vec![] };
+                    self.labels.pop();
+                    let label = if named || self.used_labels.contains(&label) { Some(label) } else { None };
+                    Instr::If { label, result, then, otherwise }
+                },
+                0x0c => Instr::Br(self.label()?),
+                0x0d => Instr::BrIf(self.label()?),
+                0x0f => Instr::Return,
+                0x10 => Instr::Call(self.function()?),
+                0x12 => Instr::ReturnCall(self.function()?),
+                0x1a => Instr::Drop,
+                0x20 => Instr::LocalGet(self.local()?),
+                0x21 => Instr::LocalSet(self.local()?),
+                0x22 => Instr::LocalTee(self.local()?),
+                0x41 => Instr::I32Const(self.reader.i32()?),
+                _ => match (NumOp::from_opcode(opcode), MemOp::from_opcode(opcode)) {
+                    (Some(op), _) => Instr::Numeric(op),
+                    (None, Some(op)) => {
+                        // align は読み書きする大きさを超えられない。書き出すときは大きさから決めるので残さない
+                        let align_offset = self.reader.offset;
+                        if self.reader.u32()? > op.align() {
+                            return Err(self.reader.error_at(align_offset, "alignment must not be larger than natural"));
+                        }
+                        Instr::Memory { op, offset: self.reader.u32()? }
+                    },
+                    (None, None) => return Err(self.reader.error_at(offset, &format!("unknown opcode {:#04x}", opcode))),
+                },
+            };
+            instructions.push(instruction);
+        }
+    }
+
+    fn end(&mut self) -> Result<Vec<Instr>> {
+        match self.instructions()? {
+            (instructions, 0x0b) => Ok(instructions),
+            _ => Err(self.reader.error_at(self.reader.offset - 1, "unexpected else")),
+        }
+    }
+
+    fn block_type(&mut self) -> Result<Option<ValType>> {
+        match self.reader.bytes.get(self.reader.offset) {
+            Some(0x40) => {
+                self.reader.offset += 1;
+                Ok(None)
+            },
+            _ => self.reader.val_type().map(Some),
+        }
+    }
+
+    fn push_label(&mut self) -> Result<String> {
+        if self.labels.len() >= MAX_NESTING {
+            return Err(self.reader.error("blocks are nested too deeply"));
+        }
+        let label = self.names.label(self.index, self.next_label).unwrap_or_else(|| format!("label{}", self.next_label));
+        self.next_label += 1;
+        self.labels.push(label.to_string());
+        Ok(label)
+    }
+
+    fn label(&mut self) -> Result<String> {
+        let depth = self.reader.usize()?;
+        match self.labels.iter().rev().nth(depth) {
+            Some(label) => {
+                self.used_labels.insert(label.to_string());
+                Ok(label.to_string())
+            },
+            None => Err(self.reader.error(&format!("label depth {} out of range", depth))),
+        }
+    }
+
+    fn function(&mut self) -> Result<String> {
+        let index = self.reader.usize()?;
+        if index < self.num_functions {
+            Ok(self.names.function(index))
+        } else {
+            Err(self.reader.error(&format!("function index {} out of range", index)))
+        }
+    }
+
+    fn local(&mut self) -> Result<String> {
+        let index = self.reader.usize()?;
+        if index < self.num_locals {
+            Ok(self.names.local(self.index, index))
+        } else {
+            Err(self.reader.error(&format!("local index {} out of range", index)))
+        }
+    }
+
+}
+
+#[cfg(test)]
+fn encode(module: &Module) -> Vec<u8> {
+    let mut buf = vec![];
+    module.write_wasm(&mut buf).unwrap();
+    buf
+}
+
+#[cfg(test)]
+fn test_module() -> Module {
+    use Instr::*;
+    Module {
+        imports: vec![Import {
+            module: "env".to_string(),
+            field: "print".to_string(),
+            name: "print".to_string(),
+            params: vec![ValType::I32],
+            results: vec![],
+        }],
+        functions: vec![
+            Function {
+                name: "main".to_string(),
+                params: vec![Local::new("n", ValType::I32)],
+                results: vec![ValType::I32],
+                locals: vec![Local::new("i", ValType::I32)],
+                body: vec![
+                    Block { label: "b".to_string(), result: Some(ValType::I32), body: vec![
+                        Loop { label: "l".to_string(), result: None, body: vec![
+                            LocalGet("i".to_string()), LocalGet("n".to_string()), Numeric(NumOp::I32GeS), BrIf("l".to_string()),
+                            I32Const(-123456), LocalTee("i".to_string()), Call("print".to_string()),
+                        ]},
+                        I32Const(1),
+                        If { label: Some("if".to_string()), result: None, then: vec![Br("if".to_string())], otherwise: vec![Call("f".to_string()), Drop] },
+                        If { label: None, result: Some(ValType::I32), then: vec![I32Const(1)], otherwise: vec![I32Const(2)] },
+                    ]},
+                    Return,
+                ],
+            },
+            Function {
+                name: "f".to_string(),
+                params: vec![],
+                results: vec![ValType::I32],
+                locals: vec![],
+                body: NumOp::ALL.iter().map(|op| Numeric(*op))
+                    .chain(MemOp::ALL.iter().map(|op| Memory { op: *op, offset: 4 }))
+                    .chain([ReturnCall("f".to_string())]).collect(),
+            },
+        ],
+        memory: Some(crate::ir::Memory { min: 1, max: Some(2) }),
+        globals: vec![Global { name: "sp".to_string(), vtype: ValType::I32, mutable: true, init: 65536 }],
+        exports: vec![
+            Export::function("main", "main"),
+            Export { name: "memory".to_string(), kind: ExportKind::Memory },
+            Export { name: "sp".to_string(), kind: ExportKind::Global("sp".to_string()) },
+        ],
+        data: vec![Data { offset: 8, bytes: b"hello\n".to_vec() }],
+        customs: vec![Custom { name: "producers".to_string(), bytes: vec![0x00] }],
+        unresolved: vec![],
+    }
+}
+
+// 最後の custom section である name section を取り除く
+#[cfg(test)]
+fn strip_names(bytes: &[u8]) -> Vec<u8> {
+    let mut offset = 8;
+    let mut stripped = bytes[..8].to_vec();
+    while offset < bytes.len() {
+        let (size, len) = leb128_to_usize(&bytes[offset + 1..]).unwrap();
+        let section = &bytes[offset..offset + 1 + len + size];
+        if section[0] != 0x00 || !section[1 + len..].starts_with(b"\x04name") {
+            stripped.extend(section);
+        }
+        offset += section.len();
+    }
+    stripped
+}
+
+#[test]
+fn test_round_trip() {
+    let module = test_module();
+    let bytes = encode(&module);
+    let decoded = read_module(&bytes).unwrap();
+    assert_eq!(decoded, module);
+    assert_eq!(encode(&decoded), bytes);
+}
+
+#[test]
+fn test_without_names() {
+    use Instr::*;
+    let bytes = strip_names(&encode(&test_module()));
+    let decoded = read_module(&bytes).unwrap();
+    assert_eq!(decoded.imports[0].name, "func0");
+    assert_eq!(decoded.exports[0], Export::function("main", "func1"));
+    assert_eq!(decoded.exports[2], Export { name: "sp".to_string(), kind: ExportKind::Global("global0".to_string()) });
+    assert_eq!(decoded.functions[0].locals, vec![Local::new("local1", ValType::I32)]);
+    // 分岐先にならない if にはラベルを付けない
+    let Block { body, .. } = &decoded.functions[0].body[0] else { panic!() };
+    assert!(matches!(&body[2], If { label: Some(_), .. }));
+    assert!(matches!(&body[3], If { label: None, .. }));
+    assert_eq!(strip_names(&encode(&decoded)), bytes);
+}
+
+#[test]
+fn test_errors() {
+    assert_eq!(read_module(b"\0asm\x02\0\0\0").unwrap_err().message, "unsupported wasm version");
+    // 本体が途中で切れている
+    let mut bytes = b"\0asm\x01\0\0\0".to_vec();
+    bytes.extend([0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, 0x03, 0x02, 0x01, 0x00]);
+    bytes.extend([0x0a, 0x05, 0x01, 0x03, 0x00, 0x41, 0x2a]);
+    assert_eq!(read_module(&bytes).unwrap_err().message, "unexpected end");
+    let len = bytes.len();
+    bytes[len - 6] = 0x07;
+    bytes[len - 4] = 0x05;
+    bytes.extend([0xfc, 0x0b]);
+    assert_eq!(read_module(&bytes).unwrap_err(), DecodeError { offset: len, message: "unknown opcode 0xfc".to_string() });
+}
@@ -0,0 +1,201 @@
+use std::fmt;
+use crate::ir::ValType;
+
+// 命令。ブロックは入れ子の命令列として持ち、分岐先はラベル名、変数と関数は名前で参照する
+#[derive(Clone, PartialEq, Debug)]
+pub enum Instr {
+    Block { label: String, result: Option<ValType>, body: Vec<Instr> },
+    Loop { label: String, result: Option<ValType>, body: Vec<Instr> },
+    // ラベルは分岐先になるときだけ付ける
+    If { label: Option<String>, result: Option<ValType>, then: Vec<Instr>, otherwise: Vec<Instr> },
+    Br(String),
+    BrIf(String),
+    Return,
+    Call(String),
+    ReturnCall(String),
+    Drop,
+    LocalGet(String),
+    LocalSet(String),
+    LocalTee(String),
+    I32Const(i32),
+    // 線形メモリのアドレスになる i32.const。オブジェクトに書き出すときだけ再配置の対象として区別する
+    Address(i32),
+    Numeric(NumOp),
+    // アドレスに offset を足した位置を読み書きする。メモリは 1 つだけ
+    Memory { op: MemOp, offset: u32 },
+}
+
+// 即値を取らない数値命令
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NumOp {
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32LtS,
+    I32GtS,
+    I32LeS,
+    I32GeS,
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32DivS,
+    I32Shl,
+    I32ShrS,
+    I32ShrU,
+}
+
+impl NumOp {
+
+    pub const ALL: [NumOp; 14] = [
+        NumOp::I32Eqz, NumOp::I32Eq, NumOp::I32Ne, NumOp::I32LtS, NumOp::I32GtS, NumOp::I32LeS, NumOp::I32GeS,
+        NumOp::I32Add, NumOp::I32Sub, NumOp::I32Mul, NumOp::I32DivS, NumOp::I32Shl, NumOp::I32ShrS, NumOp::I32ShrU,
+    ];
+
+    pub fn from_opcode(opcode: u8) -> Option<NumOp> {
+        NumOp::ALL.into_iter().find(|op| op.opcode() == opcode)
+    }
+
+    pub fn from_wat_name(name: &str) -> Option<NumOp> {
+        NumOp::ALL.into_iter().find(|op| op.wat_name() == name)
+    }
+
+    pub fn wat_name(&self) -> &'static str {
+        match self {
+            NumOp::I32Eqz => "i32.eqz",
+            NumOp::I32Eq => "i32.eq",
+            NumOp::I32Ne => "i32.ne",
+            NumOp::I32LtS => "i32.lt_s",
+            NumOp::I32GtS => "i32.gt_s",
+            NumOp::I32LeS => "i32.le_s",
+            NumOp::I32GeS => "i32.ge_s",
+            NumOp::I32Add => "i32.add",
+            NumOp::I32Sub => "i32.sub",
+            NumOp::I32Mul => "i32.mul",
+            NumOp::I32DivS => "i32.div_s",
+            NumOp::I32Shl => "i32.shl",
+            NumOp::I32ShrS => "i32.shr_s",
+            NumOp::I32ShrU => "i32.shr_u",
+        }
+    }
+
+    pub fn opcode(&self) -> u8 {
+        match self {
+            NumOp::I32Eqz => 0x45,
+            NumOp::I32Eq => 0x46,
+            NumOp::I32Ne => 0x47,
+            NumOp::I32LtS => 0x48,
+            NumOp::I32GtS => 0x4a,
+            NumOp::I32LeS => 0x4c,
+            NumOp::I32GeS => 0x4e,
+            NumOp::I32Add => 0x6a,
+            NumOp::I32Sub => 0x6b,
+            NumOp::I32Mul => 0x6c,
+            NumOp::I32DivS => 0x6d,
+            NumOp::I32Shl => 0x74,
+            NumOp::I32ShrS => 0x75,
+            NumOp::I32ShrU => 0x76,
+        }
+    }
+
+}
+
+// メモリを読み書きする命令。アドレスは符号なしの i32
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MemOp {
+    I32Load,
+    I32Load8U,
+    I32Store,
+    I32Store8,
+}
+
+impl MemOp {
+
+    pub const ALL: [MemOp; 4] = [MemOp::I32Load, MemOp::I32Load8U, MemOp::I32Store, MemOp::I32Store8];
+
+    pub fn from_opcode(opcode: u8) -> Option<MemOp> {
+        MemOp::ALL.into_iter().find(|op| op.opcode() == opcode)
+    }
+
+    pub fn from_wat_name(name: &str) -> Option<MemOp> {
+        MemOp::ALL.into_iter().find(|op| op.wat_name() == name)
+    }
+
+    pub fn wat_name(&self) -> &'static str {
+        match self {
+            MemOp::I32Load => "i32.load",
+            MemOp::I32Load8U => "i32.load8_u",
+            MemOp::I32Store => "i32.store",
+            MemOp::I32Store8 => "i32.store8",
+        }
+    }
+
+    pub fn opcode(&self) -> u8 {
+        match self {
+            MemOp::I32Load => 0x28,
+            MemOp::I32Load8U => 0x2d,
+            MemOp::I32Store => 0x36,
+            MemOp::I32Store8 => 0x3a,
+        }
+    }
+
+    // 読み書きするバイト数
+    pub fn size(&self) -> u32 {
+        match self {
+            MemOp::I32Load | MemOp::I32Store => 4,
+            MemOp::I32Load8U | MemOp::I32Store8 => 1,
+        }
+    }
+
+    // バイナリに書く align は size の 2 を底とする対数
+    pub fn align(&self) -> u32 {
+        self.size().trailing_zeros()
+    }
+
+    // store はアドレスと値を取って何も残さない
+    pub fn is_store(&self) -> bool {
+        matches!(self, MemOp::I32Store | MemOp::I32Store8)
+    }
+
+}
+
+// 入れ子を除いた命令 1 つ分の WAT 表記
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instr::Block { label, result, .. } => write!(f, "block ${}{}", label, block_type(result)),
+            Instr::Loop { label, result, .. } => write!(f, "loop ${}{}", label, block_type(result)),
+            Instr::If { label: Some(label), result, .. } => write!(f, "if ${}{}", label, block_type(result)),
+            Instr::If { label: None, result, .. } => write!(f, "if{}", block_type(result)),
+            Instr::Br(label) => write!(f, "br ${}", label),
+            Instr::BrIf(label) => write!(f, "br_if ${}", label),
+            Instr::Return => write!(f, "return"),
+            Instr::Call(name) => write!(f, "call ${}", name),
+            Instr::ReturnCall(name) => write!(f, "return_call ${}", name),
+            Instr::Drop => write!(f, "drop"),
+            Instr::LocalGet(name) => write!(f, "local.get ${}", name),
+            Instr::LocalSet(name) => write!(f, "local.set ${}", name),
+            Instr::LocalTee(name) => write!(f, "local.tee ${}", name),
+            Instr::I32Const(value) | Instr::Address(value) => write!(f, "i32.const {}", value),
+            Instr::Numeric(op) => write!(f, "{}", op.wat_name()),
+            Instr::Memory { op, offset: 0 } => write!(f, "{}", op.wat_name()),
+            Instr::Memory { op, offset } => write!(f, "{} offset={}", op.wat_name(), offset),
+        }
+    }
+}
+
+fn block_type(result: &Option<ValType>) -> String {
+    match result {
+        Some(vtype) => format!(" (result {})", vtype.wat_name()),
+        None => String::new(),
+    }
+}
+
+// 平らに書き出したときの命令数 (block などは end まで数える)
+pub fn instruction_count(instructions: &[Instr]) -> usize {
+    instructions.iter().map(|instruction| match instruction {
+        Instr::Block { body, .. } | Instr::Loop { body, .. } => 2 + instruction_count(body),
+        Instr::If { then, otherwise, .. } if otherwise.is_empty() => 2 + instruction_count(then),
+        Instr::If { then, otherwise, .. } => 3 + instruction_count(then) + instruction_count(otherwise),
+        _ => 1,
+    }).sum()
+}
@@ -0,0 +1,336 @@
+use std::io::{Write, Result};
+use crate::ir::{ExportKind, Module, ValType};
+
+// グルーコードが export する名前。wasm の export と重なってはいけない
+const HELPERS: [&str; 6] = ["init", "memoryBytes", "readString", "writeString", "readI32", "writeI32"];
+
+const RESERVED: [&str; 38] = [
+    "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default", "delete",
+    "do", "else", "enum", "export", "extends", "false", "finally", "for", "function", "if", "implements",
+    "import", "in", "instanceof", "interface", "let", "new", "null", "package", "return", "static",
+    "super", "switch", "this", "throw", "true", "typeof", "var",
+];
+
+// 読み込みと引数の検査。wasmc で始まる名前はグルーコードの中だけで使う
+const RUNTIME: &str = r#"let wasmcInstance = null;
+
+function wasmcExports() {
+  if (wasmcInstance === null) {
+    throw new Error("init() has not been called");
+  }
+  return wasmcInstance.exports;
+}
+
+function wasmcCheckArguments(name, actual, expected) {
+  if (actual !== expected) {
+    throw new TypeError(`${name}: expected ${expected} arguments, got ${actual}`);
+  }
+}
+
+function wasmcI32(name, index, value) {
+  if (!Number.isInteger(value) || value < -2147483648 || value > 2147483647) {
+    throw new TypeError(`${name}: argument ${index} must be an i32, got ${String(value)}`);
+  }
+  return value;
+}
+
+function wasmcImport(imports, module, field, builtin) {
+  const value = imports[module]?.[field] ?? builtin;
+  if (typeof value !== "function") {
+    throw new TypeError(`import ${module}.${field} must be a function`);
+  }
+  return value;
+}
+
+async function wasmcCompile(source) {
+  if (source instanceof WebAssembly.Module) {
+    return source;
+  }
+  if (source instanceof ArrayBuffer || ArrayBuffer.isView(source)) {
+    return WebAssembly.compile(source);
+  }
+  const url = new URL(source, import.meta.url);
+  if (url.protocol === "file:") {
+    const { readFile } = await import("node:fs/promises");
+    return WebAssembly.compile(await readFile(url));
+  }
+  return WebAssembly.compileStreaming(fetch(url));
+}
+
+let wasmcPending = "";
+
+function wasmcOutput(text) {
+  if (typeof process !== "undefined" && process.stdout) {
+    process.stdout.write(text);
+    return;
+  }
+  const lines = (wasmcPending + text).split("\n");
+  wasmcPending = lines.pop();
+  for (const line of lines) {
+    console.log(line);
+  }
+}
+"#;
+
+// メモリを読み書きするヘルパ。文字列は 0 で終わる UTF-8 で、アドレスは i32 のまま渡す
+const MEMORY: &str = r#"
+export function memoryBytes() {
+  return new Uint8Array(wasmcExports()[{memory}].buffer);
+}
+
+function wasmcRange(bytes, pointer, length) {
+  if (!Number.isInteger(pointer) || pointer < 0 || pointer + length > bytes.length) {
+    throw new RangeError(`address ${String(pointer)} is out of bounds`);
+  }
+}
+
+export function readString(pointer) {
+  const bytes = memoryBytes();
+  wasmcRange(bytes, pointer, 0);
+  let end = pointer;
+  while (end < bytes.length && bytes[end] !== 0) {
+    end++;
+  }
+  return new TextDecoder().decode(bytes.subarray(pointer, end));
+}
+
+export function writeString(pointer, string) {
+  const bytes = memoryBytes();
+  const encoded = new TextEncoder().encode(string);
+  wasmcRange(bytes, pointer, encoded.length + 1);
+  bytes.set(encoded, pointer);
+  bytes[pointer + encoded.length] = 0;
+  return encoded.length + 1;
+}
+
+export function readI32(pointer) {
+  const bytes = memoryBytes();
+  wasmcRange(bytes, pointer, 4);
+  return new DataView(bytes.buffer).getInt32(pointer, true);
+}
+
+export function writeI32(pointer, value) {
+  const bytes = memoryBytes();
+  wasmcRange(bytes, pointer, 4);
+  new DataView(bytes.buffer).setInt32(pointer, wasmcI32("writeI32", 1, value), true);
+}
+"#;
+
+// 型を書いた関数の宣言。パラメータの名前がなければ arg0, arg1, ... にする
+struct Signature {
+    name: String,
+    params: Vec<(String, ValType)>,
+    results: Vec<ValType>,
+}
+
+fn ts_type(results: &[ValType]) -> &'static str {
+    match results.first() {
+        Some(ValType::I32) => "number",
+        None => "void",
+    }
+}
+
+fn string_literal(text: &str) -> String {
+    let mut literal = "\"".to_string();
+    for c in text.chars() {
+        match c {
+            '"' | '\\' => literal.extend(['\\', c]),
+            c if (c as u32) < 0x20 => literal.push_str(&format!("\\u{:04x}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && !RESERVED.contains(&name)
+}
+
+// パラメータの名前を JavaScript の識別子にする。使えないか重なるときは番号で呼ぶ
+fn parameter_names(names: Vec<String>) -> Vec<String> {
+    let names: Vec<String> = names.into_iter()
+        .map(|name| name.chars().map(|c| if c.is_ascii_alphanumeric() || c == '$' { c } else { '_' }).collect())
+        .collect();
+    let unique = names.iter().enumerate().all(|(i, name)| !names[..i].contains(name));
+    if unique && names.iter().all(|name: &String| is_identifier(name) && !name.starts_with("wasmc")) {
+        names
+    } else {
+        (0..names.len()).map(|i| format!("arg{}", i)).collect()
+    }
+}
+
+// export した関数。名前はそのまま JavaScript の関数名になる
+fn exports(module: &Module) -> Vec<Signature> {
+    module.exports.iter().filter_map(|export| match &export.kind {
+        ExportKind::Function(name) => Some((&export.name, name)),
+        _ => None,
+    }).map(|(export, name)| {
+        if !is_identifier(export) || HELPERS.contains(&export.as_str()) || export.starts_with("wasmc") {
+            panic!("`{}` は JavaScript の関数名に使えません", export);
+        }
+        let (params, results) = match module.signature(name) {
+            Some(signature) => signature,
+            None => panic!("function `{}` not found", name),
+        };
+        let names = match module.function(name) {
+            Some(function) => function.params.iter().map(|param| param.name.to_string()).collect(),
+            None => (0..params.len()).map(|i| format!("arg{}", i)).collect(),
+        };
+        Signature { name: export.to_string(), params: parameter_names(names).into_iter().zip(params).collect(), results }
+    }).collect()
+}
+
+fn memory_export(module: &Module) -> Option<&str> {
+    module.exports.iter().find(|export| export.kind == ExportKind::Memory).map(|export| export.name.as_str())
+}
+
+// ホストが渡さなくてもグルーコードが用意する import。WASI と同じく標準出力に書いて 0 を返す
+fn builtin(module: &Module, import_module: &str, field: &str) -> Option<&'static str> {
+    match (import_module, field) {
+        ("env", "print_int") => Some("(value) => { wasmcOutput(String(value)); return 0; }"),
+        ("env", "print_str") if memory_export(module).is_some() => Some("(pointer) => { wasmcOutput(readString(pointer)); return 0; }"),
+        _ => None,
+    }
+}
+
+// wasm を読み込んで export した関数を包む ES モジュールを書き出す。wasm はこのファイルからの相対パス
+pub fn write_module(module: &Module, wasm: &str, write: &mut dyn Write) -> Result<()> {
+    let exports = exports(module);
+    write!(write, "{}", RUNTIME)?;
+    if let Some(memory) = memory_export(module) {
+        write!(write, "{}", MEMORY.replace("{memory}", &string_literal(memory)))?;
+    }
+
+    writeln!(write)?;
+    writeln!(write, "export async function init(imports = {{}}, source = {}) {{", string_literal(wasm))?;
+    writeln!(write, "  const module = await wasmcCompile(source);")?;
+    writeln!(write, "  wasmcInstance = await WebAssembly.instantiate(module, {{")?;
+    let mut modules: Vec<&str> = vec![];
+    for import in module.imports.iter() {
+        if !modules.contains(&import.module.as_str()) {
+            modules.push(&import.module);
+        }
+    }
+    for import_module in modules {
+        writeln!(write, "    {}: {{", string_literal(import_module))?;
+        for import in module.imports.iter().filter(|import| import.module == import_module) {
+            let builtin = builtin(module, &import.module, &import.field).map(|builtin| format!(", {}", builtin)).unwrap_or_default();
+            writeln!(write, "      {}: wasmcImport(imports, {}, {}{}),", string_literal(&import.field),
+                     string_literal(&import.module), string_literal(&import.field), builtin)?;
+        }
+        writeln!(write, "    }},")?;
+    }
+    writeln!(write, "  }});")?;
+    writeln!(write, "}}")?;
+
+    for export in exports.iter() {
+        let names: Vec<&str> = export.params.iter().map(|(name, _)| name.as_str()).collect();
+        let args: Vec<String> = names.iter().enumerate()
+            .map(|(i, name)| format!("wasmcI32({}, {}, {})", string_literal(&export.name), i, name))
+            .collect();
+        writeln!(write)?;
+        writeln!(write, "export function {}({}) {{", export.name, names.join(", "))?;
+        writeln!(write, "  wasmcCheckArguments({}, arguments.length, {});", string_literal(&export.name), names.len())?;
+        let call = format!("wasmcExports()[{}]({})", string_literal(&export.name), args.join(", "));
+        if export.results.is_empty() {
+            writeln!(write, "  {};", call)?;
+        } else {
+            writeln!(write, "  return {};", call)?;
+        }
+        writeln!(write, "}}")?;
+    }
+    Ok(())
+}
+
+// write_module が書き出すモジュールの TypeScript の型宣言
+pub fn write_declarations(module: &Module, write: &mut dyn Write) -> Result<()> {
+    let exports = exports(module);
+    // 組み込みのない import があればホストが必ず渡す
+    let required = module.imports.iter().any(|import| builtin(module, &import.module, &import.field).is_none());
+    writeln!(write, "export interface Imports {{")?;
+    let mut modules: Vec<&str> = vec![];
+    for import in module.imports.iter() {
+        if !modules.contains(&import.module.as_str()) {
+            modules.push(&import.module);
+        }
+    }
+    for import_module in modules {
+        let imports: Vec<_> = module.imports.iter().filter(|import| import.module == import_module).collect();
+        let optional = imports.iter().all(|import| builtin(module, &import.module, &import.field).is_some());
+        writeln!(write, "  {}{}: {{", string_literal(import_module), if optional { "?" } else { "" })?;
+        for import in imports {
+            let params: Vec<String> = import.params.iter().enumerate()
+                .map(|(i, vtype)| format!("arg{}: {}", i, ts_type(&[*vtype])))
+                .collect();
+            let optional = if builtin(module, &import.module, &import.field).is_some() { "?" } else { "" };
+            writeln!(write, "    {}{}: ({}) => {};", string_literal(&import.field), optional, params.join(", "), ts_type(&import.results))?;
+        }
+        writeln!(write, "  }};")?;
+    }
+    writeln!(write, "}}")?;
+    writeln!(write)?;
+    writeln!(write, "export declare function init(imports{}: Imports, source?: string | URL | BufferSource | WebAssembly.Module): Promise<void>;",
+             if required { "" } else { "?" })?;
+    if memory_export(module).is_some() {
+        writeln!(write, "export declare function memoryBytes(): Uint8Array;")?;
+        writeln!(write, "export declare function readString(pointer: number): string;")?;
+        writeln!(write, "export declare function writeString(pointer: number, string: string): number;")?;
+        writeln!(write, "export declare function readI32(pointer: number): number;")?;
+        writeln!(write, "export declare function writeI32(pointer: number, value: number): void;")?;
+    }
+    if !exports.is_empty() {
+        writeln!(write)?;
+    }
+    for export in exports.iter() {
+        let params: Vec<String> = export.params.iter().map(|(name, vtype)| format!("{}: {}", name, ts_type(&[*vtype]))).collect();
+        writeln!(write, "export declare function {}({}): {};", export.name, params.join(", "), ts_type(&export.results))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+fn glue(source: &str) -> (String, String) {
+    let module = crate::wasmc::parse(source).lower();
+    let mut js = vec![];
+    write_module(&module, "out.wasm", &mut js).unwrap();
+    let mut dts = vec![];
+    write_declarations(&module, &mut dts).unwrap();
+    (String::from_utf8(js).unwrap(), String::from_utf8(dts).unwrap())
+}
+
+#[test]
+fn test_glue() {
+    let (js, dts) = glue("main(n,delete){return twice(n)-delete;}");
+    assert!(js.contains("export async function init(imports = {}, source = \"out.wasm\") {\n"));
+    assert!(js.contains("      \"twice\": wasmcImport(imports, \"env\", \"twice\"),\n"));
+    assert!(js.contains("export function main(arg0, arg1) {\n  wasmcCheckArguments(\"main\", arguments.length, 2);\n  \
+                         return wasmcExports()[\"main\"](wasmcI32(\"main\", 0, arg0), wasmcI32(\"main\", 1, arg1));\n}\n"));
+    assert!(!js.contains("readString"));
+    assert!(dts.contains("  \"env\": {\n    \"twice\": (arg0: number) => number;\n  };\n"));
+    assert!(dts.contains("export declare function init(imports: Imports, "));
+    assert!(dts.ends_with("\nexport declare function main(arg0: number, arg1: number): number;\n"));
+}
+
+#[test]
+fn test_glue_strings() {
+    let (js, dts) = glue("main(n){print_str(\"n = \");return print_int(n);}");
+    assert!(js.contains("return new Uint8Array(wasmcExports()[\"memory\"].buffer);"));
+    assert!(js.contains("\"print_str\": wasmcImport(imports, \"env\", \"print_str\", (pointer) => "));
+    assert!(js.contains("export function main(n) {\n"));
+    assert!(dts.contains("  \"env\"?: {\n    \"print_str\"?: (arg0: number) => number;\n"));
+    assert!(dts.contains("export declare function init(imports?: Imports, "));
+    assert!(dts.contains("export declare function readString(pointer: number): string;\n"));
+    assert!(dts.ends_with("export declare function main(n: number): number;\n"));
+}
+
+#[test]
+#[should_panic(expected = "`init` は JavaScript の関数名に使えません")]
+fn test_glue_reserved() {
+    let mut module = crate::wasmc::parse("main(){return 0;}").lower();
+    module.exports[0].name = "init".to_string();
+    write_module(&module, "out.wasm", &mut vec![]).unwrap();
+}
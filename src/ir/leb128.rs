@@ -0,0 +1,120 @@
+pub fn i32_to_leb128(num: i32) -> Vec<u8> {
+    let mut cur = num;
+    let mut res = Vec::<u8>::new();
+    loop {
+        let byte = (cur & 0x7f) as u8;
+        cur >>= 7;
+        // 最後のバイトの bit 6 が符号ビットになる
+        if (cur == 0 && byte & 0x40 == 0) || (cur == -1 && byte & 0x40 != 0) {
+            res.push(byte);
+            return res;
+        }
+        res.push(0x80 + byte);
+    }
+}
+
+pub fn usize_to_leb128(num: usize) -> Vec<u8> {
+    let mut cur = num;
+    let mut res = Vec::<u8>::new();
+    while cur >= 127 {
+        res.push (0x80 +  ((cur & 0x7f) as u8));
+        cur >>= 7;
+    }
+    res.push((cur & 0x7f) as u8);
+    res
+}
+
+// 再配置で後から書き換えられるように、値によらず 5 バイトで書く
+pub fn usize_to_padded_leb128(num: u32) -> [u8; 5] {
+    let mut res = [0; 5];
+    for (i, byte) in res.iter_mut().enumerate() {
+        *byte = ((num >> (i * 7)) & 0x7f) as u8 | if i < 4 { 0x80 } else { 0 };
+    }
+    res
+}
+
+pub fn i32_to_padded_leb128(num: i32) -> [u8; 5] {
+    let mut res = [0; 5];
+    for (i, byte) in res.iter_mut().enumerate() {
+        *byte = ((num >> (i * 7)) & 0x7f) as u8 | if i < 4 { 0x80 } else { 0 };
+    }
+    res
+}
+
+// 読んだ値とバイト数を返す。途中で切れているか 32 bit に収まらなければ None
+pub fn leb128_to_i32(bytes: &[u8]) -> Option<(i32, usize)> {
+    let mut result: i64 = 0;
+    for (i, byte) in bytes.iter().enumerate().take(5) {
+        result |= ((byte & 0x7f) as i64) << (i * 7);
+        if byte & 0x80 == 0 {
+            let shift = 64 - (i + 1) * 7;
+            // bit 6 を符号として広げる
+            let value = (result << shift) >> shift;
+            return i32::try_from(value).ok().map(|value| (value, i + 1));
+        }
+    }
+    None
+}
+
+pub fn leb128_to_usize(bytes: &[u8]) -> Option<(usize, usize)> {
+    let mut result: u64 = 0;
+    for (i, byte) in bytes.iter().enumerate().take(5) {
+        result |= ((byte & 0x7f) as u64) << (i * 7);
+        if byte & 0x80 == 0 {
+            return u32::try_from(result).ok().map(|value| (value as usize, i + 1));
+        }
+    }
+    None
+}
+
+#[test]
+fn test_i32() {
+    assert_eq!(i32_to_leb128(0), vec![0x00]);
+    assert_eq!(i32_to_leb128(63), vec![0x3f]);
+    assert_eq!(i32_to_leb128(64), vec![0xc0, 0x00]);
+    assert_eq!(i32_to_leb128(126), vec![0xfe, 0x00]);
+    assert_eq!(i32_to_leb128(127), vec![0xff, 0x00]);
+    assert_eq!(i32_to_leb128(128), vec![0x80, 0x01]);
+    assert_eq!(i32_to_leb128(-1), vec![0x7f]);
+    assert_eq!(i32_to_leb128(-64), vec![0x40]);
+    assert_eq!(i32_to_leb128(-65), vec![0xbf, 0x7f]);
+    assert_eq!(i32_to_leb128(2147483647), vec![0xff, 0xff, 0xff, 0xff, 0x07]);
+    assert_eq!(i32_to_leb128(-127), vec![0x81, 0x7f]);
+    assert_eq!(i32_to_leb128(-128), vec![0x80, 0x7f]);
+    assert_eq!(i32_to_leb128(-129), vec![0xff, 0x7e]);
+    assert_eq!(i32_to_leb128(-123456), vec![0xc0, 0xbb, 0x78]);
+    assert_eq!(i32_to_leb128(-2147483648), vec![0x80, 0x80, 0x80, 0x80, 0x78]);
+}
+
+#[test]
+fn test_usize() {
+    assert_eq!(usize_to_leb128(0), vec![0x00]);
+    assert_eq!(usize_to_leb128(126), vec![0x7e]);
+    assert_eq!(usize_to_leb128(127), vec![0xff, 0x00]);
+    assert_eq!(usize_to_leb128(128), vec![0x80, 0x01]);
+    assert_eq!(usize_to_leb128(2147483647), vec![0xff, 0xff, 0xff, 0xff, 0x07]);
+}
+
+
+#[test]
+fn test_read() {
+    for num in [0, 63, 64, -1, -64, -65, 128, -123456, i32::MAX, i32::MIN] {
+        let bytes = i32_to_leb128(num);
+        assert_eq!(leb128_to_i32(&bytes), Some((num, bytes.len())));
+    }
+    for num in [0, 126, 127, 128, 2147483647] {
+        let bytes = usize_to_leb128(num);
+        assert_eq!(leb128_to_usize(&bytes), Some((num, bytes.len())));
+    }
+    assert_eq!(leb128_to_usize(&[0x80, 0x01, 0xff]), Some((128, 2)));
+    assert_eq!(leb128_to_usize(&[0x80]), None);
+    for num in [0, 127, 128, u32::MAX] {
+        assert_eq!(leb128_to_usize(&usize_to_padded_leb128(num)), Some((num as usize, 5)));
+    }
+    for num in [0, 63, -64, -65, 1024, i32::MAX, i32::MIN] {
+        assert_eq!(leb128_to_i32(&i32_to_padded_leb128(num)), Some((num, 5)));
+    }
+    assert_eq!(usize_to_padded_leb128(3), [0x83, 0x80, 0x80, 0x80, 0x00]);
+    assert_eq!(i32_to_padded_leb128(-1), [0xff, 0xff, 0xff, 0xff, 0x7f]);
+    assert_eq!(leb128_to_i32(&[0xff, 0xff, 0xff, 0xff, 0x0f]), None);
+}
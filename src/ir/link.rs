@@ -0,0 +1,512 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use crate::ir::{Data, DecodeError, Export, ExportKind, Function, Global, Import, Memory, Module};
+use crate::ir::decode::Reader;
+use crate::ir::leb128::{i32_to_padded_leb128, usize_to_padded_leb128};
+use crate::ir::object::{LINKING_VERSION, R_WASM_FUNCTION_INDEX_LEB, R_WASM_GLOBAL_INDEX_LEB, R_WASM_MEMORY_ADDR_SLEB, SYMTAB_DATA,
+                        SYMTAB_FUNCTION, SYMTAB_GLOBAL, WASM_SEGMENT_INFO, WASM_SYMBOL_TABLE, WASM_SYM_BINDING_LOCAL,
+                        WASM_SYM_EXPLICIT_NAME, WASM_SYM_EXPORTED, WASM_SYM_UNDEFINED};
+use crate::ir::wasm::{self, Code};
+
+const PAGE_SIZE: usize = 65536;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum LinkError {
+    // オブジェクトとして読めない
+    Object { object: String, message: String },
+    // どのオブジェクトにも定義がない
+    Undefined { symbol: String, object: String },
+    Duplicate { symbol: String, first: String, second: String },
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::Object { object, message } => write!(f, "{}: {}", object, message),
+            LinkError::Undefined { symbol, object } => write!(f, "{}: undefined symbol: {}", object, symbol),
+            LinkError::Duplicate { symbol, first, second } => write!(f, "duplicate symbol: {} (defined in {} and {})", symbol, first, second),
+        }
+    }
+}
+
+struct Symbol {
+    kind: u8,
+    flags: usize,
+    name: String,
+    // 関数とグローバル変数はオブジェクトの中での番号、データはセグメントの番号
+    index: usize,
+    // データのセグメントの中での位置
+    offset: usize,
+}
+
+impl Symbol {
+
+    fn defined(&self) -> bool {
+        self.flags & WASM_SYM_UNDEFINED == 0
+    }
+
+    fn local(&self) -> bool {
+        self.flags & WASM_SYM_BINDING_LOCAL != 0
+    }
+
+    // 名前を明示した未定義のシンボルは、ホストが渡す import
+    fn host(&self) -> bool {
+        !self.defined() && self.flags & WASM_SYM_EXPLICIT_NAME != 0
+    }
+
+}
+
+struct Reloc {
+    kind: u8,
+    offset: usize,
+    symbol: usize,
+    addend: i32,
+}
+
+struct Object<'a> {
+    name: &'a str,
+    // 型と名前、ローカル変数、データはここから使う。関数本体は code を書き換えたものを使う
+    module: Module,
+    // code section の中身
+    code: Vec<u8>,
+    symbols: Vec<Symbol>,
+    // セグメントごとの配置の単位 (2 の冪の指数)
+    alignments: Vec<u32>,
+    relocs: Vec<Reloc>,
+}
+
+// -c で書いたオブジェクトをまとめて 1 つのモジュールにする。関数、グローバル変数、データを
+// オブジェクトの順に並べ、シンボルを名前で解決してから再配置の位置の番号やアドレスを書き換える。
+// どこにも定義のない関数は、ホストが渡すものか allow_undefined なら import として残し、そうでなければ誤りにする
+pub fn link(objects: &[(&str, &[u8])], allow_undefined: bool) -> Result<Module, Vec<LinkError>> {
+    let objects: Vec<Object> = objects.iter()
+        .map(|(name, bytes)| read_object(name, bytes).map_err(|message| vec![LinkError::Object { object: name.to_string(), message }]))
+        .collect::<Result<_, _>>()?;
+
+    let mut errors = vec![];
+    // 種類と名前から、定義したオブジェクトとシンボルの番号を引く
+    let mut definitions: HashMap<(u8, &str), (usize, usize)> = HashMap::new();
+    for (i, object) in objects.iter().enumerate() {
+        for (j, symbol) in object.symbols.iter().enumerate() {
+            if !symbol.defined() || symbol.local() {
+                continue;
+            }
+            match definitions.get(&(symbol.kind, symbol.name.as_str())) {
+                Some((first, _)) => errors.push(LinkError::Duplicate {
+                    symbol: symbol.name.to_string(), first: objects[*first].name.to_string(), second: object.name.to_string(),
+                }),
+                None => {
+                    definitions.insert((symbol.kind, &symbol.name), (i, j));
+                },
+            }
+        }
+    }
+    // 未定義のシンボルを、定義したオブジェクトのシンボルに置き換える
+    let resolve = |object: usize, symbol: usize| -> Option<(usize, &Symbol)> {
+        let found = &objects[object].symbols[symbol];
+        if found.defined() {
+            return Some((object, found));
+        }
+        definitions.get(&(found.kind, found.name.as_str())).map(|(i, j)| (*i, &objects[*i].symbols[*j]))
+    };
+
+    // 関数の番号は import、各オブジェクトで定義した関数の順
+    let mut imports: Vec<Import> = vec![];
+    let mut function_offsets = vec![];
+    let mut next = 0;
+    for object in objects.iter() {
+        function_offsets.push(next);
+        next += object.module.functions.len();
+    }
+    // オブジェクトごとの、関数の番号から最終的な番号への対応。import は後で番号をずらす
+    let mut function_maps: Vec<Vec<Result<usize, usize>>> = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        let mut map = vec![];
+        for (index, import) in object.module.imports.iter().enumerate() {
+            let symbol = object.symbols.iter().position(|symbol| symbol.kind == SYMTAB_FUNCTION && symbol.index == index);
+            let name = symbol.map(|symbol| object.symbols[symbol].name.as_str()).unwrap_or(&import.name);
+            let host = symbol.is_some_and(|symbol| object.symbols[symbol].host());
+            match symbol.and_then(|symbol| resolve(i, symbol)).filter(|(_, symbol)| symbol.defined()) {
+                Some((owner, symbol)) => map.push(Ok(function_offsets[owner] + symbol.index - objects[owner].module.imports.len())),
+                None if host || allow_undefined => {
+                    let position = match imports.iter().position(|import| import.name == name) {
+                        Some(position) => position,
+                        None => {
+                            imports.push(Import { name: name.to_string(), ..import.clone() });
+                            imports.len() - 1
+                        },
+                    };
+                    map.push(Err(position));
+                },
+                None => {
+                    errors.push(LinkError::Undefined { symbol: name.to_string(), object: object.name.to_string() });
+                    map.push(Err(0));
+                },
+            }
+        }
+        map.extend((0..object.module.functions.len()).map(|k| Ok(function_offsets[i] + k)));
+        function_maps.push(map);
+    }
+    let function_index = |object: usize, index: usize| match function_maps[object][index] {
+        Ok(defined) => imports.len() + defined,
+        Err(import) => import,
+    };
+
+    let mut global_offsets = vec![];
+    let mut next = 0;
+    for object in objects.iter() {
+        global_offsets.push(next);
+        next += object.module.globals.len();
+    }
+
+    // データは最初のセグメントの位置から、オブジェクトの順に詰めて置く
+    let mut segment_offsets: Vec<Vec<i32>> = vec![];
+    let base = objects.iter().flat_map(|object| object.module.data.iter().map(|data| data.offset)).min().unwrap_or(0);
+    let mut end = base;
+    let mut data = vec![];
+    for object in objects.iter() {
+        let mut offsets = vec![];
+        for (segment, alignment) in object.module.data.iter().zip(object.alignments.iter()) {
+            let align = 1 << alignment;
+            end = (end + align - 1) / align * align;
+            offsets.push(end);
+            data.push(Data { offset: end, bytes: segment.bytes.clone() });
+            end += segment.bytes.len() as i32;
+        }
+        segment_offsets.push(offsets);
+    }
+
+    // 再配置
+    let mut bodies = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        let mut code = object.code.clone();
+        for reloc in object.relocs.iter() {
+            let Some(symbol) = object.symbols.get(reloc.symbol) else {
+                errors.push(object_error(object, &format!("symbol index {} out of range", reloc.symbol)));
+                continue;
+            };
+            let bytes = match (reloc.kind, symbol.kind) {
+                (R_WASM_FUNCTION_INDEX_LEB, SYMTAB_FUNCTION) => usize_to_padded_leb128(function_index(i, symbol.index) as u32),
+                (R_WASM_GLOBAL_INDEX_LEB, SYMTAB_GLOBAL) | (R_WASM_MEMORY_ADDR_SLEB, SYMTAB_DATA) => {
+                    let Some((owner, symbol)) = resolve(i, reloc.symbol) else {
+                        errors.push(LinkError::Undefined { symbol: symbol.name.to_string(), object: object.name.to_string() });
+                        continue;
+                    };
+                    if reloc.kind == R_WASM_GLOBAL_INDEX_LEB {
+                        usize_to_padded_leb128((global_offsets[owner] + symbol.index) as u32)
+                    } else {
+                        i32_to_padded_leb128(segment_offsets[owner][symbol.index] + symbol.offset as i32 + reloc.addend)
+                    }
+                },
+                (R_WASM_FUNCTION_INDEX_LEB | R_WASM_GLOBAL_INDEX_LEB | R_WASM_MEMORY_ADDR_SLEB, _) => {
+                    errors.push(object_error(object, &format!("relocation at {:#x} refers to a symbol of another kind", reloc.offset)));
+                    continue;
+                },
+                (kind, _) => {
+                    errors.push(object_error(object, &format!("unsupported relocation type {}", kind)));
+                    continue;
+                },
+            };
+            match code.get_mut(reloc.offset..reloc.offset + bytes.len()) {
+                Some(range) => range.copy_from_slice(&bytes),
+                None => errors.push(object_error(object, &format!("relocation offset {:#x} out of range", reloc.offset))),
+            }
+        }
+        match function_bodies(&code) {
+            Ok(code) if code.len() == object.module.functions.len() => bodies.extend(code),
+            Ok(_) => errors.push(object_error(object, "function and code section sizes differ")),
+            Err(error) => errors.push(object_error(object, &error.to_string())),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // 名前は IR で関数を指すのに使うので、ローカルなシンボルが重なれば番号を付けて分ける
+    let mut names: HashSet<String> = imports.iter().map(|import| import.name.to_string()).collect();
+    let mut unique = |name: &str| {
+        let mut unique = name.to_string();
+        let mut n = 1;
+        while names.contains(&unique) {
+            unique = format!("{}.{}", name, n);
+            n += 1;
+        }
+        names.insert(unique.to_string());
+        unique
+    };
+    let mut module = Module { imports, data, ..Default::default() };
+    let mut exports = vec![];
+    for object in objects.iter() {
+        let mut functions = vec![];
+        for function in object.module.functions.iter() {
+            functions.push(Function { name: unique(&function.name), body: vec![], ..function.clone() });
+        }
+        let mut globals = vec![];
+        for global in object.module.globals.iter() {
+            globals.push(Global { name: unique(&global.name), ..global.clone() });
+        }
+        for symbol in object.symbols.iter().filter(|symbol| symbol.defined() && symbol.flags & WASM_SYM_EXPORTED != 0) {
+            match symbol.kind {
+                SYMTAB_FUNCTION => exports.push(Export::function(&symbol.name, &functions[symbol.index - object.module.imports.len()].name)),
+                SYMTAB_GLOBAL => exports.push(Export { name: symbol.name.to_string(), kind: ExportKind::Global(globals[symbol.index].name.to_string()) }),
+                _ => {},
+            }
+        }
+        module.functions.extend(functions);
+        module.globals.extend(globals);
+    }
+    let pages = objects.iter().filter_map(|object| object.module.memory.as_ref().map(|memory| memory.min)).max();
+    if pages.is_some() || !module.data.is_empty() {
+        let min = pages.unwrap_or(0).max((end as usize).div_ceil(PAGE_SIZE) as u32);
+        module.memory = Some(Memory { min, max: None });
+        exports.push(Export { name: "memory".to_string(), kind: ExportKind::Memory });
+    }
+    module.exports = exports;
+
+    // 書き換えた本体を型や名前と一緒にバイナリにして、中間表現に読み戻す
+    let mut bytes = vec![];
+    wasm::write_sections(&module, Code::Bytes(&bodies), &mut bytes).unwrap();
+    Module::read_wasm(&bytes).map_err(|error| vec![LinkError::Object { object: "<output>".to_string(), message: error.to_string() }])
+}
+
+fn object_error(object: &Object, message: &str) -> LinkError {
+    LinkError::Object { object: object.name.to_string(), message: message.to_string() }
+}
+
+fn read_object<'a>(name: &'a str, bytes: &[u8]) -> Result<Object<'a>, String> {
+    let module = Module::read_wasm(bytes).map_err(|error| error.to_string())?;
+    let mut reader = Reader { bytes, offset: 8 };
+    let mut sections = vec![];
+    while reader.offset < bytes.len() {
+        let code = reader.byte().map_err(|error| error.to_string())?;
+        let size = reader.usize().map_err(|error| error.to_string())?;
+        sections.push((code, reader.offset, reader.offset + size));
+        reader.offset += size;
+    }
+    let code_index = sections.iter().position(|(code, _, _)| *code == 0x0a).ok_or("code section is missing")?;
+    let (_, start, end) = sections[code_index];
+    let mut object = Object { name, module, code: bytes[start..end].to_vec(), symbols: vec![], alignments: vec![], relocs: vec![] };
+    object.alignments = vec![0; object.module.data.len()];
+    let mut linking = false;
+    for (code, start, end) in sections {
+        if code != 0x00 {
+            continue;
+        }
+        let mut section = Reader { bytes: &bytes[..end], offset: start };
+        let name = section.name().map_err(|error| error.to_string())?;
+        let result = match name.as_str() {
+            "linking" => {
+                linking = true;
+                read_linking(&mut section, &mut object)
+            },
+            "reloc.CODE" => read_relocs(&mut section, code_index, &mut object),
+            _ if name.starts_with("reloc.") => return Err(format!("unsupported relocation section {}", name)),
+            _ => continue,
+        };
+        result.map_err(|error| error.to_string())?;
+    }
+    if !linking {
+        return Err("not a relocatable object (linking section is missing)".to_string());
+    }
+    Ok(object)
+}
+
+fn read_linking(section: &mut Reader, object: &mut Object) -> Result<(), DecodeError> {
+    let version = section.usize()?;
+    if version != LINKING_VERSION {
+        return Err(section.error(&format!("unsupported linking version {}", version)));
+    }
+    while section.offset < section.bytes.len() {
+        let kind = section.byte()?;
+        let size = section.usize()?;
+        let end = section.offset + size;
+        if end > section.bytes.len() {
+            return Err(section.error("linking subsection size out of bounds"));
+        }
+        match kind {
+            WASM_SEGMENT_INFO => {
+                let count = section.usize()?;
+                for i in 0..count {
+                    section.name()?;
+                    let alignment = section.usize()?;
+                    section.usize()?; // flags
+                    match object.alignments.get_mut(i) {
+                        Some(align) if alignment < 31 => *align = alignment as u32,
+                        _ => return Err(section.error("invalid segment info")),
+                    }
+                }
+            },
+            WASM_SYMBOL_TABLE => {
+                let count = section.usize()?;
+                for _ in 0..count {
+                    let symbol = read_symbol(section, &object.module)?;
+                    object.symbols.push(symbol);
+                }
+            },
+            _ => section.offset = end,
+        }
+        if section.offset != end {
+            return Err(section.error("linking subsection size mismatch"));
+        }
+    }
+    Ok(())
+}
+
+fn read_symbol(section: &mut Reader, module: &Module) -> Result<Symbol, DecodeError> {
+    let start = section.offset;
+    let kind = section.byte()?;
+    let flags = section.usize()?;
+    let mut symbol = Symbol { kind, flags, name: String::new(), index: 0, offset: 0 };
+    match kind {
+        SYMTAB_FUNCTION | SYMTAB_GLOBAL => {
+            symbol.index = section.usize()?;
+            let count = if kind == SYMTAB_FUNCTION { module.imports.len() + module.functions.len() } else { module.globals.len() };
+            // 未定義の関数は import を、定義した関数はそれ以外を指す。グローバル変数の import は扱わない
+            let imported = kind == SYMTAB_FUNCTION && symbol.index < module.imports.len();
+            if symbol.index >= count || imported == symbol.defined() {
+                return Err(section.error_at(start, &format!("symbol index {} out of range", symbol.index)));
+            }
+            // 名前を書いていない未定義の関数は import のフィールド名で呼ぶ
+            symbol.name = if symbol.defined() || flags & WASM_SYM_EXPLICIT_NAME != 0 {
+                section.name()?
+            } else {
+                module.imports[symbol.index].field.to_string()
+            };
+        },
+        SYMTAB_DATA => {
+            symbol.name = section.name()?;
+            if symbol.defined() {
+                symbol.index = section.usize()?;
+                symbol.offset = section.usize()?;
+                let size = section.usize()?;
+                if module.data.get(symbol.index).filter(|data| symbol.offset + size <= data.bytes.len()).is_none() {
+                    return Err(section.error_at(start, "data symbol out of range"));
+                }
+            }
+        },
+        _ => return Err(section.error_at(start, &format!("unsupported symbol kind {}", kind))),
+    }
+    Ok(symbol)
+}
+
+fn read_relocs(section: &mut Reader, code_index: usize, object: &mut Object) -> Result<(), DecodeError> {
+    if section.usize()? != code_index {
+        return Err(section.error("reloc.CODE does not refer to the code section"));
+    }
+    let count = section.usize()?;
+    for _ in 0..count {
+        let kind = section.byte()?;
+        let offset = section.usize()?;
+        let symbol = section.usize()?;
+        let addend = if kind == R_WASM_MEMORY_ADDR_SLEB { section.i32()? } else { 0 };
+        object.relocs.push(Reloc { kind, offset, symbol, addend });
+    }
+    Ok(())
+}
+
+// code section の中身を関数本体ごとに分ける
+fn function_bodies(code: &[u8]) -> Result<Vec<Vec<u8>>, DecodeError> {
+    let mut reader = Reader { bytes: code, offset: 0 };
+    let count = reader.usize()?;
+    let mut bodies = vec![];
+    for _ in 0..count {
+        let size = reader.usize()?;
+        bodies.push(reader.take(size)?.to_vec());
+    }
+    if reader.offset != code.len() {
+        return Err(reader.error("code section size mismatch"));
+    }
+    Ok(bodies)
+}
+
+#[cfg(test)]
+fn object(source: &str) -> Vec<u8> {
+    let mut module = crate::wasmc::parse(source);
+    module.set_relocatable();
+    let mut bytes = vec![];
+    module.lower().write_object(&mut bytes).unwrap();
+    bytes
+}
+
+#[cfg(test)]
+fn link_errors(objects: &[(&str, &[u8])], allow_undefined: bool) -> Vec<String> {
+    let Err(errors) = link(objects, allow_undefined) else { panic!("リンクできてしまいました") };
+    errors.iter().map(|error| error.to_string()).collect()
+}
+
+#[test]
+fn test_object() {
+    let source = "main(){print_str(\"x\");return f(2);}f(x){return x+1;}";
+    let bytes = object(source);
+    let module = Module::read_wasm(&bytes).unwrap();
+    assert!(module.customs.iter().map(|custom| custom.name.as_str()).eq(["linking", "reloc.CODE"]));
+    assert!(module.exports.is_empty());
+    // 呼び出し先は後から書き換えられるように 5 バイトで書く
+    assert!(bytes.windows(6).any(|window| window == [0x10, 0x82, 0x80, 0x80, 0x80, 0x00]));
+    // 1 つだけリンクすれば元のモジュールに戻る
+    let linked = link(&[("a.o", &bytes)], true).unwrap();
+    let mut expected = vec![];
+    crate::wasmc::parse(source).lower().write_wat(&mut expected).unwrap();
+    let mut actual = vec![];
+    linked.write_wat(&mut actual).unwrap();
+    assert_eq!(String::from_utf8(actual).unwrap(), String::from_utf8(expected).unwrap());
+}
+
+#[test]
+fn test_link() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::interpreter::Instance;
+
+    let a = object("main(){print_str(\"main\");return square(3)+inc(0);}inc(x){return x+1;}");
+    let b = object("square(x){print_str(\"square\");return inc(x*x);}");
+    let module = link(&[("a.o", &a), ("b.o", &b)], true).unwrap();
+    assert!(module.imports.iter().map(|import| import.name.as_str()).eq(["print_str"]));
+    assert!(module.functions.iter().map(|function| function.name.as_str()).eq(["main", "inc", "square"]));
+    // データはオブジェクトの順に詰めて置く
+    assert_eq!(module.data, vec![Data { offset: 1024, bytes: b"main\0".to_vec() }, Data { offset: 1029, bytes: b"square\0".to_vec() }]);
+    let mut bytes = vec![];
+    module.write_wasm(&mut bytes).unwrap();
+    crate::ir::validate(&bytes).unwrap();
+
+    let mut instance = Instance::from_module(&module);
+    let output = Rc::new(RefCell::new(vec![]));
+    let written = output.clone();
+    instance.link("env", "print_str", Box::new(move |memory, args| {
+        let start = args[0] as usize;
+        let end = start + memory[start..].iter().position(|byte| *byte == 0).unwrap();
+        written.borrow_mut().push(String::from_utf8(memory[start..end].to_vec()).unwrap());
+        Ok(vec![0])
+    }));
+    assert_eq!(instance.invoke("main", &[]), Ok(vec![11]));
+    assert_eq!(*output.borrow(), vec!["main", "square"]);
+}
+
+#[test]
+fn test_link_globals() {
+    let global = |name: &str| Global { name: name.to_string(), vtype: crate::ir::ValType::I32, mutable: false, init: name.len() as i32 };
+    let a = Module { globals: vec![global("a")], ..Default::default() };
+    let b = Module {
+        globals: vec![global("bb")],
+        exports: vec![Export { name: "bb".to_string(), kind: ExportKind::Global("bb".to_string()) }],
+        ..Default::default()
+    };
+    let (mut a_bytes, mut b_bytes) = (vec![], vec![]);
+    a.write_object(&mut a_bytes).unwrap();
+    b.write_object(&mut b_bytes).unwrap();
+    let module = link(&[("a.o", &a_bytes), ("b.o", &b_bytes)], false).unwrap();
+    assert_eq!(module.globals, vec![global("a"), global("bb")]);
+    assert_eq!(module.exports, vec![Export { name: "bb".to_string(), kind: ExportKind::Global("bb".to_string()) }]);
+}
+
+#[test]
+fn test_link_errors() {
+    let a = object("main(){return f()+g();}");
+    let b = object("f(){return 1;}");
+    assert_eq!(link_errors(&[("a.o", &a), ("b.o", &b)], false), vec!["a.o: undefined symbol: g"]);
+    let c = object("f(){return 2;}g(){return 3;}");
+    assert_eq!(link_errors(&[("a.o", &a), ("b.o", &b), ("c.o", &c)], false), vec!["duplicate symbol: f (defined in b.o and c.o)"]);
+    let mut wasm = vec![];
+    crate::wasmc::parse("main(){return 0;}").lower().write_wasm(&mut wasm).unwrap();
+    assert_eq!(link_errors(&[("a.wasm", &wasm)], false), vec!["a.wasm: not a relocatable object (linking section is missing)"]);
+}
@@ -0,0 +1,133 @@
+use std::io::{Write, Result};
+use crate::ir::{ExportKind, Module};
+use crate::ir::leb128::{i32_to_leb128, usize_to_leb128};
+use crate::ir::wasm::{self, Code, Reloc, RelocTarget, write_custom_section, write_name};
+
+// 分割コンパイルの出力。LLVM の tool-conventions (Linking.md) の linking と reloc.CODE を付ける
+
+pub(crate) const LINKING_VERSION: usize = 2;
+
+// linking section のサブセクション
+pub(crate) const WASM_SEGMENT_INFO: u8 = 5;
+pub(crate) const WASM_SYMBOL_TABLE: u8 = 8;
+
+// シンボルの種類
+pub(crate) const SYMTAB_FUNCTION: u8 = 0;
+pub(crate) const SYMTAB_DATA: u8 = 1;
+pub(crate) const SYMTAB_GLOBAL: u8 = 2;
+
+// シンボルのフラグ
+pub(crate) const WASM_SYM_BINDING_LOCAL: usize = 0x02;
+pub(crate) const WASM_SYM_UNDEFINED: usize = 0x10;
+pub(crate) const WASM_SYM_EXPORTED: usize = 0x20;
+pub(crate) const WASM_SYM_EXPLICIT_NAME: usize = 0x40;
+
+// 再配置の種類
+pub(crate) const R_WASM_FUNCTION_INDEX_LEB: u8 = 0;
+pub(crate) const R_WASM_MEMORY_ADDR_SLEB: u8 = 4;
+pub(crate) const R_WASM_GLOBAL_INDEX_LEB: u8 = 7;
+
+// シンボルは関数 (import から通しの番号順)、グローバル変数、データセグメントの順に並べる。
+// export はシンボルの EXPORTED で表し、リンクしたときにシンボルの名前で export する
+pub fn write_module(module: &Module, write: &mut dyn Write) -> Result<()> {
+    let object = Module { exports: vec![], ..module.clone() };
+    let mut relocs = vec![];
+    let code_index = wasm::write_sections(&object, Code::Relocatable(&mut relocs), write)?;
+    write_custom_section("linking", &linking_section(module)?, write)?;
+    write_custom_section("reloc.CODE", &reloc_section(module, code_index, &relocs)?, write)
+}
+
+fn linking_section(module: &Module) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    buf.write_all(&usize_to_leb128(LINKING_VERSION))?;
+    if !module.data.is_empty() {
+        let mut segments = vec![];
+        segments.write_all(&usize_to_leb128(module.data.len()))?;
+        for i in 0..module.data.len() {
+            write_name(&segment_name(i), &mut segments)?;
+            segments.write_all(&[0x00, 0x00])?; // alignment (2 の冪の指数)、flags
+        }
+        write_subsection(WASM_SEGMENT_INFO, &segments, &mut buf)?;
+    }
+    write_subsection(WASM_SYMBOL_TABLE, &symbol_table(module)?, &mut buf)?;
+    Ok(buf)
+}
+
+fn write_subsection(kind: u8, payload: &[u8], write: &mut dyn Write) -> Result<()> {
+    write.write_all(&[kind])?;
+    write.write_all(&usize_to_leb128(payload.len()))?;
+    write.write_all(payload)
+}
+
+fn symbol_table(module: &Module) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    buf.write_all(&usize_to_leb128(module.imports.len() + module.functions.len() + module.globals.len() + module.data.len()))?;
+    // ホストが渡す import は名前を明示し、リンクしても import のまま残す。ほかのオブジェクトで
+    // 定義するものは名前を書かず、import のフィールド名で解決する
+    for (i, import) in module.imports.iter().enumerate() {
+        buf.write_all(&[SYMTAB_FUNCTION])?;
+        if module.unresolved.contains(&import.name) {
+            buf.write_all(&usize_to_leb128(WASM_SYM_UNDEFINED))?;
+            buf.write_all(&usize_to_leb128(i))?;
+        } else {
+            buf.write_all(&usize_to_leb128(WASM_SYM_UNDEFINED | WASM_SYM_EXPLICIT_NAME))?;
+            buf.write_all(&usize_to_leb128(i))?;
+            write_name(&import.name, &mut buf)?;
+        }
+    }
+    for (i, function) in module.functions.iter().enumerate() {
+        let exported = module.exports.iter().any(|export| export.kind == ExportKind::Function(function.name.to_string()));
+        buf.write_all(&[SYMTAB_FUNCTION])?;
+        buf.write_all(&usize_to_leb128(if exported { WASM_SYM_EXPORTED } else { 0 }))?;
+        buf.write_all(&usize_to_leb128(module.imports.len() + i))?;
+        write_name(&function.name, &mut buf)?;
+    }
+    for (i, global) in module.globals.iter().enumerate() {
+        let exported = module.exports.iter().any(|export| export.kind == ExportKind::Global(global.name.to_string()));
+        buf.write_all(&[SYMTAB_GLOBAL])?;
+        buf.write_all(&usize_to_leb128(if exported { WASM_SYM_EXPORTED } else { 0 }))?;
+        buf.write_all(&usize_to_leb128(i))?;
+        write_name(&global.name, &mut buf)?;
+    }
+    // データはセグメントごとに、ほかのオブジェクトからは見えないシンボルにする
+    for (i, data) in module.data.iter().enumerate() {
+        buf.write_all(&[SYMTAB_DATA])?;
+        buf.write_all(&usize_to_leb128(WASM_SYM_BINDING_LOCAL))?;
+        write_name(&segment_name(i), &mut buf)?;
+        buf.write_all(&usize_to_leb128(i))?; // segment index
+        buf.write_all(&usize_to_leb128(0))?; // offset
+        buf.write_all(&usize_to_leb128(data.bytes.len()))?; // size
+    }
+    Ok(buf)
+}
+
+fn segment_name(index: usize) -> String {
+    format!(".data.{}", index)
+}
+
+fn reloc_section(module: &Module, code_index: usize, relocs: &[Reloc]) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    buf.write_all(&usize_to_leb128(code_index))?;
+    buf.write_all(&usize_to_leb128(relocs.len()))?;
+    for reloc in relocs.iter() {
+        match &reloc.target {
+            RelocTarget::Function(name) => {
+                buf.write_all(&[R_WASM_FUNCTION_INDEX_LEB])?;
+                buf.write_all(&usize_to_leb128(reloc.offset))?;
+                buf.write_all(&usize_to_leb128(module.function_index(name).unwrap()))?; // symbol index
+            },
+            RelocTarget::Memory(address) => {
+                // アドレスは、それを含むセグメントのシンボルからの距離にする
+                let segment = match module.data.iter().position(|data| data.offset <= *address && *address <= data.offset + data.bytes.len() as i32) {
+                    Some(segment) => segment,
+                    None => panic!("address {} is not in any data segment", address),
+                };
+                buf.write_all(&[R_WASM_MEMORY_ADDR_SLEB])?;
+                buf.write_all(&usize_to_leb128(reloc.offset))?;
+                buf.write_all(&usize_to_leb128(module.imports.len() + module.functions.len() + module.globals.len() + segment))?;
+                buf.write_all(&i32_to_leb128(address - module.data[segment].offset))?; // addend
+            },
+        }
+    }
+    Ok(buf)
+}
@@ -0,0 +1,148 @@
+use crate::ir::{Instr, NumOp};
+use crate::ir::Instr::*;
+#[cfg(test)]
+use crate::wasmc::parse;
+
+// 命令列の覗き穴最適化。入れ子のブロックから順に、書き換えるものがなくなるまで繰り返す
+pub fn optimize(instructions: Vec<Instr>) -> Vec<Instr> {
+    let mut current: Vec<Instr> = instructions.into_iter().map(|instruction| map_bodies(instruction, optimize)).collect();
+    loop {
+        let (next, changed) = optimize_once(current);
+        if !changed {
+            return next;
+        }
+        current = next;
+    }
+}
+
+// call の直後の return を、tail call 拡張の return_call にまとめる
+pub fn use_return_call(instructions: Vec<Instr>) -> Vec<Instr> {
+    let mut result: Vec<Instr> = Vec::with_capacity(instructions.len());
+    for instruction in instructions {
+        match (result.last(), instruction) {
+            (Some(Call(name)), Return) => {
+                let name = name.to_string();
+                result.pop();
+                result.push(ReturnCall(name));
+            },
+            (_, instruction) => result.push(map_bodies(instruction, use_return_call)),
+        }
+    }
+    result
+}
+
+fn map_bodies(instruction: Instr, f: fn(Vec<Instr>) -> Vec<Instr>) -> Instr {
+    match instruction {
+        Block { label, result, body } => Block { label, result, body: f(body) },
+        Loop { label, result, body } => Loop { label, result, body: f(body) },
+        If { label, result, then, otherwise } => If { label, result, then: f(then), otherwise: f(otherwise) },
+        instruction => instruction,
+    }
+}
+
+fn optimize_once(instructions: Vec<Instr>) -> (Vec<Instr>, bool) {
+    let mut result: Vec<Instr> = Vec::with_capacity(instructions.len());
+    let mut changed = false;
+    for instruction in instructions {
+        match (result.last(), &instruction) {
+            // 無条件分岐の後ろから、同じブロックの終わりまでは到達しない
+            (Some(Return | ReturnCall(_) | Br(_)), _) => {
+                changed = true;
+            },
+            // i32.const 0; i32.eq => i32.eqz
+            (Some(I32Const(0)), Numeric(NumOp::I32Eq)) => {
+                result.pop();
+                result.push(Numeric(NumOp::I32Eqz));
+                changed = true;
+            },
+            // local.tee; drop => local.set
+            (Some(LocalTee(name)), Drop) => {
+                let name = name.to_string();
+                result.pop();
+                result.push(LocalSet(name));
+                changed = true;
+            },
+            // 副作用のない値をすぐに捨てている
+            (Some(I32Const(_) | LocalGet(_)), Drop) => {
+                result.pop();
+                changed = true;
+            },
+            _ => result.push(instruction),
+        }
+    }
+    (result, changed)
+}
+
+#[test]
+fn test_eqz() {
+    let instructions = vec![LocalGet("a".to_string()), I32Const(0), Numeric(NumOp::I32Eq), BrIf("block0".to_string())];
+    assert_eq!(optimize(instructions), vec![LocalGet("a".to_string()), Numeric(NumOp::I32Eqz), BrIf("block0".to_string())]);
+}
+
+#[test]
+fn test_tee_drop() {
+    let instructions = vec![I32Const(1), LocalTee("a".to_string()), Drop];
+    assert_eq!(optimize(instructions), vec![I32Const(1), LocalSet("a".to_string())]);
+}
+
+#[test]
+fn test_const_drop() {
+    // 入れ子の文が返す i32.const 0 はそのまま捨てられる
+    let instructions = vec![I32Const(1), LocalTee("a".to_string()), Drop, I32Const(0), Drop, I32Const(0)];
+    assert_eq!(optimize(instructions), vec![I32Const(1), LocalSet("a".to_string()), I32Const(0)]);
+}
+
+#[test]
+fn test_after_return() {
+    let instructions = vec![
+        LocalGet("a".to_string()),
+        If { label: None, result: None, then: vec![I32Const(1), Return, Drop, I32Const(0), Drop], otherwise: vec![Call("f".to_string()), Drop] },
+        I32Const(3), Return, Drop, Block { label: "block0".to_string(), result: None, body: vec![] }, I32Const(0),
+    ];
+    assert_eq!(optimize(instructions), vec![
+        LocalGet("a".to_string()),
+        If { label: None, result: None, then: vec![I32Const(1), Return], otherwise: vec![Call("f".to_string()), Drop] },
+        I32Const(3), Return,
+    ]);
+}
+
+#[test]
+fn test_return_call() {
+    let instructions = vec![
+        LocalGet("a".to_string()), Call("f".to_string()), Return, Call("g".to_string()), Drop,
+        Block { label: "b".to_string(), result: None, body: vec![Call("h".to_string()), Return] },
+    ];
+    assert_eq!(use_return_call(instructions), vec![
+        LocalGet("a".to_string()), ReturnCall("f".to_string()), Call("g".to_string()), Drop,
+        Block { label: "b".to_string(), result: None, body: vec![ReturnCall("h".to_string())] },
+    ]);
+}
+
+#[test]
+fn test_wat_and_wasm() {
+    let mut module = parse("main(){a=0;while(a<3)a=a+1;return a;}");
+    for function in module.functions_mut() {
+        function.peephole = true;
+    }
+    let module = module.lower();
+    let mut wat = vec![];
+    module.write_wat(&mut wat).unwrap();
+    let wat = String::from_utf8(wat).unwrap();
+    assert!(wat.contains("i32.eqz\n        br_if $block"));
+    assert!(wat.contains("local.set $a\n"));
+    assert!(!wat.contains("local.tee"));
+    assert!(!wat.contains("drop"));
+
+    let mut wasm = vec![];
+    module.write_wasm(&mut wasm).unwrap();
+    // 関数本体の後ろには name section が続く
+    let code = [
+        0x41, 0x00, 0x21, 0x00, // i32.const 0; local.set $a
+        0x02, 0x40, 0x03, 0x40, // block; loop
+        0x20, 0x00, 0x41, 0x03, 0x48, 0x45, 0x0d, 0x01, // a<3; i32.eqz; br_if 1
+        0x20, 0x00, 0x41, 0x01, 0x6a, 0x21, 0x00, // a=a+1
+        0x0c, 0x00, 0x0b, 0x0b, // br 0; end; end
+        0x20, 0x00, 0x0f, 0x0b, // return a; end
+    ];
+    assert!(wasm.windows(code.len()).any(|window| window == code));
+}
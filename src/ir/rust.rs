@@ -0,0 +1,148 @@
+use std::io::{Write, Result};
+use crate::ir::{ExportKind, Module, ValType};
+
+const KEYWORDS: [&str; 39] = [
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+    "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe",
+    "use", "where", "while", "yield",
+];
+
+fn rust_type(vtype: ValType) -> &'static str {
+    match vtype {
+        ValType::I32 => "i32"
+    }
+}
+
+// 引数や結果の型の並び。wasmtime の TypedFunc などに渡せるようにタプルにする
+fn tuple(types: &[ValType]) -> String {
+    match types {
+        [vtype] => rust_type(*vtype).to_string(),
+        _ => format!("({})", types.iter().map(|vtype| rust_type(*vtype)).collect::<Vec<_>>().join(", ")),
+    }
+}
+
+// 英数字と _ 以外で区切った単語
+fn words(name: &str) -> Vec<String> {
+    name.split(|c: char| !c.is_ascii_alphanumeric()).filter(|word| !word.is_empty()).map(str::to_string).collect()
+}
+
+fn snake_case(name: &str) -> String {
+    let name = words(name).join("_");
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) || KEYWORDS.contains(&name.as_str()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+fn camel_case(name: &str) -> String {
+    let name: String = words(name).iter().map(|word| {
+        let mut chars = word.chars();
+        chars.next().map(|c| c.to_ascii_uppercase()).into_iter().chain(chars).collect::<String>()
+    }).collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) || KEYWORDS.contains(&name.as_str()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+// 組み込む側の Rust のホストのためのモジュール。export ごとに名前の定数と引数・結果の型を、
+// import のモジュールごとにホストが実装するトレイトを書く
+pub fn write_module(module: &Module, wasm: &str, write: &mut dyn Write) -> Result<()> {
+    writeln!(write, "// {} の export と、ホストが渡す import", wasm)?;
+    writeln!(write, "#![allow(dead_code)]")?;
+    writeln!(write)?;
+    writeln!(write, "pub const WASM: &str = {:?};", wasm)?;
+    if let Some(memory) = &module.memory {
+        writeln!(write, "pub const MEMORY_PAGES: u32 = {};", memory.min)?;
+        if let Some(export) = module.exports.iter().find(|export| export.kind == ExportKind::Memory) {
+            writeln!(write, "pub const MEMORY: &str = {:?};", export.name)?;
+        }
+    }
+
+    writeln!(write)?;
+    writeln!(write, "pub mod exports {{")?;
+    let mut first = true;
+    for export in module.exports.iter() {
+        let ExportKind::Function(function) = &export.kind else { continue };
+        let (params, results) = match module.signature(function) {
+            Some(signature) => signature,
+            None => panic!("function `{}` not found", function),
+        };
+        if !first {
+            writeln!(write)?;
+        }
+        first = false;
+        let name = camel_case(&export.name);
+        writeln!(write, "    pub const {}: &str = {:?};", snake_case(&export.name).to_ascii_uppercase(), export.name)?;
+        writeln!(write, "    pub type {}Params = {};", name, tuple(&params))?;
+        writeln!(write, "    pub type {}Results = {};", name, tuple(&results))?;
+    }
+    writeln!(write, "}}")?;
+
+    let mut modules: Vec<&str> = vec![];
+    for import in module.imports.iter() {
+        if !modules.contains(&import.module.as_str()) {
+            modules.push(&import.module);
+        }
+    }
+    if !modules.is_empty() {
+        writeln!(write)?;
+        writeln!(write, "// (モジュール, フィールド)")?;
+        writeln!(write, "pub const IMPORTS: &[(&str, &str)] = &[")?;
+        for import in module.imports.iter() {
+            writeln!(write, "    ({:?}, {:?}),", import.module, import.field)?;
+        }
+        writeln!(write, "];")?;
+    }
+    for import_module in modules {
+        writeln!(write)?;
+        writeln!(write, "// import {:?}", import_module)?;
+        writeln!(write, "pub trait {} {{", camel_case(import_module))?;
+        for import in module.imports.iter().filter(|import| import.module == import_module) {
+            let params: String = import.params.iter().enumerate()
+                .map(|(i, vtype)| format!(", arg{}: {}", i, rust_type(*vtype)))
+                .collect();
+            let results = if import.results.is_empty() { String::new() } else { format!(" -> {}", tuple(&import.results)) };
+            writeln!(write, "    fn {}(&mut self{}){};", snake_case(&import.field), params, results)?;
+        }
+        writeln!(write, "}}")?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bindings() {
+    let module = crate::wasmc::parse("main(a,b){print_str(\"x\");return type(a,b);}").lower();
+    let mut buf = vec![];
+    write_module(&module, "out.wasm", &mut buf).unwrap();
+    assert_eq!(String::from_utf8(buf).unwrap(), "\
+// out.wasm の export と、ホストが渡す import
+#![allow(dead_code)]
+
+pub const WASM: &str = \"out.wasm\";
+pub const MEMORY_PAGES: u32 = 1;
+pub const MEMORY: &str = \"memory\";
+
+pub mod exports {
+    pub const MAIN: &str = \"main\";
+    pub type MainParams = (i32, i32);
+    pub type MainResults = i32;
+}
+
+// (モジュール, フィールド)
+pub const IMPORTS: &[(&str, &str)] = &[
+    (\"env\", \"print_str\"),
+    (\"env\", \"type\"),
+];
+
+// import \"env\"
+pub trait Env {
+    fn print_str(&mut self, arg0: i32) -> i32;
+    fn _type(&mut self, arg0: i32, arg1: i32) -> i32;
+}
+");
+    assert_eq!((camel_case("fib-loop"), snake_case("2x"), tuple(&[])), ("FibLoop".to_string(), "_2x".to_string(), "()".to_string()));
+}
@@ -0,0 +1,357 @@
+use std::collections::HashSet;
+use std::fmt;
+use crate::ir::{DecodeError, Function, Instr, Module, NumOp, ValType};
+use crate::ir::decode::read_module;
+use crate::ir::leb128::leb128_to_usize;
+
+// custom 以外のセクションが並ぶべき順 (datacount は code より前)
+const SECTION_ORDER: [u8; 12] = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0c, 0x0a, 0x0b];
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum ValidationError {
+    // セクションの並びやサイズ、インデックスの誤り
+    Decode(DecodeError),
+    // index 番目 (import した関数から数える) の関数の型の誤り
+    Type { function: usize, message: String },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::Decode(error) => write!(f, "{}", error),
+            ValidationError::Type { function, message } => write!(f, "function {}: {}", function, message),
+        }
+    }
+}
+
+impl From<DecodeError> for ValidationError {
+    fn from(error: DecodeError) -> Self {
+        ValidationError::Decode(error)
+    }
+}
+
+// バイナリが正しいモジュールかを調べる。読めるかどうかに加えて、セクションの順序と
+// 関数ごとのオペランドスタックの型を確かめる
+pub fn validate(bytes: &[u8]) -> std::result::Result<(), ValidationError> {
+    check_section_order(bytes)?;
+    let module = read_module(bytes)?;
+    let mut names = HashSet::new();
+    for export in module.exports.iter() {
+        if !names.insert(&export.name) {
+            return Err(DecodeError { offset: 0, message: format!("duplicate export name `{}`", export.name) }.into());
+        }
+    }
+    // 上限は 4GiB
+    if let Some(memory) = &module.memory {
+        if memory.min > 65536 || memory.max.map(|max| max > 65536 || max < memory.min).unwrap_or(false) {
+            return Err(DecodeError { offset: 0, message: "invalid memory limits".to_string() }.into());
+        }
+    } else if !module.data.is_empty() {
+        return Err(DecodeError { offset: 0, message: "data segment without memory".to_string() }.into());
+    }
+    for (index, function) in module.functions.iter().enumerate() {
+        let mut checker = Checker { module: &module, function, stack: vec![], frames: vec![] };
+        let index = module.imports.len() + index;
+        checker.function().map_err(|message| ValidationError::Type { function: index, message })?;
+    }
+    Ok(())
+}
+
+fn check_section_order(bytes: &[u8]) -> std::result::Result<(), DecodeError> {
+    // ヘッダは read_module で調べる
+    let mut offset = 8;
+    let mut last: Option<usize> = None;
+    while offset < bytes.len() {
+        let code = bytes[offset];
+        let (size, len) = leb128_to_usize(&bytes[offset + 1..])
+            .ok_or_else(|| DecodeError { offset: offset + 1, message: "invalid LEB128".to_string() })?;
+        if code != 0x00 {
+            let rank = SECTION_ORDER.iter().position(|c| *c == code)
+                .ok_or_else(|| DecodeError { offset, message: format!("unknown section {:#04x}", code) })?;
+            if last.map(|last| rank <= last).unwrap_or(false) {
+                return Err(DecodeError { offset, message: format!("section {:#04x} is out of order", code) });
+            }
+            last = Some(rank);
+        }
+        offset = (offset + 1 + len).saturating_add(size);
+    }
+    Ok(())
+}
+
+struct Frame {
+    label: Option<String>,
+    // 分岐したときに渡す値の型。loop は先頭へ戻るので空
+    label_types: Vec<ValType>,
+    end_types: Vec<ValType>,
+    height: usize,
+    // 無条件分岐の後ろ。スタックはいくらでも取り出せる
+    unreachable: bool,
+}
+
+struct Checker<'a> {
+    module: &'a Module,
+    function: &'a Function,
+    stack: Vec<ValType>,
+    frames: Vec<Frame>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+impl <'a> Checker<'a> {
+
+    fn function(&mut self) -> Result<()> {
+        let results = self.function.results.clone();
+        self.push_frame(None, results.clone(), results);
+        self.instructions(&self.function.body)?;
+        self.pop_frame("function body")?;
+        Ok(())
+    }
+
+    fn instructions(&mut self, instructions: &'a [Instr]) -> Result<()> {
+        for instruction in instructions {
+            self.instruction(instruction)?;
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: &'a Instr) -> Result<()> {
+        let name = instruction.to_string();
+        match instruction {
+            Instr::Block { label, result, body } => {
+                let types: Vec<ValType> = result.iter().copied().collect();
+                self.push_frame(Some(label), types.clone(), types);
+                self.instructions(body)?;
+                let types = self.pop_frame(&name)?;
+                self.stack.extend(types);
+            },
+            Instr::Loop { label, result, body } => {
+                self.push_frame(Some(label), vec![], result.iter().copied().collect());
+                self.instructions(body)?;
+                let types = self.pop_frame(&name)?;
+                self.stack.extend(types);
+            },
+            Instr::If { label, result, then, otherwise } => {
+                self.pop(ValType::I32, &name)?;
+                let types: Vec<ValType> = result.iter().copied().collect();
+                if otherwise.is_empty() && !types.is_empty() {
+                    return Err(format!("{}: if without else cannot produce a value", name));
+                }
+                self.push_frame(label.as_ref(), types.clone(), types.clone());
+                self.instructions(then)?;
+                self.pop_frame(&name)?;
+                self.push_frame(label.as_ref(), types.clone(), types);
+                self.instructions(otherwise)?;
+                let types = self.pop_frame(&name)?;
+                self.stack.extend(types);
+            },
+            Instr::Br(label) => {
+                let types = self.label_types(label)?;
+                self.pop_all(&types, &name)?;
+                self.set_unreachable();
+            },
+            Instr::BrIf(label) => {
+                self.pop(ValType::I32, &name)?;
+                let types = self.label_types(label)?;
+                self.pop_all(&types, &name)?;
+                self.stack.extend(types);
+            },
+            Instr::Return => {
+                self.pop_all(&self.function.results, &name)?;
+                self.set_unreachable();
+            },
+            Instr::Call(callee) => {
+                let (params, results) = self.callee(callee)?;
+                self.pop_all(&params, &name)?;
+                self.stack.extend(results);
+            },
+            Instr::ReturnCall(callee) => {
+                let (params, results) = self.callee(callee)?;
+                if results != self.function.results {
+                    return Err(format!("{}: callee results differ from the caller", name));
+                }
+                self.pop_all(&params, &name)?;
+                self.set_unreachable();
+            },
+            Instr::Drop => {
+                self.pop_any(&name)?;
+            },
+            Instr::LocalGet(local) => {
+                let vtype = self.local_type(local)?;
+                self.stack.push(vtype);
+            },
+            Instr::LocalSet(local) => {
+                let vtype = self.local_type(local)?;
+                self.pop(vtype, &name)?;
+            },
+            Instr::LocalTee(local) => {
+                let vtype = self.local_type(local)?;
+                self.pop(vtype, &name)?;
+                self.stack.push(vtype);
+            },
+            Instr::I32Const(_) | Instr::Address(_) => self.stack.push(ValType::I32),
+            Instr::Numeric(op) => {
+                if *op != NumOp::I32Eqz {
+                    self.pop(ValType::I32, &name)?;
+                }
+                self.pop(ValType::I32, &name)?;
+                self.stack.push(ValType::I32);
+            },
+            Instr::Memory { op, .. } => {
+                if self.module.memory.is_none() {
+                    return Err(format!("{}: memory 0 is not defined", name));
+                }
+                if op.is_store() {
+                    self.pop(ValType::I32, &name)?;
+                }
+                self.pop(ValType::I32, &name)?;
+                if !op.is_store() {
+                    self.stack.push(ValType::I32);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn push_frame(&mut self, label: Option<&String>, label_types: Vec<ValType>, end_types: Vec<ValType>) {
+        self.frames.push(Frame { label: label.cloned(), label_types, end_types, height: self.stack.len(), unreachable: false });
+    }
+
+    // ブロックの終わりで、結果の型だけが残っていることを確かめる
+    fn pop_frame(&mut self, name: &str) -> Result<Vec<ValType>> {
+        let types = self.frames.last().unwrap().end_types.clone();
+        self.pop_all(&types, name)?;
+        let frame = self.frames.pop().unwrap();
+        if self.stack.len() != frame.height {
+            return Err(format!("{}: {} values left on the stack at end", name, self.stack.len() - frame.height));
+        }
+        Ok(types)
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    fn pop_any(&mut self, name: &str) -> Result<Option<ValType>> {
+        let frame = self.frames.last().unwrap();
+        if self.stack.len() == frame.height {
+            return if frame.unreachable {
+                Ok(None)
+            } else {
+                Err(format!("{}: operand stack underflow", name))
+            };
+        }
+        Ok(self.stack.pop())
+    }
+
+    fn pop(&mut self, expected: ValType, name: &str) -> Result<()> {
+        match self.pop_any(name)? {
+            Some(actual) if actual != expected => {
+                Err(format!("{}: expected {}, found {}", name, expected.wat_name(), actual.wat_name()))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    fn pop_all(&mut self, types: &[ValType], name: &str) -> Result<()> {
+        for vtype in types.iter().rev() {
+            self.pop(*vtype, name)?;
+        }
+        Ok(())
+    }
+
+    fn label_types(&self, label: &str) -> Result<Vec<ValType>> {
+        match self.frames.iter().rev().find(|frame| frame.label.as_deref() == Some(label)) {
+            Some(frame) => Ok(frame.label_types.clone()),
+            None => Err(format!("label {} is not defined", label)),
+        }
+    }
+
+    fn callee(&self, name: &str) -> Result<(Vec<ValType>, Vec<ValType>)> {
+        match self.module.signature(name) {
+            Some(signature) => Ok(signature),
+            None => Err(format!("function `{}` not found", name)),
+        }
+    }
+
+    fn local_type(&self, name: &str) -> Result<ValType> {
+        match self.function.params.iter().chain(self.function.locals.iter()).find(|local| local.name == name) {
+            Some(local) => Ok(local.vtype),
+            None => Err(format!("variable {} is not defined", name)),
+        }
+    }
+
+}
+
+#[cfg(test)]
+use crate::ir::{Export, Local};
+
+#[cfg(test)]
+fn validate_body(body: Vec<Instr>) -> Result<()> {
+    let module = Module {
+        functions: vec![Function {
+            name: "main".to_string(),
+            params: vec![Local::new("a", ValType::I32)],
+            results: vec![ValType::I32],
+            locals: vec![],
+            body,
+        }],
+        exports: vec![Export::function("main", "main")],
+        ..Default::default()
+    };
+    let mut bytes = vec![];
+    module.write_wasm(&mut bytes).unwrap();
+    validate(&bytes).map_err(|error| error.to_string())
+}
+
+#[test]
+fn test_operand_stack() {
+    use Instr::*;
+    let a = || LocalGet("a".to_string());
+    assert_eq!(validate_body(vec![a()]), Ok(()));
+    assert_eq!(validate_body(vec![a(), a(), Numeric(NumOp::I32Add)]), Ok(()));
+    // return の後ろは何を取り出してもよい
+    assert_eq!(validate_body(vec![a(), Return, Drop, Drop, Numeric(NumOp::I32Add)]), Ok(()));
+    assert_eq!(validate_body(vec![a(), Numeric(NumOp::I32Add)]), Err("function 0: i32.add: operand stack underflow".to_string()));
+    assert_eq!(validate_body(vec![a(), a()]), Err("function 0: function body: 1 values left on the stack at end".to_string()));
+    assert_eq!(validate_body(vec![]), Err("function 0: function body: operand stack underflow".to_string()));
+    // ブロックの中から外の値は取り出せない
+    let block = Block { label: "b".to_string(), result: None, body: vec![Drop] };
+    assert_eq!(validate_body(vec![a(), block, a()]), Err("function 0: drop: operand stack underflow".to_string()));
+    // 値を返す block への分岐は値を渡す
+    let block = Block { label: "b".to_string(), result: Some(ValType::I32), body: vec![a(), a(), BrIf("b".to_string()), Drop, a()] };
+    assert_eq!(validate_body(vec![block]), Ok(()));
+    let block = Block { label: "b".to_string(), result: Some(ValType::I32), body: vec![Br("b".to_string())] };
+    assert_eq!(validate_body(vec![block]), Err("function 0: br $b: operand stack underflow".to_string()));
+    let if_node = If { label: None, result: Some(ValType::I32), then: vec![a()], otherwise: vec![] };
+    assert_eq!(validate_body(vec![a(), if_node]), Err("function 0: if (result i32): if without else cannot produce a value".to_string()));
+}
+
+#[test]
+fn test_section_order() {
+    let mut bytes = vec![];
+    let module = Module::default();
+    module.write_wasm(&mut bytes).unwrap();
+    assert_eq!(validate(&bytes), Ok(()));
+    // 空のセクションは 3 バイトずつ。type と function を入れ替える
+    let swapped = [&bytes[..8], &bytes[11..14], &bytes[8..11], &bytes[14..]].concat();
+    assert_eq!(validate(&swapped).unwrap_err().to_string(), "offset 0xb: section 0x01 is out of order");
+    let repeated = [&bytes[..], &bytes[8..11]].concat();
+    assert_eq!(validate(&repeated).unwrap_err().to_string(), "offset 0x21: section 0x01 is out of order");
+}
+
+#[test]
+fn test_compiled_programs() {
+    let source = "fib(n){if(n<2)return n;return fib(n-1)+fib(n-2);}sum(n,a){if(n==0)return a;return sum(n-1,a+n);}\
+                  main(){a=0;for(i=0;i<10;i=i+1)a=a+fib(i);while(a>100)a=a/2;return sum(a,0);}";
+    for level in crate::optimizer::OptLevel::ALL {
+        let mut module = crate::wasmc::parse(source);
+        let mut manager = crate::optimizer::PassManager::new(level);
+        manager.enable("return-call");
+        manager.run(&mut module);
+        let mut bytes = vec![];
+        module.lower().write_wasm(&mut bytes).unwrap();
+        assert_eq!(validate(&bytes), Ok(()), "{}", level.flag());
+    }
+}
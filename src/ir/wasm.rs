@@ -0,0 +1,447 @@
+use std::io::{Write, Result};
+use crate::ir::{ExportKind, Function, Instr, Module, ValType};
+use crate::ir::leb128::{i32_to_leb128, i32_to_padded_leb128, usize_to_leb128, usize_to_padded_leb128};
+
+// 再配置できるオブジェクトで、リンクするときに書き換える値。offset は code section の中身の先頭から数える
+pub(crate) struct Reloc {
+    pub offset: usize,
+    pub target: RelocTarget,
+}
+
+pub(crate) enum RelocTarget {
+    Function(String),
+    // i32.const に書いた線形メモリのアドレス
+    Memory(i32),
+}
+
+// 関数本体の書き方
+pub(crate) enum Code<'a> {
+    Instructions,
+    // 再配置する番号やアドレスを 5 バイトに揃えて書き、その位置を集める
+    Relocatable(&'a mut Vec<Reloc>),
+    // リンカが再配置を済ませた本体をそのまま書く
+    Bytes(&'a [Vec<u8>]),
+}
+
+pub fn write_module(module: &Module, write: &mut dyn Write) -> Result<()> {
+    write_sections(module, Code::Instructions, write).map(|_| ())
+}
+
+// code section がいくつ目のセクションかを返す。reloc.CODE はこの番号で code section を指す
+pub(crate) fn write_sections(module: &Module, code: Code, write: &mut dyn Write) -> Result<usize> {
+    write.write_all(&[0x00, 0x61, 0x73, 0x6d])?; // WASM_BINARY_MAGIC
+    write.write_all(&[0x01, 0x00, 0x00, 0x00])?; // WASM_BINARY_VERSION
+    write_section(0x01, &type_section(module)?, write)?;
+    if !module.imports.is_empty() {
+        write_section(0x02, &import_section(module)?, write)?;
+    }
+    write_section(0x03, &function_section(module)?, write)?;
+    if let Some(memory) = &module.memory {
+        let mut buf = vec![0x01]; // num memories
+        write_limits(memory.min, memory.max, &mut buf)?;
+        write_section(0x05, &buf, write)?;
+    }
+    if !module.globals.is_empty() {
+        write_section(0x06, &global_section(module)?, write)?;
+    }
+    write_section(0x07, &export_section(module)?, write)?;
+    write_section(0x0a, &code_section(module, code)?, write)?;
+    if !module.data.is_empty() {
+        write_section(0x0b, &data_section(module)?, write)?;
+    }
+    write_custom_section("name", &name_section(module)?, write)?;
+    for custom in module.customs.iter() {
+        write_custom_section(&custom.name, &custom.bytes, write)?;
+    }
+    // type、function、export と、あれば import、memory、global が code の前に並ぶ
+    let optional = [!module.imports.is_empty(), module.memory.is_some(), !module.globals.is_empty()];
+    Ok(3 + optional.iter().filter(|present| **present).count())
+}
+
+pub(crate) fn write_section(code: u8, buf: &[u8], write: &mut dyn Write) -> Result<()> {
+    write.write_all(&[code])?; // section code
+    write.write_all(&usize_to_leb128(buf.len()))?; // section size
+    write.write_all(buf)?;
+    Ok(())
+}
+
+pub(crate) fn write_custom_section(name: &str, bytes: &[u8], write: &mut dyn Write) -> Result<()> {
+    let mut buf = vec![];
+    write_name(name, &mut buf)?;
+    buf.write_all(bytes)?;
+    write_section(0x00, &buf, write)
+}
+
+pub(crate) fn write_name(name: &str, write: &mut dyn Write) -> Result<()> {
+    write.write_all(&usize_to_leb128(name.len()))?; // string length
+    write.write_all(name.as_bytes())
+}
+
+fn write_limits(min: u32, max: Option<u32>, write: &mut dyn Write) -> Result<()> {
+    match max {
+        Some(max) => {
+            write.write_all(&[0x01])?; // min と max
+            write.write_all(&usize_to_leb128(min as usize))?;
+            write.write_all(&usize_to_leb128(max as usize))
+        },
+        None => {
+            write.write_all(&[0x00])?; // min のみ
+            write.write_all(&usize_to_leb128(min as usize))
+        },
+    }
+}
+
+// type は import した関数と定義した関数ごとに 1 つずつ、関数と同じ順に定義する
+fn type_section(module: &Module) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    buf.write_all(&usize_to_leb128(module.imports.len() + module.functions.len()))?; // num types
+    for import in module.imports.iter() {
+        write_func_type(&import.params, &import.results, &mut buf)?;
+    }
+    for function in module.functions.iter() {
+        let params: Vec<ValType> = function.params.iter().map(|param| param.vtype).collect();
+        write_func_type(&params, &function.results, &mut buf)?;
+    }
+    Ok(buf)
+}
+
+fn write_func_type(params: &[ValType], results: &[ValType], write: &mut dyn Write) -> Result<()> {
+    write.write_all(&[0x60])?; // func
+    write.write_all(&usize_to_leb128(params.len()))?; // num params
+    for param in params.iter() {
+        write.write_all(&[param.code()])?; // param type
+    }
+    write.write_all(&usize_to_leb128(results.len()))?; // num results
+    for result in results.iter() {
+        write.write_all(&[result.code()])?; // result type
+    }
+    Ok(())
+}
+
+fn import_section(module: &Module) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    buf.write_all(&usize_to_leb128(module.imports.len()))?; // num imports
+    for (i, import) in module.imports.iter().enumerate() {
+        write_name(&import.module, &mut buf)?; // module name
+        write_name(&import.field, &mut buf)?; // field name
+        buf.write_all(&[0x00])?; // import kind
+        buf.write_all(&usize_to_leb128(i))?; // signature index
+    }
+    Ok(buf)
+}
+
+fn function_section(module: &Module) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    buf.write_all(&usize_to_leb128(module.functions.len()))?; // num functions
+    for i in 0..module.functions.len() {
+        buf.write_all(&usize_to_leb128(module.imports.len() + i))?; // function signature index
+    }
+    Ok(buf)
+}
+
+fn global_section(module: &Module) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    buf.write_all(&usize_to_leb128(module.globals.len()))?; // num globals
+    for global in module.globals.iter() {
+        buf.write_all(&[global.vtype.code(), global.mutable as u8])?; // global type
+        buf.write_all(&[0x41])?; // i32.const
+        buf.write_all(&i32_to_leb128(global.init))?;
+        buf.write_all(&[0x0b])?; // end
+    }
+    Ok(buf)
+}
+
+fn export_section(module: &Module) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    buf.write_all(&usize_to_leb128(module.exports.len()))?; // num exports
+    for export in module.exports.iter() {
+        write_name(&export.name, &mut buf)?; // export name
+        match &export.kind {
+            ExportKind::Function(name) => {
+                buf.write_all(&[0x00])?; // export kind
+                buf.write_all(&usize_to_leb128(function_index(module, name)))?; // export func index
+            },
+            ExportKind::Memory => {
+                buf.write_all(&[0x02, 0x00])?; // memory 0
+            },
+            ExportKind::Global(name) => {
+                buf.write_all(&[0x03])?; // export kind
+                buf.write_all(&usize_to_leb128(global_index(module, name)))?;
+            },
+        }
+    }
+    Ok(buf)
+}
+
+fn code_section(module: &Module, code: Code) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    buf.write_all(&usize_to_leb128(module.functions.len()))?; // num functions
+    match code {
+        Code::Instructions => for function in module.functions.iter() {
+            let body = function_body(module, function, &mut None)?;
+            buf.write_all(&usize_to_leb128(body.len()))?; // function body size
+            buf.write_all(&body)?;
+        },
+        Code::Relocatable(relocs) => for function in module.functions.iter() {
+            let mut body_relocs = vec![];
+            let body = function_body(module, function, &mut Some(&mut body_relocs))?;
+            buf.write_all(&usize_to_leb128(body.len()))?; // function body size
+            // 本体の中での位置を code section の中での位置に直す
+            let start = buf.len();
+            relocs.extend(body_relocs.into_iter().map(|reloc| Reloc { offset: start + reloc.offset, ..reloc }));
+            buf.write_all(&body)?;
+        },
+        Code::Bytes(bodies) => for body in bodies.iter() {
+            buf.write_all(&usize_to_leb128(body.len()))?; // function body size
+            buf.write_all(body)?;
+        },
+    }
+    Ok(buf)
+}
+
+fn data_section(module: &Module) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    buf.write_all(&usize_to_leb128(module.data.len()))?; // num data segments
+    for data in module.data.iter() {
+        buf.write_all(&[0x00])?; // memory 0 に置く
+        buf.write_all(&[0x41])?; // i32.const
+        buf.write_all(&i32_to_leb128(data.offset))?;
+        buf.write_all(&[0x0b])?; // end
+        buf.write_all(&usize_to_leb128(data.bytes.len()))?;
+        buf.write_all(&data.bytes)?;
+    }
+    Ok(buf)
+}
+
+// 逆アセンブルで元の名前に戻せるように、関数 (1)、ローカル変数 (2)、ラベル (3)、
+// グローバル変数 (7) の名前を書く。ラベルの番号は関数の中で block、loop、if が現れた順
+fn name_section(module: &Module) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+
+    let names: Vec<&String> = module.imports.iter().map(|import| &import.name)
+        .chain(module.functions.iter().map(|function| &function.name)).collect();
+    write_name_map(0x01, &names.into_iter().enumerate().collect::<Vec<_>>(), &mut buf)?;
+
+    let mut locals = vec![];
+    let mut labels = vec![];
+    for (i, function) in module.functions.iter().enumerate() {
+        let index = module.imports.len() + i;
+        let names: Vec<(usize, &String)> = function.params.iter().chain(function.locals.iter())
+            .map(|local| &local.name).enumerate().collect();
+        locals.push((index, names));
+        let mut names = vec![];
+        label_names(&function.body, &mut 0, &mut names);
+        if !names.is_empty() {
+            labels.push((index, names));
+        }
+    }
+    write_indirect_name_map(0x02, &locals, &mut buf)?;
+    if !labels.is_empty() {
+        write_indirect_name_map(0x03, &labels, &mut buf)?;
+    }
+
+    if !module.globals.is_empty() {
+        let names: Vec<(usize, &String)> = module.globals.iter().map(|global| &global.name).enumerate().collect();
+        write_name_map(0x07, &names, &mut buf)?;
+    }
+    Ok(buf)
+}
+
+fn label_names<'a>(instructions: &'a [Instr], next: &mut usize, names: &mut Vec<(usize, &'a String)>) {
+    for instruction in instructions {
+        match instruction {
+            Instr::Block { label, body, .. } | Instr::Loop { label, body, .. } => {
+                names.push((*next, label));
+                *next += 1;
+                label_names(body, next, names);
+            },
+            Instr::If { label, then, otherwise, .. } => {
+                if let Some(label) = label {
+                    names.push((*next, label));
+                }
+                *next += 1;
+                label_names(then, next, names);
+                label_names(otherwise, next, names);
+            },
+            _ => {},
+        }
+    }
+}
+
+fn write_name_map(id: u8, names: &[(usize, &String)], write: &mut dyn Write) -> Result<()> {
+    let mut buf = vec![];
+    write_names(names, &mut buf)?;
+    write.write_all(&[id])?; // subsection id
+    write.write_all(&usize_to_leb128(buf.len()))?; // subsection size
+    write.write_all(&buf)
+}
+
+fn write_indirect_name_map(id: u8, maps: &[(usize, Vec<(usize, &String)>)], write: &mut dyn Write) -> Result<()> {
+    let mut buf = vec![];
+    buf.write_all(&usize_to_leb128(maps.len()))?;
+    for (index, names) in maps.iter() {
+        buf.write_all(&usize_to_leb128(*index))?;
+        write_names(names, &mut buf)?;
+    }
+    write.write_all(&[id])?; // subsection id
+    write.write_all(&usize_to_leb128(buf.len()))?; // subsection size
+    write.write_all(&buf)
+}
+
+fn write_names(names: &[(usize, &String)], write: &mut dyn Write) -> Result<()> {
+    write.write_all(&usize_to_leb128(names.len()))?;
+    for (index, name) in names.iter() {
+        write.write_all(&usize_to_leb128(*index))?;
+        write_name(name, write)?;
+    }
+    Ok(())
+}
+
+// relocs の位置は本体の先頭から数える
+fn function_body(module: &Module, function: &Function, relocs: &mut Option<&mut Vec<Reloc>>) -> Result<Vec<u8>> {
+    let mut buf = vec![];
+    buf.write_all(&usize_to_leb128(function.locals.len()))?; // local decl count
+    for local in function.locals.iter() {
+        buf.write_all(&[0x01, local.vtype.code()])?;
+    }
+    let mut labels = vec![];
+    write_instructions(module, function, &function.body, &mut labels, relocs, &mut buf)?;
+    buf.write_all(&[0x0b])?; // end
+    Ok(buf)
+}
+
+// labels は現在開いているブロックのラベル。br の深さを求めるのに使う
+fn write_instructions(module: &Module, function: &Function, instructions: &[Instr], labels: &mut Vec<String>, relocs: &mut Option<&mut Vec<Reloc>>, write: &mut Vec<u8>) -> Result<()> {
+    for instruction in instructions {
+        match instruction {
+            Instr::Block { label, result, body } => {
+                write.write_all(&[0x02, block_type(result)])?; // block
+                labels.push(label.to_string());
+                write_instructions(module, function, body, labels, relocs, write)?;
+                labels.pop();
+                write.write_all(&[0x0b])?; // end
+            },
+            Instr::Loop { label, result, body } => {
+                write.write_all(&[0x03, block_type(result)])?; // loop
+                labels.push(label.to_string());
+                write_instructions(module, function, body, labels, relocs, write)?;
+                labels.pop();
+                write.write_all(&[0x0b])?; // end
+            },
+            Instr::If { label, result, then, otherwise } => {
+                write.write_all(&[0x04, block_type(result)])?; // if
+                labels.push(label.clone().unwrap_or_default());
+                write_instructions(module, function, then, labels, relocs, write)?;
+                if !otherwise.is_empty() {
+                    write.write_all(&[0x05])?; // else
+                    write_instructions(module, function, otherwise, labels, relocs, write)?;
+                }
+                labels.pop();
+                write.write_all(&[0x0b])?; // end
+            },
+            Instr::Br(label) => {
+                write.write_all(&[0x0c])?; // br
+                write.write_all(&usize_to_leb128(label_depth(labels, label)))?;
+            },
+            Instr::BrIf(label) => {
+                write.write_all(&[0x0d])?; // br_if
+                write.write_all(&usize_to_leb128(label_depth(labels, label)))?;
+            },
+            Instr::Return => {
+                write.write_all(&[0x0f])?; // return
+            },
+            Instr::Call(name) => {
+                write.write_all(&[0x10])?; // call
+                write_function_index(module, name, relocs, write);
+            },
+            Instr::ReturnCall(name) => {
+                write.write_all(&[0x12])?; // return_call (tail call 拡張)
+                write_function_index(module, name, relocs, write);
+            },
+            Instr::Drop => {
+                write.write_all(&[0x1a])?; // drop
+            },
+            Instr::LocalGet(name) => {
+                write.write_all(&[0x20])?; // local.get
+                write.write_all(&usize_to_leb128(local_index(function, name)))?;
+            },
+            Instr::LocalSet(name) => {
+                write.write_all(&[0x21])?; // local.set
+                write.write_all(&usize_to_leb128(local_index(function, name)))?;
+            },
+            Instr::LocalTee(name) => {
+                write.write_all(&[0x22])?; // local.tee
+                write.write_all(&usize_to_leb128(local_index(function, name)))?;
+            },
+            Instr::I32Const(value) => {
+                write.write_all(&[0x41])?; // i32.const
+                write.write_all(&i32_to_leb128(*value))?; // i32 literal
+            },
+            Instr::Address(value) => {
+                write.write_all(&[0x41])?; // i32.const
+                match relocs {
+                    Some(relocs) => {
+                        relocs.push(Reloc { offset: write.len(), target: RelocTarget::Memory(*value) });
+                        write.write_all(&i32_to_padded_leb128(*value))?;
+                    },
+                    None => write.write_all(&i32_to_leb128(*value))?,
+                }
+            },
+            Instr::Numeric(op) => {
+                write.write_all(&[op.opcode()])?;
+            },
+            Instr::Memory { op, offset } => {
+                write.write_all(&[op.opcode()])?;
+                write.write_all(&usize_to_leb128(op.align() as usize))?; // align
+                write.write_all(&usize_to_leb128(*offset as usize))?;
+            },
+        }
+    }
+    Ok(())
+}
+
+fn write_function_index(module: &Module, name: &str, relocs: &mut Option<&mut Vec<Reloc>>, write: &mut Vec<u8>) {
+    let index = function_index(module, name);
+    match relocs {
+        Some(relocs) => {
+            relocs.push(Reloc { offset: write.len(), target: RelocTarget::Function(name.to_string()) });
+            write.extend(usize_to_padded_leb128(index as u32));
+        },
+        None => write.extend(usize_to_leb128(index)),
+    }
+}
+
+fn block_type(result: &Option<ValType>) -> u8 {
+    match result {
+        Some(vtype) => vtype.code(),
+        None => 0x40, // 値なし
+    }
+}
+
+fn label_depth(labels: &[String], label: &str) -> usize {
+    match labels.iter().rev().position(|l| l == label) {
+        Some(depth) => depth,
+        None => panic!("label {} is not defined", label)
+    }
+}
+
+fn local_index(function: &Function, name: &str) -> usize {
+    match function.local_index(name) {
+        Some(index) => index,
+        None => panic!("variable {} is not defined", name)
+    }
+}
+
+fn function_index(module: &Module, name: &str) -> usize {
+    match module.function_index(name) {
+        Some(index) => index,
+        None => panic!("function `{}` not found", name)
+    }
+}
+
+fn global_index(module: &Module, name: &str) -> usize {
+    match module.global_index(name) {
+        Some(index) => index,
+        None => panic!("global `{}` not found", name)
+    }
+}
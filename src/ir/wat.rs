@@ -0,0 +1,103 @@
+use std::io::{Write, Result};
+use crate::ir::{ExportKind, Function, Instr, Module, ValType};
+
+pub fn write_module(module: &Module, write: &mut dyn Write) -> Result<()> {
+    writeln!(write, "(module")?;
+    for import in module.imports.iter() {
+        write!(write, "  (import {} {} (func ${}", string(import.module.as_bytes()), string(import.field.as_bytes()), import.name)?;
+        write_types("param", &import.params, write)?;
+        write_types("result", &import.results, write)?;
+        writeln!(write, "))")?;
+    }
+    if let Some(memory) = &module.memory {
+        match memory.max {
+            Some(max) => writeln!(write, "  (memory {} {})", memory.min, max)?,
+            None => writeln!(write, "  (memory {})", memory.min)?,
+        }
+    }
+    for global in module.globals.iter() {
+        let vtype = if global.mutable { format!("(mut {})", global.vtype.wat_name()) } else { global.vtype.wat_name().to_string() };
+        writeln!(write, "  (global ${} {} (i32.const {}))", global.name, vtype, global.init)?;
+    }
+    for function in module.functions.iter() {
+        write_function(function, write)?;
+    }
+    for export in module.exports.iter() {
+        match &export.kind {
+            ExportKind::Function(name) => writeln!(write, "  (export {} (func ${}))", string(export.name.as_bytes()), name)?,
+            ExportKind::Memory => writeln!(write, "  (export {} (memory 0))", string(export.name.as_bytes()))?,
+            ExportKind::Global(name) => writeln!(write, "  (export {} (global ${}))", string(export.name.as_bytes()), name)?,
+        }
+    }
+    for data in module.data.iter() {
+        writeln!(write, "  (data (i32.const {}) {})", data.offset, string(&data.bytes))?;
+    }
+    // custom section は annotations 提案の書き方にする
+    for custom in module.customs.iter() {
+        writeln!(write, "  (@custom {} {})", string(custom.name.as_bytes()), string(&custom.bytes))?;
+    }
+    writeln!(write, ")")?;
+    Ok(())
+}
+
+fn write_types(kind: &str, types: &[ValType], write: &mut dyn Write) -> Result<()> {
+    if !types.is_empty() {
+        let names: Vec<&str> = types.iter().map(|vtype| vtype.wat_name()).collect();
+        write!(write, " ({} {})", kind, names.join(" "))?;
+    }
+    Ok(())
+}
+
+// 表示できる ASCII 以外は \hh にする
+fn string(bytes: &[u8]) -> String {
+    let mut text = String::from("\"");
+    for byte in bytes {
+        match byte {
+            b'"' | b'\\' => text.push_str(&format!("\\{}", *byte as char)),
+            0x20..=0x7e => text.push(*byte as char),
+            _ => text.push_str(&format!("\\{:02x}", byte)),
+        }
+    }
+    text.push('"');
+    text
+}
+
+fn write_function(function: &Function, write: &mut dyn Write) -> Result<()> {
+    writeln!(write, "  (func ${}", function.name)?;
+    for param in function.params.iter() {
+        writeln!(write, "    (param ${} {})", param.name, param.vtype.wat_name())?;
+    }
+    for result in function.results.iter() {
+        writeln!(write, "    (result {})", result.wat_name())?;
+    }
+    for local in function.locals.iter() {
+        writeln!(write, "    (local ${} {})", local.name, local.vtype.wat_name())?;
+    }
+    write_instructions(&function.body, 2, write)?;
+    writeln!(write, "  )")?;
+    Ok(())
+}
+
+// 入れ子の深さに合わせて字下げした平らな形式で書き出す
+fn write_instructions(instructions: &[Instr], depth: usize, write: &mut dyn Write) -> Result<()> {
+    let indent = "  ".repeat(depth);
+    for instruction in instructions {
+        writeln!(write, "{}{}", indent, instruction)?;
+        match instruction {
+            Instr::Block { body, .. } | Instr::Loop { body, .. } => {
+                write_instructions(body, depth + 1, write)?;
+                writeln!(write, "{}end", indent)?;
+            },
+            Instr::If { then, otherwise, .. } => {
+                write_instructions(then, depth + 1, write)?;
+                if !otherwise.is_empty() {
+                    writeln!(write, "{}else", indent)?;
+                    write_instructions(otherwise, depth + 1, write)?;
+                }
+                writeln!(write, "{}end", indent)?;
+            },
+            _ => {}
+        }
+    }
+    Ok(())
+}
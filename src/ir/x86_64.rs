@@ -0,0 +1,556 @@
+use std::io::{Write, Result};
+use crate::interpreter::MAX_CALL_DEPTH;
+use crate::ir::{Context, ExportKind, Function, Instr, MemOp, Memory, Module, NumOp};
+
+// System V の整数引数レジスタ
+const ARG_REGISTERS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+const PAGE_SIZE: u64 = 65536;
+
+// GNU as (AT&T 記法) の x86-64 アセンブリを書き出す。オペランドスタックはそのままマシンのスタックに積み、
+// ローカル変数は rbp からの 8 バイトずつのスロットに置く。値は下位 32 bit だけを使い、i32 の演算は
+// 32 bit 命令で行うので wasm と同じく桁あふれする。export された main があれば、コマンドライン引数を
+// 渡して結果を表示する C の main も書くので、cc でそのままリンクできる
+pub fn write_module(module: &Module, write: &mut dyn Write) -> Result<()> {
+    writeln!(write, "    .text")?;
+    for (index, function) in module.functions.iter().enumerate() {
+        FunctionWriter::new(module, function, index, write).function()?;
+    }
+    let main = module.exports.iter().find_map(|export| match &export.kind {
+        ExportKind::Function(name) if export.name == "main" => module.function(name),
+        _ => None,
+    });
+    if let Some(main) = main {
+        write_entry(main, write)?;
+    }
+    if let Some(memory) = &module.memory {
+        write_memory(module, memory, write)?;
+    }
+    for import in module.imports.iter().filter(|import| import.is_builtin(module)) {
+        write_builtin(module, &import.field, write)?;
+    }
+    write_runtime(write)?;
+    Ok(())
+}
+
+// import した関数は同じ名前の C の関数を呼ぶ。定義した関数は libc と重ならないように wc_ を付ける
+fn symbol(module: &Module, name: &str) -> String {
+    match module.imports.iter().find(|import| import.name == name) {
+        Some(import) if import.is_builtin(module) => format!("wasmc_{}", import.field),
+        Some(import) => format!("{}@PLT", import.field),
+        None => format!("wc_{}", name),
+    }
+}
+
+struct FunctionWriter<'a> {
+    context: Context<'a>,
+    index: usize,
+    write: &'a mut dyn Write,
+    next_label: usize,
+    // ローカル変数の領域のバイト数 (16 の倍数)
+    frame: usize,
+}
+
+impl <'a> FunctionWriter<'a> {
+
+    fn new(module: &'a Module, function: &'a Function, index: usize, write: &'a mut dyn Write) -> Self {
+        let locals = function.params.len() + function.locals.len();
+        let frame = (locals * 8).div_ceil(16) * 16;
+        FunctionWriter { context: Context::new(module, function), index, write, next_label: 0, frame }
+    }
+
+    fn function(&mut self) -> Result<()> {
+        writeln!(self.write)?;
+        writeln!(self.write, "wc_{}:", self.context.function.name)?;
+        self.line("pushq %rbp")?;
+        self.line("movq %rsp, %rbp")?;
+        if self.frame > 0 {
+            self.line(&format!("subq ${}, %rsp", self.frame))?;
+        }
+        // 再帰が深すぎたらインタープリタと同じくトラップにする
+        self.line(&format!("cmpl ${}, wasmc_depth(%rip)", MAX_CALL_DEPTH))?;
+        self.line("jae wasmc_trap_stack")?;
+        self.line("incl wasmc_depth(%rip)")?;
+        for (i, _) in self.context.function.params.iter().enumerate() {
+            match ARG_REGISTERS.get(i) {
+                Some(register) => self.line(&format!("movq {}, {}", register, slot(i)))?,
+                None => {
+                    self.line(&format!("movq {}(%rbp), %rax", 16 + (i - ARG_REGISTERS.len()) * 8))?;
+                    self.line(&format!("movq %rax, {}", slot(i)))?;
+                },
+            }
+        }
+        for i in 0..self.context.function.locals.len() {
+            self.line(&format!("movq $0, {}", slot(self.context.function.params.len() + i)))?;
+        }
+        self.instructions(&self.context.function.body)?;
+        if !self.context.function.results.is_empty() {
+            self.line("popq %rax")?;
+        }
+        writeln!(self.write, ".L{}_return:", self.index)?;
+        self.line("decl wasmc_depth(%rip)")?;
+        self.line("movq %rbp, %rsp")?;
+        self.line("popq %rbp")?;
+        self.line("ret")?;
+        Ok(())
+    }
+
+    fn line(&mut self, text: &str) -> Result<()> {
+        writeln!(self.write, "    {}", text)
+    }
+
+    fn new_label(&mut self) -> String {
+        let label = format!(".L{}_{}", self.index, self.next_label);
+        self.next_label += 1;
+        label
+    }
+
+    fn instructions(&mut self, instructions: &[Instr]) -> Result<()> {
+        for instruction in instructions {
+            self.instruction(instruction)?;
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, instruction: &Instr) -> Result<()> {
+        match instruction {
+            Instr::Block { label, result, body } => {
+                let end = self.new_label();
+                let results = result.iter().count();
+                self.context.push_label(label, &end, results, results);
+                self.instructions(body)?;
+                self.context.pop_label();
+                writeln!(self.write, "{}:", end)?;
+            },
+            Instr::Loop { label, result, body } => {
+                let start = self.new_label();
+                writeln!(self.write, "{}:", start)?;
+                self.context.push_label(label, &start, 0, result.iter().count());
+                self.instructions(body)?;
+                self.context.pop_label();
+            },
+            Instr::If { label, result, then, otherwise } => {
+                let otherwise_label = self.new_label();
+                let end = self.new_label();
+                self.line("popq %rax")?;
+                self.context.pop_height(1);
+                self.line("testl %eax, %eax")?;
+                self.line(&format!("je {}", if otherwise.is_empty() { &end } else { &otherwise_label }))?;
+                let results = result.iter().count();
+                self.context.push_label(label.as_deref().unwrap_or_default(), &end, results, results);
+                self.instructions(then)?;
+                if !otherwise.is_empty() {
+                    self.line(&format!("jmp {}", end))?;
+                    writeln!(self.write, "{}:", otherwise_label)?;
+                    self.context.height = self.context.labels.last().unwrap().height;
+                    self.instructions(otherwise)?;
+                }
+                self.context.pop_label();
+                writeln!(self.write, "{}:", end)?;
+            },
+            Instr::Br(label) => self.branch(label)?,
+            Instr::BrIf(label) => {
+                let skip = self.new_label();
+                self.line("popq %rax")?;
+                self.context.pop_height(1);
+                self.line("testl %eax, %eax")?;
+                self.line(&format!("je {}", skip))?;
+                let height = self.context.height;
+                self.branch(label)?;
+                self.context.height = height;
+                writeln!(self.write, "{}:", skip)?;
+            },
+            Instr::Return => {
+                if !self.context.function.results.is_empty() {
+                    self.line("popq %rax")?;
+                }
+                self.line(&format!("jmp .L{}_return", self.index))?;
+            },
+            Instr::Call(name) => {
+                let (params, results) = self.context.signature(name);
+                self.call(name, params.len())?;
+                if !results.is_empty() {
+                    self.line("pushq %rax")?;
+                    self.context.height += 1;
+                }
+            },
+            Instr::ReturnCall(name) => {
+                let (params, _) = self.context.signature(name);
+                if params.len() <= ARG_REGISTERS.len() {
+                    // 引数をレジスタに移せば、フレームを捨てて飛ぶだけでよい
+                    for (i, register) in ARG_REGISTERS.iter().take(params.len()).enumerate() {
+                        self.line(&format!("movq {}(%rsp), {}", (params.len() - 1 - i) * 8, register))?;
+                    }
+                    self.line("decl wasmc_depth(%rip)")?;
+                    self.line("movq %rbp, %rsp")?;
+                    self.line("popq %rbp")?;
+                    self.line(&format!("jmp {}", symbol(self.context.module, name)))?;
+                } else {
+                    self.call(name, params.len())?;
+                    self.line(&format!("jmp .L{}_return", self.index))?;
+                }
+            },
+            Instr::Drop => {
+                self.line("addq $8, %rsp")?;
+                self.context.pop_height(1);
+            },
+            Instr::LocalGet(name) => {
+                self.line(&format!("pushq {}", slot(self.context.local_index(name))))?;
+                self.context.height += 1;
+            },
+            Instr::LocalSet(name) => {
+                self.line("popq %rax")?;
+                self.line(&format!("movq %rax, {}", slot(self.context.local_index(name))))?;
+                self.context.pop_height(1);
+            },
+            Instr::LocalTee(name) => {
+                self.line("movq (%rsp), %rax")?;
+                self.line(&format!("movq %rax, {}", slot(self.context.local_index(name))))?;
+            },
+            Instr::I32Const(value) | Instr::Address(value) => {
+                self.line(&format!("pushq ${}", value))?;
+                self.context.height += 1;
+            },
+            Instr::Numeric(op) => self.numeric(*op)?,
+            Instr::Memory { op, offset } => self.memory(*op, *offset)?,
+        }
+        Ok(())
+    }
+
+    // 残す値を除いて、スタックをラベルの高さまで戻してから飛ぶ
+    fn branch(&mut self, name: &str) -> Result<()> {
+        let label = self.context.label(name);
+        let (target, height, arity) = (label.target.to_string(), label.height, label.arity);
+        if self.context.height != height + arity {
+            if arity > 0 {
+                self.line("popq %rax")?;
+            }
+            self.line(&format!("leaq -{}(%rbp), %rsp", self.frame + height * 8))?;
+            if arity > 0 {
+                self.line("pushq %rax")?;
+            }
+        }
+        self.line(&format!("jmp {}", target))?;
+        Ok(())
+    }
+
+    // スタックの上から count 個を引数にして呼ぶ。7 個目からはスタックに並べ直す
+    fn call(&mut self, name: &str, count: usize) -> Result<()> {
+        let on_stack = count.saturating_sub(ARG_REGISTERS.len());
+        // call の時点で rsp を 16 の倍数にする
+        let pad = (self.context.height + on_stack) % 2;
+        let reserved = (on_stack + pad) * 8;
+        if reserved > 0 {
+            self.line(&format!("subq ${}, %rsp", reserved))?;
+        }
+        // i 番目の引数は上から count - 1 - i 番目にある
+        let arg = |i: usize| reserved + (count - 1 - i) * 8;
+        for j in 0..on_stack {
+            self.line(&format!("movq {}(%rsp), %rax", arg(ARG_REGISTERS.len() + j)))?;
+            self.line(&format!("movq %rax, {}(%rsp)", j * 8))?;
+        }
+        for (i, register) in ARG_REGISTERS.iter().enumerate().take(count) {
+            self.line(&format!("movq {}(%rsp), {}", arg(i), register))?;
+        }
+        self.line(&format!("call {}", symbol(self.context.module, name)))?;
+        if reserved + count * 8 > 0 {
+            self.line(&format!("addq ${}, %rsp", reserved + count * 8))?;
+        }
+        self.context.pop_height(count);
+        Ok(())
+    }
+
+    fn numeric(&mut self, op: NumOp) -> Result<()> {
+        if op == NumOp::I32Eqz {
+            self.line("popq %rax")?;
+            self.line("testl %eax, %eax")?;
+            self.line("sete %al")?;
+            self.line("movzbl %al, %eax")?;
+            self.line("pushq %rax")?;
+            return Ok(());
+        }
+        self.line("popq %rcx")?;
+        self.line("popq %rax")?;
+        self.context.pop_height(1);
+        let compare = |set: &str| vec!["cmpl %ecx, %eax".to_string(), format!("{} %al", set), "movzbl %al, %eax".to_string()];
+        let lines = match op {
+            NumOp::I32Eqz => unreachable!(),
+            NumOp::I32Eq => compare("sete"),
+            NumOp::I32Ne => compare("setne"),
+            NumOp::I32LtS => compare("setl"),
+            NumOp::I32GtS => compare("setg"),
+            NumOp::I32LeS => compare("setle"),
+            NumOp::I32GeS => compare("setge"),
+            NumOp::I32Add => vec!["addl %ecx, %eax".to_string()],
+            NumOp::I32Sub => vec!["subl %ecx, %eax".to_string()],
+            NumOp::I32Mul => vec!["imull %ecx, %eax".to_string()],
+            NumOp::I32DivS => {
+                // idiv は 0 除算と i32::MIN / -1 で SIGFPE になるので、先に調べてトラップにする
+                let divide = self.new_label();
+                vec![
+                    "testl %ecx, %ecx".to_string(),
+                    "je wasmc_trap_div".to_string(),
+                    "cmpl $-1, %ecx".to_string(),
+                    format!("jne {}", divide),
+                    "cmpl $0x80000000, %eax".to_string(),
+                    "je wasmc_trap_overflow".to_string(),
+                    format!("{}:", divide),
+                    "cltd".to_string(),
+                    "idivl %ecx".to_string(),
+                ]
+            },
+            // シフト量は cl の下位 5 bit だけが使われる
+            NumOp::I32Shl => vec!["shll %cl, %eax".to_string()],
+            NumOp::I32ShrS => vec!["sarl %cl, %eax".to_string()],
+            NumOp::I32ShrU => vec!["shrl %cl, %eax".to_string()],
+        };
+        for line in lines {
+            if line.ends_with(':') {
+                writeln!(self.write, "{}", line)?;
+            } else {
+                self.line(&line)?;
+            }
+        }
+        self.line("pushq %rax")?;
+        Ok(())
+    }
+
+
+    // アドレスは符号なしで広げ、offset と大きさを足した終わりが範囲を超えればトラップにする
+    fn memory(&mut self, op: MemOp, offset: u32) -> Result<()> {
+        let size = match &self.context.module.memory {
+            Some(memory) => memory.min as u64 * PAGE_SIZE,
+            None => panic!("memory is not defined"),
+        };
+        if op.is_store() {
+            self.line("popq %rdx")?;
+        }
+        self.line("popq %rax")?;
+        self.line("movl %eax, %eax")?;
+        self.line(&format!("addq ${}, %rax", offset as u64 + op.size() as u64))?;
+        self.line(&format!("movq ${}, %rcx", size))?;
+        self.line("cmpq %rcx, %rax")?;
+        self.line("ja wasmc_trap_memory")?;
+        self.line("leaq wasmc_memory(%rip), %rcx")?;
+        let address = format!("-{}(%rcx,%rax)", op.size());
+        match op {
+            MemOp::I32Load => self.line(&format!("movl {}, %eax", address))?,
+            MemOp::I32Load8U => self.line(&format!("movzbl {}, %eax", address))?,
+            MemOp::I32Store => self.line(&format!("movl %edx, {}", address))?,
+            MemOp::I32Store8 => self.line(&format!("movb %dl, {}", address))?,
+        }
+        if op.is_store() {
+            self.context.pop_height(2);
+        } else {
+            self.line("pushq %rax")?;
+        }
+        Ok(())
+    }
+}
+
+fn slot(index: usize) -> String {
+    format!("-{}(%rbp)", (index + 1) * 8)
+}
+
+// C の main。引数の数を確かめて i32 に直し、結果を 1 行に 1 つずつ表示する
+fn write_entry(main: &Function, write: &mut dyn Write) -> Result<()> {
+    let count = main.params.len();
+    writeln!(write)?;
+    writeln!(write, "    .globl main")?;
+    writeln!(write, "main:")?;
+    writeln!(write, "    pushq %rbp")?;
+    writeln!(write, "    movq %rsp, %rbp")?;
+    writeln!(write, "    pushq %rbx")?;
+    writeln!(write, "    pushq %r12")?;
+    writeln!(write, "    movq %rsi, %rbx")?;
+    writeln!(write, "    cmpl ${}, %edi", count + 1)?;
+    writeln!(write, "    je .Lmain_args")?;
+    writeln!(write, "    leal -1(%rdi), %edx")?;
+    writeln!(write, "    movl ${}, %esi", count)?;
+    writeln!(write, "    leaq wasmc_usage(%rip), %rdi")?;
+    writeln!(write, "    movl $-1, %ecx")?;
+    writeln!(write, "    jmp wasmc_fail")?;
+    writeln!(write, ".Lmain_args:")?;
+    // 引数の分を確保して、rsp を 16 の倍数に保つ
+    let reserved = count.div_ceil(2) * 16;
+    if reserved > 0 {
+        writeln!(write, "    subq ${}, %rsp", reserved)?;
+    }
+    for i in 0..count {
+        writeln!(write, "    movq {}(%rbx), %rdi", (i + 1) * 8)?;
+        writeln!(write, "    call wasmc_arg")?;
+        writeln!(write, "    movq %rax, {}(%rsp)", i * 8)?;
+    }
+    for (i, register) in ARG_REGISTERS.iter().enumerate().take(count) {
+        writeln!(write, "    movq {}(%rsp), {}", i * 8, register)?;
+    }
+    if count > ARG_REGISTERS.len() {
+        // 7 個目からはスタックの先頭に詰める
+        for i in ARG_REGISTERS.len()..count {
+            writeln!(write, "    movq {}(%rsp), %rax", i * 8)?;
+            writeln!(write, "    movq %rax, {}(%rsp)", (i - ARG_REGISTERS.len()) * 8)?;
+        }
+    }
+    writeln!(write, "    call wc_{}", main.name)?;
+    if !main.results.is_empty() {
+        writeln!(write, "    leaq wasmc_result(%rip), %rdi")?;
+        writeln!(write, "    movl %eax, %esi")?;
+        writeln!(write, "    xorl %eax, %eax")?;
+        writeln!(write, "    call printf@PLT")?;
+    }
+    writeln!(write, "    xorl %eax, %eax")?;
+    writeln!(write, "    leaq -16(%rbp), %rsp")?;
+    writeln!(write, "    popq %r12")?;
+    writeln!(write, "    popq %rbx")?;
+    writeln!(write, "    popq %rbp")?;
+    writeln!(write, "    ret")?;
+    Ok(())
+}
+
+// 線形メモリ。data の初期値を並べ、残りは 0 で埋める
+fn write_memory(module: &Module, memory: &Memory, write: &mut dyn Write) -> Result<()> {
+    let size = memory.min as u64 * PAGE_SIZE;
+    let mut image = vec![];
+    for data in module.data.iter() {
+        let offset = data.offset as u32 as usize;
+        if image.len() < offset + data.bytes.len() {
+            image.resize(offset + data.bytes.len(), 0);
+        }
+        image[offset..offset + data.bytes.len()].copy_from_slice(&data.bytes);
+    }
+    writeln!(write)?;
+    writeln!(write, "    .data")?;
+    writeln!(write, "    .p2align 4")?;
+    writeln!(write, "wasmc_memory:")?;
+    for chunk in image.chunks(16) {
+        let bytes: Vec<String> = chunk.iter().map(|byte| byte.to_string()).collect();
+        writeln!(write, "    .byte {}", bytes.join(", "))?;
+    }
+    if size > image.len() as u64 {
+        writeln!(write, "    .zero {}", size - image.len() as u64)?;
+    }
+    writeln!(write, "    .text")?;
+    Ok(())
+}
+
+// 組み込み関数。WASI と同じく標準出力に書いて 0 を返す。print_str は終端の 0 がメモリの外ならトラップにする
+fn write_builtin(module: &Module, field: &str, write: &mut dyn Write) -> Result<()> {
+    writeln!(write)?;
+    writeln!(write, "wasmc_{}:", field)?;
+    writeln!(write, "    pushq %rbp")?;
+    writeln!(write, "    movq %rsp, %rbp")?;
+    if field == "print_int" {
+        writeln!(write, "    movl %edi, %esi")?;
+        writeln!(write, "    leaq wasmc_int_format(%rip), %rdi")?;
+    } else {
+        let size = module.memory.as_ref().map_or(0, |memory| memory.min as u64 * PAGE_SIZE);
+        writeln!(write, "    movl %edi, %eax")?;
+        writeln!(write, "    leaq wasmc_memory(%rip), %rsi")?;
+        writeln!(write, "    movq ${}, %rdx", size)?;
+        writeln!(write, "    movq %rax, %rcx")?;
+        writeln!(write, ".Lprint_str_scan:")?;
+        writeln!(write, "    cmpq %rdx, %rcx")?;
+        writeln!(write, "    jae wasmc_trap_memory")?;
+        writeln!(write, "    cmpb $0, (%rsi,%rcx)")?;
+        writeln!(write, "    je .Lprint_str_found")?;
+        writeln!(write, "    incq %rcx")?;
+        writeln!(write, "    jmp .Lprint_str_scan")?;
+        writeln!(write, ".Lprint_str_found:")?;
+        writeln!(write, "    addq %rax, %rsi")?;
+        writeln!(write, "    leaq wasmc_str_format(%rip), %rdi")?;
+    }
+    writeln!(write, "    xorl %eax, %eax")?;
+    writeln!(write, "    call printf@PLT")?;
+    writeln!(write, "    xorl %eax, %eax")?;
+    writeln!(write, "    popq %rbp")?;
+    writeln!(write, "    ret")?;
+    Ok(())
+}
+
+// 引数の読み込みとトラップ。メッセージは wasmc run と同じにする
+fn write_runtime(write: &mut dyn Write) -> Result<()> {
+    write.write_all(r#"
+# rdi の文字列を i32 として読む。読めなければ終了する
+wasmc_arg:
+    pushq %rbx
+    subq $16, %rsp
+    movq %rdi, %rbx
+    leaq 8(%rsp), %rsi
+    movl $10, %edx
+    call strtol@PLT
+    movq 8(%rsp), %rcx
+    cmpq %rcx, %rbx
+    je .Larg_invalid
+    cmpb $0, (%rcx)
+    jne .Larg_invalid
+    movslq %eax, %rcx
+    cmpq %rax, %rcx
+    jne .Larg_invalid
+    addq $16, %rsp
+    popq %rbx
+    ret
+.Larg_invalid:
+    movq %rbx, %rsi
+    leaq wasmc_invalid(%rip), %rdi
+    movl $-1, %ecx
+    jmp wasmc_fail
+
+wasmc_trap_div:
+    leaq wasmc_div(%rip), %rsi
+    jmp wasmc_trap
+wasmc_trap_overflow:
+    leaq wasmc_overflow(%rip), %rsi
+    jmp wasmc_trap
+wasmc_trap_stack:
+    leaq wasmc_stack(%rip), %rsi
+    jmp wasmc_trap
+wasmc_trap_memory:
+    leaq wasmc_memory_bounds(%rip), %rsi
+wasmc_trap:
+    leaq wasmc_trap_format(%rip), %rdi
+    movl $1, %ecx
+
+# rdi の書式で rsi と rdx を stderr に表示して、ecx で終了する
+wasmc_fail:
+    andq $-16, %rsp
+    pushq %rcx
+    pushq %rcx
+    movq %rdx, %rcx
+    movq %rsi, %rdx
+    movq %rdi, %rsi
+    movq stderr@GOTPCREL(%rip), %rax
+    movq (%rax), %rdi
+    xorl %eax, %eax
+    call fprintf@PLT
+    popq %rdi
+    call exit@PLT
+
+    .section .rodata
+wasmc_result:
+    .string "%d\n"
+wasmc_int_format:
+    .string "%d"
+wasmc_str_format:
+    .string "%s"
+wasmc_usage:
+    .string "`main` takes %d arguments, got %d\n"
+wasmc_invalid:
+    .string "invalid i32 argument: %s\n"
+wasmc_trap_format:
+    .string "trap: %s\n"
+wasmc_div:
+    .string "integer divide by zero"
+wasmc_overflow:
+    .string "integer overflow"
+wasmc_stack:
+    .string "call stack exhausted"
+wasmc_memory_bounds:
+    .string "out of bounds memory access"
+
+    .bss
+    .p2align 2
+wasmc_depth:
+    .zero 4
+
+    .section .note.GNU-stack,"",@progbits
+"#.as_bytes())
+}
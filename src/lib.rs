@@ -0,0 +1,10 @@
+pub mod ast;
+pub mod backend;
+pub mod evaluator;
+pub mod fuzzer;
+pub mod interpreter;
+pub mod ir;
+pub mod loader;
+pub mod optimizer;
+pub mod tokenizer;
+pub mod wasmc;
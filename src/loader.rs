@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use crate::ast::{BUILTINS, Module};
+use crate::wasmc::parse_source;
+
+// いくつかのソースを import をたどって 1 つの Module にまとめる。引数がファイルを指していればそれを、
+// そうでなければ引数そのものをソースとして読む。import のパスは書いたファイルのあるディレクトリから、
+// ファイルでないソースでは今のディレクトリから探す
+pub fn load(sources: &[&str]) -> Module {
+    let mut loader = Loader::default();
+    let mut roots = vec![];
+    for source in sources {
+        let index = if Path::new(source).is_file() {
+            loader.file(Path::new(source))
+        } else {
+            loader.text("<ソース>", source, Path::new("."))
+        };
+        roots.push(index);
+    }
+    loader.check_undefined();
+    loader.finish(&roots)
+}
+
+// ファイルかどうかを見ずに、引数をソースとして読む。ホストに渡す関数を宣言しなくても呼べる
+pub fn load_text(exp: &str) -> Module {
+    let mut loader = Loader::default();
+    let index = loader.text("<ソース>", exp, Path::new("."));
+    loader.finish(&[index])
+}
+
+// -c で 1 つのファイルだけをコンパイルする。import したファイルは名前を確かめるためだけに読み、
+// その関数の呼び出しはリンクするまで解決しない
+pub fn load_object(source: &str) -> Module {
+    let mut loader = Loader::default();
+    let index = if Path::new(source).is_file() {
+        loader.file(Path::new(source))
+    } else {
+        loader.text("<ソース>", source, Path::new("."))
+    };
+    loader.check_undefined();
+    let functions = loader.files[index].functions.clone();
+    let mut module = loader.finish(&[index]);
+    module.retain_functions(|function| functions.contains(&function.name));
+    module.set_relocatable();
+    module
+}
+
+// 関数の名前はプログラム全体で 1 つにし、重なれば誤りにする。ほかのファイルの関数は、
+// import したファイルのものか、コマンドラインで一緒に並べたファイルのものだけを呼べる
+#[derive(Default)]
+struct Loader {
+    module: Module,
+    files: Vec<SourceFile>,
+    // 読んでいる途中のファイル。ここにあるものをまた import すれば循環している
+    stack: Vec<(PathBuf, String)>,
+    // 関数の名前と、定義したファイルの番号
+    owners: HashMap<String, usize>,
+}
+
+struct SourceFile {
+    name: String,
+    // 正規化したパス。ファイルでないソースにはない
+    path: Option<PathBuf>,
+    // 関数を呼べるファイルの番号。自分も含む
+    visible: Vec<usize>,
+    functions: Vec<String>,
+}
+
+impl Loader {
+
+    fn file(&mut self, path: &Path) -> usize {
+        let canonical = match fs::canonicalize(path) {
+            Ok(canonical) => canonical,
+            Err(error) => panic!("{} を読み込めません: {}", path.display(), error),
+        };
+        let name = path.display().to_string();
+        if let Some(start) = self.stack.iter().position(|(path, _)| *path == canonical) {
+            let cycle: Vec<&str> = self.stack[start..].iter().map(|(_, name)| name.as_str()).chain([name.as_str()]).collect();
+            panic!("import が循環しています: {}", cycle.join(" -> "));
+        }
+        if let Some(index) = self.files.iter().position(|file| file.path.as_ref() == Some(&canonical)) {
+            return index;
+        }
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) => panic!("{} を読み込めません: {}", name, error),
+        };
+        self.stack.push((canonical.clone(), name.to_string()));
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let index = self.text(&name, &text, dir);
+        self.stack.pop();
+        self.files[index].path = Some(canonical);
+        index
+    }
+
+    // import したファイルを先に読むので、番号は import したものより後ろになる
+    fn text(&mut self, name: &str, exp: &str, dir: &Path) -> usize {
+        let source = parse_source(exp, &mut self.module);
+        let mut visible = vec![];
+        for import in source.imports.iter() {
+            visible.push(self.file(&dir.join(import)));
+        }
+        let index = self.files.len();
+        visible.push(index);
+        let mut functions = vec![];
+        for (host, arity) in source.hosts.iter() {
+            self.module.declare_host(host, *arity);
+        }
+        for function in source.functions {
+            match self.owners.get(&function.name) {
+                Some(owner) if *owner == index => panic!("{}: 関数 {} が重複して定義されています", name, function.name),
+                Some(owner) => panic!("関数 {} が {} と {} で重複して定義されています", function.name, self.files[*owner].name, name),
+                None => {},
+            }
+            self.owners.insert(function.name.to_string(), index);
+            functions.push(function.name.to_string());
+            self.module.add_function(function);
+        }
+        self.files.push(SourceFile { name: name.to_string(), path: None, visible, functions });
+        index
+    }
+
+    // どこにも定義のない関数は、組み込み関数か import で宣言したものだけを呼べる
+    fn check_undefined(&self) {
+        for file in self.files.iter() {
+            for name in file.functions.iter() {
+                for (call, arity) in self.module.function(name).unwrap().call_arities() {
+                    if self.owners.contains_key(&call) || BUILTINS.contains(&call.as_str()) {
+                        continue;
+                    }
+                    match self.module.host_arity(&call) {
+                        Some(declared) if declared != arity =>
+                            panic!("{}: 関数 {} の呼び出しで引数の数が違います", file.name, call),
+                        Some(_) => {},
+                        None => panic!("{}: 関数 {} が定義されていません", file.name, call),
+                    }
+                }
+            }
+        }
+        for (host, _) in self.module.hosts() {
+            if let Some(owner) = self.owners.get(host) {
+                panic!("{}: 関数 {} は import で宣言されていますが定義されています", self.files[*owner].name, host);
+            }
+        }
+    }
+
+    // 呼び出し先が見えるかを確かめる。どこにも定義のない関数は env から import するものとして残す
+    fn finish(mut self, roots: &[usize]) -> Module {
+        for file in self.files.iter_mut() {
+            if roots.contains(file.visible.last().unwrap()) {
+                file.visible.extend(roots);
+            }
+        }
+        for file in self.files.iter() {
+            for name in file.functions.iter() {
+                for call in self.module.function(name).unwrap().calls() {
+                    match self.owners.get(&call) {
+                        Some(owner) if !file.visible.contains(owner) =>
+                            panic!("{}: 関数 {} は {} で定義されていますが import されていません", file.name, call, self.files[*owner].name),
+                        _ => {},
+                    }
+                }
+            }
+        }
+        self.module
+    }
+
+}
+
+// files を一時ディレクトリに書いて、その中のパスを返す
+#[cfg(test)]
+fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wasmc-loader-{}-{}", name, std::process::id()));
+    for (path, text) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+    dir
+}
+
+#[cfg(test)]
+fn function_names(module: &Module) -> Vec<String> {
+    module.functions().map(|function| function.name.to_string()).collect()
+}
+
+#[test]
+fn test_load() {
+    let dir = write_files("load", &[
+        ("main.wc", "import \"lib/math.wc\";\nimport \"lib/../lib/math.wc\";\nmain(){print_str(\"main\");return square(3);}"),
+        ("lib/math.wc", "import \"inc.wc\";\nsquare(x){print_str(\"math\");return inc(x*x);}"),
+        ("lib/inc.wc", "inc(x){print_str(\"main\");return x+1;}"),
+    ]);
+    let module = load(&[dir.join("main.wc").to_str().unwrap()]);
+    // import したものが先に並び、同じファイルは一度しか読まない
+    assert_eq!(function_names(&module), vec!["inc", "square", "main"]);
+    // 文字列リテラルはファイルをまたいで 1 つの表に置く
+    let ir = module.lower();
+    assert_eq!(ir.data[0].bytes, b"main\0math\0");
+    assert!(ir.imports.iter().map(|import| import.field.as_str()).eq(["print_str"]));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_load_files() {
+    // コマンドラインで並べたファイルどうしは import しなくても呼べる
+    let dir = write_files("files", &[("a.wc", "main(){return f();}"), ("b.wc", "f(){return 2;}")]);
+    let (a, b) = (dir.join("a.wc"), dir.join("b.wc"));
+    assert_eq!(function_names(&load(&[a.to_str().unwrap(), b.to_str().unwrap()])), vec!["main", "f"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_load_object() {
+    let dir = write_files("object", &[
+        ("main.wc", "import \"math.wc\";\nmain(){return square(3);}\nunused(){return 0;}"),
+        ("math.wc", "square(x){return x*x;}"),
+    ]);
+    let module = load_object(dir.join("main.wc").to_str().unwrap());
+    assert_eq!(function_names(&module), vec!["main", "unused"]);
+    // import したファイルの関数は、リンクで解決する import になる
+    let ir = module.lower();
+    assert!(ir.imports.iter().map(|import| import.name.as_str()).eq(["square"]));
+    assert_eq!(function_names(&load_object(dir.join("math.wc").to_str().unwrap())), vec!["square"]);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[cfg(test)]
+fn load_error(name: &str, files: &[(&str, &str)], roots: &[&str]) -> String {
+    let dir = write_files(name, files);
+    let roots: Vec<String> = roots.iter().map(|root| dir.join(root).to_str().unwrap().to_string()).collect();
+    let roots: Vec<&str> = roots.iter().map(|root| root.as_str()).collect();
+    let Err(error) = std::panic::catch_unwind(|| load(&roots)) else { panic!("読み込めてしまいました") };
+    fs::remove_dir_all(&dir).unwrap();
+    let message = match error.downcast::<String>() {
+        Ok(message) => *message,
+        Err(_) => panic!("panic の内容が文字列ではありません"),
+    };
+    message.replace(&format!("{}/", dir.display()), "")
+}
+
+#[test]
+fn test_load_errors() {
+    assert_eq!(load_error("cycle", &[
+        ("a.wc", "import \"b.wc\";main(){return 0;}"),
+        ("b.wc", "import \"c.wc\";"),
+        ("c.wc", "import \"b.wc\";"),
+    ], &["a.wc"]), "import が循環しています: b.wc -> c.wc -> b.wc");
+    assert_eq!(load_error("duplicate", &[
+        ("a.wc", "import \"b.wc\";main(){return f();}f(){return 1;}"),
+        ("b.wc", "f(){return 2;}"),
+    ], &["a.wc"]), "関数 f が b.wc と a.wc で重複して定義されています");
+    assert_eq!(load_error("same", &[("a.wc", "main(){return 0;}f(){return 1;}f(){return 2;}")], &["a.wc"]),
+               "a.wc: 関数 f が重複して定義されています");
+    // import していないファイルの関数は呼べない
+    assert_eq!(load_error("visible", &[
+        ("a.wc", "import \"b.wc\";main(){return g();}"),
+        ("b.wc", "import \"c.wc\";f(){return g();}"),
+        ("c.wc", "g(){return 2;}"),
+    ], &["a.wc"]), "a.wc: 関数 g は c.wc で定義されていますが import されていません");
+    assert!(load_error("missing", &[("a.wc", "import \"none.wc\";")], &["a.wc"]).starts_with("none.wc を読み込めません: "));
+}
+
+#[test]
+fn test_load_hosts() {
+    // import で宣言した関数と組み込み関数は、env から import する
+    let module = load(&["import twice(x);import twice(y);main(n){print_int(n);return twice(n);}"]);
+    let ir = module.lower();
+    assert!(ir.imports.iter().map(|import| import.field.as_str()).eq(["print_int", "twice"]));
+    assert_eq!(ir.imports[1].params.len(), 1);
+    assert_eq!(load_error("undefined", &[("a.wc", "main(){return g();}")], &["a.wc"]), "a.wc: 関数 g が定義されていません");
+    assert_eq!(load_error("arity", &[("a.wc", "import g(a,b);main(){return g(1);}")], &["a.wc"]),
+               "a.wc: 関数 g の呼び出しで引数の数が違います");
+    assert_eq!(load_error("redeclare", &[("a.wc", "import g(a);import g();main(){return 0;}")], &["a.wc"]),
+               "関数 g が違う引数の数で import されています");
+    assert_eq!(load_error("defined", &[("a.wc", "import \"b.wc\";main(){return f();}"), ("b.wc", "import f();f(){return 1;}")], &["a.wc"]),
+               "b.wc: 関数 f は import で宣言されていますが定義されています");
+}
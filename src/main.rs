@@ -1,23 +1,345 @@
 extern crate core;
 
-mod wasmc;
-mod ast;
-mod tokenizer;
-
 use std::env;
+use std::fs;
+use std::io::stdout;
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use wasmc::compile;
+use wasmc::fuzzer::{fuzz, FuzzOptions, REGRESSION_DIR};
+use wasmc::ir::{validate, Module};
+use wasmc::optimizer::{OptLevel, PASS_ORDER};
+use wasmc::wasmc::{compile, run, run_and_compare, CompileOptions, RunError, EMITS};
 
 fn main() {
 
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
+    match args.get(1).map(String::as_str) {
+        Some("run") => {
+            run_command(&args[2..]);
+            return;
+        },
+        Some("fuzz") => {
+            fuzz_command(&args[2..]);
+            return;
+        },
+        Some("asm") => {
+            asm_command(&args[2..]);
+            return;
+        },
+        Some("disasm") => {
+            disasm_command(&args[2..]);
+            return;
+        },
+        Some("validate") => {
+            validate_command(&args[2..]);
+            return;
+        },
+        Some("link") => {
+            link_command(&args[2..]);
+            return;
+        },
+        _ => {}
+    }
+
+    let mut options = CompileOptions::default();
+    let mut sources = vec![];
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        if !parse_option(arg, &mut rest, &mut options) {
+            sources.push(arg);
+        }
+    }
+    if sources.is_empty() {
+        eprintln!("ソースが指定されていません");
+        exit(-1);
+    }
+
+    let sources: Vec<&str> = sources.iter().map(|source| source.as_str()).collect();
+    compile(&sources, &options);
+
+}
+
+// コンパイルのオプションなら options に反映して true を返す。値を取るものは rest から読む
+fn parse_option<'a>(arg: &str, rest: &mut impl Iterator<Item = &'a String>, options: &mut CompileOptions) -> bool {
+    if let Some(level) = OptLevel::parse(arg) {
+        options.opt_level = level;
+    } else if let Some(name) = arg.strip_prefix("-fno-") {
+        options.disable_passes.push(pass_name(name));
+    } else if let Some(name) = arg.strip_prefix("-f") {
+        options.enable_passes.push(pass_name(name));
+    } else if arg == "--return-call" {
+        options.enable_passes.push("return-call".to_string());
+    } else if arg == "-c" {
+        options.relocatable = true;
+    } else if arg == "--stats" {
+        options.print_stats = true;
+    } else if arg == "--target" {
+        match rest.next() {
+            Some(name) if options.backends.get(name).is_some() => options.target = name.to_string(),
+            Some(name) => {
+                eprintln!("不明なターゲットです: {} ({})", name, options.backends.names().join(", "));
+                exit(-1);
+            },
+            None => {
+                eprintln!("--target にはターゲット名が必要です");
+                exit(-1);
+            }
+        }
+    } else if arg == "--emit" {
+        match rest.next() {
+            Some(name) if EMITS.contains(&name.as_str()) => options.emit.push(name.to_string()),
+            Some(name) => {
+                eprintln!("不明な出力です: {} ({})", name, EMITS.join(", "));
+                exit(-1);
+            },
+            None => {
+                eprintln!("--emit には出力の種類が必要です");
+                exit(-1);
+            }
+        }
+    } else {
+        return false;
+    }
+    true
+}
+
+// -f と -fno- に渡したパスの名前を確かめる
+fn pass_name(name: &str) -> String {
+    if !PASS_ORDER.contains(&name) {
+        eprintln!("不明なパスです: {} ({})", name, PASS_ORDER.join(", "));
+        exit(-1);
+    }
+    name.to_string()
+}
+
+// wasmc run [オプション] [--invoke 関数名] [--compare] ソース [引数...]
+// ソースより後ろはすべて関数への引数として扱う (負の数を渡せるように)。
+// --compare を付けると AST の評価器でも実行し、結果が食い違えば報告する
+fn run_command(args: &[String]) {
+    let mut options = CompileOptions::default();
+    let mut export = "main".to_string();
+    let mut compare = false;
+    let mut rest = args.iter();
+    let source = loop {
+        match rest.next() {
+            Some(arg) if arg == "--invoke" => match rest.next() {
+                Some(name) => export = name.to_string(),
+                None => {
+                    eprintln!("--invoke には関数名が必要です");
+                    exit(-1);
+                }
+            },
+            Some(arg) if arg == "--compare" => compare = true,
+            Some(arg) if parse_option(arg, &mut rest, &mut options) => {},
+            Some(arg) => break arg,
+            None => {
+                eprintln!("ソースが指定されていません");
+                exit(-1);
+            }
+        }
+    };
+    let params: Vec<String> = rest.cloned().collect();
+
+    let result = if compare {
+        run_and_compare(source, &options, &export, &params)
+    } else {
+        run(source, &options, &export, &params)
+    };
+    match result {
+        Ok(results) => {
+            for result in results {
+                println!("{}", result);
+            }
+        },
+        Err(error @ RunError::Usage(_)) => {
+            eprintln!("{}", error);
+            exit(-1);
+        },
+        Err(error @ RunError::Trap(_)) => {
+            eprintln!("{}", error);
+            exit(1);
+        },
+        Err(error @ RunError::Mismatch { .. }) => {
+            eprintln!("{}", error);
+            exit(2);
+        },
+    }
+}
+
+// wasmc fuzz [--seed N] [--iterations N] [--save DIR | --no-save]
+// 失敗したプログラムは縮小して DIR (既定は fuzz/regressions) に保存し、終了コードを 1 にする
+fn fuzz_command(args: &[String]) {
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    let mut options = FuzzOptions { seed, iterations: 1000, save_dir: Some(PathBuf::from(REGRESSION_DIR)) };
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match (arg.as_str(), rest.clone().next()) {
+            ("--seed", Some(value)) => options.seed = parse_number(value),
+            ("--iterations", Some(value)) => options.iterations = parse_number(value) as usize,
+            ("--save", Some(value)) => options.save_dir = Some(PathBuf::from(value)),
+            ("--no-save", _) => {
+                options.save_dir = None;
+                continue;
+            },
+            _ => {
+                eprintln!("不明な引数です: {}", arg);
+                exit(-1);
+            }
+        }
+        rest.next();
+    }
+
+    eprintln!("seed {}, {} programs", options.seed, options.iterations);
+    let failures = fuzz(&options);
+    eprintln!("{} failures", failures);
+    if failures > 0 {
+        exit(1);
+    }
+}
+
+// wasmc asm [-o 出力] ファイル
+// WAT を読んでバイナリにする。出力の既定は拡張子を .wasm に変えたもの
+fn asm_command(args: &[String]) {
+    let (output, input) = match args {
+        [input] => (Path::new(input).with_extension("wasm"), input),
+        [flag, output, input] if flag == "-o" => (PathBuf::from(output), input),
+        _ => {
+            eprintln!("引数の個数が正しくありません");
+            exit(-1);
+        }
+    };
+    let text = match fs::read_to_string(input) {
+        Ok(text) => text,
+        Err(error) => {
+            eprintln!("{} を読み込めません: {}", input, error);
+            exit(-1);
+        }
+    };
+    let module = match Module::read_wat(&text) {
+        Ok(module) => module,
+        Err(error) => {
+            eprintln!("{}: {}", input, error);
+            exit(1);
+        }
+    };
+    let mut bytes = vec![];
+    let _ = module.write_wasm(&mut bytes);
+    // 手で書き換えたものは型が合っていないことがあるので、書き出す前に検証する
+    if let Err(error) = validate(&bytes) {
+        eprintln!("{}: {}", input, error);
+        exit(1);
+    }
+    if let Err(error) = fs::write(&output, &bytes) {
+        eprintln!("{} に書き込めません: {}", output.display(), error);
+        exit(-1);
+    }
+}
+
+// wasmc disasm ファイル
+// バイナリを読んで WAT を標準出力に書く
+fn disasm_command(args: &[String]) {
+    if args.len() != 1 {
         eprintln!("引数の個数が正しくありません");
         exit(-1);
     }
+    let bytes = read_file(&args[0]);
+    match Module::read_wasm(&bytes) {
+        Ok(module) => {
+            let _ = module.write_wat(&mut stdout());
+        },
+        Err(error) => {
+            eprintln!("{}: {}", args[0], error);
+            exit(1);
+        }
+    }
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            eprintln!("{} を読み込めません: {}", path, error);
+            exit(-1);
+        }
+    }
+}
 
-    compile(&args[1]);
+// wasmc validate ファイル...
+// 正しければ何も出力しない。誤りがあればファイルごとに表示し、終了コードを 1 にする
+fn validate_command(args: &[String]) {
+    if args.is_empty() {
+        eprintln!("ファイルが指定されていません");
+        exit(-1);
+    }
+    let mut invalid = false;
+    for path in args {
+        let bytes = read_file(path);
+        if let Err(error) = validate(&bytes) {
+            eprintln!("{}: {}", path, error);
+            invalid = true;
+        }
+    }
+    if invalid {
+        exit(1);
+    }
+}
 
+// wasmc link [-o 出力] [--allow-undefined] オブジェクト...
+// -c で書いたオブジェクトをまとめる。出力の既定は out.wasm。組み込み関数と import で宣言した関数は
+// env からの import として残す。--allow-undefined を付けると、どこにも定義のない関数もそうする
+fn link_command(args: &[String]) {
+    let mut output = "out.wasm".to_string();
+    let mut allow_undefined = false;
+    let mut inputs = vec![];
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "-o" => match rest.next() {
+                Some(path) => output = path.to_string(),
+                None => {
+                    eprintln!("-o には出力するファイルが必要です");
+                    exit(-1);
+                }
+            },
+            "--allow-undefined" => allow_undefined = true,
+            _ => inputs.push(arg),
+        }
+    }
+    if inputs.is_empty() {
+        eprintln!("オブジェクトが指定されていません");
+        exit(-1);
+    }
+    let objects: Vec<(&str, Vec<u8>)> = inputs.iter().map(|path| (path.as_str(), read_file(path))).collect();
+    let objects: Vec<(&str, &[u8])> = objects.iter().map(|(path, bytes)| (*path, bytes.as_slice())).collect();
+    let module = match Module::link(&objects, allow_undefined) {
+        Ok(module) => module,
+        Err(errors) => {
+            for error in errors {
+                eprintln!("{}", error);
+            }
+            exit(1);
+        }
+    };
+    let mut bytes = vec![];
+    let _ = module.write_wasm(&mut bytes);
+    if let Err(error) = validate(&bytes) {
+        eprintln!("{}: {}", output, error);
+        exit(1);
+    }
+    if let Err(error) = fs::write(&output, &bytes) {
+        eprintln!("{} に書き込めません: {}", output, error);
+        exit(-1);
+    }
 }
 
+fn parse_number(value: &str) -> u64 {
+    match value.parse() {
+        Ok(number) => number,
+        Err(_) => {
+            eprintln!("数値ではありません: {}", value);
+            exit(-1);
+        }
+    }
+}
@@ -0,0 +1,617 @@
+mod pass_manager;
+
+use std::collections::HashMap;
+use crate::ir;
+use crate::ast::{Assign, Block, Expr, InlineContext, InlineNode, Labels, Module, Number, ReturnNode, Stmt, TailCallNode, TailLoopNode, Variable, VisitorMut, walk_expr_mut, walk_stmt_mut};
+
+pub use pass_manager::{OptLevel, PASS_ORDER, PassManager, print_stats};
+#[cfg(test)]
+use crate::interpreter::{Instance, Trap};
+#[cfg(test)]
+use crate::wasmc::parse;
+
+// 定数畳み込みと代数的な簡約 (x+0, x*1, 2 の冪の乗除算のシフト化など)
+pub fn simplify(module: &mut Module) {
+    for function in module.functions_mut() {
+        Simplifier.visit_stmt_mut(&mut function.body);
+    }
+}
+
+struct Simplifier;
+
+impl VisitorMut for Simplifier {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+        if matches!(expr, Expr::BiOperator(_)) {
+            if let Expr::BiOperator(operator) = take_expr(expr) {
+                *expr = operator.simplify();
+            }
+        }
+    }
+}
+
+// return 以降の文、定数条件の分岐、main から呼ばれない関数を取り除き、削減したバイト数を返す
+pub fn eliminate_dead_code(module: &mut Module) -> usize {
+    let before = wasm_size(module);
+    for function in module.functions_mut() {
+        DeadCodeEliminator.visit_stmt_mut(&mut function.body);
+    }
+    remove_unreachable_functions(module);
+    before.saturating_sub(wasm_size(module))
+}
+
+struct DeadCodeEliminator;
+
+impl VisitorMut for DeadCodeEliminator {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+        let replacement = match stmt {
+            Stmt::Block(block) => {
+                // 抜けてこない文より後ろには到達しない
+                if let Some(i) = block.statements.iter().position(|s| s.terminates()) {
+                    block.statements.truncate(i + 1);
+                }
+                None
+            },
+            Stmt::If(node) => match node.condition.as_number() {
+                Some(n) if n != 0 => Some(take_stmt(&mut node.then_block)),
+                Some(_) => Some(node.else_block.as_mut().map(|els| take_stmt(els)).unwrap_or_else(|| Stmt::Block(Block::new()))),
+                None => None,
+            },
+            Stmt::While(node) if node.condition.as_number() == Some(0) => Some(Stmt::Block(Block::new())),
+            Stmt::For(node) if node.condition.as_ref().and_then(|cond| cond.as_number()) == Some(0) => {
+                // 初期化式だけは評価される
+                Some(node.initialize.take().map(Stmt::Expr).unwrap_or_else(|| Stmt::Block(Block::new())))
+            },
+            _ => None,
+        };
+        if let Some(replacement) = replacement {
+            *stmt = replacement;
+        }
+    }
+}
+
+fn remove_unreachable_functions(module: &mut Module) {
+    if module.is_relocatable() || module.functions().all(|function| function.name != "main") {
+        return;
+    }
+    let mut reachable = vec!["main".to_string()];
+    let mut i = 0;
+    while i < reachable.len() {
+        if let Some(function) = module.functions().find(|function| function.name == reachable[i]) {
+            for call in function.calls() {
+                if !reachable.contains(&call) {
+                    reachable.push(call);
+                }
+            }
+        }
+        i += 1;
+    }
+    module.retain_functions(|function| reachable.contains(&function.name));
+}
+
+// インライン展開の閾値 (命令数)。最適化レベルごとに変える
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct InlineOptions {
+    // これより大きい関数は展開しない
+    pub max_callee_size: usize,
+    // 呼び出し元がこれより大きくなる展開はしない
+    pub max_caller_size: usize,
+}
+
+impl Default for InlineOptions {
+    fn default() -> Self {
+        Self { max_callee_size: 40, max_caller_size: 2000 }
+    }
+}
+
+// 展開した呼び出し先の本体を、さらに展開できるように数回繰り返す
+const INLINE_ROUNDS: usize = 4;
+
+struct Callee {
+    params: Vec<String>,
+    locals: Vec<String>,
+    body: Stmt,
+    size: usize,
+}
+
+struct Inliner<'a> {
+    caller: String,
+    callees: &'a HashMap<String, Callee>,
+    options: InlineOptions,
+    locals: Vec<String>,
+    size: usize,
+    count: usize,
+}
+
+// 小さな非再帰関数の呼び出しを本体で置き換え、展開した呼び出しの数を返す
+pub fn inline_functions(module: &mut Module, options: InlineOptions) -> usize {
+    let mut total = 0;
+    for _ in 0..INLINE_ROUNDS {
+        let callees = inline_candidates(module, &options);
+        if callees.is_empty() {
+            break;
+        }
+        let mut count = 0;
+        for function in module.functions_mut() {
+            let mut inliner = Inliner {
+                caller: function.name.to_string(),
+                callees: &callees,
+                options,
+                locals: function.locals().to_vec(),
+                size: instruction_count(&function.body),
+                count: 0,
+            };
+            inliner.visit_stmt_mut(&mut function.body);
+            function.update_locals();
+            count += inliner.count;
+        }
+        if count == 0 {
+            break;
+        }
+        total += count;
+    }
+    total
+}
+
+fn inline_candidates(module: &Module, options: &InlineOptions) -> HashMap<String, Callee> {
+    let call_graph: HashMap<String, Vec<String>> = module.functions()
+        .map(|function| (function.name.to_string(), function.calls()))
+        .collect();
+    let mut callees = HashMap::new();
+    for function in module.functions() {
+        let size = instruction_count(&function.body);
+        if size > options.max_callee_size || is_recursive(&call_graph, &function.name) {
+            continue;
+        }
+        let params: Vec<String> = function.params.iter().map(|p| p.name.to_string()).collect();
+        callees.insert(function.name.to_string(), Callee {
+            params,
+            locals: function.locals().to_vec(),
+            body: function.body.clone(),
+            size,
+        });
+    }
+    callees
+}
+
+// name から呼び出しをたどって name 自身に戻ってくるか
+fn is_recursive(call_graph: &HashMap<String, Vec<String>>, name: &str) -> bool {
+    let mut visited: Vec<&str> = vec![];
+    let mut stack: Vec<&str> = call_graph.get(name).map(|calls| calls.iter().map(|c| c.as_str()).collect()).unwrap_or_default();
+    while let Some(current) = stack.pop() {
+        if current == name {
+            return true;
+        }
+        if visited.contains(&current) {
+            continue;
+        }
+        visited.push(current);
+        if let Some(calls) = call_graph.get(current) {
+            stack.extend(calls.iter().map(|c| c.as_str()));
+        }
+    }
+    false
+}
+
+impl VisitorMut for Inliner<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+        let callees = self.callees;
+        if let Expr::Call(call) = expr {
+            if let Some(callee) = callees.get(&call.name) {
+                if call.name != self.caller
+                    && call.arguments.len() == callee.params.len()
+                    && self.size + callee.size <= self.options.max_caller_size {
+                    self.size += callee.size;
+                    self.count += 1;
+                    let arguments = std::mem::take(&mut call.arguments);
+                    *expr = expand_call(&call.name, arguments, callee, &mut self.locals);
+                }
+            }
+        }
+    }
+}
+
+fn expand_call(name: &str, arguments: Vec<Expr>, callee: &Callee, locals: &mut Vec<String>) -> Expr {
+    // 呼び出し元の変数と衝突しない名前を割り当てる。番号は呼び出し元の関数の中で数える
+    let mut id = 0;
+    while locals.iter().any(|local| local.starts_with(&format!("{}_{}_", name, id))) {
+        id += 1;
+    }
+    let mut context = InlineContext::new();
+    for local in callee.locals.iter() {
+        let renamed = format!("{}_{}_{}", name, id, local);
+        locals.push(renamed.to_string());
+        context.renames.insert(local.to_string(), renamed);
+    }
+
+    let mut bindings = vec![];
+    for (param, arg) in callee.params.iter().zip(arguments) {
+        bindings.push(assign(&context.rename(param), arg));
+    }
+    // 呼び出しごとにローカル変数は 0 から始まる
+    for local in callee.locals.iter().skip(callee.params.len()) {
+        bindings.push(assign(&context.rename(local), Expr::Number(Number::new(0))));
+    }
+    let mut body = callee.body.clone();
+    context.visit_stmt_mut(&mut body);
+    Expr::Inline(InlineNode::new(bindings, body))
+}
+
+struct TailCallRewriter {
+    function: String,
+    params: Vec<String>,
+    temporaries: Vec<String>,
+    // パラメータ以外のローカル変数。呼び出しと同じく 0 に戻してから先頭へ分岐する
+    locals: Vec<String>,
+    count: usize,
+}
+
+// 自己末尾呼び出し (return f(...)) を、パラメータの再代入と関数先頭への分岐に置き換え、置き換えた数を返す
+pub fn eliminate_tail_recursion(module: &mut Module) -> usize {
+    let mut total = 0;
+    for function in module.functions_mut() {
+        let mut locals = function.locals().to_vec();
+        let params: Vec<String> = function.params.iter().map(|p| p.name.to_string()).collect();
+        let others = locals[params.len()..].to_vec();
+        let temporaries = params.iter().map(|param| {
+            let mut name = format!("{}_tail", param);
+            while locals.contains(&name) {
+                name.push('_');
+            }
+            locals.push(name.to_string());
+            name
+        }).collect();
+        let mut rewriter = TailCallRewriter {
+            function: function.name.to_string(),
+            params,
+            temporaries,
+            locals: others,
+            count: 0,
+        };
+        rewriter.visit_stmt_mut(&mut function.body);
+        if rewriter.count > 0 {
+            let body = take_stmt(&mut function.body);
+            function.body = Stmt::TailLoop(TailLoopNode::new(body));
+            function.update_locals();
+            total += rewriter.count;
+        }
+    }
+    total
+}
+
+impl VisitorMut for TailCallRewriter {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+        if let Stmt::Return(ReturnNode { child: Expr::Call(call) }) = stmt {
+            if call.name == self.function && call.arguments.len() == self.params.len() {
+                self.count += 1;
+                let arguments = std::mem::take(&mut call.arguments);
+                *stmt = self.tail_call(arguments);
+            }
+        }
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        // インライン展開された本体の return は関数からは抜けない
+        if !matches!(expr, Expr::Inline(_)) {
+            walk_expr_mut(self, expr);
+        }
+    }
+}
+
+impl TailCallRewriter {
+    fn tail_call(&self, arguments: Vec<Expr>) -> Stmt {
+        let mut temporaries = vec![];
+        let mut assignments = vec![];
+        for ((param, temporary), arg) in self.params.iter().zip(self.temporaries.iter()).zip(arguments) {
+            // 同じパラメータをそのまま渡している引数は代入しなくてよい
+            if matches!(&arg, Expr::Variable(variable) if &variable.name == param) {
+                continue;
+            }
+            temporaries.push(assign(temporary, arg));
+            assignments.push(assign(param, Expr::Variable(Variable::new(temporary.to_string()))));
+        }
+        for local in self.locals.iter() {
+            assignments.push(assign(local, Expr::Number(Number::new(0))));
+        }
+        Stmt::TailCall(TailCallNode::new(temporaries, assignments))
+    }
+}
+
+// 命令列に覗き穴最適化をかけてから書き出すようにする
+pub fn use_peephole(module: &mut Module) {
+    for function in module.functions_mut() {
+        function.peephole = true;
+    }
+}
+
+// 末尾位置の呼び出しを tail call 拡張の return_call で書き出すようにする
+pub fn use_return_call(module: &mut Module) {
+    for function in module.functions_mut() {
+        function.return_call = true;
+    }
+}
+
+fn assign(name: &str, value: Expr) -> Expr {
+    Expr::Assign(Assign::new(Expr::Variable(Variable::new(name.to_string())), value))
+}
+
+// 書き換えのために取り出す。元の場所には空のノードを置いておく
+fn take_expr(expr: &mut Expr) -> Expr {
+    std::mem::replace(expr, Expr::Number(Number::new(0)))
+}
+
+fn take_stmt(stmt: &mut Stmt) -> Stmt {
+    std::mem::replace(stmt, Stmt::Block(Block::new()))
+}
+
+fn instruction_count(stmt: &Stmt) -> usize {
+    let mut instructions = vec![];
+    stmt.write_instructions(&mut Labels::new(), &mut instructions);
+    ir::instruction_count(&instructions)
+}
+
+pub fn wasm_size(module: &Module) -> usize {
+    let mut buf = vec![];
+    let _ = module.lower().write_wasm(&mut buf);
+    buf.len()
+}
+
+#[cfg(test)]
+fn optimized_wat(exp: &str, pass: fn(&mut Module)) -> String {
+    let mut module = parse(exp);
+    pass(&mut module);
+    use_peephole(&mut module);
+    let mut buf = vec![];
+    module.lower().write_wat(&mut buf).unwrap();
+    // 字下げを除いて、命令の並びだけを比べられるようにする
+    String::from_utf8(buf).unwrap().lines().map(|line| format!("{}\n", line.trim_start())).collect()
+}
+
+// pass をかけてから main を実行した結果
+#[cfg(test)]
+fn optimized_result(exp: &str, pass: fn(&mut Module)) -> Result<Vec<i32>, Trap> {
+    let mut module = parse(exp);
+    pass(&mut module);
+    Instance::from_module(&module.lower()).invoke("main", &[])
+}
+
+#[cfg(test)]
+fn simplified_wat(exp: &str) -> String {
+    optimized_wat(exp, simplify)
+}
+
+#[cfg(test)]
+fn inline_wat(exp: &str) -> String {
+    optimized_wat(exp, |module| {
+        inline_functions(module, InlineOptions::default());
+        eliminate_dead_code(module);
+    })
+}
+
+#[cfg(test)]
+fn dce_wat(exp: &str) -> String {
+    optimized_wat(exp, |module| {
+        simplify(module);
+        eliminate_dead_code(module);
+    })
+}
+
+#[test]
+fn test_fold_constants() {
+    let wat = simplified_wat("main(){return 5+20-4;}");
+    assert!(wat.contains("i32.const 21\nreturn\n"));
+    assert!(!wat.contains("i32.add"));
+    assert!(!wat.contains("i32.sub"));
+
+    let wat = simplified_wat("main(){return -5*-3;}");
+    assert!(wat.contains("i32.const 15\nreturn\n"));
+
+    let wat = simplified_wat("main(){return 3*4>=12;}");
+    assert!(wat.contains("i32.const 1\nreturn\n"));
+}
+
+#[test]
+fn test_fold_wraps_around() {
+    let wat = simplified_wat("main(){return 2147483647+1;}");
+    assert!(wat.contains("i32.const -2147483648\nreturn\n"));
+
+    let wat = simplified_wat("main(){return 65536*65536;}");
+    assert!(wat.contains("i32.const 0\nreturn\n"));
+}
+
+#[test]
+fn test_identities() {
+    let wat = simplified_wat("main(){a=3;return a*1+0;}");
+    assert!(wat.contains("local.get $a\nreturn\n"));
+    assert!(!wat.contains("i32.mul"));
+    assert!(!wat.contains("i32.add"));
+
+    let wat = simplified_wat("main(){a=3;return 0+1*a-0;}");
+    assert!(wat.contains("local.get $a\nreturn\n"));
+
+    let wat = simplified_wat("main(){a=3;return a*0;}");
+    assert!(wat.contains("i32.const 0\nreturn\n"));
+
+    // 呼び出しは副作用がありうるので消さない
+    let wat = simplified_wat("main(){return f()*0;}f(){return 1;}");
+    assert!(wat.contains("call $f\ni32.const 0\ni32.mul\n"));
+}
+
+#[test]
+fn test_power_of_two() {
+    let wat = simplified_wat("main(){a=3;return a*8;}");
+    assert!(wat.contains("local.get $a\ni32.const 3\ni32.shl\n"));
+
+    let wat = simplified_wat("main(){a=3;return 4*a;}");
+    assert!(wat.contains("local.get $a\ni32.const 2\ni32.shl\n"));
+
+    let wat = simplified_wat("main(){a=-7;return a/2;}");
+    assert!(!wat.contains("i32.div_s"));
+    assert!(wat.contains("i32.const 31\ni32.shr_s\ni32.const 31\ni32.shr_u\n"));
+}
+
+#[test]
+fn test_division_traps_are_kept() {
+    let wat = simplified_wat("main(){return 1/0;}");
+    assert!(wat.contains("i32.const 1\ni32.const 0\ni32.div_s\n"));
+
+    let wat = simplified_wat("main(){return (-2147483647-1)/-1;}");
+    assert!(wat.contains("i32.div_s"));
+}
+
+#[test]
+fn test_statements_after_return() {
+    let wat = dce_wat("main(){return 1+2; return 2*3;}");
+    assert!(wat.contains("i32.const 3\nreturn\n"));
+    assert!(!wat.contains("i32.const 6"));
+
+    let wat = dce_wat("main(){a=1;if(a){return 1;}else{return 2;}a=3;return a;}");
+    assert!(!wat.contains("i32.const 3"));
+}
+
+#[test]
+fn test_constant_conditions() {
+    let wat = dce_wat("main(){if(3>2)return 1; else return 2;}");
+    assert!(!wat.contains("\nif\n"));
+    assert!(!wat.contains("i32.const 2"));
+
+    let wat = dce_wat("main(){a=5;if(0)a=1;return a;}");
+    assert!(!wat.contains("\nif\n"));
+
+    let wat = dce_wat("main(){a=5;while(1-1)a=a+1;return a;}");
+    assert!(!wat.contains("\nloop "));
+
+    let wat = dce_wat("main(){for(a=7;0;a=a+1)a=2;return a;}");
+    assert!(!wat.contains("\nloop "));
+    assert!(wat.contains("i32.const 7\nlocal.set $a\n"));
+
+    // 無限ループの後ろにも到達しない
+    let wat = dce_wat("main(){while(1)return 1;return 2;}");
+    assert!(wat.contains("\nloop "));
+    assert!(!wat.contains("i32.const 2"));
+}
+
+#[test]
+fn test_unreachable_functions() {
+    let wat = dce_wat("main(){return f(1);}f(a){return g(a);}g(a){return a;}unused(){return h();}h(){return 1;}");
+    assert!(wat.contains("(func $f"));
+    assert!(wat.contains("(func $g"));
+    assert!(!wat.contains("(func $unused"));
+    assert!(!wat.contains("(func $h"));
+}
+
+#[test]
+fn test_bytes_saved() {
+    let mut module = parse("main(){return 1; return 2;}unused(){return 3;}");
+    assert!(eliminate_dead_code(&mut module) > 0);
+    assert_eq!(eliminate_dead_code(&mut module), 0);
+}
+
+#[test]
+fn test_inline_small_function() {
+    let wat = inline_wat("main(){return sub(5,2);}sub(a,b){return a-b;}");
+    assert!(!wat.contains("call $sub"));
+    assert!(!wat.contains("(func $sub"));
+    assert!(wat.contains("(result i32)\n"));
+    assert!(wat.contains("i32.sub\nbr $inline"));
+}
+
+#[test]
+fn test_inline_skips_recursion() {
+    let wat = inline_wat("main(){return lcm(12,20);}lcm(a,b){return a/gcd(a,b)*b;}gcd(a,b){if(a<b)return gcd(b,a);if(b==0)return a;return gcd(b,a-(a/b*b));}");
+    assert!(!wat.contains("call $lcm"));
+    assert!(wat.contains("call $gcd"));
+    assert!(wat.contains("(func $gcd"));
+
+    let wat = inline_wat("main(){return even(4);}even(n){if(n==0)return 1;return odd(n-1);}odd(n){if(n==0)return 0;return even(n-1);}");
+    assert!(wat.contains("call $even"));
+}
+
+#[test]
+fn test_inline_arguments_once() {
+    let wat = inline_wat("main(){a=0;return twice(a=a+1);}twice(x){return x+x;}");
+    assert!(!wat.contains("call $twice"));
+    assert_eq!(wat.matches("local.tee $a").count() + wat.matches("local.set $a").count(), 2);
+    assert_eq!(wat.matches("i32.add").count(), 2);
+}
+
+#[test]
+fn test_inline_locals() {
+    let mut module = parse("main(){t=5;return f()+f()+t;}f(){t=t+1;return t;}");
+    inline_functions(&mut module, InlineOptions::default());
+    let main = module.functions().find(|f| f.name == "main").unwrap();
+    // 展開した呼び出しごとに別のローカル変数になる
+    assert_eq!(main.locals().len(), 3);
+    // 展開した f の t はどちらも 0 から数えるので 1+1+5
+    assert_eq!(Instance::from_module(&module.lower()).invoke("main", &[]), Ok(vec![7]));
+}
+
+#[test]
+fn test_inline_threshold() {
+    let mut module = parse("main(){return sub(5,2);}sub(a,b){return a-b;}");
+    let options = InlineOptions { max_callee_size: 2, ..InlineOptions::default() };
+    assert_eq!(inline_functions(&mut module, options), 0);
+    let options = InlineOptions { max_caller_size: 2, ..InlineOptions::default() };
+    assert_eq!(inline_functions(&mut module, options), 0);
+    assert_eq!(inline_functions(&mut module, InlineOptions::default()), 1);
+}
+
+#[test]
+fn test_tail_recursion() {
+    let wat = optimized_wat("main(){return gcd(12,20);}gcd(a,b){if(a<b)return gcd(b,a);if(b==0)return a;return gcd(b,a-(a/b*b));}", |module| {
+        assert_eq!(eliminate_tail_recursion(module), 2);
+    });
+    assert_eq!(wat.matches("call $gcd").count(), 1);
+    assert!(wat.contains("loop $tail"));
+    assert_eq!(wat.matches("br $tail").count(), 2);
+    // 引数はすべて評価してからパラメータへ代入する
+    assert!(wat.contains("local.set $b_tail"));
+    assert!(wat.contains("local.get $a_tail"));
+
+    // 末尾位置でない再帰呼び出しはそのまま
+    let mut module = parse("main(){return fib(10);}fib(a){if(a<=1)return a;return fib(a-2)+fib(a-1);}");
+    assert_eq!(eliminate_tail_recursion(&mut module), 0);
+}
+
+#[test]
+fn test_tail_recursion_resets_locals() {
+    // 呼び出しごとにローカル変数は 0 から始まるので、ループにしても前の周回の値を残さない
+    let exp = "main(){return f(2);}f(n){if(n==0)return t;t=t+1;return f(n-1);}";
+    assert_eq!(optimized_result(exp, |module| { eliminate_tail_recursion(module); }), Ok(vec![0]));
+    assert_eq!(optimized_result(exp, |_| {}), Ok(vec![0]));
+}
+
+#[test]
+fn test_tail_recursion_then_inline() {
+    let wat = optimized_wat("main(){return count(5,0);}count(n,acc){if(n==0)return acc;return count(n-1,acc+n);}", |module| {
+        eliminate_tail_recursion(module);
+        inline_functions(module, InlineOptions::default());
+        eliminate_dead_code(module);
+    });
+    // 再帰がなくなれば展開できる
+    assert!(!wat.contains("call $count"));
+    assert!(wat.contains("loop $tail"));
+    let pass = |module: &mut Module| {
+        eliminate_tail_recursion(module);
+        inline_functions(module, InlineOptions::default());
+    };
+    assert_eq!(optimized_result("main(){return count(5,0);}count(n,acc){if(n==0)return acc;return count(n-1,acc+n);}", pass), Ok(vec![15]));
+    // 代入前に読むローカル変数は、ループにしてから展開しても周回ごとに 0
+    assert_eq!(optimized_result("main(){return f(3);}f(n){if(n==0)return t;t=t+n;return f(n-1);}", pass), Ok(vec![0]));
+}
+
+#[test]
+fn test_return_call() {
+    let wat = optimized_wat("main(){return f(1);}f(x){return g(x)+1;}g(x){return x;}", use_return_call);
+    assert!(wat.contains("return_call $f"));
+    assert!(wat.contains("call $g\ni32.const 1\ni32.add\nreturn\n"));
+
+    let mut module = parse("main(){return f(1);}f(x){return x;}");
+    use_return_call(&mut module);
+    let mut wasm = vec![];
+    module.lower().write_wasm(&mut wasm).unwrap();
+    assert!(wasm.windows(3).any(|w| w == [0x41, 0x01, 0x12])); // i32.const 1; return_call
+}
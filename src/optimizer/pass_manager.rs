@@ -0,0 +1,256 @@
+use std::time::{Duration, Instant};
+use crate::ast::Module;
+use crate::optimizer::{eliminate_dead_code, eliminate_tail_recursion, inline_functions, InlineOptions, simplify, use_peephole, use_return_call, wasm_size};
+
+pub trait Pass {
+    fn name(&self) -> &'static str;
+    // module を書き換え、何をしたかの要約を返す
+    fn run(&self, module: &mut Module) -> String;
+}
+
+pub struct Simplify;
+pub struct TailRecursion;
+pub struct Inline(pub InlineOptions);
+pub struct DeadCode;
+pub struct Peephole;
+pub struct ReturnCall;
+
+impl Pass for Simplify {
+    fn name(&self) -> &'static str {
+        "simplify"
+    }
+    fn run(&self, module: &mut Module) -> String {
+        simplify(module);
+        String::new()
+    }
+}
+
+impl Pass for TailRecursion {
+    fn name(&self) -> &'static str {
+        "tail-recursion"
+    }
+    fn run(&self, module: &mut Module) -> String {
+        format!("{} tail calls", eliminate_tail_recursion(module))
+    }
+}
+
+impl Pass for Inline {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+    fn run(&self, module: &mut Module) -> String {
+        format!("{} calls inlined", inline_functions(module, self.0))
+    }
+}
+
+impl Pass for DeadCode {
+    fn name(&self) -> &'static str {
+        "dead-code"
+    }
+    fn run(&self, module: &mut Module) -> String {
+        format!("{} bytes saved", eliminate_dead_code(module))
+    }
+}
+
+impl Pass for Peephole {
+    fn name(&self) -> &'static str {
+        "peephole"
+    }
+    fn run(&self, module: &mut Module) -> String {
+        use_peephole(module);
+        String::new()
+    }
+}
+
+impl Pass for ReturnCall {
+    fn name(&self) -> &'static str {
+        "return-call"
+    }
+    fn run(&self, module: &mut Module) -> String {
+        use_return_call(module);
+        String::new()
+    }
+}
+
+// パスを追加するときの並び順
+pub const PASS_ORDER: [&str; 6] = ["simplify", "tail-recursion", "inline", "dead-code", "peephole", "return-call"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum OptLevel {
+    O0,
+    O1,
+    O2,
+    O3,
+    Os,
+}
+
+impl OptLevel {
+
+    pub const ALL: [OptLevel; 5] = [OptLevel::O0, OptLevel::O1, OptLevel::O2, OptLevel::O3, OptLevel::Os];
+
+    pub fn parse(flag: &str) -> Option<OptLevel> {
+        match flag {
+            "-O0" => Some(OptLevel::O0),
+            "-O1" => Some(OptLevel::O1),
+            "-O2" | "-O" => Some(OptLevel::O2),
+            "-O3" => Some(OptLevel::O3),
+            "-Os" => Some(OptLevel::Os),
+            _ => None
+        }
+    }
+
+    pub fn flag(&self) -> &'static str {
+        match self {
+            OptLevel::O0 => "-O0",
+            OptLevel::O1 => "-O1",
+            OptLevel::O2 => "-O2",
+            OptLevel::O3 => "-O3",
+            OptLevel::Os => "-Os",
+        }
+    }
+
+    pub fn inline_options(&self) -> InlineOptions {
+        match self {
+            OptLevel::O0 | OptLevel::O1 | OptLevel::O2 => InlineOptions::default(),
+            OptLevel::O3 => InlineOptions { max_callee_size: 120, max_caller_size: 10000 },
+            // 呼び出しより小さくなる程度の関数だけを展開する
+            OptLevel::Os => InlineOptions { max_callee_size: 8, max_caller_size: 2000 },
+        }
+    }
+
+    // -O3 と -Os は -O2 と同じパスを流し、inline_options の閾値だけが違う。
+    // パスを重ねて流しても、いまのパスでは縮むものが増えない
+    fn pass_names(&self) -> &'static [&'static str] {
+        match self {
+            OptLevel::O0 => &[],
+            OptLevel::O1 => &["simplify", "dead-code", "peephole"],
+            OptLevel::O2 | OptLevel::O3 | OptLevel::Os => &["simplify", "tail-recursion", "inline", "dead-code", "peephole"],
+        }
+    }
+
+}
+
+pub struct PassStats {
+    pub name: &'static str,
+    pub duration: Duration,
+    pub size_before: usize,
+    pub size_after: usize,
+    pub summary: String,
+}
+
+pub struct PassManager {
+    level: OptLevel,
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl PassManager {
+
+    pub fn new(level: OptLevel) -> Self {
+        let mut manager = Self { level, passes: vec![] };
+        for name in level.pass_names() {
+            manager.enable(name);
+        }
+        manager
+    }
+
+    pub fn pass_names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    // 名前でパスを有効にする。既定の並び順の位置に入れる
+    pub fn enable(&mut self, name: &str) -> bool {
+        let pass: Box<dyn Pass> = match name {
+            "simplify" => Box::new(Simplify),
+            "tail-recursion" => Box::new(TailRecursion),
+            "inline" => Box::new(Inline(self.level.inline_options())),
+            "dead-code" => Box::new(DeadCode),
+            "peephole" => Box::new(Peephole),
+            "return-call" => Box::new(ReturnCall),
+            _ => return false
+        };
+        if self.passes.iter().any(|p| p.name() == name) {
+            return true;
+        }
+        let order = |name: &str| PASS_ORDER.iter().position(|n| *n == name).unwrap_or(PASS_ORDER.len());
+        let index = self.passes.iter().position(|p| order(p.name()) > order(name)).unwrap_or(self.passes.len());
+        self.passes.insert(index, pass);
+        true
+    }
+
+    // 名前でパスを無効にする。有効になっていなくても、知っている名前なら true
+    pub fn disable(&mut self, name: &str) -> bool {
+        self.passes.retain(|pass| pass.name() != name);
+        PASS_ORDER.contains(&name)
+    }
+
+    pub fn run(&self, module: &mut Module) -> Vec<PassStats> {
+        let mut stats = vec![];
+        for pass in self.passes.iter() {
+            let size_before = wasm_size(module);
+            let start = Instant::now();
+            let summary = pass.run(module);
+            let duration = start.elapsed();
+            stats.push(PassStats {
+                name: pass.name(),
+                duration,
+                size_before,
+                size_after: wasm_size(module),
+                summary,
+            });
+        }
+        stats
+    }
+
+}
+
+pub fn print_stats(stats: &[PassStats]) {
+    eprintln!("{:<16} {:>10} {:>8} {:>8} {:>8}", "pass", "time(us)", "before", "after", "diff");
+    for stat in stats {
+        eprintln!("{:<16} {:>10} {:>8} {:>8} {:>8}  {}",
+                  stat.name,
+                  stat.duration.as_micros(),
+                  stat.size_before,
+                  stat.size_after,
+                  stat.size_after as i64 - stat.size_before as i64,
+                  stat.summary);
+    }
+}
+
+#[test]
+fn test_levels() {
+    assert!(PassManager::new(OptLevel::O0).pass_names().is_empty());
+    assert_eq!(PassManager::new(OptLevel::O1).pass_names(), vec!["simplify", "dead-code", "peephole"]);
+    assert_eq!(PassManager::new(OptLevel::O2).pass_names(), vec!["simplify", "tail-recursion", "inline", "dead-code", "peephole"]);
+    // -O3 と -Os は展開の閾値だけが違う
+    for level in [OptLevel::O3, OptLevel::Os] {
+        assert_eq!(PassManager::new(level).pass_names(), PassManager::new(OptLevel::O2).pass_names());
+        assert_ne!(level.inline_options(), OptLevel::O2.inline_options());
+    }
+    assert_eq!(OptLevel::parse("-Os"), Some(OptLevel::Os));
+    assert_eq!(OptLevel::parse("-O4"), None);
+    for level in OptLevel::ALL {
+        assert_eq!(OptLevel::parse(level.flag()), Some(level));
+    }
+}
+
+#[test]
+fn test_enable_disable() {
+    let mut manager = PassManager::new(OptLevel::O1);
+    assert!(manager.enable("inline"));
+    assert!(manager.enable("return-call"));
+    assert!(!manager.enable("unknown"));
+    assert!(manager.disable("dead-code"));
+    assert!(manager.disable("tail-recursion"));
+    assert!(!manager.disable("unknown"));
+    assert_eq!(manager.pass_names(), vec!["simplify", "inline", "peephole", "return-call"]);
+}
+
+#[test]
+fn test_run() {
+    let mut module = crate::wasmc::parse("main(){return f(1+2);}f(x){return x;}unused(){return 0;}");
+    let stats = PassManager::new(OptLevel::O2).run(&mut module);
+    assert_eq!(stats.len(), 5);
+    let dead_code = stats.iter().find(|s| s.name == "dead-code").unwrap();
+    assert!(dead_code.size_after < dead_code.size_before);
+    assert_eq!(stats.iter().find(|s| s.name == "inline").unwrap().summary, "1 calls inlined");
+}
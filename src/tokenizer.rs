@@ -2,6 +2,8 @@
 #[derive(Debug)]
 pub enum Token<'a> {
     Num(i32),
+    // エスケープを解いた文字列のバイト列
+    Str(Vec<u8>),
     Reserved(&'a str),
     Ident(&'a str),
     Return,
@@ -9,6 +11,7 @@ pub enum Token<'a> {
     Else,
     While,
     For,
+    Import,
 }
 
 pub struct TokenIterator<'a> {
@@ -21,12 +24,13 @@ struct Keyword {
 }
 
 // 予約語
-const KEYWORDS: [Keyword; 5] = [
+const KEYWORDS: [Keyword; 6] = [
     Keyword{ word: "return", token: Token::Return },
     Keyword{ word: "if", token: Token::If },
     Keyword{ word: "else", token: Token::Else },
     Keyword{ word: "while", token: Token::While },
     Keyword{ word: "for", token: Token::For },
+    Keyword{ word: "import", token: Token::Import },
 ];
 
 // 演算子などの記号。長い順に並べる
@@ -56,18 +60,21 @@ impl<'a> Iterator for TokenIterator<'a> {
             }
         }
 
-        match self.s.chars().next() {
-            Some('a'..='z' | 'A'..='Z') => {
-                let (ident, remain) = split_ident(self.s);
-                self.s = remain;
-                return Some(Token::Ident(ident));
-            }
-            _ => (),
+        if let Some(rest) = self.s.strip_prefix('"') {
+            let (bytes, remain) = split_string(rest);
+            self.s = remain;
+            return Some(Token::Str(bytes));
+        }
+
+        if let Some('a'..='z' | 'A'..='Z') = self.s.chars().next() {
+            let (ident, remain) = split_ident(self.s);
+            self.s = remain;
+            return Some(Token::Ident(ident));
         }
         let (digit_s, remain_s) = split_digit(self.s);
         if !digit_s.is_empty() {
             self.s = remain_s;
-            return Some(Token::Num(i32::from_str_radix(digit_s, 10).unwrap()));
+            return Some(Token::Num(digit_s.parse::<i32>().unwrap()));
         }
         panic!("Invalid token stream")
     }
@@ -79,17 +86,14 @@ impl TokenIterator<'_> {
         let len = word.len();
         if self.s.starts_with(word) {
             let next = self.s.split_at(len).1.chars().next();
-            match next {
-                Some(c) => {
-                    if !is_ident_char(c) {
-                        self.s = self.s.split_at(len).1;
-                        return true;
-                    }
-                },
-                _ => {}
+            if let Some(c) = next {
+                if !is_ident_char(c) {
+                    self.s = self.s.split_at(len).1;
+                    return true;
+                }
             }
         }
-        return false;
+        false
     }
 
 }
@@ -99,6 +103,26 @@ fn split_digit(s: &str) -> (&str, &str) {
     s.split_at(first_non_num_idx)
 }
 
+// 閉じる " までを読む。使えるエスケープは \n \t \\ \" だけ
+fn split_string(s: &str) -> (Vec<u8>, &str) {
+    let mut bytes = vec![];
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (bytes, s.split_at(i + 1).1),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => bytes.push(b'\n'),
+                Some((_, 't')) => bytes.push(b'\t'),
+                Some((_, '\\')) => bytes.push(b'\\'),
+                Some((_, '"')) => bytes.push(b'"'),
+                _ => panic!("文字列のエスケープが正しくありません"),
+            },
+            _ => bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    panic!("文字列が閉じられていません")
+}
+
 fn split_ident(s: &str) -> (&str, &str) {
     let index = s.find(|c| !is_ident_char(c)).unwrap_or(s.len());
     s.split_at(index)
@@ -280,3 +304,22 @@ fn test_func() {
     assert_eq!(it.next(), Some(Token::Reserved("}")));
 }
 
+#[test]
+fn test_string() {
+    let mut it = TokenIterator { s: r#"print_str("a \"b\"\n");"# }.peekable();
+    assert_eq!(it.next(), Some(Token::Ident("print_str")));
+    assert_eq!(it.next(), Some(Token::Reserved("(")));
+    assert_eq!(it.next(), Some(Token::Str(b"a \"b\"\n".to_vec())));
+    assert_eq!(it.next(), Some(Token::Reserved(")")));
+    assert_eq!(it.next(), Some(Token::Reserved(";")));
+    assert_eq!(it.next(), None);
+}
+
+#[test]
+fn test_import() {
+    let mut it = TokenIterator { s: r#"import "lib.wc"; imports=1;"# }.peekable();
+    assert_eq!(it.next(), Some(Token::Import));
+    assert_eq!(it.next(), Some(Token::Str(b"lib.wc".to_vec())));
+    assert_eq!(it.next(), Some(Token::Reserved(";")));
+    assert_eq!(it.next(), Some(Token::Ident("imports")));
+}
@@ -1,32 +1,304 @@
-use std::fs::File;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::{stdout, Write};
 use std::iter::Peekable;
-use crate::ast::{Assign, AstNode, BiOperator, BiOpKind, Block, Call, ForNode, Function, IfNode, Module, Number, Param, ReturnNode, Variable, WasmWriter, WatWriter, WhileNode};
+use std::path::Path;
+use crate::ast::{Assign, BiOperator, BiOpKind, Block, Call, Expr, ForNode, Function, IfNode, Module, Number, Param, ReturnNode, Stmt, Variable, WhileNode};
+use crate::backend::Backends;
+use crate::evaluator::{agrees, evaluate};
+use crate::interpreter::{Instance, Trap};
+use crate::loader::{load, load_object, load_text};
+use crate::ir;
+use crate::optimizer::{OptLevel, PassManager, print_stats};
 use crate::tokenizer::{Token, TokenIterator};
 
-pub fn compile(exp: &str) {
+pub struct CompileOptions {
+    pub opt_level: OptLevel,
+    // -f<pass> で有効にするパス
+    pub enable_passes: Vec<String>,
+    // -fno-<pass> で無効にするパス
+    pub disable_passes: Vec<String>,
+    // パスごとの時間とサイズを表示する
+    pub print_stats: bool,
+    // 書き出す形式。backends に登録された名前
+    pub target: String,
+    pub backends: Backends,
+    // --emit で out.wasm と一緒に書き出すもの。EMITS のどれか
+    pub emit: Vec<String>,
+    // -c でソースごとに再配置できるオブジェクトを書き出す
+    pub relocatable: bool,
+}
 
-    let mut input = Input::new(exp);
-    let module = input.tokenize();
+// --emit で選べるもの
+pub const EMITS: [&str; 3] = ["js", "header", "rust-bindings"];
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            opt_level: OptLevel::O0,
+            enable_passes: vec![],
+            disable_passes: vec![],
+            print_stats: false,
+            target: "wasm".to_string(),
+            backends: Backends::default(),
+            emit: vec![],
+            relocatable: false,
+        }
+    }
+}
+
+impl CompileOptions {
+    // 知らないパスの名前があれば誤りにする
+    pub fn pass_manager(&self) -> Result<PassManager, String> {
+        let mut manager = PassManager::new(self.opt_level);
+        for name in self.enable_passes.iter() {
+            if !manager.enable(name) {
+                return Err(format!("unknown pass {}", name));
+            }
+        }
+        for name in self.disable_passes.iter() {
+            if !manager.disable(name) {
+                return Err(format!("unknown pass {}", name));
+            }
+        }
+        Ok(manager)
+    }
+}
+
+// 並べたソースを import も含めて 1 つのプログラムとしてコンパイルし、out.* に書き出す
+pub fn compile(sources: &[&str], options: &CompileOptions) {
+    if options.relocatable {
+        compile_objects(sources, options);
+        return;
+    }
+
+    let backend = match options.backends.get(&options.target) {
+        Some(backend) => backend,
+        None => panic!("unknown target {}", options.target),
+    };
+    let module = backend.prepare(optimize(load(sources), options));
+
+    let mut file = File::create(format!("out.{}", backend.extension())).unwrap();
+    let _ = backend.write_module(&module, &mut file);
+    let _ = file.flush();
+
+    // バイナリは読める形の WAT を代わりに表示する
+    if backend.is_text() {
+        let _ = backend.write_module(&module, &mut stdout());
+    } else {
+        let mut wat_file = File::create("out.wat").unwrap();
+        let _ = module.write_wat(&mut wat_file);
+        let _ = module.write_wat(&mut stdout());
+        let _ = wat_file.flush();
+    }
+
+    // ヘッダは --target c99 の出力とも合わせて使える
+    for emit in options.emit.iter() {
+        match emit.as_str() {
+            "js" if backend.extension() != "wasm" => panic!("--emit js は wasm を書き出すターゲットでしか使えません"),
+            "js" => {
+                write_file("out.js", |write| module.write_js("./out.wasm", write));
+                write_file("out.d.ts", |write| module.write_dts(write));
+            },
+            "header" => write_file("out.h", |write| module.write_header("out", write)),
+            "rust-bindings" => write_file("out.rs", |write| module.write_rust_bindings("out.wasm", write)),
+            _ => panic!("unknown emit {}", emit),
+        }
+    }
+
+}
+
+// ソースごとに別々にコンパイルし、a.wc なら a.o のように今のディレクトリに書き出す。
+// ファイルでないソースは out.o にする。できたものは wasmc link でまとめる
+fn compile_objects(sources: &[&str], options: &CompileOptions) {
+    if options.target != "wasm" {
+        panic!("-c は wasm ターゲットでしか使えません");
+    }
+    if !options.emit.is_empty() {
+        panic!("--emit は -c と一緒には使えません");
+    }
+    for source in sources {
+        let module = optimize(load_object(source), options);
+        let path = match Path::new(source).file_stem() {
+            Some(stem) if Path::new(source).is_file() => format!("{}.o", stem.to_string_lossy()),
+            _ => "out.o".to_string(),
+        };
+        write_file(&path, |write| module.write_object(write));
+    }
+}
+
+fn write_file(path: &str, write: impl FnOnce(&mut File) -> std::io::Result<()>) {
+    let mut file = File::create(path).unwrap();
+    let _ = write(&mut file);
+    let _ = file.flush();
+}
+
+// 構文解析から最適化までを済ませて中間表現にする
+pub fn lower(exp: &str, options: &CompileOptions) -> ir::Module {
+    optimize(parse(exp), options)
+}
+
+fn optimize(mut module: Module, options: &CompileOptions) -> ir::Module {
+    let pass_manager = match options.pass_manager() {
+        Ok(pass_manager) => pass_manager,
+        Err(error) => panic!("{}", error),
+    };
+    let stats = pass_manager.run(&mut module);
+    if options.print_stats {
+        eprintln!("passes: {}", pass_manager.pass_names().join(", "));
+        print_stats(&stats);
+    }
+    module.lower()
+}
+
+// 既存のファイルを指していればその中身を、そうでなければ引数そのものをソースとする
+pub fn read_source(arg: &str) -> String {
+    if Path::new(arg).is_file() {
+        match fs::read_to_string(arg) {
+            Ok(source) => source,
+            Err(error) => panic!("{} を読み込めません: {}", arg, error),
+        }
+    } else {
+        arg.to_string()
+    }
+}
+
+pub enum RunError {
+    // 呼び出し方の誤り
+    Usage(String),
+    Trap(Trap),
+    // AST の評価器とコンパイル結果の食い違い
+    Mismatch { reference: Result<i32, Trap>, compiled: Result<Vec<i32>, Trap> },
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Usage(message) => write!(f, "{}", message),
+            RunError::Trap(trap) => write!(f, "trap: {}", trap),
+            RunError::Mismatch { reference, compiled } => {
+                let reference = match reference {
+                    Ok(value) => value.to_string(),
+                    Err(trap) => format!("trap: {}", trap),
+                };
+                let compiled = match compiled {
+                    Ok(values) => values.iter().map(|value| value.to_string()).collect::<Vec<_>>().join(" "),
+                    Err(trap) => format!("trap: {}", trap),
+                };
+                write!(f, "mismatch: reference evaluator returned {}, compiled code returned {}", reference, compiled)
+            },
+        }
+    }
+}
+
+// ファイルを書き出さずにメモリ上でコンパイルし、export を実行する。source はファイルかソースそのもの
+pub fn run(source: &str, options: &CompileOptions, export: &str, args: &[String]) -> Result<Vec<i32>, RunError> {
+    let module = optimize(load(&[source]), options);
+    let (_, values) = arguments(&module, export, args)?;
+    execute(&module, export, &values).map_err(RunError::Trap)
+}
+
+// run と同じく実行し、最適化前の AST を評価器でも実行して結果を突き合わせる
+pub fn run_and_compare(source: &str, options: &CompileOptions, export: &str, args: &[String]) -> Result<Vec<i32>, RunError> {
+    let module = optimize(load(&[source]), options);
+    let (function, values) = arguments(&module, export, args)?;
+    let compiled = execute(&module, export, &values);
+    let reference = evaluate(&load(&[source]), &function, &values);
+    if !agrees(&reference, &compiled) {
+        return Err(RunError::Mismatch { reference, compiled });
+    }
+    compiled.map_err(RunError::Trap)
+}
+
+// export される関数の名前と、引数をパラメータの型で読んだもの
+fn arguments(module: &ir::Module, export: &str, args: &[String]) -> Result<(String, Vec<i32>), RunError> {
+    let function = match module.exports.iter().find(|e| e.name == export).map(|e| &e.kind) {
+        Some(ir::ExportKind::Function(name)) => module.function(name).unwrap(),
+        _ => return Err(RunError::Usage(format!("export `{}` not found", export))),
+    };
+    if function.params.len() != args.len() {
+        return Err(RunError::Usage(format!("`{}` takes {} arguments, got {}", export, function.params.len(), args.len())));
+    }
+    let mut values = vec![];
+    for (param, arg) in function.params.iter().zip(args) {
+        match param.vtype.parse_value(arg) {
+            Some(value) => values.push(value),
+            None => return Err(RunError::Usage(format!("invalid {} argument for ${}: {}", param.vtype.wat_name(), param.name, arg))),
+        }
+    }
 
-    let mut wat_file = File::create("out.wat").unwrap();
-    let _ = module.write_wat(&mut wat_file);
-    let _ = module.write_wat(&mut stdout());
-    let _ = wat_file.flush();
+    Ok((function.name.to_string(), values))
+}
 
-    let mut wasm_file = File::create("out.wasm").unwrap();
-    let _ = module.write_wasm(None, None, &mut wasm_file);
-    let _ = wasm_file.flush();
+// 出力するバイナリと同じものを動かすため、一度 wasm に書き出してから読み込む
+fn execute(module: &ir::Module, export: &str, values: &[i32]) -> Result<Vec<i32>, Trap> {
+    let mut instance = Instance::new(&encode(module)).unwrap();
+    link_builtins(&mut instance);
+    instance.invoke(export, values)
+}
 
+// 組み込み関数。WASI と同じく標準出力に書いて 0 を返す
+fn link_builtins(instance: &mut Instance) {
+    instance.link("env", "print_int", Box::new(|_, args| {
+        print!("{}", args[0]);
+        Ok(vec![0])
+    }));
+    instance.link("env", "print_str", Box::new(|memory, args| {
+        let start = args[0] as u32 as usize;
+        let end = match memory.get(start..).and_then(|rest| rest.iter().position(|byte| *byte == 0)) {
+            Some(length) => start + length,
+            None => return Err(Trap::MemoryOutOfBounds),
+        };
+        let _ = stdout().write_all(&memory[start..end]);
+        Ok(vec![0])
+    }));
+}
+
+// バイナリに書き出す。デバッグビルドでは書き出したものを検証する
+pub(crate) fn encode(module: &ir::Module) -> Vec<u8> {
+    let mut bytes = vec![];
+    module.write_wasm(&mut bytes).unwrap();
+    if cfg!(debug_assertions) {
+        if let Err(error) = ir::validate(&bytes) {
+            panic!("生成したバイナリが不正です: {}", error);
+        }
+    }
+    bytes
+}
+
+// 1 つのソースを構文解析する。import はたどらずにそのまま相対パスで指すものとして扱う
+pub fn parse(exp: &str) -> Module {
+    load_text(exp)
+}
+
+// 1 つのソースを読んだ結果。文字列リテラルは渡した Module に置く
+pub(crate) struct SourceFile {
+    pub functions: Vec<Function>,
+    // import に書いたパス
+    pub imports: Vec<String>,
+    // import で宣言したホストの関数の名前と引数の数
+    pub hosts: Vec<(String, usize)>,
+}
+
+pub(crate) fn parse_source(exp: &str, module: &mut Module) -> SourceFile {
+    let mut input = Input::new(exp);
+    input.module = std::mem::take(module);
+    let source = input.tokenize();
+    *module = std::mem::take(&mut input.module);
+    source
 }
 
 struct Input<'a> {
     token_iterator: Peekable<TokenIterator<'a>>,
+    // 文字列リテラルはここに置いていく
+    module: Module,
 }
 
 /*
-program    = func*
-func       = ident "(" (ident ( "," ident)* )?  ")" "{" stmt* "}"
+program    = (import | func)*
+import     = "import" (string | ident params) ";"
+func       = ident params "{" stmt* "}"
+params     = "(" (ident ( "," ident)* )?  ")"
 stmt       = "return" expr
            | expr ";"
            | if "(" expr ")" stmt ("else" stmt)?
@@ -42,54 +314,81 @@ add        = mul ("+" mul | "-" mul)*
 mul        = unary ("*" unary | "/" unary)*
 unary      = ("+" | "-")? primary
 primary    = num
+           | string
            | ident ("(" (expr ( "," expr)* )? ")")?
            | "(" expr ")"
  */
 impl <'a> Input<'a> {
     fn new(input: &'a str) -> Self {
-        Self { token_iterator: TokenIterator { s: input }.peekable() }
+        Self { token_iterator: TokenIterator { s: input }.peekable(), module: Module::new() }
     }
 
-    fn tokenize(&mut self) -> Module {
+    fn tokenize(&mut self) -> SourceFile {
         self.program()
     }
 
-    fn program(&mut self) -> Module {
-        let mut module = Module::new();
-        while self.token_iterator.peek() != None {
-            module.add_function(self.func());
+    fn program(&mut self) -> SourceFile {
+        let mut source = SourceFile { functions: vec![], imports: vec![], hosts: vec![] };
+        while self.token_iterator.peek().is_some() {
+            if self.token_iterator.peek() == Some(&Token::Import) {
+                self.import(&mut source);
+            } else {
+                source.functions.push(self.func());
+            }
         }
-        module
+        source
     }
 
-    fn func(&mut self) -> Function {
+    // import "path"; はファイルを、import name(a, b); はホストが env から渡す関数を宣言する
+    fn import(&mut self, source: &mut SourceFile) {
+        self.token_iterator.next();
         match self.token_iterator.next() {
-            Some(Token::Ident(func_name)) => {
-                let mut params : Vec<Param> = Vec::new();
-                self.expect(Token::Reserved("("));
-                match self.token_iterator.next() {
-                    Some(Token::Reserved(")")) => {}
-                    Some(Token::Ident(param_name)) => {
-                        params.push(Param::new(param_name.to_string()));
-                        while self.token_iterator.peek() != Some(&Token::Reserved(")")) {
-                            self.expect(Token::Reserved(","));
-                            match self.token_iterator.next() {
-                                Some(Token::Ident(param_name)) => {
-                                    params.push(Param::new(param_name.to_string()));
-                                }
-                                _ => {
-                                    panic!("関数のパラメータ宣言にエラーがあります")
-                                }
-                            }
+            Some(Token::Str(bytes)) => match String::from_utf8(bytes) {
+                Ok(path) => source.imports.push(path),
+                Err(_) => panic!("import するパスが UTF-8 ではありません"),
+            },
+            Some(Token::Ident(name)) => {
+                let params = self.params();
+                source.hosts.push((name.to_string(), params.len()));
+            },
+            _ => panic!("import にはパスの文字列か関数の宣言が必要です"),
+        };
+        self.expect(Token::Reserved(";"));
+    }
+
+    fn params(&mut self) -> Vec<Param> {
+        let mut params : Vec<Param> = Vec::new();
+        self.expect(Token::Reserved("("));
+        match self.token_iterator.next() {
+            Some(Token::Reserved(")")) => {}
+            Some(Token::Ident(param_name)) => {
+                params.push(Param::new(param_name.to_string()));
+                while self.token_iterator.peek() != Some(&Token::Reserved(")")) {
+                    self.expect(Token::Reserved(","));
+                    match self.token_iterator.next() {
+                        Some(Token::Ident(param_name)) => {
+                            params.push(Param::new(param_name.to_string()));
+                        }
+                        _ => {
+                            panic!("関数のパラメータ宣言にエラーがあります")
                         }
-                        self.token_iterator.next();
-                    },
-                    _ => {
-                        panic!("関数のパラメータ宣言にエラーがあります")
                     }
                 }
+                self.token_iterator.next();
+            },
+            _ => {
+                panic!("関数のパラメータ宣言にエラーがあります")
+            }
+        }
+        params
+    }
+
+    fn func(&mut self) -> Function {
+        match self.token_iterator.next() {
+            Some(Token::Ident(func_name)) => {
+                let params = self.params();
                 let block = self.block();
-                return Function::new(func_name.to_string(), params, Box::new(block));
+                Function::new(func_name.to_string(), params, Stmt::Block(block))
             },
             _ => {
                 panic!("関数宣言ではありません");
@@ -97,12 +396,12 @@ impl <'a> Input<'a> {
         }
     }
 
-    fn stmt(&mut self) -> Box<dyn AstNode> {
-        let node : Box<dyn AstNode> = match self.token_iterator.peek() {
+    fn stmt(&mut self) -> Stmt {
+        let node = match self.token_iterator.peek() {
             Some(Token::Return) => {
                 self.token_iterator.next();
                 let lhs = self.expr();
-                Box::new(ReturnNode::new(lhs))
+                Stmt::Return(ReturnNode::new(lhs))
             },
             Some(Token::If) => {
                 self.token_iterator.next();
@@ -117,7 +416,7 @@ impl <'a> Input<'a> {
                     },
                     _ => None
                 };
-                return Box::new(IfNode::new(cond, then, els))
+                return Stmt::If(IfNode::new(cond, then, els))
             }
             Some(Token::While) => {
                 self.token_iterator.next();
@@ -125,7 +424,7 @@ impl <'a> Input<'a> {
                 let cond = self.expr();
                 self.expect(Token::Reserved(")"));
                 let body = self.stmt();
-                return Box::new(WhileNode::new(cond, body));
+                return Stmt::While(WhileNode::new(cond, body));
             }
             Some(Token::For) => {
                 self.token_iterator.next();
@@ -164,13 +463,13 @@ impl <'a> Input<'a> {
                     }
                 };
                 let body = self.stmt();
-                return Box::new(ForNode::new(init, cond, inc, body));
+                return Stmt::For(ForNode::new(init, cond, inc, body));
             }
             Some(Token::Reserved("{")) => {
-                return Box::new(self.block());
+                return Stmt::Block(self.block());
             }
             _ => {
-                self.expr()
+                Stmt::Expr(self.expr())
             }
         };
         self.expect(Token::Reserved(";"));
@@ -190,27 +489,24 @@ impl <'a> Input<'a> {
             let stmt = self.stmt();
             block.add_statement(stmt);
         }
-        return block;
+        block
     }
 
-    fn expr(&mut self) -> Box<dyn AstNode> {
-        return self.assign();
+    fn expr(&mut self) -> Expr {
+        self.assign()
     }
 
-    fn assign(&mut self) -> Box<dyn AstNode> {
+    fn assign(&mut self) -> Expr {
         let mut node = self.equality();
-        match self.token_iterator.peek() {
-            Some(Token::Reserved("=")) => {
-                self.token_iterator.next();
-                let right = self.assign();
-                node = Box::new(Assign::new(node, right));
-            },
-            _ => ()
+        if let Some(Token::Reserved("=")) = self.token_iterator.peek() {
+            self.token_iterator.next();
+            let right = self.assign();
+            node = Expr::Assign(Assign::new(node, right));
         }
         node
     }
 
-    fn equality(&mut self) -> Box<dyn AstNode> {
+    fn equality(&mut self) -> Expr {
         let mut node = self.relational();
 
         loop {
@@ -218,12 +514,12 @@ impl <'a> Input<'a> {
                 Some(Token::Reserved("==")) => {
                     self.token_iterator.next();
                     let right = self.relational();
-                    node = Box::new(BiOperator::new(BiOpKind::Equal, node, right));
+                    node = Expr::BiOperator(BiOperator::new(BiOpKind::Equal, node, right));
                 },
                 Some(Token::Reserved("!=")) => {
                     self.token_iterator.next();
                     let right = self.relational();
-                    node = Box::new(BiOperator::new(BiOpKind::NotEqual, node, right));
+                    node = Expr::BiOperator(BiOperator::new(BiOpKind::NotEqual, node, right));
                 },
                 _ => {
                     break;
@@ -233,29 +529,29 @@ impl <'a> Input<'a> {
         node
     }
 
-    fn relational(&mut self) -> Box<dyn AstNode> {
+    fn relational(&mut self) -> Expr {
         let mut node = self.add();
         loop {
             match self.token_iterator.peek() {
                 Some(Token::Reserved(">=")) => {
                     self.token_iterator.next();
                     let right = self.add();
-                    node = Box::new(BiOperator::new(BiOpKind::GreaterThanOrEqual, node, right));
+                    node = Expr::BiOperator(BiOperator::new(BiOpKind::GreaterThanOrEqual, node, right));
                 },
                 Some(Token::Reserved(">")) => {
                     self.token_iterator.next();
                     let right = self.add();
-                    node = Box::new(BiOperator::new(BiOpKind::GreaterThan, node, right));
+                    node = Expr::BiOperator(BiOperator::new(BiOpKind::GreaterThan, node, right));
                 },
                 Some(Token::Reserved("<=")) => {
                     self.token_iterator.next();
                     let right = self.add();
-                    node = Box::new(BiOperator::new(BiOpKind::LessThanOrEqual, node, right));
+                    node = Expr::BiOperator(BiOperator::new(BiOpKind::LessThanOrEqual, node, right));
                 },
                 Some(Token::Reserved("<")) => {
                     self.token_iterator.next();
                     let right = self.add();
-                    node = Box::new(BiOperator::new(BiOpKind::LessThan, node, right));
+                    node = Expr::BiOperator(BiOperator::new(BiOpKind::LessThan, node, right));
                 },
                 _ => {
                     break;
@@ -264,19 +560,19 @@ impl <'a> Input<'a> {
         }
         node
     }
-    fn add(&mut self) -> Box<dyn AstNode> {
+    fn add(&mut self) -> Expr {
         let mut node = self.mul();
         loop {
             match self.token_iterator.peek() {
                 Some(Token::Reserved("+")) => {
                     self.token_iterator.next();
                     let right = self.mul();
-                    node = Box::new(BiOperator::new(BiOpKind::Add, node, right));
+                    node = Expr::BiOperator(BiOperator::new(BiOpKind::Add, node, right));
                 },
                 Some(Token::Reserved("-")) => {
                     self.token_iterator.next();
                     let right = self.mul();
-                    node = Box::new(BiOperator::new(BiOpKind::Sub, node, right));
+                    node = Expr::BiOperator(BiOperator::new(BiOpKind::Sub, node, right));
                 },
                 _ => {
                     break;
@@ -286,19 +582,19 @@ impl <'a> Input<'a> {
         node
     }
 
-    fn mul(&mut self) -> Box<dyn AstNode> {
+    fn mul(&mut self) -> Expr {
         let mut node = self.unary();
         loop {
             match self.token_iterator.peek() {
                 Some(Token::Reserved("*")) => {
                     self.token_iterator.next();
                     let right = self.unary();
-                    node = Box::new(BiOperator::new(BiOpKind::Mult, node, right));
+                    node = Expr::BiOperator(BiOperator::new(BiOpKind::Mult, node, right));
                 },
                 Some(Token::Reserved("/")) => {
                     self.token_iterator.next();
                     let right = self.unary();
-                    node = Box::new(BiOperator::new(BiOpKind::Div, node, right));
+                    node = Expr::BiOperator(BiOperator::new(BiOpKind::Div, node, right));
                 },
                 _ => {
                     break;
@@ -308,17 +604,17 @@ impl <'a> Input<'a> {
         node
     }
 
-    fn unary(&mut self) -> Box<dyn AstNode> {
-        return match self.token_iterator.peek() {
+    fn unary(&mut self) -> Expr {
+        match self.token_iterator.peek() {
             Some(Token::Reserved("+")) => {
                 self.token_iterator.next();
                 self.primary()
             },
             Some(Token::Reserved("-")) => {
                 self.token_iterator.next();
-                let left = Box::new(Number::new(0));
+                let left = Expr::Number(Number::new(0));
                 let right = self.primary();
-                Box::new(BiOperator::new(BiOpKind::Sub, left, right))
+                Expr::BiOperator(BiOperator::new(BiOpKind::Sub, left, right))
             },
             _ => {
                 self.primary()
@@ -326,18 +622,24 @@ impl <'a> Input<'a> {
         }
     }
 
-    fn primary(&mut self) -> Box<dyn AstNode> {
+    fn primary(&mut self) -> Expr {
         match self.token_iterator.peek() {
             Some(Token::Reserved("(")) => {
                 self.token_iterator.next();
                 let node = self.expr();
                 self.expect(Token::Reserved(")"));
-                return node
+                node
             },
             Some(Token::Num(num)) => {
-                let node = Box::new(Number::new(*num));
+                let node = Expr::Number(Number::new(*num));
+                self.token_iterator.next();
+                node
+            },
+            // 文字列はそのアドレスの数になる
+            Some(Token::Str(bytes)) => {
+                let address = self.module.add_string(bytes);
                 self.token_iterator.next();
-                return node;
+                Expr::Number(Number::address(address))
             },
             Some(Token::Ident(name)) => {
                 let name_str = name.to_string();
@@ -357,10 +659,10 @@ impl <'a> Input<'a> {
                             }
                         }
                         self.token_iterator.next();
-                        return Box::new(Call::new(name_str, args));
+                        Expr::Call(Call::new(name_str, args))
                     }
                     _ => {
-                        return Box::new(Variable::new(name_str));
+                        Expr::Variable(Variable::new(name_str))
                     }
                 }
             },
@@ -370,14 +672,14 @@ impl <'a> Input<'a> {
         }
     }
 
-    fn expect(&mut self, expected: Token) -> Token {
+    fn expect(&mut self, expected: Token) -> Token<'_> {
         let next = self.token_iterator.next();
         match next {
             Some(token) => {
                 if token != expected {
                     panic!("unexpected token {:?}", expected);
                 }
-                return token;
+                token
             },
             _ => {
                 panic!("Invalid token stream");
@@ -387,3 +689,46 @@ impl <'a> Input<'a> {
     }
 }
 
+#[cfg(test)]
+fn run_main(exp: &str, args: &[&str]) -> Result<Vec<i32>, RunError> {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    run(exp, &CompileOptions::default(), "main", &args)
+}
+
+#[test]
+fn test_run() {
+    let source = read_source("example/fib_loop_arg.wc");
+    assert!(matches!(run_main(&source, &["10"]), Ok(results) if results == vec![55]));
+    assert!(matches!(run_main("main(a,b){return a-b;}", &["-3", "4"]), Ok(results) if results == vec![-7]));
+    assert!(matches!(run_main("main(a){return 1/a;}", &["0"]), Err(RunError::Trap(Trap::DivisionByZero))));
+    // 組み込み関数はホストがなくても呼べる
+    assert!(matches!(run_main("main(){print_str(\"x\");return print_int(3)+1;}", &[]), Ok(results) if results == vec![1]));
+    assert!(matches!(run_main("main(){print_str(\"x\");return print_str(65536);}", &[]), Err(RunError::Trap(Trap::MemoryOutOfBounds))));
+    assert_eq!(run_main(&source, &[]).err().unwrap().to_string(), "`main` takes 1 arguments, got 0");
+    assert_eq!(run_main(&source, &["x"]).err().unwrap().to_string(), "invalid i32 argument for $num: x");
+    assert_eq!(run(&source, &CompileOptions::default(), "fib2", &[]).err().unwrap().to_string(), "export `fib2` not found");
+    // ファイルを渡せば import もたどる
+    assert!(matches!(run_main("example/modules/main.wc", &["5"]), Ok(results) if results == vec![120]));
+}
+
+#[test]
+fn test_run_and_compare() {
+    let options = CompileOptions { opt_level: OptLevel::O3, ..CompileOptions::default() };
+    let source = read_source("example/fib_loop_arg.wc");
+    assert!(matches!(run_and_compare(&source, &options, "main", &["10".to_string()]), Ok(results) if results == vec![55]));
+    assert!(matches!(run_and_compare("example/modules/main.wc", &options, "main", &["7".to_string()]), Ok(results) if results == vec![360]));
+    let source = "main(a){return 1/a;}";
+    assert!(matches!(run_and_compare(source, &options, "main", &["0".to_string()]), Err(RunError::Trap(Trap::DivisionByZero))));
+    let mismatch = RunError::Mismatch { reference: Ok(1), compiled: Err(Trap::DivisionByZero) };
+    assert_eq!(mismatch.to_string(), "mismatch: reference evaluator returned 1, compiled code returned trap: integer divide by zero");
+}
+
+#[test]
+fn test_pass_manager() {
+    let options = CompileOptions { opt_level: OptLevel::O1, enable_passes: vec!["inline".to_string()], disable_passes: vec!["dead-code".to_string()], ..CompileOptions::default() };
+    assert_eq!(options.pass_manager().unwrap().pass_names(), vec!["simplify", "inline", "peephole"]);
+    let options = CompileOptions { disable_passes: vec!["inlining".to_string()], ..CompileOptions::default() };
+    assert_eq!(options.pass_manager().err(), Some("unknown pass inlining".to_string()));
+    let options = CompileOptions { enable_passes: vec!["unroll".to_string()], ..CompileOptions::default() };
+    assert_eq!(options.pass_manager().err(), Some("unknown pass unroll".to_string()));
+}
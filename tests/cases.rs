@@ -0,0 +1,58 @@
+use wasmc::optimizer::OptLevel;
+use wasmc::wasmc::{run_and_compare, CompileOptions};
+
+// test.sh から移したもの。(main の戻り値, ソース)
+const CASES: [(i32, &str); 38] = [
+    (0, "main(){return 0;}"),
+    (42, "main(){return 42;}"),
+    (2147483647, "main(){return 2147483647;}"),
+    (-123456, "main(){return -123456;}"),
+    (21, "main(){return 5+20-4;}"),
+    (21, "main(){return  5 + 20 - 4 ;}"),
+    (47, "main(){return 5+6*7;}"),
+    (15, "main(){return 5*(9-6);}"),
+    (4, "main(){return (3+5)/2;}"),
+    (15, "main(){return -5*-3;}"),
+    (5, "main(){return +30/+6;}"),
+    (1, "main(){return 3*4==12;}"),
+    (0, "main(){return 3*4==0;}"),
+    (1, "main(){return 3*4!=0;}"),
+    (0, "main(){return 3*4!=12;}"),
+    (1, "main(){return 3*4>=12;}"),
+    (0, "main(){return 3*4>=13;}"),
+    (1, "main(){return 3*4<=12;}"),
+    (0, "main(){return 3*4<=11;}"),
+    (6, "main(){a=2;b=3;return a*b;}"),
+    (6, "main(){aZ_1=2;BB=3;return aZ_1*BB;}"),
+    (3, "main(){return 1+2; return 2*3;}"),
+    (1, "main(){a=5;if(a>3)return 1;return 2;}"),
+    (1, "main(){a=5;if(a>3){return 1;}{return 2;}}"),
+    (2, "main(){a=3;if(a>3)return 1;return 2;}"),
+    (1, "main(){a=5;if(a>3)return 1; else return 2;}"),
+    (2, "main(){a=3;if(a>3)return 1; else return 2;}"),
+    (1, "main(){a=5;if(a>3)b=1; else b=2;return b;}"),
+    (2, "main(){a=3;if(a>3)b=1; else b=2;return b;}"),
+    (5, "main(){a=1;while(a<=4)a=a+1;return a;}"),
+    (15, "main(){a=0;for(i=1;i<=5;i=i+1)a=a+i;return a;}"),
+    (20, "main(){a=0;for(i=1;i<=5;i=i+1){a=a+i;a=a+1;}return a;}"),
+    (1, "main(){return sub();}sub(){return 1;}"),
+    (3, "main(){return sub(5,2);}sub(a,b){return a-b;}"),
+    (55, "main(){return fib1(10);}fib1(a){if(a<=1){return a;}return fib1(a-2)+fib1(a-1);}"),
+    (55, "main(){return fib2(10);}fib2(a){if(a<=1){return a;}p0=0;p1=1;for(i=2;i<=a;i=i+1){p2=p0+p1;p0=p1;p1=p2;}return p2;}"),
+    (60, "main(){return lcm(12,20);}lcm(a,b){return a/gcd(a,b)*b;}gcd(a,b){if(a<b)return gcd(b,a);if(a==b)return a;if(b==0)return a;return gcd(b,a-(a/b*b));}"),
+    (2, "main(){return gcd(6,4);}lcm(a,b){return a/gcd(a,b)*b;}gcd(a,b){if(a<b)return gcd(b,a);if(a==b)return a;if(b==0)return a;return gcd(b, a-(a/b*b));}"),
+];
+
+// メモリ上でコンパイルして組み込みのインタープリタで実行し、AST の評価器とも突き合わせる
+#[test]
+fn test_cases() {
+    for level in OptLevel::ALL {
+        let options = CompileOptions { opt_level: level, ..CompileOptions::default() };
+        for (expected, source) in CASES {
+            match run_and_compare(source, &options, "main", &[]) {
+                Ok(results) => assert_eq!(results, vec![expected], "{} {}", level.flag(), source),
+                Err(error) => panic!("{} {}: {}", level.flag(), source, error),
+            }
+        }
+    }
+}
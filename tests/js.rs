@@ -0,0 +1,96 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use wasmc::wasmc::{lower, CompileOptions};
+
+fn node_available() -> bool {
+    Command::new("node").arg("--version").output().map(|output| output.status.success()).unwrap_or(false)
+}
+
+// ソースを out.wasm と out.js にして、script を ES モジュールとして node で動かした標準出力を返す
+fn run_node(source: &str, script: &str, dir: &str) -> String {
+    let dir: PathBuf = env::temp_dir().join(format!("wasmc-js-{}-{}", dir, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let module = lower(source, &CompileOptions::default());
+    let mut wasm = vec![];
+    module.write_wasm(&mut wasm).unwrap();
+    let mut js = vec![];
+    module.write_js("./out.wasm", &mut js).unwrap();
+    fs::write(dir.join("out.wasm"), wasm).unwrap();
+    fs::write(dir.join("out.js"), js).unwrap();
+    fs::write(dir.join("test.mjs"), script).unwrap();
+    let output = Command::new("node").arg(dir.join("test.mjs")).output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_exports() {
+    if !node_available() {
+        eprintln!("node が見つからないので飛ばします");
+        return;
+    }
+    let script = r#"
+import { init, main } from "./out.js";
+const attempt = (f) => { try { return f(); } catch (error) { return `${error.name}: ${error.message}`; } };
+console.log(attempt(() => main(1, 2)));
+await init({ env: { twice: (n) => n * 2 } });
+console.log(main(20, 3));
+console.log(main(2147483647, 0));
+console.log(attempt(() => main(1)));
+console.log(attempt(() => main(1.5, 2)));
+console.log(attempt(() => main(1, 2147483648)));
+console.log(attempt(() => main("1", 2)));
+"#;
+    assert_eq!(run_node("main(a,b){return twice(a)+b;}", script, "exports"), "\
+Error: init() has not been called
+43
+-2
+TypeError: main: expected 2 arguments, got 1
+TypeError: main: argument 0 must be an i32, got 1.5
+TypeError: main: argument 1 must be an i32, got 2147483648
+TypeError: main: argument 0 must be an i32, got 1
+");
+}
+
+#[test]
+fn test_missing_import() {
+    if !node_available() {
+        return;
+    }
+    let script = r#"
+import { init } from "./out.js";
+await init().catch((error) => console.log(`${error.name}: ${error.message}`));
+"#;
+    assert_eq!(run_node("main(a){return twice(a);}", script, "missing"), "TypeError: import env.twice must be a function\n");
+}
+
+#[test]
+fn test_strings() {
+    if !node_available() {
+        return;
+    }
+    // print_int と print_str は渡さなければ標準出力に書く
+    let script = r#"
+import { init, main, readString, writeString, readI32, writeI32, memoryBytes } from "./out.js";
+await init();
+console.log(main(7));
+await init({ env: { print_int: (n) => { console.log(`[${n}]`); return 0; } } });
+main(8);
+console.log(writeString(0, "héllo"), readString(0), memoryBytes()[6]);
+writeI32(16, -5);
+console.log(readI32(16));
+try { readI32(memoryBytes().length - 2); } catch (error) { console.log(error.name); }
+"#;
+    assert_eq!(run_node("main(n){print_str(\"n = \");print_int(n);print_str(\"\\n\");return n*2;}", script, "strings"), "\
+n = 7
+14
+n = [8]
+
+7 héllo 0
+-5
+RangeError
+");
+}
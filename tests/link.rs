@@ -0,0 +1,75 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use wasmc::interpreter::Instance;
+
+fn wasmc(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_wasmc")).args(args).current_dir(dir).output().unwrap()
+}
+
+// example/modules のファイルを別々に -c でコンパイルしてからリンクする
+#[test]
+fn test_link() {
+    let dir = env::temp_dir().join(format!("wasmc-link-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let example = Path::new(env!("CARGO_MANIFEST_DIR")).join("example/modules");
+    let (main, math) = (example.join("main.wc"), example.join("math.wc"));
+    let output = wasmc(&dir, &["-c", main.to_str().unwrap(), math.to_str().unwrap()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = wasmc(&dir, &["link", "-o", "modules.wasm", "main.o", "math.o"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let instance = Instance::new(&fs::read(dir.join("modules.wasm")).unwrap()).unwrap();
+    assert_eq!(instance.invoke("main", &[6]), Ok(vec![360]));
+
+    let output = wasmc(&dir, &["link", "main.o"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "main.o: undefined symbol: gcd\nmain.o: undefined symbol: factorial\n");
+    let output = wasmc(&dir, &["link", "main.o", "math.o", "math.o"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(),
+               "duplicate symbol: factorial (defined in math.o and math.o)\nduplicate symbol: gcd (defined in math.o and math.o)\n");
+    assert!(!dir.join("out.wasm").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// 組み込み関数と import で宣言した関数は、--allow-undefined なしでもリンクして import のまま残す
+#[test]
+fn test_link_host() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let dir = env::temp_dir().join(format!("wasmc-link-host-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("main.wc"), "import \"show.wc\";\nimport twice(x);\nmain(n){print_str(\"n=\");return show(twice(n));}").unwrap();
+    fs::write(dir.join("show.wc"), "show(n){print_int(n);print_str(\";\");return n;}").unwrap();
+    let output = wasmc(&dir, &["-c", "main.wc", "show.wc"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = wasmc(&dir, &["link", "-o", "host.wasm", "main.o", "show.o"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let mut instance = Instance::new(&fs::read(dir.join("host.wasm")).unwrap()).unwrap();
+    let printed = Rc::new(RefCell::new(String::new()));
+    let written = printed.clone();
+    instance.link("env", "print_str", Box::new(move |memory, args| {
+        let start = args[0] as usize;
+        let end = start + memory[start..].iter().position(|byte| *byte == 0).unwrap();
+        written.borrow_mut().push_str(std::str::from_utf8(&memory[start..end]).unwrap());
+        Ok(vec![0])
+    }));
+    let written = printed.clone();
+    instance.link("env", "print_int", Box::new(move |_, args| {
+        written.borrow_mut().push_str(&args[0].to_string());
+        Ok(vec![0])
+    }));
+    instance.link("env", "twice", Box::new(|_, args| Ok(vec![args[0] * 2])));
+    assert_eq!(instance.invoke("main", &[4]), Ok(vec![8]));
+    assert_eq!(*printed.borrow(), "n=8;");
+
+    // ほかのオブジェクトで定義する関数だけが未定義になる
+    let output = wasmc(&dir, &["link", "main.o"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "main.o: undefined symbol: show\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
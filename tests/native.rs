@@ -0,0 +1,181 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use wasmc::ir::Module;
+use wasmc::optimizer::OptLevel;
+use wasmc::wasmc::{lower, run, CompileOptions};
+
+// (ソース, main の引数)。引数レジスタに収まらない呼び出しやトラップも含める
+const PROGRAMS: [(&str, &[&str]); 9] = [
+    ("main(){return 5+6*7;}", &[]),
+    ("main(a,b){return a-b;}", &["-7", "2147483647"]),
+    ("main(){return lcm(12,20);}lcm(a,b){return a/gcd(a,b)*b;}gcd(a,b){if(a<b)return gcd(b,a);if(a==b)return a;if(b==0)return a;return gcd(b,a-(a/b*b));}", &[]),
+    ("main(n){a=1;while(n>0){a=a*3;n=n-1;}return a;}", &["40"]),
+    ("f(a,b,c,d,e,f,g,h){return a-b*2+c*3-d*4+e*5-f*6+g*7-h*8;}main(x){return f(x,2,3,4,5,6,7,x+1)+f(1,1,1,1,1,1,1,1);}", &["9"]),
+    ("sum(n,a){if(n==0)return a;return sum(n-1,a+n);}main(n){return sum(n,0);}", &["100000"]),
+    ("main(a){return 1/a;}", &["0"]),
+    ("main(a){return a/-1;}", &["-2147483648"]),
+    ("f(n){return f(n+1)+1;}main(){return f(0);}", &[]),
+];
+
+fn cc_available() -> bool {
+    Command::new("cc").arg("--version").output().map(|output| output.status.success()).unwrap_or(false)
+}
+
+// アセンブリか C のソースを cc でビルドして実行し、標準出力か、失敗したときは標準エラー出力を返す
+fn run_native(code: &[u8], extension: &str, args: &[&str], dir: &Path, name: &str) -> String {
+    let source = dir.join(format!("{}.{}", name, extension));
+    let binary = dir.join(name);
+    fs::write(&source, code).unwrap();
+    let mut command = Command::new("cc");
+    if extension == "c" {
+        command.args(["-std=c99", "-pedantic", "-Wall"]);
+    }
+    let status = command.arg("-o").arg(&binary).arg(&source).status().unwrap();
+    assert!(status.success(), "cc failed for {}", source.display());
+    let output = Command::new(&binary).args(args).output().unwrap();
+    if output.status.success() {
+        String::from_utf8(output.stdout).unwrap()
+    } else {
+        String::from_utf8(output.stderr).unwrap()
+    }
+}
+
+// ネイティブに実行した結果が組み込みのインタープリタや example/ の .out と同じになる
+fn check_native(extension: &str, write: fn(&Module, &mut Vec<u8>)) {
+    if !cc_available() {
+        eprintln!("cc が見つからないので飛ばします");
+        return;
+    }
+    let dir: PathBuf = env::temp_dir().join(format!("wasmc-native-{}-{}", extension, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (i, (source, args)) in PROGRAMS.iter().enumerate() {
+        for level in [OptLevel::O0, OptLevel::O2] {
+            for return_call in [false, true] {
+                let enable_passes = if return_call { vec!["return-call".to_string()] } else { vec![] };
+                let options = CompileOptions { opt_level: level, enable_passes, ..CompileOptions::default() };
+                let args_owned: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                let expected = match run(source, &options, "main", &args_owned) {
+                    Ok(results) => results.iter().map(|result| format!("{}\n", result)).collect::<String>(),
+                    Err(error) => format!("{}\n", error),
+                };
+                let mut code = vec![];
+                write(&lower(source, &options), &mut code);
+                let actual = run_native(&code, extension, args, &dir, &format!("p{}", i));
+                assert_eq!(actual, expected, "{} {} return_call={}", source, level.flag(), return_call);
+            }
+        }
+    }
+    for entry in fs::read_dir("example").unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map(|ext| ext != "wc").unwrap_or(true) || !path.with_extension("out").exists() {
+            continue;
+        }
+        let args = fs::read_to_string(path.with_extension("args")).unwrap_or_default();
+        let args: Vec<&str> = args.split_whitespace().collect();
+        let mut code = vec![];
+        write(&lower(&fs::read_to_string(&path).unwrap(), &CompileOptions::default()), &mut code);
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        let expected = fs::read_to_string(path.with_extension("out")).unwrap();
+        assert_eq!(run_native(&code, extension, &args, &dir, name), expected, "{}", path.display());
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_x86_64() {
+    check_native("s", |module, code| module.write_x86_64(code).unwrap());
+}
+
+#[test]
+fn test_c99() {
+    check_native("c", |module, code| module.write_c99(code).unwrap());
+}
+
+// 組み込み関数はネイティブの出力が自分で用意するので、ホストなしでリンクできる
+#[test]
+fn test_builtins() {
+    if !cc_available() {
+        return;
+    }
+    let dir: PathBuf = env::temp_dir().join(format!("wasmc-builtins-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let module = lower("main(n){print_str(\"n = \");print_int(n);print_str(\"\\n\");return n;}", &CompileOptions::default());
+    let trap = lower("main(){print_str(\"x\");return print_str(65536);}", &CompileOptions::default());
+    let write = |module: &Module, extension: &str| {
+        let mut code = vec![];
+        match extension {
+            "s" => module.write_x86_64(&mut code).unwrap(),
+            _ => module.write_c99(&mut code).unwrap(),
+        }
+        code
+    };
+    for extension in ["s", "c"] {
+        assert_eq!(run_native(&write(&module, extension), extension, &["7"], &dir, "builtins"), "n = 7\n7\n");
+        assert_eq!(run_native(&write(&trap, extension), extension, &[], &dir, "trap"), "trap: out of bounds memory access\n");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// ヘッダで宣言した wc_main と import が、C99 の出力とそのままリンクできる
+#[test]
+fn test_header() {
+    if !cc_available() {
+        return;
+    }
+    let dir: PathBuf = env::temp_dir().join(format!("wasmc-header-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let module = lower("main(n){return twice(n)+offset();}", &CompileOptions::default());
+    let mut header = vec![];
+    module.write_header("out", &mut header).unwrap();
+    let header = String::from_utf8(header).unwrap();
+    assert!(header.contains("\nint32_t wc_main(int32_t v_n);\n"));
+    assert!(header.contains("\nextern int32_t twice(int32_t a0);\nextern int32_t offset(void);\n"));
+    fs::write(dir.join("out.h"), header).unwrap();
+    let mut code = vec![];
+    module.write_c99(&mut code).unwrap();
+    fs::write(dir.join("out.c"), code).unwrap();
+    fs::write(dir.join("host.c"), "#include \"out.h\"\n\
+        int32_t twice(int32_t a0) { return a0 * 2; }\n\
+        int32_t offset(void) { return 1; }\n").unwrap();
+    let binary = dir.join("host");
+    let status = Command::new("cc").args(["-std=c99", "-pedantic", "-Wall", "-Werror", "-o"]).arg(&binary)
+        .arg(dir.join("out.c")).arg(dir.join("host.c")).status().unwrap();
+    assert!(status.success());
+    let output = Command::new(&binary).arg("20").output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "41\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Rust のバインディングがそのままコンパイルでき、トレイトを実装すれば import を渡せる
+#[test]
+fn test_rust_bindings() {
+    if !Command::new("rustc").arg("--version").output().map(|output| output.status.success()).unwrap_or(false) {
+        return;
+    }
+    let dir: PathBuf = env::temp_dir().join(format!("wasmc-rust-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let module = lower("main(a,b){print_str(\"x\");return twice(a)-b;}", &CompileOptions::default());
+    let mut bindings = vec![];
+    module.write_rust_bindings("out.wasm", &mut bindings).unwrap();
+    fs::write(dir.join("out.rs"), bindings).unwrap();
+    fs::write(dir.join("host.rs"), "mod out;\n\
+        struct Host;\n\
+        impl out::Env for Host {\n\
+            fn print_str(&mut self, _: i32) -> i32 { 0 }\n\
+            fn twice(&mut self, arg0: i32) -> i32 { arg0 * 2 }\n\
+        }\n\
+        fn main() {\n\
+            let params: out::exports::MainParams = (3, 4);\n\
+            let result: out::exports::MainResults = out::Env::twice(&mut Host, params.0) - params.1;\n\
+            println!(\"{} {} {:?} {} {}\", out::WASM, out::exports::MAIN, out::IMPORTS, out::MEMORY, result);\n\
+        }\n").unwrap();
+    let binary = dir.join("host");
+    let status = Command::new("rustc").args(["--edition", "2021", "-D", "warnings", "-o"]).arg(&binary)
+        .arg(dir.join("host.rs")).status().unwrap();
+    assert!(status.success());
+    let output = Command::new(&binary).output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "out.wasm main [(\"env\", \"print_str\"), (\"env\", \"twice\")] memory 2\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
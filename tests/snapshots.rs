@@ -0,0 +1,104 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use wasmc::ir::Module;
+use wasmc::optimizer::OptLevel;
+use wasmc::wasmc::{lower, run, CompileOptions, RunError};
+
+// WASMC_UPDATE_SNAPSHOTS=1 cargo test で、比べる代わりに example/ のファイルを書き直す
+fn updating() -> bool {
+    env::var_os("WASMC_UPDATE_SNAPSHOTS").is_some()
+}
+
+// 一致しなければ failures に加える
+fn check_snapshot(path: &Path, actual: &[u8], failures: &mut Vec<String>) {
+    if updating() {
+        fs::write(path, actual).unwrap();
+        return;
+    }
+    match fs::read(path) {
+        Ok(expected) if expected == actual => {},
+        Ok(_) => failures.push(format!("{} differs:\n{}", path.display(), String::from_utf8_lossy(actual))),
+        Err(_) => failures.push(format!("{} is missing", path.display())),
+    }
+}
+
+fn examples() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir("example").unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().map(|ext| ext == "wc").unwrap_or(false))
+        .collect();
+    paths.sort();
+    paths
+}
+
+// example/*.wc を既定のオプションでコンパイルした WAT (とあればバイナリ) と、main を実行した結果を比べる。
+// 引数を取るものは .args に書いた値で実行する
+#[test]
+fn test_examples() {
+    let options = CompileOptions::default();
+    let mut failures = vec![];
+    for path in examples() {
+        let source = fs::read_to_string(&path).unwrap();
+        let module = lower(&source, &options);
+
+        let mut wat = vec![];
+        module.write_wat(&mut wat).unwrap();
+        check_snapshot(&path.with_extension("wat"), &wat, &mut failures);
+        if path.with_extension("wasm").exists() {
+            let mut wasm = vec![];
+            module.write_wasm(&mut wasm).unwrap();
+            check_snapshot(&path.with_extension("wasm"), &wasm, &mut failures);
+        }
+
+        let args: Vec<String> = fs::read_to_string(path.with_extension("args")).unwrap_or_default()
+            .split_whitespace().map(|arg| arg.to_string()).collect();
+        let out = match run(&source, &options, "main", &args) {
+            Ok(results) => results.iter().map(|result| format!("{}\n", result)).collect::<String>(),
+            // 引数が足りないものは実行しない
+            Err(RunError::Usage(_)) if !path.with_extension("out").exists() => continue,
+            Err(error) => format!("{}\n", error),
+        };
+        check_snapshot(&path.with_extension("out"), out.as_bytes(), &mut failures);
+    }
+    if !failures.is_empty() {
+        panic!("{}\nWASMC_UPDATE_SNAPSHOTS=1 cargo test で更新できます", failures.join("\n"));
+    }
+}
+
+// バイナリを逆アセンブルすると write_wat と同じ WAT に戻る
+#[test]
+fn test_disassemble() {
+    for path in examples() {
+        let source = fs::read_to_string(&path).unwrap();
+        for opt_level in OptLevel::ALL {
+            for return_call in [false, true] {
+                let enable_passes = if return_call { vec!["return-call".to_string()] } else { vec![] };
+                let options = CompileOptions { opt_level, enable_passes, ..CompileOptions::default() };
+                let module = lower(&source, &options);
+                let mut wat = vec![];
+                module.write_wat(&mut wat).unwrap();
+                let mut wasm = vec![];
+                module.write_wasm(&mut wasm).unwrap();
+                let mut disassembled = vec![];
+                Module::read_wasm(&wasm).unwrap().write_wat(&mut disassembled).unwrap();
+                assert_eq!(String::from_utf8(disassembled).unwrap(), String::from_utf8(wat).unwrap(), "{} {}", path.display(), opt_level.flag());
+            }
+        }
+    }
+}
+
+// write_wat の出力を組み立て直すと write_wasm と同じバイナリになる
+#[test]
+fn test_assemble() {
+    for path in examples() {
+        let wasm = path.with_extension("wasm");
+        if !wasm.exists() {
+            continue;
+        }
+        let module = Module::read_wat(&fs::read_to_string(path.with_extension("wat")).unwrap()).unwrap();
+        let mut bytes = vec![];
+        module.write_wasm(&mut bytes).unwrap();
+        assert!(bytes == fs::read(&wasm).unwrap(), "{}", wasm.display());
+    }
+}